@@ -0,0 +1,126 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use notify::{Event, RecursiveMode, Watcher};
+
+use crate::{
+    actor::WrapperHandle,
+    ops::{read_op_names, OPS_FILE_NAME},
+};
+
+const WHITELIST_FILE_NAME: &str = "whitelist.json";
+// Editors and scripts often write a file in several steps. Wait until a file
+// has stopped changing for this long before reacting to it.
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(500);
+
+/// Spawns a thread that watches the server's `whitelist.json` and `ops.json`
+/// files, and brings the running server in sync with them whenever they're
+/// edited by something other than the server itself.
+///
+/// When `whitelist.json` changes, the server is told to reload it with
+/// "/whitelist reload". The server can't reload `ops.json` on its own, so when
+/// that file changes, the players who were added to or removed from it are
+/// opped or deopped with "/op" and "/deop".
+///
+/// `server_dir` is the server's directory, from [Wrapper::server_dir()](crate::Wrapper::server_dir()).
+pub fn spawn(wrapper: WrapperHandle, mut server_dir: PathBuf) -> anyhow::Result<()> {
+    if server_dir.as_os_str().is_empty() {
+        server_dir = PathBuf::from(".");
+    }
+
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx)
+        .with_context(|| "Failed to set up a watcher for the whitelist and ops files")?;
+    // Watch the whole directory rather than the files themselves, since lots of
+    // editors save a file by replacing it with a new one.
+    watcher
+        .watch(&server_dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {:?} for changes", &server_dir))?;
+
+    let mut ops = read_op_names(&server_dir).unwrap_or_default();
+
+    thread::spawn(move || {
+        // Stops watching when dropped.
+        let _watcher = watcher;
+
+        loop {
+            let mut changed_files = HashSet::new();
+            match rx.recv() {
+                Ok(event) => collect_changed_files(event, &mut changed_files),
+                Err(_) => return,
+            }
+            loop {
+                match rx.recv_timeout(DEBOUNCE_DURATION) {
+                    Ok(event) => collect_changed_files(event, &mut changed_files),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            if changed_files.contains(WHITELIST_FILE_NAME) {
+                info!("{} changed. Reloading the whitelist", WHITELIST_FILE_NAME);
+                run_command(&wrapper, "/whitelist reload");
+            }
+            if changed_files.contains(OPS_FILE_NAME) {
+                match read_op_names(&server_dir) {
+                    Ok(new_ops) => {
+                        for name in new_ops.difference(&ops) {
+                            info!("{} was added to {}. Opping them", name, OPS_FILE_NAME);
+                            run_command(&wrapper, &format!("/op {}", name));
+                        }
+                        for name in ops.difference(&new_ops) {
+                            info!("{} was removed from {}. Deopping them", name, OPS_FILE_NAME);
+                            run_command(&wrapper, &format!("/deop {}", name));
+                        }
+                        ops = new_ops;
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Adds the names of the files that the provided event is about to
+/// `changed_files`, if they're files that this watcher cares about.
+fn collect_changed_files(event: notify::Result<Event>, changed_files: &mut HashSet<&str>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            warn!(
+                "Something went wrong while watching the whitelist and ops files: {}",
+                e
+            );
+            return;
+        }
+    };
+    if event.kind.is_access() {
+        return;
+    }
+
+    for path in &event.paths {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(WHITELIST_FILE_NAME) => changed_files.insert(WHITELIST_FILE_NAME),
+            Some(OPS_FILE_NAME) => changed_files.insert(OPS_FILE_NAME),
+            _ => false,
+        };
+    }
+}
+
+fn run_command(wrapper: &WrapperHandle, command: &str) {
+    if let Err(e) = wrapper.blocking_run_command(command.to_owned()) {
+        warn!(
+            "Something went wrong while trying to run {:?} after the whitelist or ops files changed: {}",
+            command, e
+        );
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    collections::BTreeSet,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{dimension::Dimension, manifest::BackupDetails, BackupMode, BackupStatus, Wrapper};
+
+// How many requests can wait in line for the wrapper before callers have to
+// wait to add theirs.
+const REQUEST_QUEUE_CAPACITY: usize = 64;
+
+type Reply<T> = oneshot::Sender<anyhow::Result<T>>;
+type OnStart = Arc<dyn Fn() + Send + Sync>;
+
+/// Something that a [WrapperHandle] asks the [Wrapper] to do.
+enum Request {
+    /// Fetch the names of the players who are online, like
+    /// [Wrapper::list_players()]. Waits for up to `timeout` for the Minecraft
+    /// server's response instead of the usual command timeout, if it's set.
+    ListPlayers {
+        timeout: Option<Duration>,
+        reply: Reply<Vec<String>>,
+    },
+    /// Stop the Minecraft server, like [Wrapper::stop_server()].
+    Stop { reply: Reply<()> },
+    /// Restart the Minecraft server, like [Wrapper::restart_server()].
+    Restart { reply: Reply<()> },
+    /// Back up the world, the way that `mode` says to.
+    MakeWorldBackup {
+        dimensions: Option<BTreeSet<Dimension>>,
+        details: BackupDetails,
+        mode: BackupMode,
+        reply: Reply<MadeBackup>,
+    },
+    /// Give the Minecraft server a command without waiting for its response,
+    /// like [Wrapper::run_custom_command()].
+    Command { command: String, reply: Reply<()> },
+    /// Give the Minecraft server a command, and collect what it writes in
+    /// response, like [Wrapper::run_command_capture_output()]. Waits for up to
+    /// `timeout` instead of the usual command timeout, if it's set.
+    CommandCaptureOutput {
+        command: String,
+        timeout: Option<Duration>,
+        reply: Reply<Vec<String>>,
+    },
+    /// Run anything else against the [Wrapper]. Sends its own reply.
+    Call(Box<dyn FnOnce(&mut Wrapper) + Send>),
+}
+
+impl Request {
+    fn handle(self, wrapper: &mut Wrapper) {
+        match self {
+            Request::ListPlayers { timeout, reply } => {
+                let _ = reply.send(wrapper.with_command_timeout(timeout, Wrapper::list_players));
+            }
+            Request::Stop { reply } => {
+                let _ = reply.send(wrapper.stop_server());
+            }
+            Request::Restart { reply } => {
+                let _ = reply.send(wrapper.restart_server());
+            }
+            Request::MakeWorldBackup {
+                dimensions,
+                details,
+                mode,
+                reply,
+            } => {
+                let dimensions = dimensions.as_ref();
+                let path = match mode {
+                    BackupMode::Stop => wrapper.make_world_backup(dimensions, &details),
+                    BackupMode::CopyThenCompress => {
+                        wrapper.make_world_backup_in_background(dimensions, &details)
+                    }
+                    BackupMode::Hot => wrapper.make_hot_world_backup(dimensions, &details),
+                };
+                let _ = reply.send(path.map(|path| MadeBackup {
+                    path,
+                    status: wrapper.last_backup(),
+                }));
+            }
+            Request::Command { command, reply } => {
+                let _ = reply.send(wrapper.run_custom_command(&command));
+            }
+            Request::CommandCaptureOutput {
+                command,
+                timeout,
+                reply,
+            } => {
+                let _ = reply.send(
+                    wrapper
+                        .with_command_timeout(timeout, |w| w.run_command_capture_output(&command)),
+                );
+            }
+            Request::Call(f) => f(wrapper),
+        }
+    }
+}
+
+/// A [Request], along with what to call once it's taken off the queue.
+struct Queued {
+    request: Request,
+    on_start: Option<OnStart>,
+}
+
+/// A world backup that was made through a [WrapperHandle].
+#[derive(Debug, Clone)]
+pub struct MadeBackup {
+    /// Where the backup's tarball is, or will be once it's compressed, for
+    /// [BackupMode::CopyThenCompress].
+    pub path: PathBuf,
+    /// How the backup went, once it's done, like which old backups were
+    /// pruned. See [Wrapper::last_backup()].
+    pub status: Option<BackupStatus>,
+}
+
+/// Lets code use a [Wrapper] without sharing it.
+///
+/// The [Wrapper] belongs to a thread that's dedicated to it. Requests are
+/// queued up and carried out one at a time, in the order they were sent, by
+/// that thread. Async code awaits the response without blocking the runtime's
+/// threads, and code that doesn't run on an async runtime, like the watchdog,
+/// uses the `blocking_` methods, which work the same way.
+///
+/// Cheap to clone, and every clone sends to the same thread.
+#[derive(Clone)]
+pub struct WrapperHandle {
+    requests: mpsc::Sender<Queued>,
+    on_start: Option<OnStart>,
+}
+
+impl WrapperHandle {
+    /// Spawns the thread that the provided [Wrapper] belongs to from now on,
+    /// and returns a handle for sending it requests.
+    ///
+    /// The thread stops, and drops the [Wrapper], once every handle is
+    /// dropped.
+    pub fn spawn(mut wrapper: Wrapper) -> WrapperHandle {
+        let (requests, mut requests_rx) = mpsc::channel::<Queued>(REQUEST_QUEUE_CAPACITY);
+        thread::spawn(move || {
+            while let Some(queued) = requests_rx.blocking_recv() {
+                if let Some(on_start) = &queued.on_start {
+                    on_start();
+                }
+                // A request that panics drops its reply, which its caller
+                // reports as an error. The Wrapper might be left with some of
+                // a command's output unread, but that's thrown away before the
+                // next command runs anyways, and the watchdog still notices a
+                // server process that's gone, so later requests carry on.
+                let _ =
+                    panic::catch_unwind(AssertUnwindSafe(|| queued.request.handle(&mut wrapper)));
+            }
+        });
+        WrapperHandle {
+            requests,
+            on_start: None,
+        }
+    }
+
+    /// Returns a handle that sends to the same thread, but that calls
+    /// `on_start` whenever one of its requests is taken off the queue, right
+    /// before it's carried out. Lets something that was queued up tell when
+    /// it started.
+    pub fn on_start(&self, on_start: impl Fn() + Send + Sync + 'static) -> WrapperHandle {
+        WrapperHandle {
+            requests: self.requests.clone(),
+            on_start: Some(Arc::new(on_start)),
+        }
+    }
+
+    /// Fetches the names of the players who are online. See
+    /// [Wrapper::list_players()].
+    ///
+    /// Waits for up to `timeout` for the Minecraft server's response, or the
+    /// configured command timeout if it's [None].
+    pub async fn list_players(&self, timeout: Option<Duration>) -> anyhow::Result<Vec<String>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Request::ListPlayers { timeout, reply }, reply_rx)
+            .await
+    }
+
+    /// Like [WrapperHandle::list_players()], for code that doesn't run on an
+    /// async runtime.
+    pub fn blocking_list_players(&self, timeout: Option<Duration>) -> anyhow::Result<Vec<String>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.blocking_send(Request::ListPlayers { timeout, reply }, reply_rx)
+    }
+
+    /// Stops the Minecraft server. See [Wrapper::stop_server()].
+    pub async fn stop(&self) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Request::Stop { reply }, reply_rx).await
+    }
+
+    /// Like [WrapperHandle::stop()], for code that doesn't run on an async
+    /// runtime.
+    pub fn blocking_stop(&self) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.blocking_send(Request::Stop { reply }, reply_rx)
+    }
+
+    /// Restarts the Minecraft server. See [Wrapper::restart_server()].
+    pub async fn restart(&self) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Request::Restart { reply }, reply_rx).await
+    }
+
+    /// Like [WrapperHandle::restart()], for code that doesn't run on an async
+    /// runtime.
+    pub fn blocking_restart(&self) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.blocking_send(Request::Restart { reply }, reply_rx)
+    }
+
+    /// Backs up the provided dimensions of the world, or all of them if it's
+    /// [None], the way that `mode` says to.
+    ///
+    /// Backups that don't stop the Minecraft server warn players first if
+    /// they're going to be kicked, and the warning is waited out here, so that
+    /// other requests aren't held up behind it. See
+    /// [Wrapper::warn_players_before_backup()].
+    pub async fn make_world_backup(
+        &self,
+        dimensions: Option<BTreeSet<Dimension>>,
+        details: BackupDetails,
+        mode: BackupMode,
+    ) -> anyhow::Result<MadeBackup> {
+        if mode == BackupMode::Hot {
+            self.warn_players_before_backup().await?;
+        }
+        let (reply, reply_rx) = oneshot::channel();
+        let request = Request::MakeWorldBackup {
+            dimensions,
+            details,
+            mode,
+            reply,
+        };
+        self.send(request, reply_rx).await
+    }
+
+    /// Like [WrapperHandle::make_world_backup()], for code that doesn't run on
+    /// an async runtime.
+    pub fn blocking_make_world_backup(
+        &self,
+        dimensions: Option<BTreeSet<Dimension>>,
+        details: BackupDetails,
+        mode: BackupMode,
+    ) -> anyhow::Result<MadeBackup> {
+        if mode == BackupMode::Hot {
+            if let Some(warning) = self.blocking_call(|w| w.warn_players_before_backup())? {
+                thread::sleep(warning);
+            }
+        }
+        let (reply, reply_rx) = oneshot::channel();
+        let request = Request::MakeWorldBackup {
+            dimensions,
+            details,
+            mode,
+            reply,
+        };
+        self.blocking_send(request, reply_rx)
+    }
+
+    /// Warns players that they're about to be kicked for a backup that doesn't
+    /// stop the Minecraft server, if they are, and waits for the warning to
+    /// run out without holding up other requests. See
+    /// [Wrapper::warn_players_before_backup()].
+    pub async fn warn_players_before_backup(&self) -> anyhow::Result<()> {
+        if let Some(warning) = self.call(|w| w.warn_players_before_backup()).await? {
+            tokio::time::sleep(warning).await;
+        }
+        Ok(())
+    }
+
+    /// Gives the Minecraft server a command without waiting for its response.
+    /// See [Wrapper::run_custom_command()].
+    pub async fn run_command(&self, command: String) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(Request::Command { command, reply }, reply_rx)
+            .await
+    }
+
+    /// Like [WrapperHandle::run_command()], for code that doesn't run on an
+    /// async runtime.
+    pub fn blocking_run_command(&self, command: String) -> anyhow::Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.blocking_send(Request::Command { command, reply }, reply_rx)
+    }
+
+    /// Gives the Minecraft server a command, and returns what it writes in
+    /// response. See [Wrapper::run_command_capture_output()].
+    ///
+    /// Waits for up to `timeout` for the response, or the configured command
+    /// timeout if it's [None].
+    pub async fn run_command_capture_output(
+        &self,
+        command: String,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Vec<String>> {
+        let (reply, reply_rx) = oneshot::channel();
+        let request = Request::CommandCaptureOutput {
+            command,
+            timeout,
+            reply,
+        };
+        self.send(request, reply_rx).await
+    }
+
+    /// Like [WrapperHandle::run_command_capture_output()], for code that
+    /// doesn't run on an async runtime.
+    pub fn blocking_run_command_capture_output(
+        &self,
+        command: String,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Vec<String>> {
+        let (reply, reply_rx) = oneshot::channel();
+        let request = Request::CommandCaptureOutput {
+            command,
+            timeout,
+            reply,
+        };
+        self.blocking_send(request, reply_rx)
+    }
+
+    /// Runs `f` against the [Wrapper] once every request that was sent before
+    /// it is done, and returns what it returns.
+    ///
+    /// Once `f` is queued up, it runs to completion even if the future that
+    /// this returns is dropped first.
+    pub async fn call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Wrapper) -> anyhow::Result<T> + Send + 'static,
+    {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(call_request(f, reply), reply_rx).await
+    }
+
+    /// Like [WrapperHandle::call()], for code that doesn't run on an async
+    /// runtime. Everything that `f` does happens without any other request
+    /// getting in between.
+    pub fn blocking_call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Wrapper) -> anyhow::Result<T> + Send + 'static,
+    {
+        let (reply, reply_rx) = oneshot::channel();
+        self.blocking_send(call_request(f, reply), reply_rx)
+    }
+
+    async fn send<T>(
+        &self,
+        request: Request,
+        reply_rx: oneshot::Receiver<anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.requests
+            .send(self.queued(request))
+            .await
+            .map_err(|_| thread_stopped())?;
+        reply_rx.await.unwrap_or_else(|_| Err(thread_panicked()))
+    }
+
+    fn blocking_send<T>(
+        &self,
+        request: Request,
+        reply_rx: oneshot::Receiver<anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.requests
+            .blocking_send(self.queued(request))
+            .map_err(|_| thread_stopped())?;
+        reply_rx
+            .blocking_recv()
+            .unwrap_or_else(|_| Err(thread_panicked()))
+    }
+
+    fn queued(&self, request: Request) -> Queued {
+        Queued {
+            request,
+            on_start: self.on_start.clone(),
+        }
+    }
+}
+
+fn call_request<T, F>(f: F, reply: Reply<T>) -> Request
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Wrapper) -> anyhow::Result<T> + Send + 'static,
+{
+    Request::Call(Box::new(move |w| {
+        let _ = reply.send(f(w));
+    }))
+}
+
+fn thread_stopped() -> anyhow::Error {
+    anyhow!("The thread talking to the Minecraft server stopped")
+}
+
+fn thread_panicked() -> anyhow::Error {
+    anyhow!("The thread talking to the Minecraft server panicked")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::tests::TestServer;
+
+    #[test]
+    fn typed_requests_reach_the_wrapper() {
+        let server = TestServer::new("actor-typed-requests");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+
+        wrapper.blocking_stop().unwrap();
+        assert!(wrapper.blocking_call(|w| w.has_exited()).unwrap());
+        wrapper.blocking_restart().unwrap();
+        assert!(!wrapper.blocking_call(|w| w.has_exited()).unwrap());
+    }
+
+    #[test]
+    fn on_start_is_called_right_before_the_request() {
+        let server = TestServer::new("actor-on-start");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let started = wrapper.on_start({
+            let order = Arc::clone(&order);
+            move || order.lock().unwrap().push("started")
+        });
+        let ran = Arc::clone(&order);
+        started
+            .blocking_call(move |_| {
+                ran.lock().unwrap().push("ran");
+                Ok(())
+            })
+            .unwrap();
+        let ran = Arc::clone(&order);
+        wrapper
+            .blocking_call(move |_| {
+                ran.lock().unwrap().push("ran without on_start");
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["started", "ran", "ran without on_start"]
+        );
+    }
+
+    #[test]
+    fn requests_after_one_that_panicked_carry_on() {
+        let server = TestServer::new("actor-panic");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+
+        let e = wrapper
+            .blocking_call(|_| -> anyhow::Result<()> { panic!("oops") })
+            .unwrap_err();
+        assert_eq!(e.to_string(), thread_panicked().to_string());
+        assert!(!wrapper.blocking_call(|w| w.has_exited()).unwrap());
+    }
+}
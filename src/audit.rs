@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use axum::{body::Body, extract::ConnectInfo, http::Request};
+use chrono::Utc;
+use log::warn;
+use serde::Serialize;
+
+use crate::auth::Caller;
+
+/// The identity recorded for commands typed into the wrapper's stdin.
+pub(crate) const LOCAL_CONSOLE_IDENTITY: &str = "local console";
+
+// Routes that change something even though they're requested with GET. Requests
+// with any other method are always treated as changing something.
+const MUTATING_GET_ROUTES: [&str; 4] =
+    ["/stop", "/restart", "/make-world-backup", "/backups/stream"];
+
+/// An append-only log of who asked the wrapper to change what. Each entry is a
+/// JSON object on its own line.
+///
+/// Does nothing if it wasn't given a path to write to.
+pub(crate) struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    identity: &'a str,
+    action: &'a str,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the provided path, creating it if it doesn't
+    /// exist yet. New entries are appended to whatever's already there.
+    pub(crate) fn open(path: Option<&str>) -> anyhow::Result<AuditLog> {
+        let path = match path {
+            Some(path) => Path::new(path),
+            None => return Ok(AuditLog { file: None }),
+        };
+
+        if let Some(parent_dir) = path.parent() {
+            fs::create_dir_all(parent_dir).with_context(|| {
+                format!(
+                    "Something went wrong while making a {:?} directory for the audit log to live in",
+                    parent_dir
+                )
+            })?;
+        }
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the audit log at {:?}", path))?;
+        Ok(AuditLog {
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Records that `identity` did `action`.
+    pub(crate) fn record(&self, identity: &str, action: &str) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let entry = AuditEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            identity,
+            action,
+        };
+        let mut line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize an audit log entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut file = file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|()| file.flush()) {
+            warn!("Failed to write to the audit log: {}", e);
+        }
+    }
+
+    /// Records the provided HTTP request, if it's one that changes something.
+    ///
+    /// Has to run after [require_token()](crate::auth::require_token), so that requests are
+    /// identified by the name of the token that they presented. Requests that
+    /// didn't present a named token are identified by the address of the
+    /// client that sent them instead.
+    pub(crate) fn record_request(&self, req: &Request<Body>) {
+        if self.file.is_none() || !is_mutating(req) {
+            return;
+        }
+
+        self.record(
+            &request_identity(req),
+            &format!("{} {}", req.method(), req.uri()),
+        );
+    }
+}
+
+fn is_mutating(req: &Request<Body>) -> bool {
+    !req.method().is_safe() || MUTATING_GET_ROUTES.contains(&req.uri().path())
+}
+
+/// Returns the name of the token that the provided request presented, or the
+/// IP address of the client that sent it if there isn't one.
+fn request_identity(req: &Request<Body>) -> String {
+    let token_name = req
+        .extensions()
+        .get::<Caller>()
+        .and_then(Caller::token_name);
+    if let Some(name) = token_name {
+        return name.to_owned();
+    }
+    match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => addr.ip().to_string(),
+        None => "unknown".to_owned(),
+    }
+}
@@ -0,0 +1,330 @@
+use std::{fmt, sync::Arc};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The name that requests made with the `api_token` from `config.yaml` are
+/// identified by.
+const ADMIN_TOKEN_NAME: &str = "admin";
+
+/// A group of routes that a token can be allowed to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Scope {
+    /// Routes that only look at the server, like `GET /info`.
+    Read,
+    /// Routes that change the game, like enabling data packs or force loading
+    /// chunks.
+    Command,
+    /// Routes that manage players, like turning on maintenance mode.
+    Moderate,
+    /// Everything, including stopping, restarting, and backing up the server,
+    /// and editing its config files.
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Scope::Read => "read",
+            Scope::Command => "command",
+            Scope::Moderate => "moderate",
+            Scope::Admin => "admin",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A token from `config.yaml`, along with what it's allowed to do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TokenConfig {
+    /// What requests made with this token are identified by. Never a secret.
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    pub(crate) token: String,
+    pub(crate) scopes: Vec<Scope>,
+}
+
+/// Who made a request, as far as the wrapper can tell. [require_token()] adds
+/// one to every request's extensions.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Caller {
+    /// Whether requests have to present a token at all.
+    auth_enabled: bool,
+    /// The name of the token that the request presented, if any.
+    token: Option<String>,
+    /// Where the token that the request presented is in the list of tokens.
+    /// Tells apart tokens that don't have names.
+    #[serde(skip)]
+    token_index: Option<usize>,
+    scopes: Vec<Scope>,
+}
+
+impl Caller {
+    /// The name of the token that the request presented, if any.
+    pub(crate) fn token_name(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Which token the request presented, or [None] if no tokens are
+    /// configured. Unlike its name, every token has one, and no two tokens
+    /// share one.
+    pub(crate) fn token_index(&self) -> Option<usize> {
+        self.token_index
+    }
+
+    /// Returns true if the caller can use routes that need the provided scope.
+    /// The admin scope can use every route.
+    fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+}
+
+/// Decides which requests are allowed through, based on the tokens in
+/// `config.yaml`.
+pub(crate) struct Auth {
+    tokens: Vec<TokenConfig>,
+}
+
+impl Auth {
+    /// `api_token` is treated like an entry in `tokens` with the admin scope.
+    /// When there aren't any tokens, every request is let through.
+    pub(crate) fn new(api_token: Option<String>, mut tokens: Vec<TokenConfig>) -> Auth {
+        if let Some(api_token) = api_token {
+            tokens.push(TokenConfig {
+                name: Some(ADMIN_TOKEN_NAME.to_owned()),
+                token: api_token,
+                scopes: vec![Scope::Admin],
+            });
+        }
+        Auth { tokens }
+    }
+
+    /// Returns who made the provided request, or [None] if it didn't present a
+    /// known token.
+    fn identify(&self, req: &Request<Body>) -> Option<Caller> {
+        if self.tokens.is_empty() {
+            return Some(Caller {
+                auth_enabled: false,
+                token: None,
+                token_index: None,
+                scopes: vec![Scope::Admin],
+            });
+        }
+
+        let presented = presented_token(req)?;
+        let presented = presented.trim();
+        // Check every token, even after finding a match, so that how long this
+        // takes doesn't give away anything about them.
+        let mut found = None;
+        for (i, token_config) in self.tokens.iter().enumerate() {
+            if tokens_match(presented, &token_config.token) && found.is_none() {
+                found = Some(i);
+            }
+        }
+        let token_index = found?;
+        let token_config = &self.tokens[token_index];
+        Some(Caller {
+            auth_enabled: true,
+            token: token_config.name.clone(),
+            token_index: Some(token_index),
+            scopes: token_config.scopes.clone(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenParams {
+    token: Option<String>,
+}
+
+/// Returns the token that a request presented in its "Authorization" header.
+///
+/// Browsers can't add headers to WebSocket requests, so `GET /console` can
+/// present its token in a `token` query parameter instead.
+fn presented_token(req: &Request<Body>) -> Option<String> {
+    let from_header = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = from_header {
+        return Some(token.to_owned());
+    }
+    if req.method() != Method::GET || req.uri().path() != "/console" {
+        return None;
+    }
+    serde_urlencoded::from_str::<TokenParams>(req.uri().query()?)
+        .ok()?
+        .token
+}
+
+/// Turns away requests that don't present a known token with a `401`, and
+/// requests whose token doesn't have the scope that the route needs with a
+/// `403`. Lets every request through if no tokens are configured.
+///
+/// The landing page at `GET /` is always let through, without a [Caller].
+pub(crate) async fn require_token(
+    auth: Arc<Auth>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    // It doesn't give anything away, and browsers can't send a token.
+    if req.method() == Method::GET && req.uri().path() == "/" {
+        return next.run(req).await;
+    }
+
+    let caller = match auth.identify(&req) {
+        Some(caller) => caller,
+        None => {
+            let err_msg = "This request needs an \"Authorization: Bearer <token>\" header with a valid API token";
+            warn!("{} {}: {}", req.method(), req.uri().path(), err_msg);
+            let mut headers = HeaderMap::new();
+            headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return (StatusCode::UNAUTHORIZED, headers, err_msg).into_response();
+        }
+    };
+
+    if let Some(scope) = required_scope(req.method(), req.uri().path()) {
+        if !caller.allows(scope) {
+            let err_msg = format!(
+                "This request needs a token with the {:?} scope",
+                scope.to_string()
+            );
+            warn!("{} {}: {}", req.method(), req.uri().path(), err_msg);
+            return (StatusCode::FORBIDDEN, err_msg).into_response();
+        }
+    }
+
+    req.extensions_mut().insert(caller);
+    next.run(req).await
+}
+
+/// Returns the scope that a token needs to make a request to the provided
+/// route, or [None] if any valid token can.
+///
+/// Routes that aren't listed here need the admin scope, so that new routes are
+/// locked down until somebody decides otherwise.
+fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let scope = match (method, segments.as_slice()) {
+        (&Method::GET, ["whoami"]) => return None,
+        (
+            &Method::GET,
+            ["info"]
+            | ["startup-warnings"]
+            | ["crashes"]
+            | ["list-players"]
+            | ["performance"]
+            | ["time"]
+            | ["query"]
+            | ["ping"]
+            | ["events"]
+            | ["logs"]
+            | ["backups"]
+            | ["datapacks"]
+            | ["forceload"]
+            | ["ops"]
+            | ["bans", "ips"]
+            | ["server-icon"]
+            | ["stats", "commands" | "stdout"],
+        ) => Scope::Read,
+        (&Method::POST, ["datapacks", _, "enable" | "disable"] | ["forceload"]) => Scope::Command,
+        (&Method::POST, ["maintenance"]) => Scope::Moderate,
+        _ => Scope::Admin,
+    };
+    Some(scope)
+}
+
+/// Compares every byte of both tokens' SHA-256 hashes, so that how long it
+/// takes doesn't give away how long the expected token is, or how much of a
+/// guessed one was right.
+pub(crate) fn tokens_match(presented: &str, expected: &str) -> bool {
+    let presented = Sha256::digest(presented.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    presented
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_properties_need_admin() {
+        assert_eq!(
+            required_scope(&Method::GET, "/properties/raw"),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/properties/raw"),
+            Some(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn read_routes_need_read() {
+        assert_eq!(required_scope(&Method::GET, "/info"), Some(Scope::Read));
+        assert_eq!(
+            required_scope(&Method::GET, "/stats/stdout"),
+            Some(Scope::Read)
+        );
+        assert_eq!(required_scope(&Method::GET, "/whoami"), None);
+    }
+
+    #[test]
+    fn unlisted_routes_need_admin() {
+        assert_eq!(
+            required_scope(&Method::GET, "/diagnostics"),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/command"),
+            Some(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("hunter2", "hunter2"));
+        assert!(!tokens_match("hunter3", "hunter2"));
+        assert!(!tokens_match("hunter", "hunter2"));
+        assert!(!tokens_match("", "hunter2"));
+        assert!(tokens_match("", ""));
+    }
+
+    fn request_with_token(token: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/info")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn unnamed_tokens_are_told_apart() {
+        let unnamed = |token: &str| TokenConfig {
+            name: None,
+            token: token.to_owned(),
+            scopes: vec![Scope::Read],
+        };
+        let auth = Auth::new(None, vec![unnamed("first"), unnamed("second")]);
+
+        let first = auth.identify(&request_with_token("first")).unwrap();
+        let second = auth.identify(&request_with_token("second")).unwrap();
+        assert_eq!(first.token_name(), None);
+        assert_eq!(second.token_name(), None);
+        assert_ne!(first.token_index(), second.token_index());
+        assert!(auth.identify(&request_with_token("third")).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use crate::{actor::WrapperHandle, events::ServerEvent};
+
+// Replaced with the name of the player who joined in on-join commands.
+const PLAYER_PLACEHOLDER: &str = "{player}";
+
+/// A command to run whenever a player joins the server.
+///
+/// In a config file, this is either a plain string, which runs when any player
+/// joins, or a `command` with a list of `players` that it's limited to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OnJoinCommand {
+    AnyPlayer(String),
+    SomePlayers {
+        command: String,
+        players: Vec<String>,
+    },
+}
+
+impl OnJoinCommand {
+    /// Returns the command to run when the provided player joins, with the
+    /// `{player}` placeholder filled in, or [None] if this command doesn't
+    /// apply to them.
+    pub fn for_player(&self, player: &str) -> Option<String> {
+        let command = match self {
+            OnJoinCommand::AnyPlayer(command) => command,
+            OnJoinCommand::SomePlayers { command, players } => {
+                // Minecraft usernames are case-insensitive.
+                if !players.iter().any(|p| p.eq_ignore_ascii_case(player)) {
+                    return None;
+                }
+                command
+            }
+        };
+        Some(command.replace(PLAYER_PLACEHOLDER, player))
+    }
+}
+
+/// Spawns a thread that runs the provided commands whenever a player joins the
+/// server.
+///
+/// The commands run after the line announcing the join has been printed and
+/// handed to every other consumer, and they wait in line behind anything else
+/// that's talking to the server at the time. Commands run in the order that
+/// they're listed in.
+///
+/// A player who rejoins within `rejoin_debounce` of leaving doesn't set off the
+/// commands again, so that a player with a bad connection who keeps dropping
+/// and reconnecting isn't welcomed over and over.
+///
+/// Only the server itself can trigger these commands. See [ServerEvent::parse]
+/// for why a command whose output mentions somebody joining can't set off an
+/// endless loop.
+pub fn spawn_on_join_commands(
+    wrapper: WrapperHandle,
+    commands: Vec<OnJoinCommand>,
+    rejoin_debounce: Duration,
+) {
+    // When each player who left recently did so, keyed by their lowercased
+    // name, since Minecraft usernames are case-insensitive.
+    let mut left_at: HashMap<String, Instant> = HashMap::new();
+
+    thread::spawn(move || {
+        let mut events = match wrapper.blocking_call(|w| Ok(w.subscribe())) {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+        loop {
+            let player = match events.blocking_recv() {
+                Ok(ServerEvent::PlayerJoined(player)) => player,
+                Ok(ServerEvent::PlayerLeft(player)) => {
+                    let now = Instant::now();
+                    left_at.retain(|_, &mut left| now.duration_since(left) < rejoin_debounce);
+                    left_at.insert(player.to_lowercase(), now);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "On-join commands fell behind, and missed {} server events",
+                        skipped
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if let Some(left) = left_at.remove(&player.to_lowercase()) {
+                if left.elapsed() < rejoin_debounce {
+                    info!(
+                        "Skipping on-join commands for {}, who rejoined {}ms after leaving",
+                        &player,
+                        left.elapsed().as_millis()
+                    );
+                    continue;
+                }
+            }
+
+            for command in commands.iter().filter_map(|c| c.for_player(&player)) {
+                info!("Running on-join command for {}: {}", &player, &command);
+                if let Err(e) = wrapper.blocking_run_command(command) {
+                    warn!(
+                        "Something went wrong while trying to run an on-join command for {}: {}",
+                        &player, e
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Spawns a thread that logs who's online every `interval`, like
+/// "Online (3): a, b, c".
+///
+/// The list is kept up to date from players joining and leaving, so the server
+/// is only sent "/list" once, to find out who's already online, and again if
+/// the thread falls too far behind on events to trust its list.
+pub fn spawn_roster_log(wrapper: WrapperHandle, interval: Duration) {
+    thread::spawn(move || {
+        let mut events = match wrapper.blocking_call(|w| Ok(w.subscribe())) {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+        let mut roster = fetch_roster(&wrapper);
+        loop {
+            thread::sleep(interval);
+            loop {
+                match events.try_recv() {
+                    Ok(ServerEvent::PlayerJoined(player)) => {
+                        roster.insert(player);
+                    }
+                    Ok(ServerEvent::PlayerLeft(player)) => {
+                        roster.remove(&player);
+                    }
+                    // A new server process is spinning up, so nobody's online.
+                    Ok(ServerEvent::StartupProgress(_)) => roster.clear(),
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Lagged(skipped)) => {
+                        warn!(
+                            "The online player log fell behind, and missed {} server events",
+                            skipped
+                        );
+                        roster = fetch_roster(&wrapper);
+                    }
+                    Err(TryRecvError::Closed) => return,
+                }
+            }
+
+            let players: Vec<&str> = roster.iter().map(String::as_str).collect();
+            info!("Online ({}): {}", players.len(), players.join(", "));
+        }
+    });
+}
+
+/// Asks the Minecraft server who's online. Returns an empty list if something
+/// goes wrong.
+fn fetch_roster(wrapper: &WrapperHandle) -> BTreeSet<String> {
+    match wrapper.blocking_list_players(None) {
+        Ok(players) => players.into_iter().collect(),
+        Err(e) => {
+            warn!(
+                "Something went wrong while trying to fetch the list of online players: {}",
+                e
+            );
+            BTreeSet::new()
+        }
+    }
+}
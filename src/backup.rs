@@ -0,0 +1,718 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File, Metadata},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Context;
+use log::warn;
+use sha2::{Digest, Sha256};
+use sysinfo::Disks;
+
+use crate::{
+    backup_files,
+    backup_files::BackupFormat,
+    compression::{BackupCompression, Encoder},
+    dimension::{self, Dimension},
+    encryption::{self, BackupEncryption, EncryptingWriter},
+    error::WrapperError,
+    exclude::Exclude,
+};
+
+// Added to the end of a backup's file name until it's finished.
+const UNFINISHED_BACKUP_SUFFIX: &str = ".tmp";
+
+/// Returns the path that the backup at `path` is written to until it's
+/// finished.
+pub(crate) fn unfinished_path(path: &Path) -> PathBuf {
+    let mut unfinished = path.as_os_str().to_owned();
+    unfinished.push(UNFINISHED_BACKUP_SUFFIX);
+    PathBuf::from(unfinished)
+}
+
+/// Writes a tarball into `writer`, compressed with `compression` at `level`,
+/// with whatever `append` adds to it, and returns `writer` once the tarball is
+/// finished.
+pub(crate) fn write_tarball<W, F>(
+    writer: W,
+    compression: BackupCompression,
+    level: Option<u32>,
+    append: F,
+) -> anyhow::Result<W>
+where
+    W: Write,
+    F: FnOnce(&mut tar::Builder<Encoder<W>>) -> anyhow::Result<()>,
+{
+    let encoder = compression.encoder(writer, level).with_context(|| {
+        format!(
+            "Failed to start compressing the tarball with {}",
+            compression
+        )
+    })?;
+    let mut tarball = tar::Builder::new(encoder);
+    append(&mut tarball)?;
+    tarball
+        .into_inner()
+        .and_then(Encoder::finish)
+        .with_context(|| "Failed to finish writing the tarball")
+}
+
+/// Writes a tarball to a new file at `tarball_path`, compressed with
+/// `compression` at `level`, with whatever `append` adds to it. It's encrypted
+/// afterwards if there's an `encryption`.
+///
+/// The tarball is written to [unfinished_path()] first, and only renamed once
+/// it's finished, so a file at `tarball_path` is always a complete backup, even
+/// if the wrapper dies partway through. The unfinished tarball is deleted
+/// whenever it can't be finished, like when the disk fills up. Once it's
+/// finished, its checksum is written next to it.
+pub(crate) fn write_tarball_file<F>(
+    tarball_path: &Path,
+    compression: BackupCompression,
+    level: Option<u32>,
+    encryption: Option<&BackupEncryption>,
+    append: F,
+) -> anyhow::Result<()>
+where
+    F: FnOnce(
+        &mut tar::Builder<Encoder<EncryptingWriter<HashingWriter<File>>>>,
+    ) -> anyhow::Result<()>,
+{
+    let tmp_path = unfinished_path(tarball_path);
+    let tarball_file = File::create(&tmp_path)
+        .with_context(|| format!("Failed to create new tarball at {:?}", &tmp_path))?;
+    let writer = HashingWriter {
+        inner: tarball_file,
+        hasher: Sha256::new(),
+    };
+    let result = EncryptingWriter::new(writer, encryption)
+        .and_then(|writer| write_tarball(writer, compression, level, append))
+        .and_then(|writer| {
+            let writer = writer
+                .finish()
+                .with_context(|| "Failed to finish encrypting the tarball")?;
+            writer.inner.sync_all()?;
+            Ok(format!("{:x}", writer.hasher.finalize()))
+        })
+        .and_then(|sha256| {
+            fs::rename(&tmp_path, tarball_path).with_context(|| {
+                format!("Failed to rename {:?} to {:?}", &tmp_path, tarball_path)
+            })?;
+            backup_files::write_checksum(tarball_path, &sha256);
+            Ok(())
+        });
+    if let Err(e) = result {
+        // Don't leave a partial tarball lying around.
+        if let Err(remove_err) = fs::remove_file(&tmp_path) {
+            warn!(
+                "Failed to delete the unfinished backup at {:?}: {}",
+                &tmp_path, remove_err
+            );
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Passes everything that's written to it on to `inner`, and keeps a SHA-256
+/// checksum of it along the way.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Recursively adds the directory at `src_path` to `builder`, giving it the
+/// name `archive_path` inside the archive.
+///
+/// Works like [tar::Builder::append_dir_all()], except that it gives up with a
+/// [WrapperError::BackupTimedOut] if it's still going when `deadline` passes.
+///
+/// `src_path` itself is always followed if it's a symlink, so that a world that
+/// lives on another volume is backed up. Symlinks inside of it are followed if
+/// `follow_symlinks` is set, and added as symlinks otherwise. See
+/// [walk_metadata()].
+///
+/// Anything inside of `src_path` that `exclude` excludes is left out.
+pub(crate) fn append_dir_all<W: Write>(
+    builder: &mut tar::Builder<W>,
+    archive_path: &Path,
+    src_path: &Path,
+    exclude: &Exclude,
+    follow_symlinks: bool,
+    deadline: Option<Instant>,
+) -> anyhow::Result<()> {
+    builder.follow_symlinks(follow_symlinks);
+    let mut visited = HashSet::new();
+    let mut stack = vec![(src_path.to_path_buf(), archive_path.to_path_buf())];
+    while let Some((src, dest)) = stack.pop() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(WrapperError::BackupTimedOut.into());
+        }
+        if exclude.excludes(&src) {
+            continue;
+        }
+
+        let is_root = src == src_path;
+        let metadata = match walk_metadata(&src, is_root || follow_symlinks, &mut visited)? {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        if metadata.is_dir() {
+            // The root of the archive doesn't need an entry of its own.
+            if dest != Path::new("") {
+                builder
+                    .append_dir(&dest, &src)
+                    .with_context(|| format!("Failed to add {:?} to the tarball", &src))?;
+            }
+            let entries =
+                fs::read_dir(&src).with_context(|| format!("Failed to read {:?}", &src))?;
+            for entry in entries {
+                let entry = entry.with_context(|| format!("Failed to read {:?}", &src))?;
+                let entry_dest: PathBuf = dest.join(entry.file_name());
+                stack.push((entry.path(), entry_dest));
+            }
+        } else {
+            builder
+                .append_path_with_name(&src, &dest)
+                .with_context(|| format!("Failed to add {:?} to the tarball", &src))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A file that [append_dir_all()] would add to a tarball.
+pub(crate) struct WalkedFile {
+    /// Where the file is on disk.
+    pub(crate) src: PathBuf,
+    /// Where the file goes inside of the tarball.
+    pub(crate) archive_path: PathBuf,
+    pub(crate) metadata: Metadata,
+}
+
+/// Returns every file inside of the directory at `src_path` that
+/// [append_dir_all()] would add to a tarball, with `archive_path` in front of
+/// where each one goes inside of it. Directories themselves are left out.
+///
+/// Symlinks and `exclude` are treated the same way that [append_dir_all()]
+/// treats them. Gives up with a [WrapperError::BackupTimedOut] if it's still
+/// going when `deadline` passes.
+pub(crate) fn walk_files(
+    archive_path: &Path,
+    src_path: &Path,
+    exclude: &Exclude,
+    follow_symlinks: bool,
+    deadline: Option<Instant>,
+) -> anyhow::Result<Vec<WalkedFile>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![(src_path.to_path_buf(), archive_path.to_path_buf())];
+    while let Some((src, dest)) = stack.pop() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(WrapperError::BackupTimedOut.into());
+        }
+        if exclude.excludes(&src) {
+            continue;
+        }
+
+        let is_root = src == src_path;
+        let metadata = match walk_metadata(&src, is_root || follow_symlinks, &mut visited)? {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        if metadata.is_dir() {
+            let entries =
+                fs::read_dir(&src).with_context(|| format!("Failed to read {:?}", &src))?;
+            for entry in entries {
+                let entry = entry.with_context(|| format!("Failed to read {:?}", &src))?;
+                let entry_dest: PathBuf = dest.join(entry.file_name());
+                stack.push((entry.path(), entry_dest));
+            }
+        } else {
+            files.push(WalkedFile {
+                src,
+                archive_path: dest,
+                metadata,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Adds each of the provided files to `builder`, where [walk_files()] says
+/// they go. Symlinks are added as symlinks unless `follow_symlinks` is set.
+/// Gives up with a [WrapperError::BackupTimedOut] if it's still going when
+/// `deadline` passes.
+pub(crate) fn append_files<W: Write>(
+    builder: &mut tar::Builder<W>,
+    files: &[WalkedFile],
+    follow_symlinks: bool,
+    deadline: Option<Instant>,
+) -> anyhow::Result<()> {
+    builder.follow_symlinks(follow_symlinks);
+    for file in files {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(WrapperError::BackupTimedOut.into());
+        }
+        builder
+            .append_path_with_name(&file.src, &file.archive_path)
+            .with_context(|| format!("Failed to add {:?} to the tarball", &file.src))?;
+    }
+    Ok(())
+}
+
+/// Recursively copies the directory at `src_path` to `dest_path`, which
+/// shouldn't exist yet.
+///
+/// Symlinks and `exclude` are treated the same way that [append_dir_all()]
+/// treats them, so a backup of the copy holds the same things as a backup of
+/// the original. Symlinks that aren't followed are copied as symlinks, on
+/// platforms that have them. Gives up with a [WrapperError::BackupTimedOut] if
+/// it's still going when `deadline` passes.
+pub(crate) fn copy_dir_all(
+    src_path: &Path,
+    dest_path: &Path,
+    exclude: &Exclude,
+    follow_symlinks: bool,
+    deadline: Option<Instant>,
+) -> anyhow::Result<()> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![(src_path.to_path_buf(), dest_path.to_path_buf())];
+    while let Some((src, dest)) = stack.pop() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(WrapperError::BackupTimedOut.into());
+        }
+        if exclude.excludes(&src) {
+            continue;
+        }
+
+        let is_root = src == src_path;
+        let metadata = match walk_metadata(&src, is_root || follow_symlinks, &mut visited)? {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        if metadata.is_dir() {
+            fs::create_dir_all(&dest).with_context(|| format!("Failed to create {:?}", &dest))?;
+            let entries =
+                fs::read_dir(&src).with_context(|| format!("Failed to read {:?}", &src))?;
+            for entry in entries {
+                let entry = entry.with_context(|| format!("Failed to read {:?}", &src))?;
+                let entry_dest: PathBuf = dest.join(entry.file_name());
+                stack.push((entry.path(), entry_dest));
+            }
+        } else if metadata.file_type().is_symlink() {
+            copy_symlink(&src, &dest)?;
+        } else {
+            fs::copy(&src, &dest)
+                .with_context(|| format!("Failed to copy {:?} to {:?}", &src, &dest))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes a symlink at `dest` that points to the same place as the one at
+/// `src`.
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    let target = fs::read_link(src).with_context(|| format!("Failed to read {:?}", src))?;
+    std::os::unix::fs::symlink(&target, dest)
+        .with_context(|| format!("Failed to create a symlink at {:?}", dest))
+}
+
+/// Makes a symlink at `dest` that points to the same place as the one at
+/// `src`.
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, _dest: &Path) -> anyhow::Result<()> {
+    warn!(
+        "Skipping {:?}, which is a symlink. Turn on follow_symlinks to copy what it points to",
+        src
+    );
+    Ok(())
+}
+
+/// Returns the combined size of every file in the directory at `path`, in
+/// bytes.
+///
+/// Symlinks are treated the same way that [append_dir_all()] treats them, so
+/// this is what a backup of the directory would hold. Anything that `exclude`
+/// excludes is left out, too.
+pub(crate) fn dir_size(
+    path: &Path,
+    exclude: &Exclude,
+    follow_symlinks: bool,
+) -> anyhow::Result<u64> {
+    let mut size = 0;
+    let mut visited = HashSet::new();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(src) = stack.pop() {
+        if exclude.excludes(&src) {
+            continue;
+        }
+        let is_root = src == path;
+        let metadata = match walk_metadata(&src, is_root || follow_symlinks, &mut visited)? {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        if metadata.is_dir() {
+            let entries =
+                fs::read_dir(&src).with_context(|| format!("Failed to read {:?}", &src))?;
+            for entry in entries {
+                let entry = entry.with_context(|| format!("Failed to read {:?}", &src))?;
+                stack.push(entry.path());
+            }
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Returns the metadata for `path` when walking a directory tree, following it
+/// if it's a symlink and `follow` is set.
+///
+/// Returns [None] for anything that should be skipped: a followed symlink
+/// that's broken, or a directory that was already visited through a symlink,
+/// which keeps symlink loops from going on forever.
+fn walk_metadata(
+    path: &Path,
+    follow: bool,
+    visited: &mut HashSet<PathBuf>,
+) -> anyhow::Result<Option<Metadata>> {
+    if !follow {
+        return fs::symlink_metadata(path)
+            .map(Some)
+            .with_context(|| format!("Failed to read metadata for {:?}", path));
+    }
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && fs::symlink_metadata(path).is_ok() => {
+            warn!("Skipping {:?}, which is a broken symlink", path);
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read metadata for {:?}", path));
+        }
+    };
+    if metadata.is_dir() {
+        let real_path = fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve the real path of {:?}", path))?;
+        if !visited.insert(real_path) {
+            warn!(
+                "Skipping {:?}, which is a directory that was already backed up through a symlink",
+                path
+            );
+            return Ok(None);
+        }
+    }
+    Ok(Some(metadata))
+}
+
+/// Returns how many bytes are free on the disk that `path` is on, or [None] if
+/// that can't be worked out.
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+    let path = fs::canonicalize(path).ok()?;
+    let disks = Disks::new_with_refreshed_list();
+    // The disk that's mounted closest to the path is the one that it's on.
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().components().count())
+        .map(|disk| disk.available_space())
+}
+
+/// Returns a [WrapperError::InsufficientDiskSpace] if a tarball that's
+/// `estimated` bytes big wouldn't leave at least `min_free` bytes out of the
+/// `available` ones free.
+pub(crate) fn check_free_space(
+    estimated: u64,
+    available: u64,
+    min_free: u64,
+) -> Result<(), WrapperError> {
+    let needed = estimated.saturating_add(min_free);
+    if available < needed {
+        return Err(WrapperError::InsufficientDiskSpace { needed, available });
+    }
+    Ok(())
+}
+
+/// What's in a world backup's tarball.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TarballContents {
+    pub(crate) dimensions: BTreeSet<Dimension>,
+    /// The names of the `backup_extra_dirs` in it.
+    pub(crate) extra_dirs: BTreeSet<String>,
+    pub(crate) files: usize,
+    /// How many bytes the files take up once they're unpacked.
+    pub(crate) unpacked_bytes: u64,
+}
+
+impl TarballContents {
+    /// Counts something in the tarball, going by the first two parts of where
+    /// it is inside of it, and how many bytes it takes up if it's a file.
+    pub(crate) fn add(&mut self, first: &OsStr, second: Option<&OsStr>, file_size: Option<u64>) {
+        if first == dimension::EXTRA_DIRS_ARCHIVE_DIR {
+            if let Some(name) = second {
+                self.extra_dirs.insert(name.to_string_lossy().into_owned());
+            }
+        } else {
+            self.dimensions.insert(dimension::dimension_of(first));
+        }
+        if let Some(size) = file_size {
+            self.files += 1;
+            self.unpacked_bytes += size;
+        }
+    }
+}
+
+/// Reads through the tarball at `path`, and works out which dimensions it has
+/// from where its files are. How it's compressed, and whether it's encrypted,
+/// is worked out from its file name. Encrypted tarballs are decrypted with
+/// `encryption`. Returns a [WrapperError::InvalidArgument] if
+/// anything in it would end up outside of the directory it's unpacked into.
+pub(crate) fn inspect_tarball(
+    path: &Path,
+    encryption: Option<&BackupEncryption>,
+) -> anyhow::Result<TarballContents> {
+    let mut archive = open_tarball(path, encryption)?;
+    let mut contents = TarballContents::default();
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", path))?;
+        let entry_path = entry.path()?;
+        let mut components = Vec::new();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_owned()),
+                Component::CurDir => {}
+                _ => {
+                    return Err(WrapperError::InvalidArgument(format!(
+                        "{:?} has {:?} in it, which would end up outside of the world directory",
+                        path, entry_path
+                    ))
+                    .into())
+                }
+            }
+        }
+        let first = match components.first() {
+            Some(first) => first,
+            // The world directory itself.
+            None => continue,
+        };
+        let file_size = entry.header().entry_type().is_file().then(|| entry.size());
+        contents.add(first, components.get(1).map(OsString::as_os_str), file_size);
+    }
+    Ok(contents)
+}
+
+/// Unpacks the tarball at `path` into `dir`, which is created if it doesn't
+/// exist. Encrypted tarballs are decrypted with `encryption`.
+pub(crate) fn unpack_tarball(
+    path: &Path,
+    dir: &Path,
+    encryption: Option<&BackupEncryption>,
+) -> anyhow::Result<()> {
+    let mut archive = open_tarball(path, encryption)?;
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    archive
+        .unpack(dir)
+        .with_context(|| format!("Failed to unpack {:?} into {:?}", path, dir))
+}
+
+/// Opens the tarball at `path` for reading, decrypting it with `encryption`
+/// and decompressing it however its file name says to.
+fn open_tarball(
+    path: &Path,
+    encryption: Option<&BackupEncryption>,
+) -> anyhow::Result<tar::Archive<Box<dyn io::Read>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let format = BackupFormat::of_path(path);
+    let reader = encryption::decrypting_reader(file, path, format.encrypted, encryption)?;
+    let compression = format.compression;
+    let reader = compression.decoder(reader).with_context(|| {
+        format!(
+            "Failed to start decompressing {:?} with {}",
+            path, compression
+        )
+    })?;
+    Ok(tar::Archive::new(reader))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// A world that lives on "another volume", with a server directory that
+    /// points to it with a symlink, which are deleted when it's dropped.
+    ///
+    /// The world has a symlink inside of it, too, to a directory outside of
+    /// it.
+    struct SymlinkedWorld {
+        dir: TempDir,
+    }
+
+    impl SymlinkedWorld {
+        fn new(name: &str) -> SymlinkedWorld {
+            let dir = TempDir::new(name);
+            fs::create_dir_all(dir.join("volume/world/region")).unwrap();
+            fs::create_dir_all(dir.join("outside")).unwrap();
+            fs::create_dir_all(dir.join("server")).unwrap();
+            fs::write(dir.join("volume/world/level.dat"), "level").unwrap();
+            fs::write(dir.join("volume/world/region/r.0.0.mca"), "region").unwrap();
+            fs::write(dir.join("outside/extra.dat"), "extra").unwrap();
+            symlink(dir.join("outside"), dir.join("volume/world/link")).unwrap();
+            symlink(dir.join("volume/world"), dir.join("server/world")).unwrap();
+            SymlinkedWorld { dir }
+        }
+
+        fn world(&self) -> PathBuf {
+            self.dir.join("server/world")
+        }
+
+        /// Backs the world up the way [append_dir_all()] does, and returns
+        /// each path in the tarball along with whether it's a symlink.
+        fn tarball_entries(&self, follow_symlinks: bool) -> Vec<(String, bool)> {
+            let mut builder = tar::Builder::new(Vec::new());
+            append_dir_all(
+                &mut builder,
+                Path::new("world"),
+                &self.world(),
+                &Exclude::default(),
+                follow_symlinks,
+                None,
+            )
+            .unwrap();
+            let tarball = builder.into_inner().unwrap();
+            let mut archive = tar::Archive::new(tarball.as_slice());
+            let mut entries: Vec<(String, bool)> = archive
+                .entries()
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (
+                        entry.path().unwrap().to_string_lossy().into_owned(),
+                        entry.header().entry_type().is_symlink(),
+                    )
+                })
+                .collect();
+            entries.sort();
+            entries
+        }
+    }
+
+    #[test]
+    fn symlinked_world_is_backed_up() {
+        let world = SymlinkedWorld::new("symlinked-world");
+        // The world goes in as a directory, rather than as the symlink that
+        // points to it, but symlinks inside of it stay symlinks.
+        assert_eq!(
+            world.tarball_entries(false),
+            [
+                ("world".to_owned(), false),
+                ("world/level.dat".to_owned(), false),
+                ("world/link".to_owned(), true),
+                ("world/region".to_owned(), false),
+                ("world/region/r.0.0.mca".to_owned(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn symlinks_in_the_world_are_followed_when_asked() {
+        let world = SymlinkedWorld::new("follow-symlinks");
+        assert_eq!(
+            world.tarball_entries(true),
+            [
+                ("world".to_owned(), false),
+                ("world/level.dat".to_owned(), false),
+                ("world/link".to_owned(), false),
+                ("world/link/extra.dat".to_owned(), false),
+                ("world/region".to_owned(), false),
+                ("world/region/r.0.0.mca".to_owned(), false),
+            ]
+        );
+        let size = dir_size(&world.world(), &Exclude::default(), true).unwrap();
+        assert_eq!(
+            size,
+            ("level".len() + "region".len() + "extra".len()) as u64
+        );
+    }
+
+    #[test]
+    fn symlink_loops_are_only_followed_once() {
+        let world = SymlinkedWorld::new("symlink-loop");
+        symlink(
+            world.dir.join("volume/world"),
+            world.dir.join("outside/loop"),
+        )
+        .unwrap();
+        let entries = world.tarball_entries(true);
+        assert!(entries.contains(&("world/link/extra.dat".to_owned(), false)));
+        assert!(!entries
+            .iter()
+            .any(|(path, _)| path.starts_with("world/link/loop/")));
+    }
+
+    #[test]
+    fn free_space_has_to_fit_the_backup_and_the_minimum() {
+        assert!(check_free_space(100, 150, 50).is_ok());
+        assert!(matches!(
+            check_free_space(100, 149, 50),
+            Err(WrapperError::InsufficientDiskSpace {
+                needed: 150,
+                available: 149
+            })
+        ));
+        assert!(matches!(
+            check_free_space(100, 1000, u64::MAX),
+            Err(WrapperError::InsufficientDiskSpace {
+                needed: u64::MAX,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unfinished_tarball_is_deleted() {
+        let dir = TempDir::new("unfinished-tarball");
+        let path = dir.join("backup.tar.gz");
+        let e = write_tarball_file(&path, BackupCompression::default(), None, None, |_| {
+            anyhow::bail!("The disk filled up")
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "The disk filled up");
+        assert!(!path.exists());
+        assert!(!unfinished_path(&path).exists());
+    }
+
+    #[test]
+    fn finished_tarball_is_moved_into_place() {
+        let dir = TempDir::new("finished-tarball");
+        let path = dir.join("backup.tar.gz");
+        write_tarball_file(&path, BackupCompression::default(), None, None, |_| Ok(())).unwrap();
+        assert!(path.is_file());
+        assert!(!unfinished_path(&path).exists());
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    cmp::Reverse,
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    compression::BackupCompression,
+    dimension::Dimension,
+    error::WrapperError,
+    incremental,
+    manifest::{self, BackupMetadata},
+    verification::{self, BackupVerification},
+};
+
+// What every world backup used to be named after: when it was made, in UTC,
+// like "2022-11-30 02:00:14.123456789 UTC". Backups named like this are always
+// found, whatever `backup_file_name_format` is now.
+const LEGACY_FILE_NAME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f UTC";
+/// What world backups are named after unless `backup_file_name_format` says
+/// otherwise, like "2022-11-30 02:00:14.123456789 UTC". An extension for how
+/// they're compressed goes after it.
+#[cfg(not(windows))]
+pub const DEFAULT_FILE_NAME_FORMAT: &str = LEGACY_FILE_NAME_FORMAT;
+/// What world backups are named after unless `backup_file_name_format` says
+/// otherwise, like "2022-11-30 02-00-14.123456789 UTC", since file names can't
+/// have colons in them on Windows. An extension for how they're compressed
+/// goes after it.
+#[cfg(windows)]
+pub const DEFAULT_FILE_NAME_FORMAT: &str = "%Y-%m-%d %H-%M-%S%.f UTC";
+// Added to the end of an encrypted backup's file name.
+const ENCRYPTED_FILE_SUFFIX: &str = ".age";
+// Backups are identified by when they were made, too, in a way that doesn't
+// need escaping in URLs, like "2022-11-30T02-00-14.123456789Z".
+const BACKUP_ID_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.fZ";
+// Added to the end of a backup's path for the file that its checksum is kept
+// in.
+const CHECKSUM_FILE_SUFFIX: &str = ".sha256";
+// Added to the end of a backup's path for the file that lists every file in
+// the world when it was made, for incremental backups.
+const SNAPSHOT_FILE_SUFFIX: &str = ".snapshot.json";
+// Added to the end of a backup's path for the file that says how verifying it
+// went.
+const VERIFICATION_FILE_SUFFIX: &str = ".verification.json";
+
+/// A world backup in the `backup_dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackupFile {
+    /// What the backup goes by in URLs, like
+    /// "2022-11-30T02-00-14.123456789Z".
+    pub id: String,
+    pub file_name: String,
+    /// When the backup was made, as an RFC 3339 timestamp.
+    pub created_at: String,
+    pub size_bytes: u64,
+    /// How the tarball is compressed, going by its file name's extension.
+    pub compression: BackupCompression,
+    /// Whether the tarball is encrypted, going by whether its file name ends
+    /// with ".age".
+    pub encrypted: bool,
+    /// The tarball's SHA-256 checksum, in hex. [None] for backups that were
+    /// made before the wrapper started keeping checksums.
+    pub sha256: Option<String>,
+    /// The ID of the backup that this one only has the changes since, if it's
+    /// an incremental backup. Restoring it restores that one first.
+    pub based_on: Option<String>,
+    /// How verifying the backup went the last time it was verified, or [None]
+    /// if it never was.
+    pub verification: Option<BackupVerification>,
+    /// What the `backup_dir`'s manifest says about the backup, like its name
+    /// and why it was made. [None] for backups that were made before the
+    /// wrapper started keeping one.
+    pub metadata: Option<BackupMetadata>,
+}
+
+/// How a world backup's tarball is stored, going by its file name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupFormat {
+    pub compression: BackupCompression,
+    pub encrypted: bool,
+}
+
+impl BackupFormat {
+    /// Returns how the backup with the provided file name is stored, or [None]
+    /// if its file name doesn't end with a tarball's extension.
+    pub fn from_file_name(file_name: &str) -> Option<BackupFormat> {
+        let (file_name, encrypted) = match file_name.strip_suffix(ENCRYPTED_FILE_SUFFIX) {
+            Some(file_name) => (file_name, true),
+            None => (file_name, false),
+        };
+        Some(BackupFormat {
+            compression: BackupCompression::from_file_name(file_name)?,
+            encrypted,
+        })
+    }
+
+    /// Like [BackupFormat::from_file_name()], but for the file at the
+    /// provided path. Anything that isn't recognized is assumed to be a plain
+    /// gzipped tarball, like every backup used to be.
+    pub fn of_path(path: &Path) -> BackupFormat {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(BackupFormat::from_file_name)
+            .unwrap_or_default()
+    }
+
+    /// Returns what backups stored this way end their file names with,
+    /// without a leading dot, like "tar.gz.age".
+    pub fn extension(self) -> String {
+        let mut extension = self.compression.extension().to_owned();
+        if self.encrypted {
+            extension.push_str(ENCRYPTED_FILE_SUFFIX);
+        }
+        extension
+    }
+
+    /// Returns the MIME type of a backup that's stored this way.
+    pub fn content_type(self) -> &'static str {
+        if self.encrypted {
+            "application/octet-stream"
+        } else {
+            self.compression.content_type()
+        }
+    }
+}
+
+/// What restoring a world backup replaces, and where what it replaces goes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RestorePlan {
+    pub id: String,
+    /// The dimensions in the backup, which replace the world's. The world's
+    /// other dimensions are left alone.
+    pub dimensions: Vec<Dimension>,
+    /// The names of the `backup_extra_dirs` in the backup, which replace the
+    /// directories with the same names in the server's directory.
+    pub extra_dirs: Vec<String>,
+    /// How many files are in the backup.
+    pub files: usize,
+    /// How many bytes the backup takes up once it's unpacked.
+    pub unpacked_bytes: u64,
+    /// The directory in the server's directory that the dimensions being
+    /// replaced are moved to, instead of being deleted.
+    pub moved_aside_to: String,
+    /// Whether nothing was actually restored.
+    pub dry_run: bool,
+}
+
+/// The directory that world backups are kept in, and what they're named. Cheap
+/// enough to clone.
+#[derive(Debug, Clone)]
+pub(crate) struct BackupDir {
+    path: PathBuf,
+    file_name_format: String,
+}
+
+impl BackupDir {
+    /// Returns an error if backups can't be named after when they were made
+    /// with `file_name_format`, and then found again. It has to be a
+    /// `strftime`-style format that covers the date and time down to the
+    /// second, at least, and can't have a slash in it.
+    pub(crate) fn new(path: PathBuf, file_name_format: &str) -> anyhow::Result<BackupDir> {
+        let invalid = |reason: &str| {
+            WrapperError::InvalidArgument(format!(
+                "{:?} can't be used as the backup_file_name_format: {}",
+                file_name_format, reason
+            ))
+        };
+        if StrftimeItems::new(file_name_format).any(|item| matches!(item, Item::Error)) {
+            return Err(invalid("it isn't a valid strftime format").into());
+        }
+        // Any time with every field filled in does.
+        let sample = Utc
+            .with_ymd_and_hms(2022, 11, 30, 2, 0, 14)
+            .unwrap()
+            .with_nanosecond(123_456_789)
+            .unwrap();
+        let mut file_name = String::new();
+        if write!(file_name, "{}", sample.format(file_name_format)).is_err() {
+            return Err(invalid("it isn't a valid strftime format").into());
+        }
+        if file_name.is_empty() || file_name.contains(['/', '\\']) {
+            return Err(invalid("file names can't be empty, or have slashes in them").into());
+        }
+        let parsed = NaiveDateTime::parse_from_str(&file_name, file_name_format);
+        if parsed.map(|parsed| parsed.and_utc().timestamp()) != Ok(sample.timestamp()) {
+            return Err(invalid(
+                "backups couldn't be found again by when they were made, since it doesn't have the date and time down to the second",
+            )
+            .into());
+        }
+        Ok(BackupDir {
+            path,
+            file_name_format: file_name_format.to_owned(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path to write a new backup that's stored as `format` to,
+    /// named after `made_at`.
+    pub(crate) fn new_backup_path(&self, made_at: DateTime<Utc>, format: BackupFormat) -> PathBuf {
+        self.path.join(format!(
+            "{}.{}",
+            made_at.format(&self.file_name_format),
+            format.extension()
+        ))
+    }
+
+    /// Returns the ID of the world backup at the provided path, or [None] if
+    /// it isn't named like one.
+    pub(crate) fn id_of(&self, path: &Path) -> Option<String> {
+        let name = path.file_name()?.to_str()?;
+        self.made_at(name).map(|made_at| id(&made_at))
+    }
+
+    /// Returns when the backup with the provided file name was made, or
+    /// [None] if it isn't named like a backup.
+    fn made_at(&self, file_name: &str) -> Option<DateTime<Utc>> {
+        let format = BackupFormat::from_file_name(file_name)?;
+        let stem = file_name
+            .strip_suffix(&format.extension())?
+            .strip_suffix('.')?;
+        self.parse_made_at(stem)
+    }
+
+    /// Returns when the backup with the provided file name, without its
+    /// extension, was made, going by the `file_name_format`, or by what
+    /// backups used to be named after.
+    fn parse_made_at(&self, stem: &str) -> Option<DateTime<Utc>> {
+        [self.file_name_format.as_str(), LEGACY_FILE_NAME_FORMAT]
+            .into_iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(stem, format).ok())
+            .map(|made_at| made_at.and_utc())
+    }
+}
+
+/// Returns the world backups in the provided directory, and when each one was
+/// made. Backups that aren't finished yet, and files that aren't backups, are
+/// left out.
+pub(crate) fn find(dir: &BackupDir) -> anyhow::Result<Vec<(PathBuf, DateTime<Utc>)>> {
+    let entries = fs::read_dir(&dir.path)
+        .with_context(|| format!("Failed to look for world backups in {:?}", &dir.path))?;
+    let backups = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let made_at = dir.made_at(&name)?;
+            Some((entry.path(), made_at))
+        })
+        .filter(|(path, _)| path.is_file())
+        .collect();
+    Ok(backups)
+}
+
+/// Returns the world backups in the provided directory, newest first.
+pub(crate) fn list(dir: &BackupDir) -> anyhow::Result<Vec<BackupFile>> {
+    let mut backups = find(dir)?;
+    backups.sort_by_key(|(_, made_at)| Reverse(*made_at));
+    let mut manifest = manifest::read(&dir.path);
+    backups
+        .into_iter()
+        .map(|(path, made_at)| {
+            let id = id(&made_at);
+            let metadata = manifest
+                .iter()
+                .position(|(entry_id, _)| *entry_id == id)
+                .map(|i| manifest.swap_remove(i).1);
+            let size_bytes = fs::metadata(&path)
+                .with_context(|| format!("Failed to read {:?}", &path))?
+                .len();
+            let format = BackupFormat::of_path(&path);
+            Ok(BackupFile {
+                id,
+                file_name: file_name(&path),
+                created_at: made_at.to_rfc3339(),
+                size_bytes,
+                compression: format.compression,
+                encrypted: format.encrypted,
+                sha256: read_checksum(&path),
+                based_on: incremental::read_snapshot(&path).and_then(|snapshot| snapshot.based_on),
+                verification: verification::read_verification(&path),
+                metadata,
+            })
+        })
+        .collect()
+}
+
+/// Returns the path to the world backup in the provided directory with the
+/// provided ID, or a [WrapperError::BackupNotFound] if there isn't one.
+pub(crate) fn path_of(dir: &BackupDir, id: &str) -> anyhow::Result<PathBuf> {
+    find(dir)?
+        .into_iter()
+        .find(|(_, made_at)| self::id(made_at) == id)
+        .map(|(path, _)| path)
+        .ok_or_else(|| WrapperError::BackupNotFound(id.to_owned()).into())
+}
+
+/// Returns the ID of the world backup that was made at the provided time.
+pub(crate) fn id(made_at: &DateTime<Utc>) -> String {
+    made_at.format(BACKUP_ID_FORMAT).to_string()
+}
+
+/// Deletes the world backup at the provided path, along with its checksum,
+/// snapshot, and verification, and drops it from the `backup_dir`'s manifest.
+pub(crate) fn delete(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)?;
+    for (what, sidecar_path) in [
+        ("checksum", checksum_path(path)),
+        ("snapshot", snapshot_path(path)),
+        ("verification", verification_path(path)),
+    ] {
+        match fs::remove_file(sidecar_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => warn!(
+                "Failed to delete the {} of the world backup at {:?}: {}",
+                what, path, e
+            ),
+            _ => {}
+        }
+    }
+    manifest::forget(path);
+    Ok(())
+}
+
+/// Writes the SHA-256 checksum of the world backup at the provided path next
+/// to it, in the format that `sha256sum --check` reads. Something going wrong
+/// is only logged, since the backup itself is fine either way.
+pub(crate) fn write_checksum(path: &Path, sha256: &str) {
+    let contents = format!("{}  {}\n", sha256, file_name(path));
+    if let Err(e) = fs::write(checksum_path(path), contents) {
+        warn!(
+            "Failed to write the checksum of the world backup at {:?}: {}",
+            path, e
+        );
+    }
+}
+
+/// Returns the SHA-256 checksum that was written next to the world backup at
+/// the provided path, if there is one.
+pub(crate) fn read_checksum(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(checksum_path(path)).ok()?;
+    let sha256 = contents.split_whitespace().next()?;
+    Some(sha256.to_owned())
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(CHECKSUM_FILE_SUFFIX);
+    PathBuf::from(checksum_path)
+}
+
+/// Returns the path to the file that lists every file in the world when the
+/// world backup at the provided path was made. Only incremental backups, and
+/// the full backups that they're based on, have one.
+pub(crate) fn snapshot_path(path: &Path) -> PathBuf {
+    let mut snapshot_path = path.as_os_str().to_owned();
+    snapshot_path.push(SNAPSHOT_FILE_SUFFIX);
+    PathBuf::from(snapshot_path)
+}
+
+/// Returns the path to the file that says how verifying the world backup at
+/// the provided path went.
+pub(crate) fn verification_path(path: &Path) -> PathBuf {
+    let mut verification_path = path.as_os_str().to_owned();
+    verification_path.push(VERIFICATION_FILE_SUFFIX);
+    PathBuf::from(verification_path)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backup::unfinished_path, retention::BackupRetention, temp_dir::TempDir};
+
+    /// A `backup_dir` with a finished backup and an unfinished one in it,
+    /// which is deleted when it's dropped.
+    struct UnfinishedBackup {
+        _temp_dir: TempDir,
+        dir: BackupDir,
+        finished: PathBuf,
+        unfinished: PathBuf,
+    }
+
+    impl UnfinishedBackup {
+        fn new(name: &str) -> UnfinishedBackup {
+            let temp_dir = TempDir::new(name);
+            let dir = BackupDir::new(temp_dir.path().to_owned(), DEFAULT_FILE_NAME_FORMAT).unwrap();
+            let format = BackupFormat::default();
+            let finished =
+                dir.new_backup_path(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(), format);
+            let unfinished = unfinished_path(
+                &dir.new_backup_path(Utc.with_ymd_and_hms(2022, 1, 2, 0, 0, 0).unwrap(), format),
+            );
+            fs::write(&finished, "").unwrap();
+            fs::write(&unfinished, "").unwrap();
+            UnfinishedBackup {
+                _temp_dir: temp_dir,
+                dir,
+                finished,
+                unfinished,
+            }
+        }
+    }
+
+    #[test]
+    fn unfinished_backups_arent_listed() {
+        let backups = UnfinishedBackup::new("unfinished-not-listed");
+        let found: Vec<_> = find(&backups.dir)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0], backups.finished);
+        let listed = list(&backups.dir).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].file_name, file_name(&backups.finished));
+    }
+
+    #[test]
+    fn unfinished_backups_arent_pruned() {
+        let backups = UnfinishedBackup::new("unfinished-not-pruned");
+        let retention = BackupRetention {
+            keep_last: 1,
+            ..BackupRetention::default()
+        };
+        let deleted = crate::retention::prune_backups(&backups.dir, &retention);
+        assert!(deleted.is_empty(), "{:?}", deleted);
+        assert!(backups.finished.exists());
+        assert!(backups.unfinished.exists());
+    }
+}
@@ -0,0 +1,27 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const BANNED_IPS_FILE_NAME: &str = "banned-ips.json";
+
+/// An IP address ban from the server's `banned-ips.json` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpBan {
+    pub ip: String,
+    /// Why the IP address was banned.
+    pub reason: String,
+    /// When the ban runs out, or "forever" if it never does.
+    pub expires: String,
+    /// Who banned the IP address, like "Server" or an operator's name.
+    pub source: String,
+}
+
+/// Returns every IP address ban in the server's `banned-ips.json` file.
+pub(crate) fn read_banned_ips(server_dir: &Path) -> anyhow::Result<Vec<IpBan>> {
+    let path = server_dir.join(BANNED_IPS_FILE_NAME);
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", &path))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse the contents of {:?}", &path))
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use anyhow::bail;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use xz2::{read::XzDecoder, write::XzEncoder};
+
+/// How world backups' tarballs are compressed.
+///
+/// Each one ends its backups' file names with a different extension, like
+/// ".tar.zst", which is how backups that were compressed differently are told
+/// apart later on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupCompression {
+    #[default]
+    Gzip,
+    /// Much faster than gzip, and usually smaller too.
+    Zstd,
+    /// Smaller than gzip or zstd, but much slower.
+    Xz,
+    /// A plain tarball, for worlds on a filesystem that compresses everything
+    /// already.
+    None,
+}
+
+// Every kind of compression, in the order that file names are checked against
+// their extensions.
+const ALL: [BackupCompression; 4] = [
+    BackupCompression::Gzip,
+    BackupCompression::Zstd,
+    BackupCompression::Xz,
+    BackupCompression::None,
+];
+
+impl BackupCompression {
+    /// Returns what this ends backups' file names with, without a leading dot,
+    /// like "tar.gz".
+    pub fn extension(self) -> &'static str {
+        match self {
+            BackupCompression::Gzip => "tar.gz",
+            BackupCompression::Zstd => "tar.zst",
+            BackupCompression::Xz => "tar.xz",
+            BackupCompression::None => "tar",
+        }
+    }
+
+    /// Returns the MIME type of a tarball that's compressed this way.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BackupCompression::Gzip => "application/gzip",
+            BackupCompression::Zstd => "application/zstd",
+            BackupCompression::Xz => "application/x-xz",
+            BackupCompression::None => "application/x-tar",
+        }
+    }
+
+    /// Returns how the tarball with the provided file name is compressed, going
+    /// by its extension, or [None] if it isn't a tarball.
+    pub fn from_file_name(file_name: &str) -> Option<BackupCompression> {
+        ALL.into_iter()
+            .find(|compression| file_name.ends_with(&format!(".{}", compression.extension())))
+    }
+
+    /// Returns an error if the provided level doesn't go with this kind of
+    /// compression. [None] always does, and stands for the usual level.
+    pub fn check_level(self, level: Option<u32>) -> anyhow::Result<()> {
+        let level = match level {
+            Some(level) => level,
+            None => return Ok(()),
+        };
+        let max = match self {
+            BackupCompression::Gzip | BackupCompression::Xz => 9,
+            BackupCompression::Zstd => 22,
+            BackupCompression::None => {
+                bail!("Plain tarballs aren't compressed, so they don't take a level")
+            }
+        };
+        if level > max {
+            bail!("{} levels go from 0 to {}, not {}", self, max, level);
+        }
+        Ok(())
+    }
+
+    /// Returns a writer that compresses everything that's written to it this
+    /// way, at the provided level, before passing it on to `writer`.
+    pub(crate) fn encoder<W: Write>(self, writer: W, level: Option<u32>) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            BackupCompression::Gzip => Encoder::Gzip(GzEncoder::new(
+                writer,
+                level.map_or_else(flate2::Compression::default, flate2::Compression::new),
+            )),
+            BackupCompression::Zstd => Encoder::Zstd(zstd::Encoder::new(
+                writer,
+                // 0 picks zstd's usual level.
+                level.unwrap_or(0) as i32,
+            )?),
+            BackupCompression::Xz => Encoder::Xz(XzEncoder::new(writer, level.unwrap_or(6))),
+            BackupCompression::None => Encoder::None(writer),
+        })
+    }
+
+    /// Returns a reader that decompresses what it reads from `reader` this way.
+    pub(crate) fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            BackupCompression::Gzip => Box::new(GzDecoder::new(reader)),
+            BackupCompression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            BackupCompression::Xz => Box::new(XzDecoder::new(reader)),
+            BackupCompression::None => Box::new(reader),
+        })
+    }
+}
+
+impl fmt::Display for BackupCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BackupCompression::Gzip => "gzip",
+            BackupCompression::Zstd => "zstd",
+            BackupCompression::Xz => "xz",
+            BackupCompression::None => "none",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Compresses everything that's written to it with one of the kinds of
+/// [BackupCompression], and passes it on to the writer inside of it.
+pub(crate) enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+    None(W),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes out whatever's left, and returns the writer inside.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+            Encoder::None(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::None(writer) => writer.flush(),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::Response,
+};
+use log::{info, warn};
+use mc_server_wrapper::{actor::WrapperHandle, state::StateMachine};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::audit::AuditLog;
+
+// Starts the messages that come from the wrapper itself, rather than the
+// Minecraft server.
+const NOTICE_PREFIX: &str = "[mc-server-wrapper] ";
+
+/// Turns a `GET /console` request into a WebSocket that works like the
+/// wrapper's terminal. Every line that the terminal would print is sent to the
+/// client as a text message, and every text message that the client sends is
+/// passed on to the Minecraft server as a command.
+///
+/// `identity` is who commands are recorded as in the audit log.
+pub(crate) async fn upgrade(
+    ws: WebSocketUpgrade,
+    identity: String,
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    audit_log: Arc<AuditLog>,
+) -> Response {
+    ws.on_upgrade(move |socket| serve(socket, identity, wrapper, state, audit_log))
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    identity: String,
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    audit_log: Arc<AuditLog>,
+) {
+    let mut lines = match wrapper.call(|w| Ok(w.subscribe_console())).await {
+        Ok(lines) => lines,
+        Err(e) => {
+            warn!("{}: Failed to subscribe to the console: {}", identity, e);
+            return;
+        }
+    };
+    info!("{}: Connected to the console", identity);
+
+    loop {
+        tokio::select! {
+            line = lines.recv() => {
+                let text = match line {
+                    Ok(line) => line,
+                    Err(RecvError::Lagged(missed)) => {
+                        format!("{}Fell behind, and skipped {} lines", NOTICE_PREFIX, missed)
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                let cmd = match message {
+                    Some(Ok(Message::Text(cmd))) => cmd,
+                    // Pings are answered on their own, and there's nothing to
+                    // do with binary messages.
+                    Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                let notice = run_command(&cmd, &identity, &wrapper, &state, &audit_log).await;
+                if let Some(notice) = notice {
+                    let text = format!("{}{}", NOTICE_PREFIX, notice);
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    info!("{}: Disconnected from the console", identity);
+}
+
+/// Passes the provided command on to the Minecraft server. Its response shows
+/// up on the console like any other line. Returns what to tell the client if
+/// the command wasn't passed on.
+async fn run_command(
+    cmd: &str,
+    identity: &str,
+    wrapper: &WrapperHandle,
+    state: &StateMachine,
+    audit_log: &AuditLog,
+) -> Option<String> {
+    let cmd = cmd.trim();
+    if cmd.is_empty() {
+        return None;
+    }
+    if cmd.contains('\n') {
+        return Some("Send one command per message".to_owned());
+    }
+    // Stopping the server takes the HTTP API down with it, which only the
+    // wrapper's terminal and GET /stop know how to do.
+    if cmd.trim_start_matches('/') == "stop" {
+        return Some(
+            "The console doesn't stop the Minecraft server. Use GET /stop, or type /stop into the wrapper's terminal".to_owned(),
+        );
+    }
+    audit_log.record(identity, cmd);
+    if let Err(e) = state.ensure_running() {
+        warn!(
+            "{}: Didn't pass {:?} on to the Minecraft server: {}",
+            identity, cmd, e
+        );
+        return Some(format!("Didn't pass {:?} on: {}", cmd, e));
+    }
+
+    let result = wrapper.run_command(cmd.to_owned()).await;
+    match result {
+        Ok(()) => {
+            info!("{}: Passed {:?} on to the Minecraft server", identity, cmd);
+            None
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to pass {:?} on to the Minecraft server: {}",
+                cmd, e
+            );
+            warn!("{}: {}", identity, err_msg);
+            Some(err_msg)
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::error::WrapperError;
+
+// Minecraft worlds end at the world border, which can't be any further than
+// this many blocks from the center of the world.
+pub(crate) const MAX_BLOCK_COORDINATE: i32 = 30_000_000;
+
+/// One coordinate of a position in a command, like the "~5" in "/tp ~ ~5 ~".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coordinate {
+    /// A position in the world, like "100" or "-3.5".
+    Absolute(f64),
+    /// An offset from wherever the command runs, like "~" or "~-2".
+    Relative(f64),
+    /// An offset along the direction that whatever runs the command is
+    /// facing, like "^" or "^1".
+    Local(f64),
+}
+
+impl fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (prefix, value) = match *self {
+            Coordinate::Absolute(value) => return write!(f, "{}", value),
+            Coordinate::Relative(offset) => ("~", offset),
+            Coordinate::Local(offset) => ("^", offset),
+        };
+        if value == 0.0 {
+            f.write_str(prefix)
+        } else {
+            write!(f, "{}{}", prefix, value)
+        }
+    }
+}
+
+/// Parses the `count` whitespace-separated coordinates in `input`, like
+/// "~ 64 ~-5", the way that the Minecraft server would.
+///
+/// Returns a [WrapperError::InvalidArgument] if `input` doesn't have `count`
+/// coordinates, or if the server would reject them. See
+/// [validate_coordinates()].
+pub fn parse_coordinates(input: &str, count: usize) -> Result<Vec<Coordinate>, WrapperError> {
+    let coordinates = input
+        .split_whitespace()
+        .map(parse_coordinate)
+        .collect::<Result<Vec<Coordinate>, WrapperError>>()?;
+    if coordinates.len() != count {
+        return Err(WrapperError::InvalidArgument(format!(
+            "{:?} should have {} coordinates, but it has {}",
+            input,
+            count,
+            coordinates.len()
+        )));
+    }
+    validate_coordinates(&coordinates)?;
+    Ok(coordinates)
+}
+
+/// Returns a [WrapperError::InvalidArgument] if the Minecraft server would
+/// reject these coordinates as a position. Local coordinates can't be mixed
+/// with the other kinds, and absolute ones have to be inside of the world.
+pub fn validate_coordinates(coordinates: &[Coordinate]) -> Result<(), WrapperError> {
+    let local = coordinates
+        .iter()
+        .filter(|coordinate| matches!(coordinate, Coordinate::Local(_)))
+        .count();
+    if local != 0 && local != coordinates.len() {
+        return Err(WrapperError::InvalidArgument(
+            "Local coordinates, like ^1, can't be mixed with other kinds of coordinates".to_owned(),
+        ));
+    }
+    for coordinate in coordinates {
+        if let Coordinate::Absolute(value) = *coordinate {
+            check_in_world(value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns a [WrapperError::InvalidArgument] if the provided block coordinate
+/// is past the furthest that the world border can be.
+pub(crate) fn check_in_world(value: f64) -> Result<(), WrapperError> {
+    let max = f64::from(MAX_BLOCK_COORDINATE);
+    if !(-max..=max).contains(&value) {
+        return Err(WrapperError::InvalidArgument(format!(
+            "{} is outside of the world. Coordinates must be between -{} and {}",
+            value, MAX_BLOCK_COORDINATE, MAX_BLOCK_COORDINATE
+        )));
+    }
+    Ok(())
+}
+
+/// Parses one coordinate, like "100", "~", or "^-2.5".
+fn parse_coordinate(s: &str) -> Result<Coordinate, WrapperError> {
+    let parsed = if let Some(offset) = s.strip_prefix('~') {
+        parse_number(offset, true).map(Coordinate::Relative)
+    } else if let Some(offset) = s.strip_prefix('^') {
+        parse_number(offset, true).map(Coordinate::Local)
+    } else {
+        parse_number(s, false).map(Coordinate::Absolute)
+    };
+    parsed.ok_or_else(|| {
+        WrapperError::InvalidArgument(format!(
+            "{:?} isn't a coordinate. Use a number, or ~ or ^ followed by an optional number",
+            s
+        ))
+    })
+}
+
+/// Parses a number the way that the Minecraft server does. An empty string is
+/// zero if `allow_empty` is set, like the offset in "~".
+fn parse_number(s: &str, allow_empty: bool) -> Option<f64> {
+    if s.is_empty() {
+        return allow_empty.then_some(0.0);
+    }
+    // The server only understands plain decimal numbers, so things like "1e5"
+    // and "inf", which Rust would parse, are turned away.
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Coordinate::{Absolute, Local, Relative};
+
+    #[test]
+    fn parses_each_kind_of_coordinate() {
+        let cases: [(&str, [Coordinate; 3]); 6] = [
+            (
+                "100 64 -3.5",
+                [Absolute(100.0), Absolute(64.0), Absolute(-3.5)],
+            ),
+            ("~ ~ ~", [Relative(0.0), Relative(0.0), Relative(0.0)]),
+            (
+                "~5 ~-2 ~0.5",
+                [Relative(5.0), Relative(-2.0), Relative(0.5)],
+            ),
+            ("~ 64 ~-5", [Relative(0.0), Absolute(64.0), Relative(-5.0)]),
+            ("^ ^ ^", [Local(0.0), Local(0.0), Local(0.0)]),
+            ("^1 ^-2.5 ^", [Local(1.0), Local(-2.5), Local(0.0)]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_coordinates(input, 3).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+        assert_eq!(
+            parse_coordinates("  1   ~2 ", 2).unwrap(),
+            [Absolute(1.0), Relative(2.0)]
+        );
+    }
+
+    #[test]
+    fn turns_away_invalid_coordinates() {
+        let cases = [
+            // Not numbers, or numbers the server doesn't understand.
+            "a 64 0",
+            "1e5 64 0",
+            "inf 64 0",
+            "NaN 64 0",
+            "~~ 64 0",
+            "^~ ^ ^",
+            "~1.2.3 64 0",
+            "~x 64 0",
+            "+5 64 0",
+            // Local coordinates mixed with other kinds.
+            "^ ~ ^",
+            "^1 64 ^",
+            // Outside of the world.
+            "30000001 64 0",
+            "0 64 -30000000.5",
+            // The wrong number of coordinates.
+            "",
+            "1 2",
+            "1 2 3 4",
+        ];
+        for input in cases {
+            assert!(
+                matches!(
+                    parse_coordinates(input, 3),
+                    Err(WrapperError::InvalidArgument(_))
+                ),
+                "{:?} should have been turned away",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn relative_coordinates_can_be_outside_of_the_world() {
+        assert_eq!(
+            parse_coordinates("~30000001 30000000 -30000000", 3).unwrap(),
+            [
+                Relative(30_000_001.0),
+                Absolute(30_000_000.0),
+                Absolute(-30_000_000.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn displays_the_way_the_server_reads_them() {
+        let coordinates = parse_coordinates("~ ~-2 100.5", 3).unwrap();
+        let displayed: Vec<String> = coordinates.iter().map(Coordinate::to_string).collect();
+        assert_eq!(displayed, ["~", "~-2", "100.5"]);
+        assert_eq!(Local(0.0).to_string(), "^");
+        assert_eq!(Local(1.5).to_string(), "^1.5");
+    }
+}
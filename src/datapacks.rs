@@ -0,0 +1,116 @@
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    error::WrapperError,
+    outcome::{parse_outcome, CommandOutcome, OutcomeMessages},
+};
+
+// Matches both lines that the Minecraft server writes in response to
+// "/datapack list". One is about enabled data packs, and the other is about
+// available ones.
+pub(crate) static DATAPACK_LIST_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: There are (\d+ data pack\(s\)|no data packs|no more data packs)").unwrap()
+});
+
+// Matches every line that the Minecraft server might write in response to
+// "/datapack enable" or "/datapack disable".
+pub(crate) static DATAPACK_TOGGLE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: (Enabling data pack|Disabling data pack|Unknown data pack|Pack '.*' is (already|not) enabled|Unknown or incomplete command)").unwrap()
+});
+
+// What "/datapack enable" and "/datapack disable" write for each of their
+// outcomes, on top of the messages that every command shares. The server says
+// something like "Pack '[vanilla]' is already enabled!" when there's nothing to
+// change.
+const TOGGLE_OUTCOME_MESSAGES: OutcomeMessages = OutcomeMessages {
+    changed: &["Enabling data pack", "Disabling data pack"],
+    no_change: &["Pack '"],
+    target_not_found: &["Unknown data pack"],
+};
+
+// Matches the Minecraft server's response to "/reload".
+pub(crate) static RELOAD_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Reloading!").unwrap());
+
+/// The data packs that the Minecraft server knows about.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Datapacks {
+    /// The names of data packs that are turned on.
+    pub enabled: Vec<String>,
+    /// The names of data packs that could be turned on, but aren't.
+    pub available: Vec<String>,
+}
+
+/// Adds what one of the lines that the Minecraft server writes in response to
+/// "/datapack list" says to `datapacks`. Those lines look something like this:
+/// [16:14:22] [Server thread/INFO]: There are 2 data pack(s) enabled: [vanilla (built-in)], [file/mypack.zip (world)]
+/// [16:14:22] [Server thread/INFO]: There are no more data packs available
+pub(crate) fn parse_list_line(line: &str, datapacks: &mut Datapacks) -> anyhow::Result<()> {
+    let unexpected_response = || {
+        anyhow!(
+            "Unexpected response to the \"/datapack list\" command: {:?}",
+            line
+        )
+    };
+
+    let (_, message) = line.split_once("]: ").ok_or_else(unexpected_response)?;
+    let list = if message.contains(" enabled") {
+        &mut datapacks.enabled
+    } else if message.contains(" available") {
+        &mut datapacks.available
+    } else {
+        return Err(unexpected_response());
+    };
+
+    // Lines that say that there aren't any data packs don't have a list.
+    if let Some((_, names)) = message.split_once(": ") {
+        *list = names
+            .split("], [")
+            .map(|entry| entry.trim().trim_start_matches('[').trim_end_matches(']'))
+            // Each entry is the data pack's name, followed by where it came from
+            // in parentheses.
+            .map(|entry| match entry.rsplit_once(" (") {
+                Some((name, _)) => name.to_owned(),
+                None => entry.to_owned(),
+            })
+            .collect();
+    }
+    Ok(())
+}
+
+/// Checks the Minecraft server's response to "/datapack enable" or
+/// "/datapack disable" for errors.
+///
+/// Asking to enable a data pack that's already enabled, or to disable one
+/// that's already disabled, isn't treated as an error. It's
+/// [CommandOutcome::NoChange] instead.
+pub(crate) fn check_toggle_response(
+    cmd: &str,
+    name: &str,
+    response: &str,
+) -> anyhow::Result<CommandOutcome> {
+    parse_outcome(response, &TOGGLE_OUTCOME_MESSAGES)
+        .check(cmd, || WrapperError::DatapackNotFound(name.to_owned()))
+}
+
+/// Returns the provided data pack name quoted so that it can be passed to a
+/// command, even if it has spaces in it.
+///
+/// Returns a [WrapperError::InvalidArgument] if the name can't be a data pack's
+/// name.
+pub(crate) fn quote_name(name: &str) -> Result<String, WrapperError> {
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return Err(WrapperError::InvalidArgument(format!(
+            "{:?} isn't a valid data pack name",
+            name
+        )));
+    }
+    Ok(format!(
+        "\"{}\"",
+        name.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{error::WrapperError, exclude::Exclude};
+
+/// One of the dimensions that a Minecraft world is split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Overworld,
+    Nether,
+    End,
+}
+
+impl Dimension {
+    pub const ALL: [Dimension; 3] = [Dimension::Overworld, Dimension::Nether, Dimension::End];
+
+    /// The name that this dimension goes by in the HTTP API, like "nether".
+    pub fn name(self) -> &'static str {
+        match self {
+            Dimension::Overworld => "overworld",
+            Dimension::Nether => "nether",
+            Dimension::End => "end",
+        }
+    }
+
+    /// The directory inside of a world directory that this dimension is saved
+    /// in. The overworld is saved in the world directory itself.
+    fn dir_name(self) -> Option<&'static str> {
+        match self {
+            Dimension::Overworld => None,
+            Dimension::Nether => Some("DIM-1"),
+            Dimension::End => Some("DIM1"),
+        }
+    }
+
+    /// What Bukkit-based servers add to the end of the world directory's name
+    /// to get the name of the separate world directory for this dimension.
+    fn bukkit_suffix(self) -> Option<&'static str> {
+        match self {
+            Dimension::Overworld => None,
+            Dimension::Nether => Some("_nether"),
+            Dimension::End => Some("_the_end"),
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses a comma-separated list of dimension names, like "overworld,end".
+///
+/// Returns a [WrapperError::InvalidArgument] if one of the names isn't a
+/// dimension, or if there aren't any names at all.
+pub fn parse_dimensions(list: &str) -> Result<BTreeSet<Dimension>, WrapperError> {
+    let mut dimensions = BTreeSet::new();
+    for name in list
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let dimension = Dimension::ALL
+            .into_iter()
+            .find(|dimension| dimension.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                WrapperError::InvalidArgument(format!(
+                    "{:?} isn't a dimension. Pick from overworld, nether, and end",
+                    name
+                ))
+            })?;
+        dimensions.insert(dimension);
+    }
+    if dimensions.is_empty() {
+        return Err(WrapperError::InvalidArgument(
+            "Pick at least one dimension to back up".to_owned(),
+        ));
+    }
+    Ok(dimensions)
+}
+
+/// Returns the dimension that a file or directory at the top of a backup
+/// belongs to, like "DIM-1" for the nether. Everything that isn't another
+/// dimension's directory belongs to the overworld.
+pub(crate) fn dimension_of(name: &OsStr) -> Dimension {
+    Dimension::ALL
+        .into_iter()
+        .find(|dimension| {
+            dimension
+                .dir_name()
+                .is_some_and(|dir_name| name == dir_name)
+        })
+        .unwrap_or(Dimension::Overworld)
+}
+
+/// The directory at the top of a backup that the `backup_extra_dirs` go in,
+/// each under its own name, so that they're kept apart from the world's
+/// files.
+pub(crate) const EXTRA_DIRS_ARCHIVE_DIR: &str = "mc-server-wrapper-extra-dirs";
+
+/// Where one dimension's files are on disk, and where they go in a backup.
+/// One of the `backup_extra_dirs` is backed up the same way, without a
+/// dimension.
+#[derive(Debug, Clone)]
+pub(crate) struct DimensionDir {
+    /// [None] for one of the `backup_extra_dirs`.
+    pub(crate) dimension: Option<Dimension>,
+    /// The directory that the dimension is saved in.
+    pub(crate) path: PathBuf,
+    /// Where that directory goes inside of a backup, relative to the root of
+    /// the backup.
+    pub(crate) archive_path: PathBuf,
+    /// What's left out of `path`, like directories inside of it that belong
+    /// to other dimensions, and shouldn't be backed up with this one.
+    pub(crate) exclude: Exclude,
+}
+
+impl DimensionDir {
+    /// Returns where one of the `backup_extra_dirs` is on disk, and where it
+    /// goes in a backup: inside of [EXTRA_DIRS_ARCHIVE_DIR], under its own
+    /// name.
+    pub(crate) fn extra(path: PathBuf) -> DimensionDir {
+        let name = path.file_name().unwrap_or_default().to_owned();
+        DimensionDir {
+            dimension: None,
+            archive_path: Path::new(EXTRA_DIRS_ARCHIVE_DIR).join(name),
+            path,
+            exclude: Exclude::default(),
+        }
+    }
+}
+
+/// Returns where every dimension that the world in `world_dir` has is saved.
+///
+/// Vanilla servers save the nether and the end in the "DIM-1" and "DIM1"
+/// directories inside of the world directory. Bukkit-based servers give them
+/// their own world directories next to it instead, like "world_nether/DIM-1".
+/// Either way, they're put where a vanilla server keeps them in backups, which
+/// Bukkit-based servers move to their own layout the next time they start.
+pub(crate) fn find_dimension_dirs(world_dir: &Path) -> Vec<DimensionDir> {
+    let mut dirs = vec![DimensionDir {
+        dimension: Some(Dimension::Overworld),
+        path: world_dir.to_path_buf(),
+        archive_path: PathBuf::new(),
+        exclude: Exclude {
+            paths: Dimension::ALL
+                .into_iter()
+                .filter_map(Dimension::dir_name)
+                .map(|dir_name| world_dir.join(dir_name))
+                .collect(),
+            ..Exclude::default()
+        },
+    }];
+    for dimension in [Dimension::Nether, Dimension::End] {
+        let (dir_name, suffix) = match (dimension.dir_name(), dimension.bukkit_suffix()) {
+            (Some(dir_name), Some(suffix)) => (dir_name, suffix),
+            _ => continue,
+        };
+        let mut bukkit_world_dir = world_dir.as_os_str().to_owned();
+        bukkit_world_dir.push(suffix);
+        let candidates = [
+            world_dir.join(dir_name),
+            PathBuf::from(bukkit_world_dir).join(dir_name),
+        ];
+        if let Some(path) = candidates.into_iter().find(|path| path.is_dir()) {
+            dirs.push(DimensionDir {
+                dimension: Some(dimension),
+                path,
+                archive_path: PathBuf::from(dir_name),
+                exclude: Exclude::default(),
+            });
+        }
+    }
+    dirs
+}
+
+/// Picks out the directories for the `requested` dimensions, or every
+/// dimension when nothing in particular was requested.
+///
+/// Returns a [WrapperError::InvalidArgument] if one of the `requested`
+/// dimensions isn't in `dirs`.
+pub(crate) fn select_dimension_dirs(
+    dirs: Vec<DimensionDir>,
+    requested: Option<&BTreeSet<Dimension>>,
+) -> Result<Vec<DimensionDir>, WrapperError> {
+    let requested = match requested {
+        Some(requested) => requested,
+        None => return Ok(dirs),
+    };
+    let missing: Vec<&str> = requested
+        .iter()
+        .filter(|dimension| !dirs.iter().any(|dir| dir.dimension == Some(**dimension)))
+        .map(|dimension| dimension.name())
+        .collect();
+    if !missing.is_empty() {
+        return Err(WrapperError::InvalidArgument(format!(
+            "The world doesn't have these dimensions yet: {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(dirs
+        .into_iter()
+        .filter(|dir| dir.dimension.is_some_and(|d| requested.contains(&d)))
+        .collect())
+}
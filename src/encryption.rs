@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Read, Write},
+    iter,
+    path::Path,
+};
+
+use age::{
+    secrecy::SecretString,
+    stream::{StreamReader, StreamWriter},
+    Decryptor, Encryptor, IdentityFile, NoCallbacks,
+};
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// Encrypts world backups with [age](https://age-encryption.org), so that
+/// they're safe to keep somewhere that isn't trusted with the world, like
+/// third-party storage.
+///
+/// Exactly one of `passphrase` and `identity_file` has to be set. Encrypted
+/// backups end with ".age", and can be decrypted with the `age` command line
+/// tool, too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupEncryption {
+    /// The passphrase to encrypt backups with, and decrypt them with when
+    /// they're restored.
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    /// An age identity file, like one made with `age-keygen`. Backups are
+    /// encrypted to the public keys of the secret keys in it, and decrypted
+    /// with those secret keys when they're restored.
+    #[serde(default)]
+    pub identity_file: Option<String>,
+}
+
+impl BackupEncryption {
+    /// Returns an error if backups can't be encrypted this way no matter what,
+    /// like if the identity file doesn't have any keys in it.
+    pub fn check(&self) -> anyhow::Result<()> {
+        match (&self.passphrase, &self.identity_file) {
+            (Some(_), Some(_)) => bail!("Only one of passphrase and identity_file can be set"),
+            (None, None) => bail!("Either passphrase or identity_file has to be set"),
+            (Some(passphrase), None) if passphrase.is_empty() => {
+                bail!("The passphrase can't be empty")
+            }
+            (Some(_), None) => Ok(()),
+            (None, Some(identity_file)) => {
+                let recipients = self
+                    .read_identity_file(identity_file)?
+                    .to_recipients()
+                    .map_err(|e| anyhow!("{}", e))
+                    .with_context(|| format!("Failed to read the keys in {:?}", identity_file))?;
+                if recipients.is_empty() {
+                    bail!("{:?} doesn't have any keys in it", identity_file);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns a writer that encrypts everything that's written to it, and
+    /// passes it on to `writer`.
+    pub(crate) fn encryptor<W: Write>(&self, writer: W) -> anyhow::Result<StreamWriter<W>> {
+        let encryptor = match (&self.passphrase, &self.identity_file) {
+            (Some(passphrase), _) => {
+                Encryptor::with_user_passphrase(SecretString::from(passphrase.clone()))
+            }
+            (None, Some(identity_file)) => {
+                let recipients = self
+                    .read_identity_file(identity_file)?
+                    .to_recipients()
+                    .map_err(|e| anyhow!("{}", e))
+                    .with_context(|| format!("Failed to read the keys in {:?}", identity_file))?;
+                Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as _))
+                    .map_err(|e| anyhow!("{}", e))
+                    .with_context(|| "Failed to start encrypting the backup")?
+            }
+            (None, None) => bail!("Either passphrase or identity_file has to be set"),
+        };
+        encryptor
+            .wrap_output(writer)
+            .with_context(|| "Failed to start encrypting the backup")
+    }
+
+    /// Returns a reader that decrypts what it reads from `reader`.
+    pub(crate) fn decryptor<R: Read>(&self, reader: R) -> anyhow::Result<StreamReader<R>> {
+        let decryptor = Decryptor::new(reader)
+            .map_err(|e| anyhow!("{}", e))
+            .with_context(|| "Failed to read the backup's encryption header")?;
+        let result = match (&self.passphrase, &self.identity_file) {
+            (Some(passphrase), _) => {
+                let identity = age::scrypt::Identity::new(SecretString::from(passphrase.clone()));
+                decryptor.decrypt(iter::once(&identity as _))
+            }
+            (None, Some(identity_file)) => {
+                let identities = self
+                    .read_identity_file(identity_file)?
+                    .into_identities()
+                    .map_err(|e| anyhow!("{}", e))
+                    .with_context(|| format!("Failed to read the keys in {:?}", identity_file))?;
+                decryptor.decrypt(identities.iter().map(|i| i.as_ref() as _))
+            }
+            (None, None) => bail!("Either passphrase or identity_file has to be set"),
+        };
+        result.map_err(|e| {
+            anyhow!(
+                "Failed to decrypt the backup. It might have been encrypted with a different passphrase or key: {}",
+                e
+            )
+        })
+    }
+
+    fn read_identity_file(&self, identity_file: &str) -> anyhow::Result<IdentityFile<NoCallbacks>> {
+        IdentityFile::from_file(identity_file.to_owned())
+            .with_context(|| format!("Failed to read the identity file {:?}", identity_file))
+    }
+}
+
+/// Encrypts everything that's written to it with a [BackupEncryption], if
+/// there is one, and passes it on to the writer inside of it.
+pub(crate) enum EncryptingWriter<W: Write> {
+    Plain(W),
+    Age(StreamWriter<W>),
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub(crate) fn new(writer: W, encryption: Option<&BackupEncryption>) -> anyhow::Result<Self> {
+        Ok(match encryption {
+            Some(encryption) => EncryptingWriter::Age(encryption.encryptor(writer)?),
+            None => EncryptingWriter::Plain(writer),
+        })
+    }
+
+    /// Writes out whatever's left, and returns the writer inside.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            EncryptingWriter::Plain(writer) => Ok(writer),
+            EncryptingWriter::Age(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncryptingWriter::Plain(writer) => writer.write(buf),
+            EncryptingWriter::Age(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncryptingWriter::Plain(writer) => writer.flush(),
+            EncryptingWriter::Age(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Returns a reader that decrypts what it reads from the file at `path` with
+/// `encryption`, or a plain reader if the file isn't `encrypted`.
+pub(crate) fn decrypting_reader<'a, R: Read + 'a>(
+    reader: R,
+    path: &Path,
+    encrypted: bool,
+    encryption: Option<&BackupEncryption>,
+) -> anyhow::Result<Box<dyn Read + 'a>> {
+    if !encrypted {
+        return Ok(Box::new(reader));
+    }
+    let encryption = encryption.ok_or_else(|| {
+        anyhow!(
+            "{:?} is encrypted, but there's no backup_encryption in the config to decrypt it with",
+            path
+        )
+    })?;
+    Ok(Box::new(encryption.decryptor(reader)?))
+}
@@ -0,0 +1,74 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::state::ServerState;
+
+use thiserror::Error;
+
+/// Errors that callers might want to handle specifically, rather than treating
+/// them as "something went wrong".
+///
+/// Methods on [Wrapper](crate::Wrapper) return these wrapped in an
+/// [anyhow::Error]. Use [anyhow::Error::downcast_ref()] to check for one.
+#[derive(Debug, Error)]
+pub enum WrapperError {
+    #[error("{0:?} doesn't exist. The Minecraft server creates it the first time it starts")]
+    PropertiesNotFound(PathBuf),
+    #[error("{0:?} already exists")]
+    PropertiesAlreadyExist(PathBuf),
+    #[error("The backup took too long, and was abandoned")]
+    BackupTimedOut,
+    #[error("There isn't enough free disk space for a backup. It needs {needed} bytes, including the min_free_space_bytes margin, but only {available} bytes are free")]
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    #[error("Timed out after {timeout:?} waiting for the Minecraft server to write a line matching {pattern:?}")]
+    OutputTimedOut { pattern: String, timeout: Duration },
+    #[error("The Minecraft server process isn't running anymore")]
+    ProcessExited,
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error(
+        "The Minecraft server doesn't know the command {0:?}, or didn't understand its arguments"
+    )]
+    CommandRejected(String),
+    #[error("The Minecraft server didn't let the wrapper run {0:?}")]
+    CommandNotPermitted(String),
+    #[error("There isn't a server icon at {0:?}")]
+    ServerIconNotFound(PathBuf),
+    #[error("There isn't a data pack called {0:?}")]
+    DatapackNotFound(String),
+    #[error("There isn't a player called {0:?}")]
+    PlayerNotFound(String),
+    #[error("There isn't a world backup called {0:?}")]
+    BackupNotFound(String),
+    #[error("The Minecraft server didn't start with the world backup {id:?}, so the world was put back the way it was: {error}")]
+    RestoredWorldFailedToStart { id: String, error: String },
+    #[error("The world backup {id:?} can't be deleted, since the incremental backups {dependents:?} are based on it. Delete them first")]
+    BackupInUse { id: String, dependents: Vec<String> },
+    #[error("The pre-backup command {command:?} failed, so the backup wasn't made: {error}")]
+    BackupHookFailed { command: String, error: String },
+    #[error("Another mc-server-wrapper (pid {pid}) is already managing this Minecraft server. If it isn't, delete {path:?}")]
+    ServerDirLocked { path: PathBuf, pid: u32 },
+    #[error("{0:?} is locked, so the Minecraft server can't use its world. Another server might be running against the same world. If not, delete that stale session.lock file, or turn on force_unlock")]
+    WorldLocked(PathBuf),
+    #[error(
+        "The Minecraft server's EULA hasn't been agreed to. Set eula=true in its eula.txt file"
+    )]
+    EulaNotAccepted,
+    #[error("The Minecraft server couldn't listen on its port. Something else is probably using it: {0}")]
+    PortInUse(String),
+    #[error("Java couldn't run the Minecraft server's jar. Double check the server_jar_path in mc-server-wrapper's config.yaml: {0}")]
+    BadJar(String),
+    #[error("The Minecraft server is waiting for an answer to {0:?} on stdin. Add a response for it to startup_prompts")]
+    AwaitingInput(String),
+    #[error("Saving has been frozen since {0}. Unfreeze it first")]
+    SavesAlreadyFrozen(String),
+    #[error("Saving isn't frozen")]
+    SavesNotFrozen,
+    #[error("{0}")]
+    NotSupported(String),
+    #[error("The Minecraft server turned down the RCON password. Double check rcon.password in server.properties")]
+    RconLoginFailed,
+    #[error("The Minecraft server is already {0}")]
+    OperationInProgress(ServerState),
+    #[error("The Minecraft server can't take commands right now, because it's {0}")]
+    ServerNotReady(ServerState),
+}
@@ -0,0 +1,377 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+// How many server events can be waiting for a slow subscriber before it starts
+// missing them.
+const CHANNEL_CAPACITY: usize = 1024;
+
+// What comes after a player's name in the messages that the Minecraft server
+// writes when they die, like "player1 was slain by Zombie". Deaths caused by
+// something with a name, like a player or a named mob, only ever add to the
+// end of these.
+const DEATH_MESSAGES: [&str; 19] = [
+    " was ",
+    " walked into ",
+    " drowned",
+    " died",
+    " blew up",
+    " burned to death",
+    " fell ",
+    " hit the ground too hard",
+    " experienced kinetic energy",
+    " went up in flames",
+    " went off with a bang",
+    " tried to swim in lava",
+    " discovered the floor was lava",
+    " suffocated in a wall",
+    " starved to death",
+    " froze to death",
+    " withered away",
+    " left the confines of this world",
+    " didn't want to live in the same world as ",
+];
+
+/// Returns the sending half of a new channel for [ServerEvent]s.
+///
+/// Pass it to [Wrapper::new_with_events()](crate::Wrapper::new_with_events) to
+/// receive events while the server is still spinning up, before there's a
+/// [Wrapper](crate::Wrapper) to subscribe to.
+pub fn channel() -> broadcast::Sender<ServerEvent> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// Something noteworthy that happened on the Minecraft server, parsed from a
+/// line that the server wrote to stdout.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    PlayerJoined(String),
+    PlayerLeft(String),
+    /// A player said something in chat.
+    ChatMessage {
+        player: String,
+        message: String,
+    },
+    /// A player died. `message` is the whole death message, like "player1 was
+    /// slain by Zombie".
+    PlayerDied {
+        player: String,
+        message: String,
+    },
+    /// The server couldn't keep up, and fell this far behind.
+    ServerOverloaded {
+        behind: Duration,
+        ticks: u64,
+    },
+    /// The server finished spinning up, which took this long.
+    Done(Duration),
+    /// A line that the server wrote while it was spinning up.
+    StartupProgress(StartupProgress),
+}
+
+/// How far along the Minecraft server is with spinning up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartupProgress {
+    /// A modded server started loading this many mods.
+    LoadingMods(usize),
+    /// The server is this many percent done preparing the area around the
+    /// world spawn.
+    PreparingSpawnArea(u8),
+    /// A line that doesn't say how far along the server is.
+    Line(String),
+}
+
+impl StartupProgress {
+    /// Parses a line that the Minecraft server wrote to stdout while it was
+    /// spinning up. Lines that don't say how far along the server is are kept
+    /// as they are.
+    pub fn parse(line: &str) -> StartupProgress {
+        // Look something like this:
+        // [16:14:22] [Worker-Main-1/INFO]: Preparing spawn area: 50%
+        // [16:14:22] [main/INFO]: Loading 87 mods:
+        if let Some((_, message)) = line.split_once("]: ") {
+            if let Some(percent) = message
+                .strip_prefix("Preparing spawn area: ")
+                .and_then(|rest| rest.trim().strip_suffix('%'))
+                .and_then(|percent| percent.parse().ok())
+            {
+                return StartupProgress::PreparingSpawnArea(percent);
+            }
+            if let Some(count) = message
+                .strip_prefix("Loading ")
+                .and_then(|rest| rest.split_once(' '))
+                .filter(|(_, rest)| rest.starts_with("mods"))
+                .and_then(|(count, _)| count.parse().ok())
+            {
+                return StartupProgress::LoadingMods(count);
+            }
+        }
+        StartupProgress::Line(line.to_owned())
+    }
+}
+
+impl ServerEvent {
+    /// Parses a line that the Minecraft server wrote to stdout into a
+    /// [ServerEvent], if that line describes one.
+    ///
+    /// Parsing is strict on purpose. Only the server itself can produce a line
+    /// that's parsed into an event: a chat message, or the output of a command
+    /// like "/say", that happens to contain "joined the game" is ignored.
+    pub fn parse(line: &str) -> Option<ServerEvent> {
+        if let Some(message) = server_thread_message(line, "WARN") {
+            return parse_overloaded(message);
+        }
+        let message = server_thread_message(line, "INFO")?;
+
+        // A chat message can end with "joined the game", too, so anything
+        // that isn't just a player's name falls through to the other kinds.
+        if let Some(name) = message
+            .strip_suffix(" joined the game")
+            .filter(|name| is_valid_player_name(name))
+        {
+            return Some(ServerEvent::PlayerJoined(name.to_owned()));
+        }
+        if let Some(name) = message
+            .strip_suffix(" left the game")
+            .filter(|name| is_valid_player_name(name))
+        {
+            return Some(ServerEvent::PlayerLeft(name.to_owned()));
+        }
+        if let Some(event) = parse_chat_message(message) {
+            return Some(event);
+        }
+        if let Some(event) = parse_done(message) {
+            return Some(event);
+        }
+
+        parse_death(message)
+    }
+}
+
+/// Returns true if the provided line could be quoting something that a player
+/// typed, like a chat message, a "/say" or "/me" message, or a death message
+/// that names an item or a mob. Lines like these can say anything, so they're
+/// never taken for the server's response to a command.
+pub(crate) fn quotes_players(line: &str) -> bool {
+    let (_, message) = line.split_once("]: ").unwrap_or(("", line));
+    let message = message.strip_prefix("[Not Secure] ").unwrap_or(message);
+    let quoted_name = |open: &str, close: &str| {
+        message
+            .strip_prefix(open)
+            .and_then(|rest| rest.split_once(close))
+            .is_some_and(|(name, _)| is_valid_player_name(name))
+    };
+    // "<player1> hi" in chat, "[player1] hi" from "/say", and "* player1 waves"
+    // from "/me".
+    quoted_name("<", "> ")
+        || quoted_name("[", "] ")
+        || quoted_name("* ", " ")
+        || matches!(
+            ServerEvent::parse(line),
+            Some(ServerEvent::PlayerDied { .. })
+        )
+}
+
+/// Parses a chat message, which looks something like "<player1> hello". Servers
+/// that can't verify who sent a message put "[Not Secure] " in front of it.
+fn parse_chat_message(message: &str) -> Option<ServerEvent> {
+    let message = message.strip_prefix("[Not Secure] ").unwrap_or(message);
+    let (name, text) = message.strip_prefix('<')?.split_once("> ")?;
+    is_valid_player_name(name).then(|| ServerEvent::ChatMessage {
+        player: name.to_owned(),
+        message: text.to_owned(),
+    })
+}
+
+/// Parses a death message, like "player1 was slain by Zombie".
+fn parse_death(message: &str) -> Option<ServerEvent> {
+    let (name, _) = message.split_once(' ')?;
+    let rest = &message[name.len()..];
+    (is_valid_player_name(name) && DEATH_MESSAGES.iter().any(|death| rest.starts_with(death))).then(
+        || ServerEvent::PlayerDied {
+            player: name.to_owned(),
+            message: message.to_owned(),
+        },
+    )
+}
+
+/// Parses the line that the server writes when it finishes spinning up, which
+/// looks something like "Done (9.797s)! For help, type "help"".
+fn parse_done(message: &str) -> Option<ServerEvent> {
+    let (seconds, _) = message.strip_prefix("Done (")?.split_once("s)!")?;
+    let seconds: f64 = seconds.parse().ok()?;
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .map(ServerEvent::Done)
+}
+
+/// Parses the warning that the server writes when it falls behind, which looks
+/// something like "Can't keep up! Is the server overloaded? Running 2013ms or
+/// 40 ticks behind".
+fn parse_overloaded(message: &str) -> Option<ServerEvent> {
+    let rest = message
+        .strip_prefix("Can't keep up! Is the server overloaded? Running ")?
+        .strip_suffix(" ticks behind")?;
+    let (millis, ticks) = rest.split_once("ms or ")?;
+    Some(ServerEvent::ServerOverloaded {
+        behind: Duration::from_millis(millis.parse().ok()?),
+        ticks: ticks.parse().ok()?,
+    })
+}
+
+/// Returns the message part of a line logged by the server's main thread at the
+/// provided level, like "INFO". Lines like that look something like this:
+/// [16:14:22] [Server thread/INFO]: player1 joined the game
+///
+/// Only the first "]: " ends the line's header, so a chat message that quotes
+/// a header isn't taken for one.
+fn server_thread_message<'a>(line: &'a str, level: &str) -> Option<&'a str> {
+    let (header, message) = line.split_once("]: ")?;
+    let thread = header.rsplit_once(" [")?.1;
+    (thread.strip_prefix("Server thread/")? == level).then_some(message)
+}
+
+/// Returns true if the provided string could be a Minecraft username: between
+/// 1 and 16 letters, numbers, or underscores.
+fn is_valid_player_name(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanilla_lines_are_parsed() {
+        let chat = |player: &str, message: &str| ServerEvent::ChatMessage {
+            player: player.to_owned(),
+            message: message.to_owned(),
+        };
+        let died = |player: &str, message: &str| ServerEvent::PlayerDied {
+            player: player.to_owned(),
+            message: message.to_owned(),
+        };
+        let cases = [
+            (
+                "[16:14:22] [Server thread/INFO]: player1 joined the game",
+                Some(ServerEvent::PlayerJoined("player1".to_owned())),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player_1 left the game",
+                Some(ServerEvent::PlayerLeft("player_1".to_owned())),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: <player1> hello there",
+                Some(chat("player1", "hello there")),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: [Not Secure] <player1> hi",
+                Some(chat("player1", "hi")),
+            ),
+            // Chat that imitates the server is still just chat.
+            (
+                "[16:14:22] [Server thread/INFO]: <player1> player2 joined the game",
+                Some(chat("player1", "player2 joined the game")),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: <player1> [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2013ms or 40 ticks behind",
+                Some(chat(
+                    "player1",
+                    "[Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2013ms or 40 ticks behind",
+                )),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player1 was slain by Zombie",
+                Some(died("player1", "player1 was slain by Zombie")),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player1 fell from a high place",
+                Some(died("player1", "player1 fell from a high place")),
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player1 drowned",
+                Some(died("player1", "player1 drowned")),
+            ),
+            (
+                "[16:14:22] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2013ms or 40 ticks behind",
+                Some(ServerEvent::ServerOverloaded {
+                    behind: Duration::from_millis(2013),
+                    ticks: 40,
+                }),
+            ),
+            (
+                r#"[16:14:22] [Server thread/INFO]: Done (9.797s)! For help, type "help""#,
+                Some(ServerEvent::Done(Duration::from_secs_f64(9.797))),
+            ),
+            // "/say" messages can say anything.
+            (
+                "[16:14:22] [Server thread/INFO]: [Server] player2 joined the game",
+                None,
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: [player1] player2 left the game",
+                None,
+            ),
+            // Other threads don't have events.
+            (
+                "[16:14:22] [User Authenticator #1/INFO]: UUID of player player1 is 069a79f4-44e9-4726-a5be-fca90e38aaf5",
+                None,
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player1[/127.0.0.1:51234] logged in with entity id 123 at (0.5, 64.0, 0.5)",
+                None,
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: Starting minecraft server version 1.20.1",
+                None,
+            ),
+            ("player1 joined the game", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(ServerEvent::parse(line), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn lines_that_quote_players_are_noticed() {
+        let cases = [
+            ("[16:14:22] [Server thread/INFO]: <player1> hello", true),
+            ("[16:14:22] [Server thread/INFO]: [Not Secure] <player1> hello", true),
+            ("[16:14:22] [Server thread/INFO]: [player1] hello", true),
+            ("[16:14:22] [Server thread/INFO]: * player1 waves", true),
+            (
+                "[16:14:22] [Server thread/INFO]: player1 was slain by Zombie",
+                true,
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: There are 1 of a max of 20 players online: player1",
+                false,
+            ),
+            (
+                "[16:14:22] [Server thread/INFO]: player1 joined the game",
+                false,
+            ),
+            ("[16:14:22] [Server thread/INFO]: Saved the game", false),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(quotes_players(line), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn player_names_are_checked() {
+        for name in ["a", "player1", "Player_One", "abcdefghijklmnop"] {
+            assert!(is_valid_player_name(name), "{:?}", name);
+        }
+        for name in [
+            "",
+            "abcdefghijklmnopq",
+            "player one",
+            "<player1>",
+            "plåyer",
+            "player-1",
+        ] {
+            assert!(!is_valid_player_name(name), "{:?}", name);
+        }
+    }
+}
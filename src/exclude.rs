@@ -0,0 +1,153 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// What a world backup leaves out of a directory that it backs up.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Exclude {
+    /// Paths that are left out, like the directories that belong to other
+    /// dimensions, which are backed up on their own.
+    pub(crate) paths: Vec<PathBuf>,
+    /// The `backup_exclude` patterns.
+    pub(crate) patterns: ExcludePatterns,
+}
+
+impl Exclude {
+    /// Returns whether the file or directory at `path` is left out. Nothing
+    /// inside of a directory that's left out is walked through.
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        self.paths.iter().any(|excluded| excluded == path) || self.patterns.is_match(path)
+    }
+}
+
+/// The compiled `backup_exclude` patterns. Cheap to clone.
+///
+/// Patterns with a slash in them, like "world/data/*.dat" or
+/// "plugins/dynmap/web/tiles", are matched against the whole path from the
+/// server's directory. Patterns without one, like "session.lock" or "*.tmp",
+/// are matched against file names, wherever they are.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExcludePatterns {
+    // Where the paths that `paths` are matched against start from.
+    server_dir: PathBuf,
+    names: Arc<GlobSet>,
+    paths: Arc<GlobSet>,
+}
+
+impl ExcludePatterns {
+    /// Compiles the provided `backup_exclude` patterns, for backing up
+    /// directories inside of `server_dir`.
+    pub(crate) fn new(server_dir: &Path, patterns: &[String]) -> anyhow::Result<ExcludePatterns> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            // A leading slash would never match, since paths are relative, but
+            // it still means that the pattern is matched against whole paths,
+            // like "/logs" only leaving out the server's own logs directory.
+            let trimmed = pattern.trim_start_matches('/');
+            let glob = GlobBuilder::new(trimmed)
+                .literal_separator(true)
+                .build()
+                .with_context(|| {
+                    format!("{:?} isn't a valid pattern for backup_exclude", pattern)
+                })?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        Ok(ExcludePatterns {
+            server_dir: server_dir.to_path_buf(),
+            names: Arc::new(names.build()?),
+            paths: Arc::new(paths.build()?),
+        })
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        if path
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name))
+        {
+            return true;
+        }
+        path.strip_prefix(&self.server_dir)
+            .is_ok_and(|relative| self.paths.is_match(relative))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> ExcludePatterns {
+        let patterns: Vec<String> = patterns.iter().map(|&p| p.to_owned()).collect();
+        ExcludePatterns::new(Path::new("/server"), &patterns).unwrap()
+    }
+
+    #[test]
+    fn patterns_match_the_paths_they_should() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("session.lock", "/server/world/session.lock", true),
+            ("session.lock", "/server/world_nether/session.lock", true),
+            ("session.lock", "/server/world/session.lock.bak", false),
+            ("*.tmp", "/server/world/region/r.0.0.mca.tmp", true),
+            ("*.tmp", "/server/world/level.dat", false),
+            ("world/data/*.dat", "/server/world/data/raids.dat", true),
+            (
+                "world/data/*.dat",
+                "/server/world/data/villages/old.dat",
+                false,
+            ),
+            (
+                "world/data/*.dat",
+                "/server/other/world/data/raids.dat",
+                false,
+            ),
+            ("world/data/*.dat", "/server/world/raids.dat", false),
+            (
+                "world/**/*.dat",
+                "/server/world/data/villages/old.dat",
+                true,
+            ),
+            ("/logs", "/server/logs", true),
+            ("/logs", "/server/world/logs", false),
+        ];
+        for &(pattern, path, expected) in cases {
+            assert_eq!(
+                patterns(&[pattern]).is_match(Path::new(path)),
+                expected,
+                "{:?} and {:?}",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn paths_outside_of_the_server_dir_only_match_by_name() {
+        let patterns = patterns(&["world/data/*.dat", "*.tmp"]);
+        assert!(!patterns.is_match(Path::new("/elsewhere/world/data/raids.dat")));
+        assert!(patterns.is_match(Path::new("/elsewhere/r.0.0.mca.tmp")));
+    }
+
+    #[test]
+    fn no_patterns_match_nothing() {
+        assert!(!patterns(&[]).is_match(Path::new("/server/world/level.dat")));
+    }
+
+    #[test]
+    fn invalid_globs_are_turned_away() {
+        let e = ExcludePatterns::new(Path::new("/server"), &["world/[".to_owned()]).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("isn't a valid pattern for backup_exclude"),
+            "{}",
+            e
+        );
+    }
+}
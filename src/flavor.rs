@@ -0,0 +1,94 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+// Vanilla servers, and most things built on top of them, write a line that
+// looks something like this while they're starting:
+// [16:14:20] [Server thread/INFO]: Starting minecraft server version 1.20.1
+static MINECRAFT_VERSION_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Starting minecraft server version (\S+)").unwrap());
+
+// Bukkit-based servers write something like this:
+// [16:14:20] [Server thread/INFO]: This server is running Paper version git-Paper-196 (MC: 1.20.1) (Implementing API version 1.20.1-R0.1-SNAPSHOT)
+static BUKKIT_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: This server is running (\w+) version (\S+)").unwrap());
+// [16:14:18] [main/INFO]: Loading Minecraft 1.20.1 with Fabric Loader 0.14.21
+static FABRIC_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Loading Minecraft \S+ with Fabric Loader").unwrap());
+// [16:14:18] [main/INFO] [cp.mo.mo.Launcher/MODLAUNCHER]: ModLauncher running: args [--launchTarget, forgeserver, --fml.forgeVersion, 47.1.0, ...]
+static FORGE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: ModLauncher running: .*--fml\.(neoForge|forge)Version").unwrap()
+});
+
+// Matches every line that says which version or flavor of Minecraft server is
+// running.
+pub(crate) static IDENTITY_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    let patterns = [
+        &MINECRAFT_VERSION_PATTERN,
+        &BUKKIT_PATTERN,
+        &FABRIC_PATTERN,
+        &FORGE_PATTERN,
+    ];
+    let patterns: Vec<String> = patterns
+        .iter()
+        .map(|pattern| format!("(?:{})", pattern.as_str()))
+        .collect();
+    Regex::new(&patterns.join("|")).unwrap()
+});
+
+/// Which kind of Minecraft server is running, as far as the wrapper can tell
+/// from what it wrote while it was starting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerFlavor {
+    /// The server didn't say that it's anything else.
+    Vanilla,
+    Paper,
+    Purpur,
+    Folia,
+    Spigot,
+    CraftBukkit,
+    Fabric,
+    Forge,
+    NeoForge,
+    /// A Bukkit-based server that the wrapper doesn't recognize.
+    OtherBukkit,
+}
+
+impl ServerFlavor {
+    /// Returns the flavor that the provided line gives away, if it gives one
+    /// away.
+    pub(crate) fn detect(line: &str) -> Option<ServerFlavor> {
+        if let Some(captures) = BUKKIT_PATTERN.captures(line) {
+            let flavor = match &captures[1] {
+                "Paper" => ServerFlavor::Paper,
+                "Purpur" => ServerFlavor::Purpur,
+                "Folia" => ServerFlavor::Folia,
+                // Spigot calls itself CraftBukkit, but its version says Spigot.
+                "CraftBukkit" if captures[2].contains("Spigot") => ServerFlavor::Spigot,
+                "CraftBukkit" => ServerFlavor::CraftBukkit,
+                _ => ServerFlavor::OtherBukkit,
+            };
+            return Some(flavor);
+        }
+        if FABRIC_PATTERN.is_match(line) {
+            return Some(ServerFlavor::Fabric);
+        }
+        if let Some(captures) = FORGE_PATTERN.captures(line) {
+            return Some(match &captures[1] {
+                "neoForge" => ServerFlavor::NeoForge,
+                _ => ServerFlavor::Forge,
+            });
+        }
+        None
+    }
+}
+
+/// Returns the version of Minecraft that the provided line says the server is
+/// running, like "1.20.1", if it says one.
+pub(crate) fn parse_minecraft_version(line: &str) -> Option<String> {
+    MINECRAFT_VERSION_PATTERN
+        .captures(line)
+        .map(|captures| captures[1].to_owned())
+}
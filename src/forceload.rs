@@ -0,0 +1,216 @@
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, bail};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    coordinates::{self, Coordinate},
+    error::WrapperError,
+};
+
+// The most chunks that the Minecraft server will force load or unload with a
+// single command.
+const MAX_CHUNKS_PER_COMMAND: i64 = 256;
+
+// Matches every line that the Minecraft server might write in response to a
+// "/forceload" command.
+pub(crate) static FORCELOAD_RESPONSE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"\]: (Marked|Unmarked|No chunks were|No force loaded chunks|A force loaded chunk|\d+ force loaded chunks|Too many chunks|Unknown or incomplete command)",
+    )
+    .unwrap()
+});
+
+/// The position of a column of blocks in the world, in block coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ColumnPos {
+    fn validate(&self) -> Result<(), WrapperError> {
+        coordinates::validate_coordinates(&[
+            Coordinate::Absolute(f64::from(self.x)),
+            Coordinate::Absolute(f64::from(self.z)),
+        ])
+    }
+
+    /// Returns the chunk that this column is in.
+    fn chunk(&self) -> ChunkPos {
+        ChunkPos {
+            x: self.x.div_euclid(16),
+            z: self.z.div_euclid(16),
+        }
+    }
+}
+
+/// The position of a chunk in the world, in chunk coordinates. A chunk is a
+/// 16 by 16 block column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Something to do with the chunks that the Minecraft server keeps loaded at
+/// all times, even when there aren't any players nearby.
+///
+/// Positions are block coordinates in the overworld. When `to` is given, every
+/// chunk in the rectangle between `from` and `to` is affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ForceloadAction {
+    Add {
+        from: ColumnPos,
+        to: Option<ColumnPos>,
+    },
+    Remove {
+        from: ColumnPos,
+        to: Option<ColumnPos>,
+    },
+    RemoveAll,
+    Query,
+}
+
+impl ForceloadAction {
+    /// Returns a [WrapperError::InvalidArgument] if the Minecraft server would
+    /// reject this action's coordinates.
+    pub fn validate(&self) -> Result<(), WrapperError> {
+        let (from, to) = match self {
+            ForceloadAction::Add { from, to } | ForceloadAction::Remove { from, to } => (from, to),
+            ForceloadAction::RemoveAll | ForceloadAction::Query => return Ok(()),
+        };
+        from.validate()?;
+        let to = match to {
+            Some(to) => to,
+            None => return Ok(()),
+        };
+        to.validate()?;
+
+        let (from, to) = (from.chunk(), to.chunk());
+        let width = i64::from(from.x.abs_diff(to.x)) + 1;
+        let length = i64::from(from.z.abs_diff(to.z)) + 1;
+        if width * length > MAX_CHUNKS_PER_COMMAND {
+            return Err(WrapperError::InvalidArgument(format!(
+                "That area covers {} chunks, but at most {} can be changed at once",
+                width * length,
+                MAX_CHUNKS_PER_COMMAND
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the "/forceload" command that carries out this action.
+    pub(crate) fn command(&self) -> String {
+        let range = |from: &ColumnPos, to: &Option<ColumnPos>| match to {
+            Some(to) => format!("{} {} {} {}", from.x, from.z, to.x, to.z),
+            None => format!("{} {}", from.x, from.z),
+        };
+        match self {
+            ForceloadAction::Add { from, to } => format!("/forceload add {}", range(from, to)),
+            ForceloadAction::Remove { from, to } => {
+                format!("/forceload remove {}", range(from, to))
+            }
+            ForceloadAction::RemoveAll => "/forceload remove all".to_owned(),
+            ForceloadAction::Query => "/forceload query".to_owned(),
+        }
+    }
+}
+
+/// What the Minecraft server reports after a [ForceloadAction].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ForceloadResponse {
+    /// This many chunks were newly marked to be force loaded. Chunks that were
+    /// already marked aren't counted.
+    Marked { count: usize },
+    /// This many chunks stopped being force loaded.
+    Unmarked { count: usize },
+    /// No chunks are force loaded anymore.
+    UnmarkedAll,
+    /// These chunks are force loaded.
+    Loaded { count: usize, chunks: Vec<ChunkPos> },
+}
+
+/// Parses the Minecraft server's response to a "/forceload" command. Responses
+/// look something like this:
+/// [16:14:22] [Server thread/INFO]: Marked 4 chunks in minecraft:overworld from [0, 0] to [1, 1] to be force loaded
+/// [16:14:22] [Server thread/INFO]: 2 force loaded chunks were found in minecraft:overworld at: [0, 0], [1, 0]
+pub(crate) fn parse_forceload_response(response: &str) -> anyhow::Result<ForceloadResponse> {
+    let unexpected_response = || {
+        anyhow!(
+            "Unexpected response to a \"/forceload\" command: {:?}",
+            response
+        )
+    };
+
+    let (_, message) = response.split_once("]: ").ok_or_else(unexpected_response)?;
+
+    if message.starts_with("Marked chunk ") {
+        return Ok(ForceloadResponse::Marked { count: 1 });
+    }
+    if message.starts_with("Unmarked chunk ") {
+        return Ok(ForceloadResponse::Unmarked { count: 1 });
+    }
+    if message.starts_with("Unmarked all ") {
+        return Ok(ForceloadResponse::UnmarkedAll);
+    }
+    if message.starts_with("No chunks were marked") {
+        return Ok(ForceloadResponse::Marked { count: 0 });
+    }
+    if message.starts_with("No chunks were removed") {
+        return Ok(ForceloadResponse::Unmarked { count: 0 });
+    }
+    if message.starts_with("No force loaded chunks") {
+        return Ok(ForceloadResponse::Loaded {
+            count: 0,
+            chunks: Vec::new(),
+        });
+    }
+    if let Some(rest) = message.strip_prefix("Marked ") {
+        let count = leading_count(rest).ok_or_else(unexpected_response)?;
+        return Ok(ForceloadResponse::Marked { count });
+    }
+    if let Some(rest) = message.strip_prefix("Unmarked ") {
+        let count = leading_count(rest).ok_or_else(unexpected_response)?;
+        return Ok(ForceloadResponse::Unmarked { count });
+    }
+    if message.starts_with("A force loaded chunk") || message.contains(" force loaded chunks ") {
+        let (_, positions) = message
+            .split_once(" at: ")
+            .ok_or_else(unexpected_response)?;
+        let chunks = parse_chunk_positions(positions).ok_or_else(unexpected_response)?;
+        return Ok(ForceloadResponse::Loaded {
+            count: chunks.len(),
+            chunks,
+        });
+    }
+
+    // Something like "Too many chunks in the specified area", or the server not
+    // knowing the command at all.
+    bail!("The Minecraft server rejected the command: {}", message)
+}
+
+/// Parses the number at the start of something like "4 chunks in ...".
+fn leading_count(s: &str) -> Option<usize> {
+    s.split_once(' ')?.0.parse().ok()
+}
+
+/// Parses a list of chunk positions like "[0, 0], [1, -2]".
+fn parse_chunk_positions(s: &str) -> Option<Vec<ChunkPos>> {
+    s.split("], [")
+        .map(|pos| {
+            let (x, z) = pos
+                .trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split_once(',')?;
+            Some(ChunkPos {
+                x: x.trim().parse().ok()?,
+                z: z.trim().parse().ok()?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,38 @@
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, bail};
+use regex::Regex;
+use serde::Serialize;
+
+// Matches every line that the Minecraft server might write in response to a
+// "/time query" command, which look something like this:
+// [16:14:22] [Server thread/INFO]: The time is 6000
+pub(crate) static TIME_QUERY_RESPONSE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: (The time is \d+|Unknown or incomplete command|Incorrect argument)").unwrap()
+});
+
+/// What time it is in the Minecraft world, in ticks. There are 20 ticks in a
+/// second, and 24000 in a Minecraft day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GameTime {
+    /// How far into the current day it is, from 0 to 23999. 0 is sunrise, and
+    /// 6000 is noon.
+    pub daytime: u64,
+    /// How many ticks the world has been running for, in total. Unlike the
+    /// other two, "/time set" doesn't change this.
+    pub gametime: u64,
+    /// How many days have gone by in the world.
+    pub day: u64,
+}
+
+/// Parses the Minecraft server's response to a "/time query" command.
+pub(crate) fn parse_time_query_response(response: &str) -> anyhow::Result<u64> {
+    let (_, message) = response.split_once("]: ").unwrap_or(("", response));
+    match message.strip_prefix("The time is ") {
+        Some(ticks) => ticks
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Unexpected time: {:?}", message)),
+        None => bail!("The Minecraft server rejected the command: {}", message),
+    }
+}
@@ -1,28 +1,112 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    future::Future,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
+    body::{Body, Bytes, StreamBody},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    BoxError, Json,
 };
+use chrono::{DateTime, Utc};
 use log::{info, warn};
-use mc_server_wrapper::Wrapper;
-use tokio::sync::oneshot;
+use mc_server_wrapper::{
+    actor::{MadeBackup, WrapperHandle},
+    backup_files::{BackupFile, BackupFormat, RestorePlan},
+    bans::IpBan,
+    datapacks::Datapacks,
+    dimension::{self, Dimension},
+    error::WrapperError,
+    events::{ServerEvent, StartupProgress},
+    flavor::ServerFlavor,
+    forceload::{ForceloadAction, ForceloadResponse},
+    game_time::GameTime,
+    logs::{LogLevel, LogLine},
+    manifest::{BackupDetails, BackupTrigger},
+    ops::Op,
+    outcome::CommandOutcome,
+    performance::PerformanceSnapshot,
+    ping::PingStatus,
+    probe::ServerProbe,
+    query::QueryStatus,
+    remote_backup::UploadStatus,
+    state::{ServerState, StateMachine, Transition},
+    stats::{CommandCounters, CommandStats, StdoutStats},
+    verification::BackupVerification,
+    watchdog::Crash,
+    BackupMode, BackupStatus, CrashReport, Readiness, Wrapper,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot, watch,
+    },
+    task, time,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tower::timeout::error::Elapsed;
+
+use crate::{
+    auth::Caller,
+    jobs::{Job, JobHandle, JobKind, Jobs},
+    send_api_server_shutdown_signal,
+};
 
-use crate::send_api_server_shutdown_signal;
+// How many chunks of a streamed backup can be waiting to be sent to the client
+// before the backup waits for the client to catch up.
+const BACKUP_STREAM_CHUNKS_IN_FLIGHT: usize = 16;
+// The size of each chunk of a streamed backup.
+const BACKUP_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+// How many events can be waiting to be sent to a GET /events client before
+// it starts missing them.
+const EVENT_STREAM_EVENTS_IN_FLIGHT: usize = 64;
+// The longest command timeout that a request can ask for.
+const MAX_COMMAND_TIMEOUT_MS: u64 = 60_000;
+// How long clients are asked to wait before trying a command again while the
+// Minecraft server isn't running.
+const SERVER_NOT_READY_RETRY_AFTER_SECONDS: u64 = 10;
+// How often a POST /make-world-backup job checks on the backup's upload, once
+// the backup is made.
+const UPLOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 pub(crate) async fn stop_server(
-    wrapper: Arc<Mutex<Wrapper>>,
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    stop_requested: Arc<AtomicBool>,
     shutdown_signal_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
 ) -> Result<StatusCode, Response> {
-    if let Err(e) = wrapper.lock().unwrap().stop_server() {
+    let transition = begin_operation(&state, ServerState::Stopping, "GET /stop")
+        .map_err(IntoResponse::into_response)?;
+    // Let the watchdog know that this stop is intentional before the request
+    // waits in line, in case something ahead of it takes a while.
+    stop_requested.store(true, Ordering::SeqCst);
+    if let Err(e) = wrapper.stop().await {
         let err_msg = format!(
             "Something went wrong while trying to stop the server: {}",
             e
         );
         warn!("GET /stop: {}", &err_msg);
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response());
+        // The server might still be running, and if it goes down later on, the
+        // watchdog should treat that like a crash.
+        stop_requested.store(false, Ordering::SeqCst);
+        return Err((error_status(&e), err_msg).into_response());
     }
+    transition.finish(ServerState::Stopped);
 
     if let Err(e) = send_api_server_shutdown_signal(shutdown_signal_tx) {
         let err_msg = format!(
@@ -36,10 +120,170 @@ pub(crate) async fn stop_server(
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Every route that the wrapper serves, for the landing page. Keep this in sync
+// with the routes in main.rs.
+const ENDPOINTS: &[&str] = &[
+    "GET /whoami",
+    "GET /info",
+    "GET /diagnostics",
+    "GET /startup-warnings",
+    "GET /crashes",
+    "GET /list-players",
+    "GET /performance",
+    "GET /time",
+    "GET /query",
+    "GET /ping",
+    "GET /stats/commands",
+    "POST /stats/commands/reset",
+    "GET /stats/stdout",
+    "GET /logs",
+    "GET /events",
+    "GET /console",
+    "POST /console/pause",
+    "POST /console/resume",
+    "GET /properties/raw",
+    "PUT /properties/raw",
+    "POST /properties/init",
+    "GET /server-icon",
+    "PUT /server-icon",
+    "GET /datapacks",
+    "POST /datapacks/:name/enable",
+    "POST /datapacks/:name/disable",
+    "GET /forceload",
+    "POST /forceload",
+    "GET /ops",
+    "PUT /op/:name",
+    "GET /bans/ips",
+    "POST /maintenance",
+    "POST /command",
+    "POST /save/freeze",
+    "POST /save/unfreeze",
+    "GET /make-world-backup",
+    "POST /make-world-backup",
+    "GET /backups/stream",
+    "GET /backups",
+    "GET /backups/:id/download",
+    "DELETE /backups/:id",
+    "POST /backups/:id/verify",
+    "POST /backups/:id/restore",
+    "POST /validate-launch",
+    "GET /restart",
+    "POST /restart",
+    "GET /jobs/:id",
+    "GET /stop",
+    "POST /shutdown-api",
+];
+
+/// What `GET /` responds with. Anyone can see it, so it never holds anything
+/// from the config.
+#[derive(Serialize)]
+pub(crate) struct LandingPage {
+    name: &'static str,
+    version: &'static str,
+    endpoints: &'static [&'static str],
+}
+
+pub(crate) async fn landing_page() -> Json<LandingPage> {
+    LandingPage {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        endpoints: ENDPOINTS,
+    }
+    .into()
+}
+
+pub(crate) async fn whoami(caller: Caller) -> Json<Caller> {
+    caller.into()
+}
+
+pub(crate) async fn command_stats(stats: CommandStats) -> Json<BTreeMap<String, CommandCounters>> {
+    stats.snapshot().into()
+}
+
+#[derive(Serialize)]
+pub(crate) struct StdoutStatsResponse {
+    capacity: usize,
+    dropped_lines: u64,
+}
+
+pub(crate) async fn stdout_stats(stats: StdoutStats, capacity: usize) -> Json<StdoutStatsResponse> {
+    StdoutStatsResponse {
+        capacity,
+        dropped_lines: stats.dropped_lines(),
+    }
+    .into()
+}
+
+pub(crate) async fn set_console_paused(
+    console_paused: Arc<AtomicBool>,
+    paused: bool,
+) -> StatusCode {
+    console_paused.store(paused, Ordering::SeqCst);
+    info!(
+        "{} printing the Minecraft server's output",
+        if paused { "Paused" } else { "Resumed" }
+    );
+    StatusCode::NO_CONTENT
+}
+
+pub(crate) async fn reset_command_stats(stats: CommandStats) -> StatusCode {
+    stats.reset();
+    info!("Reset the command stats");
+    StatusCode::NO_CONTENT
+}
+
+pub(crate) async fn shutdown_api(
+    shutdown_signal_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+) -> Result<StatusCode, Response> {
+    if let Err(e) = send_api_server_shutdown_signal(shutdown_signal_tx) {
+        let err_msg = format!(
+            "Something went wrong while trying to stop the API server: {}",
+            e
+        );
+        warn!("POST /shutdown-api: {}", err_msg);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response());
+    }
+
+    info!("Shutting down the API server. The Minecraft server will keep running");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lets a request that sends the Minecraft server commands override the
+/// configured command timeout with `?timeout_ms=`.
+#[derive(Deserialize)]
+pub(crate) struct TimeoutParams {
+    timeout_ms: Option<u64>,
+}
+
+impl TimeoutParams {
+    /// Returns the command timeout that the request asked for, or a 400 if
+    /// it's zero or longer than [MAX_COMMAND_TIMEOUT_MS].
+    fn command_timeout(&self, route: &str) -> Result<Option<Duration>, (StatusCode, String)> {
+        match self.timeout_ms {
+            None => Ok(None),
+            Some(ms) if (1..=MAX_COMMAND_TIMEOUT_MS).contains(&ms) => {
+                Ok(Some(Duration::from_millis(ms)))
+            }
+            Some(ms) => {
+                let err_msg = format!(
+                    "timeout_ms has to be between 1 and {}, not {}",
+                    MAX_COMMAND_TIMEOUT_MS, ms
+                );
+                warn!("{}: {}", route, err_msg);
+                Err((StatusCode::BAD_REQUEST, err_msg))
+            }
+        }
+    }
+}
+
 pub(crate) async fn list_players(
-    wrapper: Arc<Mutex<Wrapper>>,
+    wrapper: WrapperHandle,
+    params: TimeoutParams,
 ) -> Result<Json<Vec<String>>, Response> {
-    match wrapper.lock().unwrap().list_players() {
+    let timeout = params
+        .command_timeout("GET /list-players")
+        .map_err(IntoResponse::into_response)?;
+    match wrapper.list_players(timeout).await {
         Ok(players) => Ok(players.into()),
         Err(e) => {
             let err_msg = format!(
@@ -47,17 +291,191 @@ pub(crate) async fn list_players(
                 e
             );
             warn!("GET /list-players: {}", err_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response())
+            Err((error_status(&e), err_msg).into_response())
         }
     }
 }
 
-pub(crate) async fn make_world_backup(wrapper: Arc<Mutex<Wrapper>>) -> Result<String, Response> {
-    let mut w = wrapper.lock().unwrap();
-    match w.make_world_backup() {
-        Ok(tarball_path) => {
-            let response_msg = format!(
-                "Created a new world backup: {}",
+pub(crate) async fn restart_server(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+) -> Result<StatusCode, Response> {
+    let _transition = begin_operation(&state, ServerState::Restarting, "GET /restart")
+        .map_err(IntoResponse::into_response)?;
+    restart(&wrapper, "GET /restart")
+        .await
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(IntoResponse::into_response)
+}
+
+/// Like [restart_server()], but responds with a `202` and the job that
+/// restarts the server in the background right away.
+pub(crate) async fn start_restart_job(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    jobs: Jobs,
+) -> Result<Response, Response> {
+    let transition = begin_operation(&state, ServerState::Restarting, "POST /restart")
+        .map_err(IntoResponse::into_response)?;
+    let job = spawn_job(
+        wrapper,
+        jobs,
+        JobKind::Restart,
+        transition,
+        "Restarting the Minecraft server",
+        |wrapper| async move {
+            restart(&wrapper, "POST /restart")
+                .await
+                .map(|()| serde_json::Value::Null)
+        },
+    );
+    Ok(job)
+}
+
+async fn restart(wrapper: &WrapperHandle, route: &str) -> Result<(), (StatusCode, String)> {
+    if let Err(e) = wrapper.restart().await {
+        let err_msg = format!(
+            "Something went wrong while trying to restart the server: {}",
+            e
+        );
+        warn!("{}: {}", route, &err_msg);
+        return Err((error_status(&e), err_msg));
+    }
+
+    info!("Restarted the Minecraft server");
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BackupParams {
+    /// A comma-separated list of the dimensions to back up, like
+    /// "overworld,end". Every dimension is backed up if it's missing.
+    dimensions: Option<String>,
+    #[serde(default)]
+    mode: BackupMode,
+    /// A short, human-friendly name for the backup, which goes in the
+    /// `backup_dir`'s manifest.
+    name: Option<String>,
+    description: Option<String>,
+}
+
+impl BackupParams {
+    /// Parses the dimensions to back up, or returns a 400 if one of them isn't
+    /// a dimension.
+    fn dimensions(&self, route: &str) -> Result<Option<BTreeSet<Dimension>>, (StatusCode, String)> {
+        let list = match &self.dimensions {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+        dimension::parse_dimensions(list).map(Some).map_err(|e| {
+            let err_msg = e.to_string();
+            warn!("{}: {}", route, err_msg);
+            (StatusCode::BAD_REQUEST, err_msg)
+        })
+    }
+
+    /// Returns what the backup is for, or a 400 if its name or description
+    /// won't do.
+    fn details(&self, route: &str) -> Result<BackupDetails, (StatusCode, String)> {
+        let details = BackupDetails {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            trigger: BackupTrigger::Manual,
+        };
+        details.check().map_err(|e| {
+            let err_msg = e.to_string();
+            warn!("{}: {}", route, err_msg);
+            (StatusCode::BAD_REQUEST, err_msg)
+        })?;
+        Ok(details)
+    }
+}
+
+pub(crate) async fn make_world_backup(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    params: BackupParams,
+) -> Result<String, Response> {
+    let dimensions = params
+        .dimensions("GET /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    let details = params
+        .details("GET /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    let _transition = begin_operation(&state, ServerState::BackingUp, "GET /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    back_up(
+        &wrapper,
+        dimensions,
+        details,
+        params.mode,
+        "GET /make-world-backup",
+    )
+    .await
+    .map_err(IntoResponse::into_response)
+}
+
+/// Like [make_world_backup()], but responds with a `202` and the job that
+/// makes the backup in the background right away. The job's result is what
+/// [make_world_backup()] would have responded with.
+pub(crate) async fn start_backup_job(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    jobs: Jobs,
+    params: BackupParams,
+) -> Result<Response, Response> {
+    let dimensions = params
+        .dimensions("POST /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    let details = params
+        .details("POST /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    let transition = begin_operation(&state, ServerState::BackingUp, "POST /make-world-backup")
+        .map_err(IntoResponse::into_response)?;
+    let mode = params.mode;
+    let job = spawn_job(
+        wrapper,
+        jobs,
+        JobKind::Backup,
+        transition,
+        "Backing up the world",
+        move |wrapper| async move {
+            back_up(
+                &wrapper,
+                dimensions,
+                details,
+                mode,
+                "POST /make-world-backup",
+            )
+            .await
+            .map(serde_json::Value::String)
+        },
+    );
+    Ok(job)
+}
+
+async fn back_up(
+    wrapper: &WrapperHandle,
+    dimensions: Option<BTreeSet<Dimension>>,
+    details: BackupDetails,
+    mode: BackupMode,
+    route: &str,
+) -> Result<String, (StatusCode, String)> {
+    let response_prefix = match mode {
+        BackupMode::Stop => "Created a new world backup",
+        BackupMode::CopyThenCompress => {
+            "Copied the world, and started compressing it into a new world backup in the background"
+        }
+        BackupMode::Hot => "Created a new world backup without stopping the Minecraft server",
+    };
+    match wrapper.make_world_backup(dimensions, details, mode).await {
+        Ok(MadeBackup {
+            path: tarball_path,
+            status: last_backup,
+        }) => {
+            let mut response_msg = format!(
+                "{}: {}",
+                response_prefix,
                 // TODO: Revisit unwrap() call here.
                 //
                 // This func is already pretty verbose... not sure if the extra
@@ -65,27 +483,1504 @@ pub(crate) async fn make_world_backup(wrapper: Arc<Mutex<Wrapper>>) -> Result<St
                 tarball_path.into_os_string().into_string().unwrap()
             );
             info!("{}", &response_msg);
+            // Backups that are compressed in the background are pruned once
+            // they're done, and that shows up in GET /diagnostics instead.
+            if mode != BackupMode::CopyThenCompress {
+                let pruned = last_backup
+                    .as_ref()
+                    .map(|b| b.pruned.clone())
+                    .unwrap_or_default();
+                if !pruned.is_empty() {
+                    response_msg.push_str(&format!(
+                        "\nDeleted old backups that backup_retention doesn't keep: {}",
+                        pruned.join(", ")
+                    ));
+                }
+                let hooks = last_backup
+                    .as_ref()
+                    .map(|b| b.hooks.clone())
+                    .unwrap_or_default();
+                let verification = last_backup.and_then(|b| b.verification);
+                if let Some(e) = verification.and_then(|v| v.error) {
+                    response_msg.push_str(&format!(
+                        "\nThe new backup failed verification, so no old backups were deleted: {}",
+                        e
+                    ));
+                }
+                for hook in hooks {
+                    if let Some(e) = hook.error {
+                        response_msg.push_str(&format!(
+                            "\nThe post-backup command {:?} failed: {}",
+                            hook.command, e
+                        ));
+                    }
+                }
+            }
             Ok(response_msg)
         }
         Err(e) => {
+            let status = error_status(&e);
             let mut err_msg = format!(
                 "Something went wrong while trying to make a server backup: {}",
                 e
             );
+            // The server is left running when there isn't room for a backup,
+            // when the world doesn't have one of the requested dimensions, or
+            // when a pre-backup command fails, so there's nothing to restart.
+            // Hot backups never stop it.
+            if mode == BackupMode::Hot
+                || matches!(
+                    e.downcast_ref(),
+                    Some(
+                        WrapperError::InsufficientDiskSpace { .. }
+                            | WrapperError::InvalidArgument(_)
+                            | WrapperError::BackupHookFailed { .. }
+                    )
+                )
+            {
+                warn!("{}: {}", route, &err_msg);
+                return Err((status, err_msg));
+            }
             // Try to restart the Minecraft server again before building a
             // Response.
-            match w.restart_server() {
+            match wrapper.restart().await {
                 Ok(()) => {
-                    warn!("GET /make-world-backup: {}", &err_msg);
-                    Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response())
+                    warn!("{}: {}", route, &err_msg);
+                    Err((status, err_msg))
                 }
                 Err(e) => {
                     let err_msg_addendum = format!("\nAfter failing to make that backup, something went wrong while trying to restart the Minecraft server: {}", e);
                     err_msg.push_str(&err_msg_addendum);
-                    warn!("GET /make-world-backup: {}", &err_msg);
-                    Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response())
+                    warn!("{}: {}", route, &err_msg);
+                    Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg))
+                }
+            }
+        }
+    }
+}
+
+pub(crate) async fn validate_launch(wrapper: WrapperHandle) -> Result<StatusCode, Response> {
+    if let Err(e) = wrapper.call(|w| w.validate_launch()).await {
+        let err_msg = format!(
+            "The Minecraft server failed to start in a throwaway directory, so restarting it will probably fail, too: {}",
+            e
+        );
+        warn!("POST /validate-launch: {}", err_msg);
+        return Err((error_status(&e), err_msg).into_response());
+    }
+
+    info!("Validated that the Minecraft server can start");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Turns a request that took longer than the request timeout into a `504`.
+pub(crate) async fn handle_timeout_error(
+    method: Method,
+    uri: Uri,
+    e: BoxError,
+) -> (StatusCode, String) {
+    let (status, err_msg) = if e.is::<Elapsed>() {
+        (
+            StatusCode::GATEWAY_TIMEOUT,
+            "The Minecraft server took too long to respond to this request".to_owned(),
+        )
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Something went wrong while handling this request: {}", e),
+        )
+    };
+    warn!("{} {}: {}", method, uri.path(), err_msg);
+    (status, err_msg)
+}
+
+pub(crate) async fn stream_world_backup(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    params: BackupParams,
+) -> Result<(HeaderMap, StreamBody<ReceiverStream<io::Result<Bytes>>>), Response> {
+    let dimensions = params
+        .dimensions("GET /backups/stream")
+        .map_err(IntoResponse::into_response)?;
+    if params.mode != BackupMode::Stop {
+        let err_msg = "Streamed backups never stop the Minecraft server, so they don't take a mode";
+        warn!("GET /backups/stream: {}", err_msg);
+        return Err((StatusCode::BAD_REQUEST, err_msg).into_response());
+    }
+    let transition = begin_operation(&state, ServerState::BackingUp, "GET /backups/stream")
+        .map_err(IntoResponse::into_response)?;
+    // Once the response starts, there's no way to send an error status, so
+    // make sure the world has every requested dimension first.
+    let requested = dimensions.clone();
+    let format = match wrapper
+        .call(move |w| {
+            w.check_backup_dimensions(requested.as_ref())
+                .map(|()| w.backup_format())
+        })
+        .await
+    {
+        Ok(format) => format,
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to stream a world backup: {}",
+                e
+            );
+            warn!("GET /backups/stream: {}", err_msg);
+            return Err((error_status(&e), err_msg).into_response());
+        }
+    };
+    // Wait for the warning to run out before the response starts, so that the
+    // client isn't left with a response that sits there without any bytes.
+    if let Err(e) = wrapper.warn_players_before_backup().await {
+        let err_msg = format!(
+            "Something went wrong while trying to warn players before streaming a world backup: {}",
+            e
+        );
+        warn!("GET /backups/stream: {}", err_msg);
+        return Err((error_status(&e), err_msg).into_response());
+    }
+
+    let (tx, rx) = mpsc::channel(BACKUP_STREAM_CHUNKS_IN_FLIGHT);
+    tokio::spawn(async move {
+        let _transition = transition;
+        let writer =
+            BufWriter::with_capacity(BACKUP_STREAM_CHUNK_SIZE, ChannelWriter { tx: tx.clone() });
+        let result = wrapper
+            .call(move |w| w.stream_world_backup(writer, dimensions.as_ref()))
+            .await;
+        match result {
+            Ok(()) => info!("Streamed a new world backup"),
+            Err(e) => {
+                warn!(
+                    "GET /backups/stream: Something went wrong while trying to stream a world backup: {}",
+                    e
+                );
+                // The response has already started, so the only way to tell
+                // the client that something went wrong is to cut it off.
+                let _ = tx.send(Err(io::Error::other(e.to_string()))).await;
+            }
+        }
+    });
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    let content_disposition = format!(
+        "attachment; filename=\"{}.{}\"",
+        Utc::now().format("%Y-%m-%d_%H-%M-%S"),
+        format.extension()
+    );
+    if let Ok(value) = HeaderValue::from_str(&content_disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok((headers, StreamBody::new(ReceiverStream::new(rx))))
+}
+
+/// Sends everything that's written to it along a channel, to be streamed to an
+/// HTTP client.
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "The client stopped receiving the backup",
+                )
+            })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) async fn list_backups(
+    wrapper: WrapperHandle,
+) -> Result<Json<Vec<BackupFile>>, Response> {
+    match wrapper.call(|w| w.list_backups()).await {
+        Ok(backups) => Ok(backups.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to list the world backups: {}",
+                e
+            );
+            warn!("GET /backups: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn download_backup(
+    wrapper: WrapperHandle,
+    id: String,
+) -> Result<(HeaderMap, StreamBody<ReceiverStream<io::Result<Bytes>>>), Response> {
+    let result = {
+        let id = id.clone();
+        wrapper.call(move |w| w.backup_path(&id)).await
+    };
+    let open = match result {
+        Ok(path) => File::open(&path)
+            .await
+            .map(|file| (file, BackupFormat::of_path(&path)))
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    let (mut file, format) = match open {
+        Ok(opened) => opened,
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to download the world backup {:?}: {}",
+                id, e
+            );
+            warn!("GET /backups/:id/download: {}", err_msg);
+            return Err((error_status(&e), err_msg).into_response());
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    if let Ok(metadata) = file.metadata().await {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(metadata.len()));
+    }
+    let content_disposition = format!("attachment; filename=\"{}.{}\"", id, format.extension());
+    if let Ok(value) = HeaderValue::from_str(&content_disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    let (tx, rx) = mpsc::channel(BACKUP_STREAM_CHUNKS_IN_FLIGHT);
+    tokio::spawn(async move {
+        let mut buf = vec![0; BACKUP_STREAM_CHUNK_SIZE];
+        loop {
+            let chunk = match file.read(&mut buf).await {
+                Ok(0) => return,
+                Ok(read) => Ok(Bytes::copy_from_slice(&buf[..read])),
+                Err(e) => {
+                    warn!(
+                        "GET /backups/:id/download: Something went wrong while reading the world backup {:?}: {}",
+                        id, e
+                    );
+                    Err(e)
+                }
+            };
+            let failed = chunk.is_err();
+            // The client stopped receiving the backup.
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    Ok((headers, StreamBody::new(ReceiverStream::new(rx))))
+}
+
+pub(crate) async fn delete_backup(
+    wrapper: WrapperHandle,
+    id: String,
+) -> Result<StatusCode, Response> {
+    let result = {
+        let id = id.clone();
+        wrapper.call(move |w| w.delete_backup(&id)).await
+    };
+    if let Err(e) = result {
+        let err_msg = format!(
+            "Something went wrong while trying to delete the world backup {:?}: {}",
+            id, e
+        );
+        warn!("DELETE /backups/:id: {}", err_msg);
+        return Err((error_status(&e), err_msg).into_response());
+    }
+
+    info!("Deleted the world backup {:?}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Responds with how verifying the backup went, whether it passed or not.
+pub(crate) async fn verify_backup(
+    wrapper: WrapperHandle,
+    id: String,
+) -> Result<Json<BackupVerification>, Response> {
+    let result = {
+        let id = id.clone();
+        wrapper.call(move |w| w.verify_backup(&id)).await
+    };
+    match result {
+        Ok(verification) => {
+            match &verification.error {
+                None => info!("Verified the world backup {:?}", id),
+                Some(e) => warn!("The world backup {:?} failed verification: {}", id, e),
+            }
+            Ok(verification.into())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to verify the world backup {:?}: {:#}",
+                id, e
+            );
+            warn!("POST /backups/:id/verify: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RestoreParams {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    confirm: bool,
+}
+
+/// Responds with what restoring the backup would replace for a dry run.
+/// Otherwise, responds with a `202` and the job that restores it in the
+/// background right away. The job's result is the [RestorePlan] that was
+/// carried out.
+pub(crate) async fn restore_backup(
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    jobs: Jobs,
+    id: String,
+    params: RestoreParams,
+) -> Result<Response, Response> {
+    if !params.dry_run && !params.confirm {
+        let err_msg = "Restoring a backup replaces the world. Add ?dry_run=true to see what it would replace, or ?confirm=true to go ahead";
+        warn!("POST /backups/:id/restore: {}", err_msg);
+        return Err((StatusCode::BAD_REQUEST, err_msg).into_response());
+    }
+    // Dry runs don't touch the server, so they can happen alongside anything.
+    if params.dry_run {
+        let result = wrapper
+            .call(move |w| Ok(restore_backup_blocking(w, &id, true)))
+            .await
+            .unwrap_or_else(|e| Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())));
+        return result
+            .map(|plan| Json(plan).into_response())
+            .map_err(IntoResponse::into_response);
+    }
+    let transition = begin_operation(&state, ServerState::Restoring, "POST /backups/:id/restore")
+        .map_err(IntoResponse::into_response)?;
+    let job = spawn_job(
+        wrapper,
+        jobs,
+        JobKind::Restore,
+        transition,
+        "Restoring the world backup",
+        move |wrapper| async move {
+            let plan = wrapper
+                .call(move |w| Ok(restore_backup_blocking(w, &id, false)))
+                .await
+                .unwrap_or_else(|e| Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?;
+            serde_json::to_value(plan)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        },
+    );
+    Ok(job)
+}
+
+fn restore_backup_blocking(
+    w: &mut Wrapper,
+    id: &str,
+    dry_run: bool,
+) -> Result<RestorePlan, (StatusCode, String)> {
+    let e = match w.restore_backup(id, dry_run) {
+        Ok(plan) => {
+            if !dry_run {
+                info!(
+                    "Restored the world backup {:?}. What it replaced was moved to {:?}",
+                    id, &plan.moved_aside_to
+                );
+            }
+            return Ok(plan);
+        }
+        Err(e) => e,
+    };
+    let status = error_status(&e);
+    let mut err_msg = format!(
+        "Something went wrong while trying to restore the world backup {:?}: {:#}",
+        id, e
+    );
+    // Most problems come up before the server is stopped, but it might not
+    // have made it back up otherwise.
+    if w.has_exited().unwrap_or(false) {
+        if let Err(e) = w.restart_server() {
+            err_msg.push_str(&format!("\nAfter failing to restore that backup, something went wrong while trying to restart the Minecraft server: {}", e));
+            warn!("POST /backups/:id/restore: {}", &err_msg);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, err_msg));
+        }
+    }
+    warn!("POST /backups/:id/restore: {}", &err_msg);
+    Err((status, err_msg))
+}
+
+/// Carries out `f` in the background as a job, and responds with a `202` and
+/// the job, with a `Location` header pointing at `GET /jobs/:id`. The job
+/// reports how the server is doing while it starts back up as its progress,
+/// and `transition` ends when the job does.
+///
+/// `f` is handed a handle for the wrapper that marks the job as running once
+/// its first request is taken off the queue.
+fn spawn_job<F, Fut>(
+    wrapper: WrapperHandle,
+    jobs: Jobs,
+    kind: JobKind,
+    transition: Transition,
+    progress: &'static str,
+    f: F,
+) -> Response
+where
+    F: FnOnce(WrapperHandle) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<serde_json::Value, (StatusCode, String)>> + Send + 'static,
+{
+    let handle = jobs.create(kind);
+    let job = handle.job();
+    tokio::spawn(async move {
+        // Subscribe before the job is queued up, so that nothing the server
+        // writes while it starts back up is missed.
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        if let Ok(events) = wrapper.call(|w| Ok(w.subscribe())).await {
+            tokio::spawn(follow_startup_progress(handle.clone(), events, done_rx));
+        }
+        let result = f(wrapper.on_start({
+            let handle = handle.clone();
+            move || handle.start(progress)
+        }))
+        .await;
+        drop(done_tx);
+        drop(transition);
+        if kind == JobKind::Backup && result.is_ok() {
+            follow_uploads(&wrapper, &handle).await;
+        }
+        match result {
+            Ok(result) => handle.succeed(result),
+            Err((status, err_msg)) => handle.fail(status, err_msg),
+        }
+    });
+
+    let mut headers = HeaderMap::new();
+    if let Ok(location) = HeaderValue::from_str(&format!("/jobs/{}", job.id())) {
+        headers.insert(header::LOCATION, location);
+    }
+    (StatusCode::ACCEPTED, headers, Json(job)).into_response()
+}
+
+/// Reports how uploads of world backups to `backup_upload` and
+/// `backup_upload_ssh` are going as the job's progress, until they're all
+/// done. Returns right away if there aren't any.
+async fn follow_uploads(wrapper: &WrapperHandle, handle: &JobHandle) {
+    let uploads = match wrapper.call(|w| Ok(w.uploads())).await {
+        Ok(uploads) => uploads,
+        Err(_) => return,
+    };
+    while uploads.pending() > 0 {
+        let progress = match uploads.last().filter(|status| status.finished_at.is_none()) {
+            Some(status) => format!(
+                "Uploading the world backup to {}: {} of {} bytes",
+                status.url, status.uploaded_bytes, status.size_bytes
+            ),
+            None => "Compressing the world backup".to_owned(),
+        };
+        handle.set_progress(progress);
+        time::sleep(UPLOAD_PROGRESS_INTERVAL).await;
+    }
+}
+
+/// Reports how far along the Minecraft server is with starting back up as the
+/// job's progress, until `done` is dropped.
+async fn follow_startup_progress(
+    handle: JobHandle,
+    mut events: broadcast::Receiver<ServerEvent>,
+    mut done: oneshot::Receiver<()>,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = &mut done => return,
+        };
+        let progress = match event {
+            Ok(ServerEvent::StartupProgress(StartupProgress::LoadingMods(mods))) => {
+                format!(
+                    "Starting the Minecraft server back up: loading {} mods",
+                    mods
+                )
+            }
+            Ok(ServerEvent::StartupProgress(StartupProgress::PreparingSpawnArea(percent))) => {
+                format!(
+                    "Starting the Minecraft server back up: preparing the spawn area, {}%",
+                    percent
+                )
+            }
+            Ok(ServerEvent::Done(_)) => "Started the Minecraft server back up".to_owned(),
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        handle.set_progress(progress);
+    }
+}
+
+pub(crate) async fn job(jobs: Jobs, id: String) -> Result<Json<Job>, (StatusCode, String)> {
+    jobs.get(&id).map(Json).ok_or_else(|| {
+        let err_msg = format!(
+            "There isn't a job with the ID {:?}. Jobs are forgotten a while after they finish, and when the wrapper restarts",
+            id
+        );
+        warn!("GET /jobs/:id: {}", err_msg);
+        (StatusCode::NOT_FOUND, err_msg)
+    })
+}
+
+/// Begins an operation that stops the Minecraft server, or responds with a
+/// `409` if another one is already in progress.
+fn begin_operation(
+    state: &StateMachine,
+    during: ServerState,
+    route: &str,
+) -> Result<Transition, (StatusCode, String)> {
+    state.begin(during).map_err(|e| {
+        warn!("{}: {}", route, e);
+        (StatusCode::CONFLICT, e.to_string())
+    })
+}
+
+/// Turns away requests that send the Minecraft server commands with a `503`
+/// while it isn't running, like while it's restarting or being backed up,
+/// instead of holding them up until it's done.
+pub(crate) async fn require_running(
+    state: StateMachine,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if let Err(e) = state.ensure_running() {
+        warn!("{} {}: {}", req.method(), req.uri().path(), e);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(SERVER_NOT_READY_RETRY_AFTER_SECONDS),
+        );
+        return (StatusCode::SERVICE_UNAVAILABLE, headers, e.to_string()).into_response();
+    }
+    next.run(req).await
+}
+
+/// Returns the status code to respond with when talking to the Minecraft server
+/// fails with the provided error.
+fn error_status(e: &anyhow::Error) -> StatusCode {
+    match e.downcast_ref::<WrapperError>() {
+        Some(WrapperError::ProcessExited) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(WrapperError::ServerNotReady(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(WrapperError::InvalidArgument(_)) => StatusCode::BAD_REQUEST,
+        Some(WrapperError::CommandRejected(_)) => StatusCode::BAD_REQUEST,
+        Some(WrapperError::CommandNotPermitted(_)) => StatusCode::FORBIDDEN,
+        Some(WrapperError::PropertiesNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(WrapperError::ServerIconNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(WrapperError::DatapackNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(WrapperError::PlayerNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(WrapperError::BackupNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(WrapperError::BackupInUse { .. }) => StatusCode::CONFLICT,
+        Some(WrapperError::RestoredWorldFailedToStart { .. }) => StatusCode::UNPROCESSABLE_ENTITY,
+        Some(WrapperError::InsufficientDiskSpace { .. }) => StatusCode::INSUFFICIENT_STORAGE,
+        Some(WrapperError::SavesAlreadyFrozen(_)) => StatusCode::CONFLICT,
+        Some(WrapperError::SavesNotFrozen) => StatusCode::CONFLICT,
+        Some(WrapperError::NotSupported(_)) => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// General information about the Minecraft server. Fields that couldn't be
+/// determined are null.
+#[derive(Serialize)]
+pub(crate) struct ServerInfo {
+    max_players: Option<usize>,
+    server_port: Option<u16>,
+    readiness: Readiness,
+    maintenance: bool,
+    saves_frozen_since: Option<String>,
+    stdout_connected: bool,
+    state: ServerState,
+    server_dir: String,
+    world_dir: String,
+    backup_dir: String,
+}
+
+pub(crate) async fn info(wrapper: WrapperHandle) -> Result<Json<ServerInfo>, Response> {
+    match wrapper.call(|w| Ok(server_info(w))).await {
+        Ok(info) => Ok(info.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch info about the server: {}",
+                e
+            );
+            warn!("GET /info: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+fn server_info(w: &mut Wrapper) -> ServerInfo {
+    // Check before talking to the Minecraft server below, which kills it if the
+    // wrapper can't read what it writes.
+    let stdout_connected = !w.stdout_reader_died().unwrap_or(false);
+    // Prefer server.properties since reading it doesn't involve talking to the
+    // Minecraft server, but fall back to asking the server if that file can't
+    // be read.
+    let max_players = match w
+        .server_properties()
+        .ok()
+        .and_then(|properties| properties.get_parsed("max-players"))
+    {
+        Some(max_players) => Some(max_players),
+        None => w
+            .max_players()
+            .map_err(|e| warn!("GET /info: Failed to get the max number of players: {}", e))
+            .ok(),
+    };
+
+    ServerInfo {
+        max_players,
+        server_port: w.server_port(),
+        readiness: w.readiness(),
+        maintenance: w.maintenance_mode(),
+        saves_frozen_since: w.saves_frozen_at().map(|at| at.to_rfc3339()),
+        stdout_connected,
+        state: w.state_machine().current(),
+        server_dir: w.server_dir().to_string_lossy().into_owned(),
+        world_dir: w.world_dir().to_string_lossy().into_owned(),
+        backup_dir: w.backup_dir().to_string_lossy().into_owned(),
+    }
+}
+
+pub(crate) async fn init_server_properties(
+    wrapper: WrapperHandle,
+) -> Result<(StatusCode, String), Response> {
+    match wrapper.call(|w| w.init_server_properties()).await {
+        Ok(path) => {
+            let response_msg = format!("Created a default server.properties file at {:?}", path);
+            info!("{}", &response_msg);
+            Ok((StatusCode::CREATED, response_msg))
+        }
+        Err(e) => {
+            let status = match e.downcast_ref::<WrapperError>() {
+                Some(WrapperError::PropertiesAlreadyExist(_)) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let err_msg = format!(
+                "Something went wrong while trying to create a server.properties file: {}",
+                e
+            );
+            warn!("POST /properties/init: {}", err_msg);
+            Err((status, err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn forceload(
+    wrapper: WrapperHandle,
+    action: ForceloadAction,
+    params: TimeoutParams,
+) -> Result<Json<ForceloadResponse>, Response> {
+    let method = if action == ForceloadAction::Query {
+        "GET"
+    } else {
+        "POST"
+    };
+    let timeout = params
+        .command_timeout(&format!("{} /forceload", method))
+        .map_err(IntoResponse::into_response)?;
+    match wrapper
+        .call(move |w| w.with_command_timeout(timeout, |w| w.forceload(action)))
+        .await
+    {
+        Ok(response) => Ok(response.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to manage force loaded chunks: {}",
+                e
+            );
+            warn!("{} /forceload: {}", method, err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MaintenanceParams {
+    on: bool,
+}
+
+/// The result of turning maintenance mode on or off.
+#[derive(Serialize)]
+pub(crate) struct MaintenanceStatus {
+    maintenance: bool,
+    kicked: Vec<String>,
+}
+
+pub(crate) async fn set_maintenance_mode(
+    wrapper: WrapperHandle,
+    params: MaintenanceParams,
+    timeout_params: TimeoutParams,
+) -> Result<Json<MaintenanceStatus>, Response> {
+    let on = params.on;
+    let timeout = timeout_params
+        .command_timeout("POST /maintenance")
+        .map_err(IntoResponse::into_response)?;
+    let result = wrapper
+        .call(move |w| {
+            let kicked = w.with_command_timeout(timeout, |w| w.set_maintenance_mode(on))?;
+            Ok(MaintenanceStatus {
+                maintenance: w.maintenance_mode(),
+                kicked,
+            })
+        })
+        .await;
+    match result {
+        Ok(status) => {
+            info!(
+                "Turned maintenance mode {}",
+                if params.on { "on" } else { "off" }
+            );
+            Ok(status.into())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to turn maintenance mode {}: {}",
+                if params.on { "on" } else { "off" },
+                e
+            );
+            warn!("POST /maintenance: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommandRequest {
+    command: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CommandOutput {
+    lines: Vec<String>,
+}
+
+pub(crate) async fn run_command(
+    wrapper: WrapperHandle,
+    request: CommandRequest,
+    timeout_params: TimeoutParams,
+) -> Result<Json<CommandOutput>, Response> {
+    let timeout = timeout_params
+        .command_timeout("POST /command")
+        .map_err(IntoResponse::into_response)?;
+    let command = request.command;
+    let result = wrapper
+        .run_command_capture_output(command.clone(), timeout)
+        .await;
+    match result {
+        Ok(lines) => {
+            info!("Passed {:?} on to the Minecraft server", &command);
+            Ok(CommandOutput { lines }.into())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to pass {:?} on to the Minecraft server: {}",
+                &command, e
+            );
+            warn!("POST /command: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LogsParams {
+    /// How many of the most recent lines to return.
+    tail: Option<usize>,
+    /// Only lines that were read after this are returned. In RFC 3339 format.
+    since: Option<String>,
+    /// Only lines that were logged at this level or above are returned.
+    level: Option<LogLevel>,
+}
+
+pub(crate) async fn logs(
+    wrapper: WrapperHandle,
+    params: LogsParams,
+) -> Result<Json<Vec<LogLine>>, Response> {
+    let since = match params.since.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(since)) => Some(since.with_timezone(&Utc)),
+        Some(Err(e)) => {
+            let err_msg = format!(
+                "since has to be a timestamp in RFC 3339 format, like \"2022-11-30T02:00:14Z\": {}",
+                e
+            );
+            warn!("GET /logs: {}", err_msg);
+            return Err((StatusCode::BAD_REQUEST, err_msg).into_response());
+        }
+        None => None,
+    };
+    let LogsParams { tail, level, .. } = params;
+    match wrapper
+        .call(move |w| Ok(w.recent_logs(tail, since, level)))
+        .await
+    {
+        Ok(lines) => Ok(lines.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the server's recent logs: {}",
+                e
+            );
+            warn!("GET /logs: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EventsParams {
+    /// Whether to send every line that the Minecraft server logs, too.
+    #[serde(default)]
+    logs: bool,
+}
+
+/// What `GET /events` sends for each [ServerEvent], and for each line that the
+/// Minecraft server logs.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamedEvent {
+    PlayerJoined {
+        player: String,
+    },
+    PlayerLeft {
+        player: String,
+    },
+    Chat {
+        player: String,
+        message: String,
+    },
+    PlayerDied {
+        player: String,
+        message: String,
+    },
+    ServerOverloaded {
+        behind_ms: u128,
+        ticks: u64,
+    },
+    ServerStarted {
+        startup_ms: u128,
+    },
+    LoadingMods {
+        mods: usize,
+    },
+    PreparingSpawnArea {
+        percent: u8,
+    },
+    Log {
+        line: String,
+    },
+    /// The client fell behind, and missed this many events or lines.
+    Missed {
+        count: u64,
+    },
+}
+
+impl StreamedEvent {
+    /// Returns [None] for events that the client would get as log lines
+    /// anyway.
+    fn from_server_event(event: ServerEvent) -> Option<StreamedEvent> {
+        let event = match event {
+            ServerEvent::PlayerJoined(player) => StreamedEvent::PlayerJoined { player },
+            ServerEvent::PlayerLeft(player) => StreamedEvent::PlayerLeft { player },
+            ServerEvent::ChatMessage { player, message } => StreamedEvent::Chat { player, message },
+            ServerEvent::PlayerDied { player, message } => {
+                StreamedEvent::PlayerDied { player, message }
+            }
+            ServerEvent::ServerOverloaded { behind, ticks } => StreamedEvent::ServerOverloaded {
+                behind_ms: behind.as_millis(),
+                ticks,
+            },
+            ServerEvent::Done(took) => StreamedEvent::ServerStarted {
+                startup_ms: took.as_millis(),
+            },
+            ServerEvent::StartupProgress(StartupProgress::LoadingMods(mods)) => {
+                StreamedEvent::LoadingMods { mods }
+            }
+            ServerEvent::StartupProgress(StartupProgress::PreparingSpawnArea(percent)) => {
+                StreamedEvent::PreparingSpawnArea { percent }
+            }
+            ServerEvent::StartupProgress(StartupProgress::Line(_)) => return None,
+        };
+        Some(event)
+    }
+}
+
+pub(crate) async fn stream_events(
+    wrapper: WrapperHandle,
+    params: EventsParams,
+    mut api_shutting_down: watch::Receiver<bool>,
+) -> Result<Sse<ReceiverStream<Result<Event, Infallible>>>, Response> {
+    let (mut events, mut lines) = match wrapper
+        .call(|w| Ok((w.subscribe(), w.subscribe_console())))
+        .await
+    {
+        Ok(receivers) => receivers,
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to subscribe to server events: {}",
+                e
+            );
+            warn!("GET /events: {}", err_msg);
+            return Err((error_status(&e), err_msg).into_response());
+        }
+    };
+
+    let (tx, rx) = mpsc::channel(EVENT_STREAM_EVENTS_IN_FLIGHT);
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => match StreamedEvent::from_server_event(event) {
+                        Some(event) => event,
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(count)) => StreamedEvent::Missed { count },
+                    Err(RecvError::Closed) => break,
+                },
+                line = lines.recv(), if params.logs => match line {
+                    Ok(line) => StreamedEvent::Log { line },
+                    Err(RecvError::Lagged(count)) => StreamedEvent::Missed { count },
+                    Err(RecvError::Closed) => break,
+                },
+                // The client went away.
+                _ = tx.closed() => break,
+                _ = api_shutting_down.changed() => break,
+            };
+            let event = match Event::default().json_data(&event) {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("GET /events: Failed to serialize an event: {}", e);
+                    continue;
                 }
+            };
+            if tx.send(Ok(event)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::default()))
+}
+
+pub(crate) async fn freeze_saves(wrapper: WrapperHandle) -> Result<String, Response> {
+    match wrapper.call(|w| w.freeze_saves()).await {
+        Ok(()) => {
+            let response_msg = "Saved the world to disk, and froze saving";
+            info!("{}", response_msg);
+            Ok(response_msg.to_string())
+        }
+        Err(e) => {
+            let err_msg = format!("Something went wrong while trying to freeze saving: {}", e);
+            warn!("POST /save/freeze: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn unfreeze_saves(wrapper: WrapperHandle) -> Result<String, Response> {
+    match wrapper.call(|w| w.unfreeze_saves()).await {
+        Ok(()) => {
+            let response_msg = "Unfroze saving";
+            info!("{}", response_msg);
+            Ok(response_msg.to_string())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to unfreeze saving: {}",
+                e
+            );
+            warn!("POST /save/unfreeze: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn server_properties_raw(wrapper: WrapperHandle) -> Result<String, Response> {
+    wrapper
+        .call(|w| w.server_properties_raw())
+        .await
+        .map_err(|e| {
+            let err_msg = format!(
+                "Something went wrong while trying to read the server.properties file: {}",
+                e
+            );
+            warn!("GET /properties/raw: {}", err_msg);
+            (error_status(&e), err_msg).into_response()
+        })
+}
+
+pub(crate) async fn replace_server_properties_raw(
+    wrapper: WrapperHandle,
+    contents: String,
+) -> Result<StatusCode, Response> {
+    match wrapper
+        .call(move |w| w.replace_server_properties_raw(&contents))
+        .await
+    {
+        Ok(path) => {
+            info!("Replaced the server.properties file at {:?}", path);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to replace the server.properties file: {}",
+                e
+            );
+            warn!("PUT /properties/raw: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn server_icon(wrapper: WrapperHandle) -> Result<(HeaderMap, Vec<u8>), Response> {
+    match wrapper.call(|w| w.server_icon()).await {
+        Ok(png) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+            Ok((headers, png))
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to read the server icon: {}",
+                e
+            );
+            warn!("GET /server-icon: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn replace_server_icon(
+    wrapper: WrapperHandle,
+    png: Bytes,
+) -> Result<StatusCode, Response> {
+    match wrapper.call(move |w| w.replace_server_icon(&png)).await {
+        Ok(path) => {
+            info!("Replaced the server icon at {:?}", path);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to replace the server icon: {}",
+                e
+            );
+            warn!("PUT /server-icon: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn list_datapacks(
+    wrapper: WrapperHandle,
+    params: TimeoutParams,
+) -> Result<Json<Datapacks>, Response> {
+    let timeout = params
+        .command_timeout("GET /datapacks")
+        .map_err(IntoResponse::into_response)?;
+    match wrapper
+        .call(move |w| w.with_command_timeout(timeout, Wrapper::list_datapacks))
+        .await
+    {
+        Ok(datapacks) => Ok(datapacks.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the list of data packs: {}",
+                e
+            );
+            warn!("GET /datapacks: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DatapackParams {
+    #[serde(default)]
+    reload: bool,
+}
+
+pub(crate) async fn set_datapack_enabled(
+    wrapper: WrapperHandle,
+    name: String,
+    enabled: bool,
+    params: DatapackParams,
+    timeout_params: TimeoutParams,
+) -> Result<Json<CommandOutcome>, Response> {
+    let action = if enabled { "enable" } else { "disable" };
+    let reload = params.reload;
+    let timeout = timeout_params
+        .command_timeout(&format!("POST /datapacks/{}/{}", &name, action))
+        .map_err(IntoResponse::into_response)?;
+    let result = wrapper
+        .call({
+            let name = name.clone();
+            move |w| {
+                w.with_command_timeout(timeout, |w| {
+                    let outcome = if enabled {
+                        w.enable_datapack(&name)?
+                    } else {
+                        w.disable_datapack(&name)?
+                    };
+                    if reload {
+                        w.reload()?;
+                    }
+                    Ok(outcome)
+                })
             }
+        })
+        .await;
+    match result {
+        Ok(outcome) => {
+            if outcome == CommandOutcome::Changed {
+                info!(
+                    "{} the {:?} data pack",
+                    if enabled { "Enabled" } else { "Disabled" },
+                    &name
+                );
+            }
+            Ok(outcome.into())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to {} the {:?} data pack: {}",
+                action, &name, e
+            );
+            warn!("POST /datapacks/{}/{}: {}", &name, action, err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn startup_warnings(
+    wrapper: WrapperHandle,
+) -> Result<Json<Vec<String>>, Response> {
+    match wrapper.call(|w| Ok(w.startup_warnings().to_vec())).await {
+        Ok(warnings) => Ok(warnings.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the server's startup warnings: {}",
+                e
+            );
+            warn!("GET /startup-warnings: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn crashes(wrapper: WrapperHandle) -> Result<Json<Vec<Crash>>, Response> {
+    match wrapper.call(|w| Ok(w.crashes())).await {
+        Ok(crashes) => Ok(crashes.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the server's crash history: {}",
+                e
+            );
+            warn!("GET /crashes: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+/// Everything that's handy to have when troubleshooting, all in one place.
+/// Fields that couldn't be determined are null, and `notes` says why.
+#[derive(Serialize)]
+pub(crate) struct Diagnostics {
+    wrapper_version: &'static str,
+    wrapper_uptime_seconds: i64,
+    /// The wrapper's config, with anything secret redacted.
+    config: Option<serde_json::Value>,
+    java_version: Option<String>,
+    minecraft_version: Option<String>,
+    server_flavor: Option<ServerFlavor>,
+    server_uptime_seconds: Option<i64>,
+    state: ServerState,
+    readiness: Readiness,
+    recent_warnings: Vec<String>,
+    last_backup: Option<BackupStatus>,
+    last_upload: Option<UploadStatus>,
+    last_crash_report: Option<CrashReport>,
+    notes: Vec<String>,
+}
+
+pub(crate) async fn diagnostics(
+    wrapper: WrapperHandle,
+    config: Arc<Result<serde_json::Value, String>>,
+    wrapper_started_at: DateTime<Utc>,
+) -> Result<Json<Diagnostics>, Response> {
+    let result = wrapper
+        .call(move |w| Ok(collect_diagnostics(w, &config, wrapper_started_at)))
+        .await;
+    match result {
+        Ok(diagnostics) => Ok(diagnostics.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to gather diagnostics: {}",
+                e
+            );
+            warn!("GET /diagnostics: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
         }
     }
 }
+
+/// Fills in as much of a [Diagnostics] as possible. Something going wrong with
+/// one field only leaves that field null.
+fn collect_diagnostics(
+    w: &mut Wrapper,
+    config: &Result<serde_json::Value, String>,
+    wrapper_started_at: DateTime<Utc>,
+) -> Diagnostics {
+    let mut notes = Vec::new();
+
+    let config = config
+        .as_ref()
+        .map_err(|e| notes.push(format!("config: {}", e)))
+        .ok()
+        .cloned();
+    let java_version = w
+        .java_version()
+        .map_err(|e| notes.push(format!("java_version: {:#}", e)))
+        .ok();
+    let server_uptime_seconds = match w.has_exited() {
+        Ok(false) => Some((Utc::now() - w.server_started_at()).num_seconds()),
+        Ok(true) => {
+            notes.push(
+                "server_uptime_seconds: The Minecraft server process isn't running".to_owned(),
+            );
+            None
+        }
+        Err(e) => {
+            notes.push(format!("server_uptime_seconds: {:#}", e));
+            None
+        }
+    };
+    if w.minecraft_version().is_none() {
+        notes.push("minecraft_version: The Minecraft server didn't say which version it is while it was spinning up".to_owned());
+    }
+    let last_crash_report = w
+        .last_crash_report()
+        .map_err(|e| notes.push(format!("last_crash_report: {:#}", e)))
+        .ok()
+        .flatten();
+
+    Diagnostics {
+        wrapper_version: env!("CARGO_PKG_VERSION"),
+        wrapper_uptime_seconds: (Utc::now() - wrapper_started_at).num_seconds(),
+        config,
+        java_version,
+        minecraft_version: w.minecraft_version().map(str::to_owned),
+        server_flavor: w.server_flavor(),
+        server_uptime_seconds,
+        state: w.state_machine().current(),
+        readiness: w.readiness(),
+        recent_warnings: w.recent_warnings(),
+        last_backup: w.last_backup(),
+        last_upload: w.last_upload(),
+        last_crash_report,
+        notes,
+    }
+}
+
+pub(crate) async fn performance(
+    wrapper: WrapperHandle,
+    params: TimeoutParams,
+) -> Result<Json<PerformanceSnapshot>, Response> {
+    let timeout = params
+        .command_timeout("GET /performance")
+        .map_err(IntoResponse::into_response)?;
+    match wrapper
+        .call(move |w| w.with_command_timeout(timeout, Wrapper::performance_snapshot))
+        .await
+    {
+        Ok(snapshot) => Ok(snapshot.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch a performance snapshot: {}",
+                e
+            );
+            warn!("GET /performance: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn game_time(
+    wrapper: WrapperHandle,
+    params: TimeoutParams,
+) -> Result<Json<GameTime>, Response> {
+    let timeout = params
+        .command_timeout("GET /time")
+        .map_err(IntoResponse::into_response)?;
+    match wrapper
+        .call(move |w| w.with_command_timeout(timeout, Wrapper::get_time))
+        .await
+    {
+        Ok(time) => Ok(time.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the time in the world: {}",
+                e
+            );
+            warn!("GET /time: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn query_status(probe: ServerProbe) -> Result<Json<QueryStatus>, Response> {
+    // Like pinging, querying doesn't go through the wrapper.
+    let result = task::spawn_blocking(move || probe.query_status())
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(status) => Ok(status.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to query the Minecraft server: {}",
+                e
+            );
+            warn!("GET /query: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn ping(probe: ServerProbe) -> Result<Json<PingStatus>, Response> {
+    // Pinging doesn't go through the wrapper, so it doesn't wait for backups or
+    // restarts to finish, but it still blocks on the network.
+    let result = task::spawn_blocking(move || probe.ping())
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(status) => Ok(status.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to ping the Minecraft server: {}",
+                e
+            );
+            warn!("GET /ping: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn list_banned_ips(wrapper: WrapperHandle) -> Result<Json<Vec<IpBan>>, Response> {
+    match wrapper.call(|w| w.read_banned_ips()).await {
+        Ok(bans) => Ok(bans.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the list of banned IP addresses: {}",
+                e
+            );
+            warn!("GET /bans/ips: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+pub(crate) async fn list_ops(wrapper: WrapperHandle) -> Result<Json<Vec<Op>>, Response> {
+    match wrapper.call(|w| w.ops()).await {
+        Ok(ops) => Ok(ops.into()),
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to fetch the list of operators: {}",
+                e
+            );
+            warn!("GET /ops: {}", err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OpParams {
+    level: u8,
+}
+
+pub(crate) async fn op_with_level(
+    wrapper: WrapperHandle,
+    name: String,
+    params: OpParams,
+    timeout_params: TimeoutParams,
+) -> Result<Json<CommandOutcome>, Response> {
+    let level = params.level;
+    let timeout = timeout_params
+        .command_timeout(&format!("PUT /op/{}", &name))
+        .map_err(IntoResponse::into_response)?;
+    let result = wrapper
+        .call({
+            let name = name.clone();
+            move |w| w.with_command_timeout(timeout, |w| w.op_with_level(&name, level))
+        })
+        .await;
+    match result {
+        Ok(outcome) => {
+            if outcome == CommandOutcome::Changed {
+                info!("Made {} an operator with permission level {}", &name, level);
+            }
+            Ok(outcome.into())
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Something went wrong while trying to make {} an operator: {}",
+                &name, e
+            );
+            warn!("PUT /op/{}: {}", &name, err_msg);
+            Err((error_status(&e), err_msg).into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_outcomes_map_to_statuses() {
+        let status = |e: WrapperError| error_status(&e.into());
+        assert_eq!(
+            status(WrapperError::PlayerNotFound("Steve".to_owned())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(WrapperError::DatapackNotFound("file/x.zip".to_owned())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(WrapperError::CommandRejected("/op".to_owned())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(WrapperError::CommandNotPermitted("/op Steve".to_owned())),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            error_status(&anyhow::anyhow!("Unexpected response")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn dead_server_is_a_503() {
+        assert_eq!(
+            error_status(&WrapperError::ProcessExited.into()),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::WrapperError;
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 5 * 60;
+// How much of what a command wrote to stdout and to stderr to keep. The end is
+// kept, since that's usually where errors are.
+const MAX_OUTPUT_CHARS: usize = 2000;
+// How many bytes of each output stream to hold onto while a command runs, so
+// that one that writes a lot doesn't use up memory. Enough for
+// `MAX_OUTPUT_CHARS` of anything.
+const MAX_OUTPUT_BYTES: usize = MAX_OUTPUT_CHARS * 4;
+// How often to check whether a command exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// How long to keep reading a command's output after it exits. Something that
+// it started in the background might hold onto its stdout forever.
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Shell commands to run before and after each world backup, like to snapshot
+/// a database, mount a remote share, or tell a monitoring system how it went.
+///
+/// They're run one after the other with `sh -c`, or `cmd /C` on Windows, in
+/// the server's directory. If one of the `before` commands fails, the rest
+/// aren't run, and neither is the backup. The `after` commands are run either
+/// way, even if the backup or one of them fails, so that they can clean up
+/// after the `before` ones. They're told how the backup went with the
+/// `BACKUP_SUCCEEDED` environment variable, which is "true" or "false", and
+/// where it was written to with `BACKUP_PATH`, unless it was streamed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupHooks {
+    #[serde(default)]
+    pub before: Vec<String>,
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// How long each command has to finish before it's killed, and counted as
+    /// failed.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    DEFAULT_TIMEOUT_SECONDS
+}
+
+impl BackupHooks {
+    /// Returns an error if these commands can't be run no matter what.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.timeout_seconds == 0 {
+            bail!("timeout_seconds has to be at least 1");
+        }
+        if self
+            .before
+            .iter()
+            .chain(&self.after)
+            .any(|command| command.trim().is_empty())
+        {
+            bail!("Commands can't be empty");
+        }
+        Ok(())
+    }
+}
+
+/// When a hook was run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    Before,
+    After,
+}
+
+impl HookStage {
+    fn describe(self) -> &'static str {
+        match self {
+            HookStage::Before => "pre-backup",
+            HookStage::After => "post-backup",
+        }
+    }
+}
+
+/// How running one of the [BackupHooks] went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HookResult {
+    pub stage: HookStage,
+    pub command: String,
+    /// Whether it exited with 0 in time.
+    pub succeeded: bool,
+    /// [None] if it couldn't be run, if it was killed for taking too long, or
+    /// if a signal killed it.
+    pub exit_code: Option<i32>,
+    /// The end of what it wrote to stdout.
+    pub stdout: String,
+    /// The end of what it wrote to stderr.
+    pub stderr: String,
+    pub duration_ms: u64,
+    /// What went wrong, if it didn't exit with 0.
+    pub error: Option<String>,
+}
+
+/// Runs the [BackupHooks] for one backup, and keeps track of how each of them
+/// went.
+pub(crate) struct HookRun {
+    hooks: Option<BackupHooks>,
+    server_dir: PathBuf,
+    results: Vec<HookResult>,
+}
+
+impl HookRun {
+    pub(crate) fn new(hooks: Option<BackupHooks>, server_dir: PathBuf) -> HookRun {
+        HookRun {
+            hooks,
+            server_dir,
+            results: Vec::new(),
+        }
+    }
+
+    /// Runs the `before` commands, and returns a
+    /// [WrapperError::BackupHookFailed] as soon as one of them fails.
+    pub(crate) fn before(&mut self) -> anyhow::Result<()> {
+        let commands = self
+            .hooks
+            .as_ref()
+            .map(|hooks| hooks.before.clone())
+            .unwrap_or_default();
+        for command in commands {
+            let result = self.run(HookStage::Before, &command, &[]);
+            let error = result.error.clone();
+            self.results.push(result);
+            if let Some(error) = error {
+                return Err(WrapperError::BackupHookFailed { command, error }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every `after` command, for a backup that was written to
+    /// `backup_path`, if it was written to disk. Commands that fail are only
+    /// logged, and show up in [HookRun::into_results()].
+    pub(crate) fn after(&mut self, succeeded: bool, backup_path: Option<&Path>) {
+        let commands = self
+            .hooks
+            .as_ref()
+            .map(|hooks| hooks.after.clone())
+            .unwrap_or_default();
+        let mut env = vec![("BACKUP_SUCCEEDED", succeeded.to_string())];
+        if let Some(path) = backup_path {
+            env.push(("BACKUP_PATH", path.to_string_lossy().into_owned()));
+        }
+        for command in commands {
+            let result = self.run(HookStage::After, &command, &env);
+            self.results.push(result);
+        }
+    }
+
+    /// Returns how each command that was run went, in the order they were
+    /// run in.
+    pub(crate) fn into_results(self) -> Vec<HookResult> {
+        self.results
+    }
+
+    fn run(&self, stage: HookStage, command: &str, env: &[(&str, String)]) -> HookResult {
+        let timeout = Duration::from_secs(
+            self.hooks
+                .as_ref()
+                .map_or(DEFAULT_TIMEOUT_SECONDS, |hooks| hooks.timeout_seconds),
+        );
+        info!("Running the {} command {:?}", stage.describe(), command);
+        let started_at = Instant::now();
+        let mut result = HookResult {
+            stage,
+            command: command.to_owned(),
+            succeeded: false,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration_ms: 0,
+            error: None,
+        };
+        match run_shell(command, &self.server_dir, env, timeout) {
+            Ok(output) => {
+                result.exit_code = output.exit_code;
+                result.stdout = output.stdout;
+                result.stderr = output.stderr;
+                result.succeeded = output.exit_code == Some(0);
+                if output.timed_out {
+                    result.error = Some(format!("Killed after {:?}", timeout));
+                } else if !result.succeeded {
+                    result.error = Some(match output.exit_code {
+                        Some(code) => format!("Exited with {}", code),
+                        None => "Killed by a signal".to_owned(),
+                    });
+                }
+            }
+            Err(e) => result.error = Some(format!("Failed to run it: {}", e)),
+        }
+        result.duration_ms = started_at.elapsed().as_millis() as u64;
+        if let Some(error) = &result.error {
+            warn!(
+                "The {} command {:?} failed: {}. It wrote this to stderr:\n{}",
+                stage.describe(),
+                command,
+                error,
+                &result.stderr
+            );
+        }
+        result
+    }
+}
+
+struct ShellOutput {
+    exit_code: Option<i32>,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+}
+
+/// Runs `command` with the platform's shell in `dir`, and kills it if it
+/// hasn't exited once `timeout` runs out.
+fn run_shell(
+    command: &str,
+    dir: &Path,
+    env: &[(&str, String)],
+    timeout: Duration,
+) -> io::Result<ShellOutput> {
+    #[cfg(unix)]
+    let mut shell = {
+        use std::os::unix::process::CommandExt;
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        // So that killing it kills whatever it started, too.
+        shell.process_group(0);
+        shell
+    };
+    #[cfg(not(unix))]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+    shell
+        .current_dir(dir)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = shell.spawn()?;
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    if let Some(pipe) = child.stdout.take() {
+        read_in_background(pipe, Arc::clone(&stdout), done_tx.clone());
+    }
+    if let Some(pipe) = child.stderr.take() {
+        read_in_background(pipe, Arc::clone(&stderr), done_tx.clone());
+    }
+    drop(done_tx);
+
+    let deadline = Instant::now() + timeout;
+    let (status, timed_out) = loop {
+        if let Some(status) = child.try_wait()? {
+            break (Some(status), false);
+        }
+        if Instant::now() >= deadline {
+            kill(&mut child)?;
+            break (None, true);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let grace_deadline = Instant::now() + OUTPUT_GRACE_PERIOD;
+    while let Some(remaining) = grace_deadline.checked_duration_since(Instant::now()) {
+        // Disconnected once both pipes are closed.
+        if done_rx.recv_timeout(remaining).is_err() {
+            break;
+        }
+    }
+
+    let tail = |output: &Mutex<Vec<u8>>| {
+        let output = output.lock().unwrap();
+        let output = String::from_utf8_lossy(&output);
+        let output = output.trim();
+        let skip = output.chars().count().saturating_sub(MAX_OUTPUT_CHARS);
+        output.chars().skip(skip).collect()
+    };
+    Ok(ShellOutput {
+        exit_code: status.and_then(|status| status.code()),
+        timed_out,
+        stdout: tail(&stdout),
+        stderr: tail(&stderr),
+    })
+}
+
+/// Reads `pipe` into `output` until it's closed, on another thread.
+fn read_in_background<R: Read + Send + 'static>(
+    mut pipe: R,
+    output: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Sender<()>,
+) {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    let mut output = output.lock().unwrap();
+                    output.extend_from_slice(&buf[..read]);
+                    let excess = output.len().saturating_sub(MAX_OUTPUT_BYTES);
+                    output.drain(..excess);
+                }
+            }
+        }
+        let _ = done.send(());
+    });
+}
+
+/// Kills the provided command, along with everything it started on Unix, and
+/// waits for it to exit.
+fn kill(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // The command leads its own process group, so a negative ID signals
+        // every process in it.
+        //
+        // SAFETY: kill() doesn't touch any memory that we own.
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    }
+    #[cfg(not(unix))]
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
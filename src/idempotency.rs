@@ -0,0 +1,365 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::{info, warn};
+
+use crate::auth::Caller;
+
+/// The header that clients put a key of their choosing in to make a request
+/// safe to retry.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// Added to responses that were replayed instead of carrying out the request
+// again.
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+// How long a key is remembered after the request that first used it came in.
+const KEY_TTL: Duration = Duration::from_secs(60 * 60);
+// The most keys that are remembered at once. The oldest one is forgotten first.
+const MAX_KEYS: usize = 1000;
+// The longest key that's accepted.
+const MAX_KEY_LEN: usize = 255;
+
+// A key, along with which token the request that used it presented, so that
+// callers can't see each other's responses. Tokens are told apart by
+// Caller::token_index(), since they don't all have names, and names can repeat.
+type KeyId = (Option<usize>, String);
+
+/// The response to a request with an `Idempotency-Key`, kept around for
+/// retries.
+#[derive(Clone)]
+struct RecordedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl IntoResponse for RecordedResponse {
+    fn into_response(self) -> Response {
+        (self.status, self.headers, self.body).into_response()
+    }
+}
+
+struct Entry {
+    // The method and URI of the request that first used the key.
+    route: String,
+    seen_at: Instant,
+    // [None] while that request is still being carried out.
+    response: Option<RecordedResponse>,
+}
+
+/// What to do with a request that has an `Idempotency-Key`.
+enum Claim {
+    /// Nobody used the key before, so carry out the request.
+    New,
+    /// A request with the key is still being carried out.
+    InProgress,
+    /// Respond with what the request that used the key first got.
+    Replay(RecordedResponse),
+    /// The key was already used for a request to the provided route.
+    Mismatch(String),
+}
+
+/// The `Idempotency-Key`s that were used recently, and what the requests that
+/// used them got back.
+#[derive(Default)]
+pub(crate) struct IdempotencyKeys {
+    entries: Mutex<HashMap<KeyId, Entry>>,
+}
+
+impl IdempotencyKeys {
+    fn claim(&self, id: &KeyId, route: &str) -> Claim {
+        self.claim_at(id, route, Instant::now())
+    }
+
+    /// Like [IdempotencyKeys::claim()], as if it were `now`.
+    fn claim_at(&self, id: &KeyId, route: &str, now: Instant) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| now.duration_since(entry.seen_at) < KEY_TTL);
+        if let Some(entry) = entries.get(id) {
+            return if entry.route != route {
+                Claim::Mismatch(entry.route.clone())
+            } else if let Some(response) = &entry.response {
+                Claim::Replay(response.clone())
+            } else {
+                Claim::InProgress
+            };
+        }
+
+        if entries.len() >= MAX_KEYS {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.seen_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            id.clone(),
+            Entry {
+                route: route.to_owned(),
+                seen_at: now,
+                response: None,
+            },
+        );
+        Claim::New
+    }
+
+    fn remember(&self, id: &KeyId, response: RecordedResponse) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(id) {
+            entry.response = Some(response);
+        }
+    }
+
+    fn forget(&self, id: &KeyId) {
+        self.entries.lock().unwrap().remove(id);
+    }
+}
+
+/// Carries out a POST request with an `Idempotency-Key` header only the first
+/// time that its caller uses that key, and responds to repeats with whatever
+/// the first one got. Requests without the header are always carried out.
+///
+/// Requests with a key are carried out to the end even if the client gives up
+/// on them, so that its retry can be answered with how it went.
+pub(crate) async fn deduplicate(
+    keys: Arc<IdempotencyKeys>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if req.method() != Method::POST {
+        return next.run(req).await;
+    }
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => key,
+        None => return next.run(req).await,
+    };
+    let route = format!("{} {}", req.method(), req.uri());
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_owned(),
+        _ => {
+            let err_msg = format!(
+                "The Idempotency-Key header has to be between 1 and {} visible ASCII characters",
+                MAX_KEY_LEN
+            );
+            warn!("{}: {}", route, err_msg);
+            return (StatusCode::BAD_REQUEST, err_msg).into_response();
+        }
+    };
+    let token = req
+        .extensions()
+        .get::<Caller>()
+        .and_then(Caller::token_index);
+    let id = (token, key);
+
+    match keys.claim(&id, &route) {
+        Claim::New => {}
+        Claim::Replay(mut response) => {
+            info!(
+                "{}: Replaying the response to an earlier request with the same Idempotency-Key",
+                route
+            );
+            response
+                .headers
+                .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+            return response.into_response();
+        }
+        Claim::InProgress => {
+            let err_msg = "A request with this Idempotency-Key is still being carried out";
+            warn!("{}: {}", route, err_msg);
+            return (StatusCode::CONFLICT, err_msg).into_response();
+        }
+        Claim::Mismatch(other_route) => {
+            let err_msg = format!(
+                "This Idempotency-Key was already used for {}. Use a new key for every request",
+                other_route
+            );
+            warn!("{}: {}", route, err_msg);
+            return (StatusCode::UNPROCESSABLE_ENTITY, err_msg).into_response();
+        }
+    }
+
+    let task = tokio::spawn({
+        let keys = Arc::clone(&keys);
+        let id = id.clone();
+        async move {
+            let response = next.run(req).await;
+            record(&keys, &id, response).await
+        }
+    });
+    match task.await {
+        Ok(response) => response,
+        Err(e) => {
+            keys.forget(&id);
+            let err_msg = format!("The task handling this request panicked: {}", e);
+            warn!("{}: {}", route, err_msg);
+            (StatusCode::INTERNAL_SERVER_ERROR, err_msg).into_response()
+        }
+    }
+}
+
+/// Reads the whole response to a request with an `Idempotency-Key`, remembers
+/// it for retries, and returns it.
+async fn record(keys: &IdempotencyKeys, id: &KeyId, response: Response) -> Response {
+    // The request was turned away before it did anything, like while the
+    // Minecraft server was restarting, so a retry should carry it out.
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        keys.forget(id);
+        return response;
+    }
+
+    let (parts, mut body) = response.into_parts();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(e) => {
+                keys.forget(id);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to read the response to this request: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    }
+    let response = RecordedResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: Bytes::from(bytes),
+    };
+    keys.remember(id, response.clone());
+    response.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "POST /command";
+
+    fn id(token: Option<usize>, key: &str) -> KeyId {
+        (token, key.to_owned())
+    }
+
+    fn response(body: &'static str) -> RecordedResponse {
+        RecordedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    fn replayed_body(claim: Claim) -> Bytes {
+        match claim {
+            Claim::Replay(response) => response.body,
+            _ => panic!("Expected the response to be replayed"),
+        }
+    }
+
+    #[test]
+    fn a_key_is_carried_out_once_and_then_replayed() {
+        let keys = IdempotencyKeys::default();
+        let key = id(Some(0), "key");
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::New));
+        // Still being carried out, which the middleware answers with a 409.
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::InProgress));
+
+        keys.remember(&key, response("done"));
+        assert_eq!(replayed_body(keys.claim(&key, ROUTE)), "done");
+        assert_eq!(replayed_body(keys.claim(&key, ROUTE)), "done");
+    }
+
+    #[test]
+    fn a_key_used_for_another_route_is_a_mismatch() {
+        let keys = IdempotencyKeys::default();
+        let key = id(Some(0), "key");
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::New));
+        keys.remember(&key, response("done"));
+        // The middleware answers this with a 422.
+        match keys.claim(&key, "POST /maintenance?enabled=true") {
+            Claim::Mismatch(route) => assert_eq!(route, ROUTE),
+            _ => panic!("Expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn forgotten_keys_can_be_used_again() {
+        let keys = IdempotencyKeys::default();
+        let key = id(Some(0), "key");
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::New));
+        keys.forget(&key);
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::New));
+
+        // Remembering a response for a key that was forgotten in the meantime
+        // doesn't bring it back.
+        keys.forget(&key);
+        keys.remember(&key, response("done"));
+        assert!(matches!(keys.claim(&key, ROUTE), Claim::New));
+    }
+
+    #[test]
+    fn keys_expire() {
+        let keys = IdempotencyKeys::default();
+        let key = id(Some(0), "key");
+        let start = Instant::now();
+        assert!(matches!(keys.claim_at(&key, ROUTE, start), Claim::New));
+        keys.remember(&key, response("done"));
+
+        let almost = start + KEY_TTL - Duration::from_secs(1);
+        assert_eq!(replayed_body(keys.claim_at(&key, ROUTE, almost)), "done");
+        assert!(matches!(
+            keys.claim_at(&key, ROUTE, start + KEY_TTL),
+            Claim::New
+        ));
+    }
+
+    #[test]
+    fn the_oldest_key_is_forgotten_once_there_are_too_many() {
+        let keys = IdempotencyKeys::default();
+        let start = Instant::now();
+        for i in 0..MAX_KEYS {
+            let at = start + Duration::from_millis(i as u64);
+            assert!(matches!(
+                keys.claim_at(&id(None, &i.to_string()), ROUTE, at),
+                Claim::New
+            ));
+        }
+        let at = start + Duration::from_millis(MAX_KEYS as u64);
+        assert!(matches!(
+            keys.claim_at(&id(None, "one more"), ROUTE, at),
+            Claim::New
+        ));
+
+        let entries = keys.entries.lock().unwrap();
+        assert_eq!(entries.len(), MAX_KEYS);
+        assert!(!entries.contains_key(&id(None, "0")));
+        assert!(entries.contains_key(&id(None, "1")));
+        assert!(entries.contains_key(&id(None, "one more")));
+    }
+
+    #[test]
+    fn tokens_dont_see_each_others_keys() {
+        let keys = IdempotencyKeys::default();
+        let first = id(Some(0), "key");
+        let second = id(Some(1), "key");
+        let no_token = id(None, "key");
+        assert!(matches!(keys.claim(&first, ROUTE), Claim::New));
+        keys.remember(&first, response("first"));
+
+        assert!(matches!(keys.claim(&second, ROUTE), Claim::New));
+        keys.remember(&second, response("second"));
+        assert!(matches!(keys.claim(&no_token, ROUTE), Claim::New));
+
+        assert_eq!(replayed_body(keys.claim(&first, ROUTE)), "first");
+        assert_eq!(replayed_body(keys.claim(&second, ROUTE)), "second");
+    }
+}
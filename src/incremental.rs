@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{Instant, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup::{self, TarballContents, WalkedFile},
+    backup_files::{self, BackupDir},
+    dimension::DimensionDir,
+    error::WrapperError,
+    exclude::Exclude,
+};
+
+/// Makes world backups incremental, so that most of them only have the files
+/// that changed since the backup before, instead of the whole world.
+///
+/// A file counts as changed when its size or when it was last modified is
+/// different. Every `full_backup_every`th backup is a full one, which starts a
+/// new chain of incremental backups. Restoring an incremental backup unpacks
+/// every backup in its chain, starting from the full one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IncrementalBackups {
+    /// How many backups a chain has, including the full one it starts with.
+    /// 1 makes every backup a full one.
+    pub full_backup_every: u32,
+}
+
+impl IncrementalBackups {
+    /// Returns an error if backups can't be made this way no matter what.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.full_backup_every == 0 {
+            bail!("full_backup_every has to be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Every file in the world when a backup was made, written next to it, so
+/// that the next backup can tell what changed since.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    /// The ID of the backup that this one only has the changes since, or
+    /// [None] for a full backup.
+    pub(crate) based_on: Option<String>,
+    /// Every file in the world, by where it goes inside of a tarball, like
+    /// "region/r.0.0.mca".
+    pub(crate) files: BTreeMap<String, FileState>,
+}
+
+/// What a file looked like when a backup was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileState {
+    pub(crate) size: u64,
+    /// When the file was last modified, in nanoseconds since the Unix epoch.
+    pub(crate) modified_nanos: u64,
+}
+
+/// A backup that's about to be made: what goes in its tarball, and what's
+/// written next to it.
+pub(crate) struct PendingBackup {
+    pub(crate) files: Vec<WalkedFile>,
+    pub(crate) snapshot: Snapshot,
+}
+
+/// Works out what goes in the next backup of the provided dimensions of the
+/// world: only the files that changed since the newest backup in `dir` with a
+/// snapshot, or every file if it's time for a full backup.
+pub(crate) fn prepare(
+    dir: &BackupDir,
+    config: &IncrementalBackups,
+    dimension_dirs: &[DimensionDir],
+    follow_symlinks: bool,
+    deadline: Option<Instant>,
+) -> anyhow::Result<PendingBackup> {
+    let mut walked = Vec::new();
+    for dimension_dir in dimension_dirs {
+        walked.extend(backup::walk_files(
+            &dimension_dir.archive_path,
+            &dimension_dir.path,
+            &dimension_dir.exclude,
+            follow_symlinks,
+            deadline,
+        )?);
+    }
+    let files: BTreeMap<String, FileState> = walked
+        .iter()
+        .map(|file| {
+            let modified_nanos = file
+                .metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos() as u64);
+            let state = FileState {
+                size: file.metadata.len(),
+                modified_nanos,
+            };
+            (archive_key(&file.archive_path), state)
+        })
+        .collect();
+
+    let base = match newest_base(dir, config) {
+        Ok(base) => base,
+        Err(e) => {
+            warn!(
+                "Couldn't find a world backup to base an incremental one on, so making a full one: {:#}",
+                e
+            );
+            None
+        }
+    };
+    let (based_on, walked) = match base {
+        Some((id, base)) => {
+            let changed = walked
+                .into_iter()
+                .filter(|file| {
+                    let key = archive_key(&file.archive_path);
+                    base.files.get(&key) != files.get(&key)
+                })
+                .collect();
+            (Some(id), changed)
+        }
+        None => (None, walked),
+    };
+    match &based_on {
+        Some(id) => info!(
+            "Making an incremental world backup of the {} files that changed since {}",
+            walked.len(),
+            id
+        ),
+        None => info!("Making a full world backup, which starts a new chain of incremental ones"),
+    }
+    Ok(PendingBackup {
+        files: walked,
+        snapshot: Snapshot { based_on, files },
+    })
+}
+
+/// Returns the ID and snapshot of the newest backup in `dir` that has one, if
+/// the next backup can be based on it without making its chain longer than
+/// `full_backup_every`.
+fn newest_base(
+    dir: &BackupDir,
+    config: &IncrementalBackups,
+) -> anyhow::Result<Option<(String, Snapshot)>> {
+    let mut backups = backup_files::find(dir)?;
+    backups.sort_by_key(|(_, made_at)| *made_at);
+    let newest = backups
+        .iter()
+        .rev()
+        .find_map(|(path, made_at)| Some((backup_files::id(made_at), read_snapshot(path)?)));
+    let (id, snapshot) = match newest {
+        Some(newest) => newest,
+        None => return Ok(None),
+    };
+    let chain_len = chain(dir, &id)?.len();
+    if chain_len >= config.full_backup_every as usize {
+        return Ok(None);
+    }
+    Ok(Some((id, snapshot)))
+}
+
+/// Writes the provided snapshot next to the world backup at the provided path.
+/// Something going wrong is only logged, since the backup itself is fine. The
+/// next backup is just based on an older one instead.
+pub(crate) fn write_snapshot(path: &Path, snapshot: &Snapshot) {
+    let snapshot_path = backup_files::snapshot_path(path);
+    let result = serde_json::to_vec(snapshot)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| fs::write(&snapshot_path, json).map_err(anyhow::Error::from));
+    if let Err(e) = result {
+        warn!(
+            "Failed to write the snapshot of the world backup at {:?}: {}",
+            path, e
+        );
+    }
+}
+
+/// Returns the snapshot that was written next to the world backup at the
+/// provided path, if there is one.
+pub(crate) fn read_snapshot(path: &Path) -> Option<Snapshot> {
+    let json = fs::read(backup_files::snapshot_path(path)).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Returns every backup that has to be unpacked to restore the one with the
+/// provided ID, in the order they have to be unpacked in, starting with the
+/// full backup that its chain starts with. Backups without a snapshot are a
+/// chain of their own.
+pub(crate) fn chain(dir: &BackupDir, id: &str) -> anyhow::Result<Vec<(PathBuf, Option<Snapshot>)>> {
+    let backup_count = backup_files::find(dir)?.len();
+    let mut chain = Vec::new();
+    let mut next = Some(id.to_owned());
+    while let Some(id) = next {
+        if chain.len() > backup_count {
+            bail!("The world backup {:?} is based on itself", id);
+        }
+        let path = backup_files::path_of(dir, &id).map_err(|e| match e.downcast_ref() {
+            Some(WrapperError::BackupNotFound(_)) if !chain.is_empty() => anyhow!(
+                "The world backup {:?} is missing, so the incremental backups after it can't be restored",
+                id
+            ),
+            _ => e,
+        })?;
+        let snapshot = read_snapshot(&path);
+        next = snapshot.as_ref().and_then(|s| s.based_on.clone());
+        chain.push((path, snapshot));
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Returns the IDs of the backups in `dir` that are based on the one with the
+/// provided ID.
+pub(crate) fn dependents(dir: &BackupDir, id: &str) -> anyhow::Result<Vec<String>> {
+    Ok(backup_files::find(dir)?
+        .into_iter()
+        .filter(|(path, _)| {
+            read_snapshot(path).is_some_and(|snapshot| snapshot.based_on.as_deref() == Some(id))
+        })
+        .map(|(_, made_at)| backup_files::id(&made_at))
+        .collect())
+}
+
+/// Returns the backups out of `backups` that one of the `kept` ones is based
+/// on, directly or not, and so can't be deleted without breaking its chain.
+pub(crate) fn bases_of(backups: &[(PathBuf, DateTime<Utc>)], kept: &[PathBuf]) -> HashSet<PathBuf> {
+    let paths_by_id: HashMap<String, &PathBuf> = backups
+        .iter()
+        .map(|(path, made_at)| (backup_files::id(made_at), path))
+        .collect();
+    let mut bases = HashSet::new();
+    for path in kept {
+        let mut next = read_snapshot(path).and_then(|snapshot| snapshot.based_on);
+        while let Some(base) = next.and_then(|id| paths_by_id.get(&id).copied()) {
+            if !bases.insert(base.clone()) {
+                break;
+            }
+            next = read_snapshot(base).and_then(|snapshot| snapshot.based_on);
+        }
+    }
+    bases
+}
+
+/// Returns what restoring a backup with the provided snapshot leaves in the
+/// world, once every backup in its chain is unpacked.
+pub(crate) fn contents(snapshot: &Snapshot) -> TarballContents {
+    let mut contents = TarballContents::default();
+    for (key, state) in &snapshot.files {
+        let mut parts = key.split('/').map(OsStr::new);
+        let first = parts.next().unwrap_or_default();
+        contents.add(first, parts.next(), Some(state.size));
+    }
+    contents
+}
+
+/// Deletes every file inside of `dir` that isn't in the provided snapshot, so
+/// that files that were deleted from the world between the backups in a chain
+/// don't come back when it's restored.
+pub(crate) fn remove_unlisted_files(dir: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    for file in backup::walk_files(Path::new(""), dir, &Exclude::default(), false, None)? {
+        if !snapshot
+            .files
+            .contains_key(&archive_key(&file.archive_path))
+        {
+            fs::remove_file(&file.src)
+                .with_context(|| format!("Failed to delete {:?}", &file.src))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns where a file goes inside of a tarball, with forward slashes no
+/// matter the platform, for looking it up in a [Snapshot].
+fn archive_key(archive_path: &Path) -> String {
+    archive_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        backup_files::{BackupFormat, DEFAULT_FILE_NAME_FORMAT},
+        retention::{self, BackupRetention},
+        temp_dir::TempDir,
+    };
+
+    /// A `backup_dir` to make backups in, which is deleted when it's dropped.
+    struct Backups {
+        _temp_dir: TempDir,
+        dir: BackupDir,
+    }
+
+    impl Backups {
+        fn new(name: &str) -> Backups {
+            let temp_dir = TempDir::new(name);
+            let dir = BackupDir::new(temp_dir.path().to_owned(), DEFAULT_FILE_NAME_FORMAT).unwrap();
+            Backups {
+                _temp_dir: temp_dir,
+                dir,
+            }
+        }
+
+        /// Makes an empty backup on the provided day of January 2022, based
+        /// on the one with the provided ID if there is one, and returns its
+        /// path and ID. Backups made with `snapshot` set to false don't have
+        /// one, like the ones made before incremental backups were turned on.
+        fn add(&self, day: u32, based_on: Option<&str>, snapshot: bool) -> (PathBuf, String) {
+            let made_at = Utc.with_ymd_and_hms(2022, 1, day, 0, 0, 0).unwrap();
+            let path = self.dir.new_backup_path(made_at, BackupFormat::default());
+            fs::write(&path, "").unwrap();
+            if snapshot {
+                let snapshot = Snapshot {
+                    based_on: based_on.map(str::to_owned),
+                    files: BTreeMap::new(),
+                };
+                write_snapshot(&path, &snapshot);
+            }
+            (path, backup_files::id(&made_at))
+        }
+    }
+
+    #[test]
+    fn chain_starts_with_the_full_backup() {
+        let backups = Backups::new("incremental-chain");
+        let (full, full_id) = backups.add(1, None, true);
+        let (first, first_id) = backups.add(2, Some(&full_id), true);
+        let (second, second_id) = backups.add(3, Some(&first_id), true);
+
+        let full_chain = chain(&backups.dir, &second_id).unwrap();
+        let paths: Vec<&PathBuf> = full_chain.iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, [&full, &first, &second]);
+        assert_eq!(full_chain[0].1.as_ref().unwrap().based_on, None);
+        assert_eq!(
+            full_chain[2].1.as_ref().unwrap().based_on.as_deref(),
+            Some(first_id.as_str())
+        );
+
+        // Backups without a snapshot are a chain of their own.
+        let (plain, plain_id) = backups.add(4, None, false);
+        let plain_chain = chain(&backups.dir, &plain_id).unwrap();
+        assert_eq!(plain_chain.len(), 1);
+        assert_eq!(plain_chain[0].0, plain);
+        assert!(plain_chain[0].1.is_none());
+    }
+
+    #[test]
+    fn chain_with_a_missing_base_cant_be_restored() {
+        let backups = Backups::new("incremental-missing-base");
+        let (_, full_id) = backups.add(1, None, true);
+        let (first, first_id) = backups.add(2, Some(&full_id), true);
+        let (_, second_id) = backups.add(3, Some(&first_id), true);
+        fs::remove_file(&first).unwrap();
+
+        let e = chain(&backups.dir, &second_id).unwrap_err();
+        assert!(e.to_string().contains("is missing"), "{}", e);
+        assert!(e.to_string().contains(&first_id), "{}", e);
+
+        // A backup that was never there is just not found.
+        let e = chain(&backups.dir, "2023-01-01T00-00-00Z").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(WrapperError::BackupNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn dependents_are_only_the_backups_based_directly_on_one() {
+        let backups = Backups::new("incremental-dependents");
+        let (_, full_id) = backups.add(1, None, true);
+        let (_, first_id) = backups.add(2, Some(&full_id), true);
+        backups.add(3, Some(&first_id), true);
+
+        assert_eq!(dependents(&backups.dir, &full_id).unwrap(), [first_id]);
+    }
+
+    #[test]
+    fn bases_of_follows_every_kept_backup_back_to_its_full_one() {
+        let backups = Backups::new("incremental-bases-of");
+        let (old, _) = backups.add(1, None, false);
+        let (full, full_id) = backups.add(2, None, true);
+        let (first, first_id) = backups.add(3, Some(&full_id), true);
+        let (second, _) = backups.add(4, Some(&first_id), true);
+        let all = backup_files::find(&backups.dir).unwrap();
+
+        let bases = bases_of(&all, &[second]);
+        assert_eq!(bases, HashSet::from([full.clone(), first]));
+        assert!(bases_of(&all, &[full, old]).is_empty());
+    }
+
+    #[test]
+    fn pruning_keeps_the_bases_of_kept_backups() {
+        let backups = Backups::new("incremental-pruning");
+        let (old, _) = backups.add(1, None, false);
+        let (full, full_id) = backups.add(2, None, true);
+        let (first, first_id) = backups.add(3, Some(&full_id), true);
+        let (second, _) = backups.add(4, Some(&first_id), true);
+
+        let retention = BackupRetention {
+            keep_last: 1,
+            ..BackupRetention::default()
+        };
+        let deleted = retention::prune_backups(&backups.dir, &retention);
+        assert_eq!(deleted, [old.file_name().unwrap().to_string_lossy()]);
+        assert!(!old.exists());
+        assert!(full.exists());
+        assert!(first.exists());
+        assert!(second.exists());
+    }
+
+    #[test]
+    fn files_that_arent_in_the_snapshot_are_removed() {
+        let dir = TempDir::new("incremental-remove-unlisted");
+        fs::create_dir_all(dir.join("region")).unwrap();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("level.dat"), "level").unwrap();
+        fs::write(dir.join("region/r.0.0.mca"), "region").unwrap();
+        fs::write(dir.join("data/raids.dat"), "raids").unwrap();
+
+        let state = FileState {
+            size: 0,
+            modified_nanos: 0,
+        };
+        let snapshot = Snapshot {
+            based_on: None,
+            files: BTreeMap::from([
+                ("level.dat".to_owned(), state),
+                ("region/r.0.0.mca".to_owned(), state),
+            ]),
+        };
+        remove_unlisted_files(dir.path(), &snapshot).unwrap();
+        assert!(dir.join("level.dat").exists());
+        assert!(dir.join("region/r.0.0.mca").exists());
+        assert!(!dir.join("data/raids.dat").exists());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::Serialize;
+
+// The most finished jobs that are remembered at once. The one that finished
+// first is forgotten first. Jobs that haven't finished yet are never forgotten.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// What a job does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobKind {
+    Backup,
+    Restore,
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    /// Waiting for requests that came in before it to finish.
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// An operation that takes too long to keep a request open for, like backing
+/// up the world, carried out in the background. What `GET /jobs/:id` responds
+/// with.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Job {
+    id: String,
+    kind: JobKind,
+    status: JobStatus,
+    /// What the job is up to right now, like "Backing up the world".
+    progress: Option<String>,
+    created_at: String,
+    started_at: Option<String>,
+    finished_at: Option<String>,
+    /// What the route that started the job would have responded with if it
+    /// had waited for it, once it succeeds.
+    result: Option<serde_json::Value>,
+    /// What went wrong, once it fails.
+    error: Option<String>,
+    /// The status code that the route that started the job would have
+    /// responded with if it had waited for it, once it fails.
+    error_status: Option<u16>,
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Every job that's running, along with the ones that finished recently.
+///
+/// Cheap to clone, and every clone shares the same jobs. Jobs only live in
+/// memory, so they're forgotten when the wrapper stops.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Jobs {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    jobs: HashMap<String, Job>,
+    // The IDs of the jobs that finished, in the order they finished in.
+    finished: VecDeque<String>,
+    next_id: u64,
+}
+
+impl Jobs {
+    /// Adds a queued job of the provided kind, and returns a handle for
+    /// reporting how it goes.
+    pub(crate) fn create(&self, kind: JobKind) -> JobHandle {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Utc::now();
+        // The counter starts over whenever the wrapper does, so the time keeps
+        // IDs from before a restart from being handed out again.
+        let id = format!("{}-{}", now.format("%Y%m%dT%H%M%SZ"), inner.next_id);
+        inner.next_id += 1;
+        inner.jobs.insert(
+            id.clone(),
+            Job {
+                id: id.clone(),
+                kind,
+                status: JobStatus::Queued,
+                progress: None,
+                created_at: now.to_rfc3339(),
+                started_at: None,
+                finished_at: None,
+                result: None,
+                error: None,
+                error_status: None,
+            },
+        );
+        JobHandle {
+            jobs: self.clone(),
+            id,
+        }
+    }
+
+    /// Returns the job with the provided ID, if it's running or finished
+    /// recently.
+    pub(crate) fn get(&self, id: &str) -> Option<Job> {
+        self.inner.lock().unwrap().jobs.get(id).cloned()
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.inner.lock().unwrap().jobs.get_mut(id) {
+            f(job);
+        }
+    }
+
+    fn finish(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.jobs.get_mut(id) {
+            Some(job) => {
+                f(job);
+                job.progress = None;
+                job.finished_at = Some(Utc::now().to_rfc3339());
+            }
+            None => return,
+        }
+        inner.finished.push_back(id.to_owned());
+        while inner.finished.len() > MAX_FINISHED_JOBS {
+            if let Some(oldest) = inner.finished.pop_front() {
+                inner.jobs.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Reports how one job is going. Cheap to clone.
+#[derive(Debug, Clone)]
+pub(crate) struct JobHandle {
+    jobs: Jobs,
+    id: String,
+}
+
+impl JobHandle {
+    /// Returns the job the way it is right now.
+    pub(crate) fn job(&self) -> Job {
+        // Jobs aren't forgotten until they've finished, and only handles
+        // finish them.
+        self.jobs.get(&self.id).unwrap()
+    }
+
+    /// Marks the job as running, with the provided progress, unless it's
+    /// already started.
+    pub(crate) fn start(&self, progress: &str) {
+        self.jobs.update(&self.id, |job| {
+            if job.status != JobStatus::Queued {
+                return;
+            }
+            job.status = JobStatus::Running;
+            job.started_at = Some(Utc::now().to_rfc3339());
+            job.progress = Some(progress.to_owned());
+        });
+    }
+
+    pub(crate) fn set_progress(&self, progress: String) {
+        self.jobs.update(&self.id, |job| {
+            if job.status == JobStatus::Running {
+                job.progress = Some(progress);
+            }
+        });
+    }
+
+    pub(crate) fn succeed(&self, result: serde_json::Value) {
+        self.jobs.finish(&self.id, |job| {
+            job.status = JobStatus::Succeeded;
+            job.result = Some(result);
+        });
+    }
+
+    pub(crate) fn fail(&self, status: StatusCode, error: String) {
+        self.jobs.finish(&self.id, |job| {
+            job.status = JobStatus::Failed;
+            job.error = Some(error);
+            job.error_status = Some(status.as_u16());
+        });
+    }
+}
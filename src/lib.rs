@@ -1,90 +1,1858 @@
+pub mod acl_watcher;
+pub mod actor;
+pub mod automation;
+mod backup;
+pub mod backup_files;
+pub mod bans;
+pub mod compression;
+pub mod coordinates;
+pub mod datapacks;
+pub mod dimension;
+pub mod encryption;
+pub mod error;
+pub mod events;
+mod exclude;
+pub mod flavor;
+pub mod forceload;
+pub mod game_time;
+pub mod hooks;
+pub mod incremental;
+mod line_channel;
+mod lockfile;
+pub mod log_files;
+pub mod logs;
+pub mod manifest;
+pub mod memory;
+pub mod ops;
+pub mod outcome;
+mod output;
+pub mod performance;
+pub mod ping;
+pub mod probe;
+pub mod properties;
+pub mod query;
+pub mod rcon;
+pub mod remote_backup;
+pub mod retention;
+pub mod schedule;
+pub mod server_icon;
+pub mod state;
+pub mod stats;
+#[cfg(test)]
+mod temp_dir;
+pub mod verification;
+pub mod watchdog;
+
 use std::{
-    error,
-    fs::File,
-    io::{self, BufRead, Write},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
     process,
-    sync::mpsc::{self, Receiver},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, LazyLock, Mutex,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context};
-use chrono::Utc;
-use flate2::{write::GzEncoder, Compression};
+use backup_files::{BackupDir, BackupFile, BackupFormat, RestorePlan};
+use bans::IpBan;
+use chrono::{DateTime, Utc};
+use compression::BackupCompression;
+use datapacks::Datapacks;
+use dimension::{Dimension, DimensionDir};
+use encryption::{BackupEncryption, EncryptingWriter};
+use events::ServerEvent;
+use exclude::{Exclude, ExcludePatterns};
+use flavor::ServerFlavor;
+use forceload::{ForceloadAction, ForceloadResponse};
+use game_time::GameTime;
+use hooks::{BackupHooks, HookResult, HookRun};
+use incremental::IncrementalBackups;
+use line_channel::LineReceiver;
+use lockfile::ServerDirLock;
+use log::{error, info, warn};
+use log_files::LogFiles;
+use logs::{LogBuffer, LogLevel, LogLine};
+use manifest::{BackupDetails, BackupMetadata};
+use memory::MaxMemory;
+use ops::Op;
+use outcome::CommandOutcome;
+use output::{EchoFilter, LineForwarder, OutputStream, RaiseOnDrop, RecentLines};
+use performance::PerformanceSnapshot;
+use probe::ServerProbe;
+use properties::ServerProperties;
+use rcon::RconClient;
+use regex::Regex;
+use remote_backup::{Destination, S3Destination, SshDestination, UploadStatus, Uploads};
+use retention::BackupRetention;
+use serde::{Deserialize, Serialize};
+use state::StateMachine;
+use stats::{CommandStats, StdoutStats};
+use tokio::sync::broadcast;
+use verification::BackupVerification;
+use watchdog::{Crash, CrashHistory};
+
+/// Settings that control how a [Wrapper] launches and manages the Minecraft
+/// server process.
+#[derive(Debug, Clone)]
+pub struct WrapperConfig {
+    /// Path to the server.jar file provided by Mojang.
+    pub server_jar_path: String,
+    /// The directory that the Minecraft server runs in, and keeps its files
+    /// in, like `server.properties` and the world directory. When it's
+    /// [None], the server runs in the wrapper's working directory. See
+    /// [Wrapper::server_dir()].
+    pub server_dir: Option<PathBuf>,
+    /// The max size of the server process's memory allocation buffer on the
+    /// JVM.
+    pub max_memory: MaxMemory,
+    /// Whether to spawn the server process in its own process group. Only has
+    /// an effect on Unix.
+    ///
+    /// When it's set, signals sent to the wrapper's process group (like the
+    /// SIGINT from pressing Ctrl-C in a terminal) don't reach the server
+    /// process directly, so the wrapper gets a chance to stop it gracefully.
+    /// When the wrapper needs to kill the server forcefully, it kills the whole
+    /// group, including any processes the server spawned.
+    pub own_process_group: bool,
+    /// The port that the Minecraft server listens for players on. When it's
+    /// [None], the port is read from `server.properties` instead.
+    ///
+    /// Features that talk to the server over the network use this, so setting
+    /// it explicitly keeps them working even if `server.properties` can't be
+    /// read.
+    pub server_port: Option<u16>,
+    /// How long to wait for the Minecraft server to respond to a command before
+    /// giving up.
+    pub command_timeout: Duration,
+    /// How many times to re-send a command that the Minecraft server didn't
+    /// respond to in time before giving up. Each retry waits twice as long as
+    /// the last attempt did.
+    ///
+    /// Only commands that don't change anything are ever retried. Of the
+    /// built-in methods, that's [Wrapper::list_players()] and
+    /// [Wrapper::max_players()].
+    pub command_retries: u32,
+    /// How long to wait for a freshly-spawned Minecraft server to finish
+    /// spinning up before giving up on it.
+    pub startup_timeout: Duration,
+    /// What to do if the Minecraft server doesn't finish spinning up within
+    /// `startup_timeout`.
+    pub startup_timeout_action: StartupTimeoutAction,
+    /// The message that players who are kicked when maintenance mode is turned
+    /// on see.
+    pub maintenance_message: String,
+    /// Whether to treat what the server writes to stderr like what it writes
+    /// to stdout. For setups that send the server's logs to stderr.
+    ///
+    /// Lines that show up on both streams are only handled once.
+    pub read_stderr_as_logs: bool,
+    /// Environment variables to set for the server process, on top of the ones
+    /// it inherits from the wrapper.
+    pub server_env: HashMap<String, String>,
+    /// Whether to keep the server process from inheriting the wrapper's
+    /// environment variables, so that it only gets the ones in `server_env`.
+    ///
+    /// The `java` executable is looked up using the `PATH` in `server_env` if
+    /// there is one, or else the wrapper's `PATH`.
+    pub server_env_clear: bool,
+    /// How long a world backup is allowed to take before it's abandoned. When
+    /// it's [None], backups can take as long as they need to.
+    ///
+    /// A backup that's abandoned leaves no tarball behind, and the server is
+    /// started back up like usual.
+    pub backup_timeout: Option<Duration>,
+    /// Whether to delete the world directory's `session.lock` file before
+    /// starting the server, in case a server that didn't shut down cleanly
+    /// left it behind.
+    ///
+    /// Only turn this on if nothing else could be using the world. Two servers
+    /// running against the same world will corrupt it.
+    pub force_unlock: bool,
+    /// Whether to keep the Minecraft server's responses to commands that the
+    /// wrapper sends on its own, like the "/list" command behind
+    /// [Wrapper::list_players()], from being printed on the host. Responses to
+    /// commands that are passed along with [Wrapper::run_custom_command()]
+    /// are always printed.
+    pub suppress_command_echo: bool,
+    /// Prompts that the server might wait for an answer to on stdin while it's
+    /// starting, and how to answer them.
+    pub startup_prompts: Vec<StartupPrompt>,
+    /// A regular expression that matches the line that the Minecraft server
+    /// writes when it's done saving the world on its way down. When it's
+    /// [None], the line that vanilla servers write is looked for.
+    ///
+    /// If the server exits cleanly after [Wrapper::stop_server()] without
+    /// writing a matching line, a warning is logged, since it might not have
+    /// saved everything.
+    pub stop_ready_pattern: Option<String>,
+    /// How many of the lines that the Minecraft server writes can wait to be
+    /// read by the wrapper at once. Has to be at least 1.
+    ///
+    /// Most lines are never read, since the wrapper only looks for the ones it
+    /// expects after sending a command. When more lines than this pile up, the
+    /// oldest ones are dropped instead of the server being made to wait. See
+    /// [Wrapper::stdout_stats()].
+    pub stdout_channel_capacity: usize,
+    /// How many of the most recent lines that the Minecraft server wrote to
+    /// keep, for [Wrapper::recent_logs()]. Nothing is kept if it's 0.
+    pub log_buffer_lines: usize,
+    /// Where to write every line that the Minecraft server writes, on top of
+    /// printing it. When it's [None], lines are only printed.
+    pub log_files: Option<LogFiles>,
+    /// What to tell players with "/say" before a backup starts. When it's
+    /// [None], backups start without a word.
+    pub backup_announce_message: Option<String>,
+    /// What to tell players with "/say" after a backup finishes successfully.
+    /// When it's [None], nothing is said.
+    pub backup_complete_message: Option<String>,
+    /// How to get players off of the server before a backup that doesn't stop
+    /// it, like [Wrapper::stream_world_backup()]. When it's [None], players
+    /// are left alone.
+    pub backup_drain: Option<BackupDrain>,
+    /// How many bytes of disk space to leave free when making a world backup.
+    /// Backups that would leave less than that are refused before the server
+    /// is stopped.
+    pub min_free_space_bytes: u64,
+    /// Whether to follow symlinks inside of the world directory when making
+    /// a world backup, and back up what they point to. Otherwise, they're
+    /// backed up as symlinks. The world directory itself is always followed, so a world
+    /// that's symlinked onto another volume is backed up either way.
+    pub follow_symlinks: bool,
+    /// Directories to put in world backups on top of the world, like the
+    /// world directories that a multiworld plugin makes, or `plugins/`.
+    /// Relative ones are relative to the server's directory. Each one goes
+    /// in the backup under its own name, so no two of them can have the same
+    /// name. They're left out of backups of only some dimensions.
+    pub backup_extra_dirs: Vec<PathBuf>,
+    /// The directory to write world backups to, and to look for them in. A
+    /// leading "~" is the home directory, "$VAR" and "${VAR}" are
+    /// environment variables, and relative paths are relative to the server's
+    /// directory. It's created if it doesn't exist. When it's [None], backups
+    /// go in the server's directory.
+    pub backup_dir: Option<PathBuf>,
+    /// What to name world backups, as a `strftime`-style format for when they
+    /// were made, in UTC. See
+    /// [DEFAULT_FILE_NAME_FORMAT](backup_files::DEFAULT_FILE_NAME_FORMAT).
+    pub backup_file_name_format: String,
+    /// Glob patterns for files and directories to leave out of world
+    /// backups, like "session.lock" or "plugins/dynmap/web/tiles". Patterns
+    /// with a slash in them are matched against the whole path from the
+    /// server's directory, and the rest are matched against file names.
+    pub backup_exclude: Vec<String>,
+    /// How to compress world backups, including streamed ones.
+    pub backup_compression: BackupCompression,
+    /// How hard to compress world backups. What it goes up to depends on
+    /// `backup_compression`. See [BackupCompression::check_level()]. When it's
+    /// [None], each kind of compression uses its usual level.
+    pub backup_compression_level: Option<u32>,
+    /// How to encrypt world backups, including streamed ones. When it's
+    /// [None], they aren't encrypted. Encrypted backups are decrypted with it
+    /// when they're restored, too.
+    pub backup_encryption: Option<BackupEncryption>,
+    /// How to make world backups incremental. When it's [None], every backup
+    /// has the whole world in it. Backups of only some dimensions, and ones
+    /// made with [Wrapper::make_world_backup_in_background()], always do.
+    pub incremental_backups: Option<IncrementalBackups>,
+    /// Whether to verify world backups that are written to the `backup_dir`
+    /// once they're finished, by test extracting them. See
+    /// [Wrapper::verify_backup()].
+    pub verify_backups: bool,
+    /// How long the Minecraft server has to keep running after it spins up
+    /// with a world backup that was just restored, for the restore to count.
+    /// If it exits before then, or doesn't spin up at all, the world is put
+    /// back the way it was. See [Wrapper::restore_backup()].
+    pub restore_check: Duration,
+    /// Shell commands to run before and after each world backup. When it's
+    /// [None], nothing is run.
+    pub backup_hooks: Option<BackupHooks>,
+    /// Which world backups to keep in the `backup_dir` after a new one is
+    /// written there. The rest are deleted.
+    pub backup_retention: BackupRetention,
+    /// Where to upload world backups that are written to the `backup_dir`,
+    /// once they're finished. When it's [None], they stay on the
+    /// same disk as the world.
+    pub backup_upload: Option<S3Destination>,
+    /// Another machine to copy world backups that are written to the
+    /// `backup_dir` to over SSH, once they're finished. When it's [None], they
+    /// aren't copied anywhere over SSH. They're copied there after they're
+    /// uploaded to `backup_upload`, if that's set, too.
+    pub backup_upload_ssh: Option<SshDestination>,
+}
+
+/// How a [Wrapper] gets players off of the server before a backup that doesn't
+/// stop it, for a snapshot that nobody changes while it's being taken.
+///
+/// Players are warned with "/say", and kicked once the warning runs out.
+/// Vanilla servers can't keep them from reconnecting on their own, so
+/// maintenance mode can be turned on for the length of the backup, too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupDrain {
+    /// How long to wait between warning players and kicking them.
+    pub warning: Duration,
+    /// What players see in the warning, and when they're kicked.
+    pub message: String,
+    /// Whether to turn maintenance mode on until the backup is done, if it
+    /// isn't on already. Operators can still reconnect.
+    pub maintenance: bool,
+}
+
+/// How a world backup keeps the Minecraft server's files from changing while
+/// they're backed up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackupMode {
+    /// Keep the server stopped until the tarball is finished, like
+    /// [Wrapper::make_world_backup()].
+    #[default]
+    Stop,
+    /// Keep the server stopped while the world is copied, and compress the
+    /// copy in the background after it's started back up, like
+    /// [Wrapper::make_world_backup_in_background()].
+    CopyThenCompress,
+    /// Keep the server running, and keep it from saving while the tarball is
+    /// written, like [Wrapper::make_hot_world_backup()].
+    Hot,
+}
+
+/// A prompt that the Minecraft server might wait for an answer to on stdin
+/// while it's starting, like a mod asking for its license to be accepted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartupPrompt {
+    /// A regular expression that matches the prompt.
+    pub pattern: String,
+    /// What to answer the prompt with. When it's [None], the server is killed
+    /// instead, and a
+    /// [WrapperError::AwaitingInput](error::WrapperError::AwaitingInput) is
+    /// returned.
+    #[serde(default)]
+    pub response: Option<String>,
+}
+
+/// What a [Wrapper] does when the Minecraft server doesn't finish spinning up
+/// in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupTimeoutAction {
+    /// Give up on the server, and return an error.
+    FailHard,
+    /// Log a warning, and carry on as if the server were up. Its
+    /// [Readiness] is [Readiness::Unknown] until it's seen to be ready.
+    ///
+    /// Useful for servers that take a very long time to start, or that don't
+    /// announce that they're ready in a way that the wrapper recognizes.
+    ProceedAnyway,
+}
+
+/// Whether the Minecraft server is known to have finished spinning up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Readiness {
+    /// The server announced that it's ready to accept commands.
+    Ready,
+    /// The server didn't announce that it's ready in time, and the wrapper
+    /// carried on anyways. It might be fine, or it might still be starting.
+    Unknown,
+}
+
+/// How a backup went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackupStatus {
+    /// Whether the backup was streamed somewhere else, rather than written to
+    /// disk.
+    pub streamed: bool,
+    pub succeeded: bool,
+    /// What went wrong, if something did.
+    pub error: Option<String>,
+    /// When the backup finished or gave up, as an RFC 3339 timestamp.
+    pub finished_at: String,
+    /// The names of the old backups that were deleted after this one was
+    /// made, since the [BackupRetention] doesn't keep them.
+    pub pruned: Vec<String>,
+    /// How verifying the backup went, if `verify_backups` is set and it was
+    /// written to disk.
+    pub verification: Option<BackupVerification>,
+    /// How each of the `backup_hooks` that was run for the backup went, in
+    /// the order they were run in. A failed `before` command means that the
+    /// backup wasn't made.
+    pub hooks: Vec<HookResult>,
+    /// Why a scheduled backup was skipped, like "no activity", if it was.
+    /// Nothing was backed up in that case, though it still counts as having
+    /// succeeded.
+    pub skipped: Option<String>,
+}
+
+impl BackupStatus {
+    /// Describes a backup that just finished with the provided result.
+    fn new<T>(streamed: bool, result: &anyhow::Result<T>) -> BackupStatus {
+        BackupStatus {
+            streamed,
+            succeeded: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            finished_at: Utc::now().to_rfc3339(),
+            pruned: Vec::new(),
+            verification: None,
+            hooks: Vec::new(),
+            skipped: None,
+        }
+    }
+}
+
+/// The start of a crash report that the Minecraft server wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrashReport {
+    pub file_name: String,
+    /// When the report was last modified, as an RFC 3339 timestamp.
+    pub modified_at: String,
+    /// The first few lines of the report, which say what went wrong.
+    pub head: String,
+}
+
+// When the Minecraft server finishes spinning up, it writes a line to stdout
+// that looks something like this:
+// [02:00:14] [Server thread/INFO]: Done (9.797s)! For help, type "help"
+pub(crate) static SERVER_READY_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Done \([0-9.]+s\)!").unwrap());
+
+// When the Minecraft server is done saving the world on its way down, vanilla
+// servers write a line that looks something like this:
+// [16:14:22] [Server thread/INFO]: ThreadedAnvilChunkStorage: All dimensions are saved
+const DEFAULT_STOP_READY_PATTERN: &str = r"All dimensions are saved";
+// How long to keep waiting for more of what the Minecraft server wrote after
+// its process exits, for lines that were still on their way to the wrapper.
+const STOP_OUTPUT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+// When the world is already locked, like when another Minecraft server is using
+// it, the server fails to start and writes a line that looks something like
+// this:
+// net.minecraft.util.DirectoryLock$LockException: /srv/mc/./world/session.lock: already locked (possibly by other Minecraft instance?)
+static WORLD_LOCKED_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\S*session\.lock): already locked").unwrap());
+
+// When the Minecraft server is started in a directory where its EULA hasn't
+// been agreed to, it writes a line like this and exits:
+// [12:00:00] [ServerMain/INFO]: You need to agree to the EULA in order to run the server. Go to eula.txt for more info.
+static EULA_NOT_AGREED_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: You need to agree to the EULA").unwrap());
+
+// When something else is already listening on the Minecraft server's port, it
+// writes a line like this and exits:
+// [12:00:00] [Server thread/WARN]: **** FAILED TO BIND TO PORT!
+static PORT_IN_USE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\*\*\*\* FAILED TO BIND TO PORT").unwrap());
+
+// When Java can't run the server jar at all, it writes a line like one of these
+// to stderr:
+// Error: Unable to access jarfile server.jar
+// Error: Invalid or corrupt jarfile server.jar
+// no main manifest attribute, in server.jar
+static BAD_JAR_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Error: (?:Unable to access|Invalid or corrupt) jarfile|no main manifest attribute")
+        .unwrap()
+});
+
+// Matches the lines that tell whether the Minecraft server spun up
+// successfully.
+static STARTUP_OUTCOME_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        "{}|{}|{}|{}|{}",
+        SERVER_READY_PATTERN.as_str(),
+        WORLD_LOCKED_PATTERN.as_str(),
+        EULA_NOT_AGREED_PATTERN.as_str(),
+        PORT_IN_USE_PATTERN.as_str(),
+        BAD_JAR_PATTERN.as_str()
+    ))
+    .unwrap()
+});
+
+// Matches the lines that the Minecraft server logs at the WARN or ERROR level,
+// which look something like this:
+// [12:00:00] [Server thread/WARN]: Ambiguity between arguments [teleport, destination] and [teleport, targets] with inputs: [Player, 0123, @e, dd12be42-52a9-4a91-a8a1-11c01849e498]
+pub(crate) static WARNING_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[[^\]]*\] \[[^\]]*/(?:WARN|ERROR)\]: ").unwrap());
+
+// The most warnings from a single startup to remember. Modded servers can
+// write thousands.
+const MAX_STARTUP_WARNINGS: usize = 200;
+
+// Where the Minecraft server writes crash reports, relative to its directory,
+// and how many lines of the newest one to return.
+const CRASH_REPORTS_DIR_NAME: &str = "crash-reports";
+const CRASH_REPORT_HEAD_LINES: usize = 20;
+
+// How long to wait for the rest of what a Minecraft server process that exited
+// while it was starting wrote to stderr.
+const LEFTOVER_STDERR_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How many of the last lines that a server started by
+// [Wrapper::validate_launch()] wrote to include in the error if it fails.
+const VALIDATE_LAUNCH_OUTPUT_LINES: usize = 20;
+
+// The name of the file that the Minecraft server locks to keep other servers
+// from using the same world at the same time.
+const SESSION_LOCK_FILE_NAME: &str = "session.lock";
+// Added to the end of a backup's file name to get the name of the directory
+// that the world is copied into before it's compressed in the background.
+const STAGING_DIR_SUFFIX: &str = ".staging";
+// Where a world backup is unpacked, relative to the server's directory, before
+// it's swapped in for the world.
+const RESTORE_STAGING_DIR_NAME: &str = "world.restoring";
+// Starts the name of the directory that the dimensions replaced by a restored
+// backup are moved to, which ends with when.
+const RESTORE_ASIDE_DIR_PREFIX: &str = "world-before-restore-";
+// How often to check that the Minecraft server is still running while it's
+// given its restore_check with a restored world backup.
+const RESTORE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// Matches the Minecraft server's responses to "/whitelist on" and
+// "/whitelist off".
+static WHITELIST_TOGGLE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Whitelist is (now|already) turned (on|off)").unwrap());
+
+// Matches the Minecraft server's response to the "/kick" command.
+static KICK_RESPONSE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: (Kicked |No player was found)").unwrap());
+
+// Match the Minecraft server's responses to "/save-off", "/save-all", and
+// "/save-on".
+static SAVE_OFF_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Automatic saving is now disabled|Saving is already turned off").unwrap()
+});
+static SAVED_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Saved the game").unwrap());
+static SAVE_ON_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Automatic saving is now enabled|Saving is already turned on").unwrap()
+});
+
+// The least amount of time to give the Minecraft server to save the world to
+// disk with "/save-all flush", no matter how short the command timeout is.
+const MIN_SAVE_ALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Once the Minecraft server starts responding to a command that's passed along
+// with Wrapper::run_command_capture_output(), how long it can go without
+// writing anything before its response is considered finished.
+const COMMAND_OUTPUT_QUIET_PERIOD: Duration = Duration::from_millis(250);
+
+// Matches the Minecraft server's response to the "/list" command. See
+// parse_list_response().
+static LIST_RESPONSE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"There are \d+ of a max of \d+ players online:").unwrap());
 
 pub struct Wrapper {
+    // TODO: Move these onto tokio::process, so that the server's stdin and
+    // stdout can be used from async code without a dedicated thread.
     process: process::Child,
     stdin: process::ChildStdin,
-    stdout: Receiver<String>,
+    stdout: LineReceiver,
+    // Raised when the thread that reads the server process's stdout stops, for
+    // any reason. Replaced whenever a new server process is spawned.
+    stdout_reader_exited: Arc<AtomicBool>,
     // TODO: Do we want to save stderr for anything?
-    server_jar_path: String,
-    max_memory_buffer_size: u16,
+    config: WrapperConfig,
+    // Set when somebody asks the server to stop, and cleared whenever a new
+    // server process is spawned. Lets the watchdog tell the difference between
+    // a server that crashed and one that was shut down on purpose.
+    //
+    // Shared so that callers can raise it before their request for this
+    // Wrapper waits in line, and so the watchdog can check it without one.
+    stop_requested: Arc<AtomicBool>,
+    // The result of the last "/list" command, if it's still fresh. Cleared
+    // whenever a new server process is spawned.
+    player_list_cache: Option<PlayerList>,
+    // Carries events parsed from the server's stdout to anybody who subscribes.
+    // Outlives any single server process, so subscribers keep receiving events
+    // across restarts.
+    events: broadcast::Sender<ServerEvent>,
+    // The port that the Minecraft server listens for players on, if it's known.
+    // Worked out again whenever a new server process is spawned.
+    server_port: Option<u16>,
+    // Whether the current server process is known to have finished spinning
+    // up.
+    readiness: Readiness,
+    // The warnings and errors that the current server process wrote while it
+    // was spinning up.
+    startup_warnings: Vec<String>,
+    // The most recent lines that any server process wrote, whether it was
+    // spinning up or not, along with the most recent warnings and errors.
+    // Filled in by the threads that read the server's output.
+    logs: Arc<LogBuffer>,
+    // What the current server process said about itself while it was spinning
+    // up.
+    minecraft_version: Option<String>,
+    server_flavor: Option<ServerFlavor>,
+    // When the current server process was spawned.
+    server_started_at: DateTime<Utc>,
+    // The first line of `java -version`, once it's been asked for.
+    java_version: Option<String>,
+    // How the last backup went, if one was taken since the wrapper started.
+    last_backup: Arc<Mutex<Option<BackupStatus>>>,
+    // How the last upload of a backup went, or how the one that's going right
+    // now is going.
+    uploads: Uploads,
+    // The crashes that the watchdog noticed since the wrapper started.
+    crash_history: Arc<Mutex<CrashHistory>>,
+    // Set while maintenance mode is on. Remembers whether the whitelist was
+    // already on beforehand, so that turning maintenance mode off leaves it
+    // the way it was.
+    maintenance: Option<Maintenance>,
+    // When players were last warned that they'd be kicked for a backup, if
+    // that backup hasn't started yet. See Wrapper::warn_players_before_backup().
+    drain_warned_at: Option<Instant>,
+    // When saving was frozen for an external snapshot, if it's frozen right
+    // now. Cleared whenever a new server process is spawned, since saving
+    // starts out turned on.
+    saves_frozen_at: Option<DateTime<Utc>>,
+    // Keeps operations that stop the server from running on top of each other.
+    state: StateMachine,
+    // Hides the responses to commands that the wrapper sends on its own.
+    echo_filter: Arc<EchoFilter>,
+    // The compiled patterns from `startup_prompts`, in the same order.
+    prompt_patterns: Arc<Vec<Regex>>,
+    // The compiled `stop_ready_pattern`, or the default one.
+    stop_ready_pattern: Regex,
+    // The compiled `backup_exclude` patterns.
+    backup_exclude: ExcludePatterns,
+    // How the commands that the wrapper sent on its own have fared.
+    command_stats: CommandStats,
+    // Counts the lines that were dropped from `stdout` before they were read.
+    // Outlives any single server process.
+    stdout_stats: StdoutStats,
+    // Where the Minecraft server runs and keeps its files. Resolved once, so
+    // that every feature that touches those files agrees on where they are.
+    server_dir: PathBuf,
+    // Where world backups go.
+    backup_dir: BackupDir,
+    // The connection to the Minecraft server's RCON port, once something has
+    // used it. Dropped whenever a new server process is spawned, or if
+    // something goes wrong with it.
+    rcon: Option<RconClient>,
+    // Keeps other wrappers away from the Minecraft server's directory. Dropped
+    // after the server process is killed, if it's still running then.
+    server_dir_lock: Option<ServerDirLock>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Maintenance {
+    whitelist_was_on: bool,
+}
+
+/// What the Minecraft server reports in response to the "/list" command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerList {
+    /// The number of players who are currently online.
+    pub online: usize,
+    /// The max number of players who can be online at once.
+    pub max: usize,
+    /// The names of players who are currently online.
+    pub players: Vec<String>,
 }
 
 impl Wrapper {
     /// Spawns a new Minecraft server process, blocks until that server has
     /// finished spinning up and is ready to accept commands, and returns a
     /// [Wrapper].
-    pub fn new(
-        max_memory_buffer_size: u16,
-        server_jar_path: &str,
-    ) -> Result<Wrapper, Box<dyn error::Error>> {
-        let (process, stdin, stdout_rx) =
-            spawn_server_process(max_memory_buffer_size, server_jar_path)?;
+    ///
+    /// A lockfile holding this process's pid is created in the Minecraft
+    /// server's directory first, so that two wrappers never manage the same
+    /// server. If another wrapper that's still running already holds it, a
+    /// [WrapperError::ServerDirLocked](error::WrapperError::ServerDirLocked) is
+    /// returned without starting anything. The lockfile is deleted when the
+    /// [Wrapper] is dropped, or by [Wrapper::release_server_dir_lock()].
+    pub fn new(config: WrapperConfig) -> Result<Wrapper, Box<dyn std::error::Error>> {
+        Wrapper::new_with_events(config, events::channel())
+    }
+
+    /// Like [Wrapper::new()], but sends [ServerEvent]s along the provided
+    /// channel, which can be subscribed to beforehand.
+    ///
+    /// Subscribe before calling this to follow along while the server spins
+    /// up. Until it's ready, every line that it writes is sent as a
+    /// [ServerEvent::StartupProgress].
+    pub fn new_with_events(
+        config: WrapperConfig,
+        events: broadcast::Sender<ServerEvent>,
+    ) -> Result<Wrapper, Box<dyn std::error::Error>> {
+        let server_dir = resolve_server_dir(&config);
+        let server_dir_lock = ServerDirLock::acquire(&server_dir)?;
+        let echo_filter = Arc::new(EchoFilter::default());
+        let prompt_patterns = Arc::new(compile_prompt_patterns(&config.startup_prompts)?);
+        let stop_ready_pattern = compile_stop_ready_pattern(config.stop_ready_pattern.as_deref())?;
+        let backup_exclude = ExcludePatterns::new(&server_dir, &config.backup_exclude)?;
+        let backup_dir = match &config.backup_dir {
+            Some(dir) => server_dir.join(expand_path(dir)?),
+            None => server_dir.clone(),
+        };
+        fs::create_dir_all(&backup_dir)
+            .with_context(|| format!("Failed to create the backup_dir {:?}", &backup_dir))?;
+        let backup_dir = BackupDir::new(backup_dir, &config.backup_file_name_format)?;
+        check_backup_extra_dirs(
+            &config.backup_extra_dirs,
+            &properties::world_dir(&server_dir),
+        )?;
+        if config.stdout_channel_capacity == 0 {
+            return Err(error::WrapperError::InvalidArgument(
+                "stdout_channel_capacity has to be at least 1".to_owned(),
+            )
+            .into());
+        }
+        let logs = Arc::new(LogBuffer::new(
+            config.log_buffer_lines,
+            config.log_files.clone(),
+        ));
+        let stdout_stats = StdoutStats::default();
+        let (process, stdin, stdout_rx, stdout_reader_exited) = spawn_server_process(
+            &config,
+            &server_dir,
+            events.clone(),
+            Arc::clone(&echo_filter),
+            Arc::clone(&prompt_patterns),
+            Arc::clone(&logs),
+            stdout_stats.clone(),
+        )?;
 
         let mut wrapper = Wrapper {
             process,
             stdin,
             stdout: stdout_rx,
-            server_jar_path: server_jar_path.to_owned(),
-            max_memory_buffer_size,
+            stdout_reader_exited,
+            config,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            player_list_cache: None,
+            events,
+            server_port: None,
+            readiness: Readiness::Unknown,
+            startup_warnings: Vec::new(),
+            logs,
+            minecraft_version: None,
+            server_flavor: None,
+            server_started_at: Utc::now(),
+            java_version: None,
+            last_backup: Arc::new(Mutex::new(None)),
+            uploads: Uploads::default(),
+            crash_history: Arc::new(Mutex::new(CrashHistory::default())),
+            maintenance: None,
+            drain_warned_at: None,
+            saves_frozen_at: None,
+            state: StateMachine::new(),
+            echo_filter,
+            prompt_patterns,
+            stop_ready_pattern,
+            backup_exclude,
+            command_stats: CommandStats::default(),
+            stdout_stats,
+            server_dir,
+            backup_dir,
+            rcon: None,
+            server_dir_lock: Some(server_dir_lock),
+        };
+        wrapper.server_port = wrapper.resolve_server_port();
+        wrapper.wait_for_server_to_spin_up()?;
+
+        Ok(wrapper)
+    }
+
+    /// Blocks until the Minecraft server has finished spinning up.
+    ///
+    /// If that takes longer than the startup timeout, what happens depends on
+    /// the [StartupTimeoutAction] in the [WrapperConfig].
+    ///
+    /// Returns the matching [WrapperError](error::WrapperError) right away if
+    /// the server can't start because of something that trying again won't
+    /// fix, like its world being locked, its EULA not being agreed to, its
+    /// port being in use, or its jar being broken. See [startup_failure()].
+    /// Prompts from `startup_prompts` are answered along the way, and a
+    /// [WrapperError::AwaitingInput](error::WrapperError::AwaitingInput) is
+    /// returned for ones that don't have a response.
+    ///
+    /// Lines that the server logs at the WARN or ERROR level along the way are
+    /// collected into [Wrapper::startup_warnings()].
+    fn wait_for_server_to_spin_up(&mut self) -> anyhow::Result<()> {
+        self.readiness = Readiness::Unknown;
+        self.startup_warnings.clear();
+        self.minecraft_version = None;
+        self.server_flavor = None;
+        let mut patterns = vec![
+            format!("(?:{})", STARTUP_OUTCOME_PATTERN.as_str()),
+            format!("(?:{})", WARNING_PATTERN.as_str()),
+            format!("(?:{})", flavor::IDENTITY_PATTERN.as_str()),
+        ];
+        patterns.extend(
+            self.prompt_patterns
+                .iter()
+                .map(|pattern| format!("(?:{})", pattern.as_str())),
+        );
+        let startup_pattern = Regex::new(&patterns.join("|"))?;
+
+        let deadline = Instant::now() + self.config.startup_timeout;
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let line = match self.wait_for_line(&startup_pattern, remaining) {
+                Ok(line) => line,
+                Err(e) => {
+                    // Anything other than a timeout means that the server
+                    // closed stdout, which it only does when it exits.
+                    let exited = !matches!(
+                        e.downcast_ref(),
+                        Some(error::WrapperError::OutputTimedOut { .. })
+                    );
+                    if exited {
+                        if let Some(failure) = self.leftover_stderr_failure() {
+                            return Err(failure.into());
+                        }
+                    }
+                    break Err(e);
+                }
+            };
+            if let Some(failure) = startup_failure(&line) {
+                // The server gives up on its own, but make sure that it's
+                // really gone.
+                if let Err(e) = self.kill_server() {
+                    warn!("Failed to kill the Minecraft server process: {}", e);
+                }
+                return Err(failure.into());
+            }
+            if SERVER_READY_PATTERN.is_match(&line) {
+                self.server_flavor.get_or_insert(ServerFlavor::Vanilla);
+                break Ok(());
+            }
+            if let Some(version) = flavor::parse_minecraft_version(&line) {
+                self.minecraft_version = Some(version);
+            }
+            if let Some(flavor) = ServerFlavor::detect(&line) {
+                self.server_flavor = Some(flavor);
+            }
+            if WARNING_PATTERN.is_match(&line) && self.startup_warnings.len() < MAX_STARTUP_WARNINGS
+            {
+                self.startup_warnings.push(line.clone());
+            }
+            if self.prompt_patterns.iter().any(|p| p.is_match(&line)) {
+                self.answer_startup_prompt(&line)?;
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.readiness = Readiness::Ready;
+                Ok(())
+            }
+            Err(e)
+                if self.config.startup_timeout_action == StartupTimeoutAction::ProceedAnyway
+                    && matches!(
+                        e.downcast_ref(),
+                        Some(error::WrapperError::OutputTimedOut { .. })
+                    ) =>
+            {
+                warn!(
+                    "The Minecraft server didn't announce that it finished spinning up within {}s. Carrying on anyways",
+                    self.config.startup_timeout.as_secs()
+                );
+                Ok(())
+            }
+            Err(e) => Err(e.context("The Minecraft server didn't finish spinning up")),
+        }
+    }
+
+    /// Returns what kept a Minecraft server process that exited while it was
+    /// starting from starting, according to what it wrote to stderr, like Java
+    /// not being able to run its jar. Only does anything when stderr isn't read
+    /// as logs, since otherwise those lines were already checked.
+    fn leftover_stderr_failure(&mut self) -> Option<error::WrapperError> {
+        let stderr = self.process.stderr.take()?;
+
+        // Something that the server spawned could keep stderr open after it
+        // exited, so don't wait on it forever.
+        let (lines_tx, lines_rx) = mpsc::channel();
+        thread::spawn(move || send_lines(io::BufReader::new(stderr), lines_tx));
+        let deadline = Instant::now() + LEFTOVER_STDERR_TIMEOUT;
+        while let Ok(line) =
+            lines_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if let Some(failure) = startup_failure(&line) {
+                return Some(failure);
+            }
+        }
+        None
+    }
+
+    /// Answers the prompt from `startup_prompts` that the provided line
+    /// matches.
+    ///
+    /// If the prompt doesn't have a response, the server is killed, since it
+    /// would wait for an answer forever, and a
+    /// [WrapperError::AwaitingInput](error::WrapperError::AwaitingInput) is
+    /// returned.
+    fn answer_startup_prompt(&mut self, line: &str) -> anyhow::Result<()> {
+        let prompt = self
+            .prompt_patterns
+            .iter()
+            .position(|pattern| pattern.is_match(line))
+            .map(|i| &self.config.startup_prompts[i]);
+        let response = match prompt.and_then(|prompt| prompt.response.clone()) {
+            Some(response) => response,
+            None => {
+                if let Err(e) = self.kill_server() {
+                    warn!("Failed to kill the Minecraft server process: {}", e);
+                }
+                return Err(error::WrapperError::AwaitingInput(line.trim().to_owned()).into());
+            }
+        };
+
+        info!(
+            "Answering the Minecraft server's prompt {:?} with {:?}",
+            line.trim(),
+            &response
+        );
+        // Don't go through run_custom_command(), which would throw away lines
+        // that the server wrote in the meantime, like the one saying that it's
+        // ready.
+        self.write_to_stdin(format!("{}\n", response).as_bytes())
+            .with_context(|| "Failed to answer the Minecraft server's prompt")
+    }
+
+    /// Returns whether the Minecraft server is known to have finished spinning
+    /// up.
+    pub fn readiness(&self) -> Readiness {
+        self.readiness
+    }
+
+    /// Returns the lines that the current Minecraft server process logged at
+    /// the WARN or ERROR level while it was spinning up, like ones about
+    /// deprecated settings or data packs that couldn't be loaded. Only the
+    /// first couple hundred are kept.
+    ///
+    /// Lines that come in after the startup timeout ran out aren't included,
+    /// even if the server carried on anyways.
+    pub fn startup_warnings(&self) -> &[String] {
+        &self.startup_warnings
+    }
+
+    /// Returns the most recent lines that the Minecraft server logged at the
+    /// WARN or ERROR level, oldest first, across every server process since
+    /// the wrapper started.
+    pub fn recent_warnings(&self) -> Vec<String> {
+        self.logs.recent_warnings()
+    }
+
+    /// Returns the most recent lines that the Minecraft server wrote, oldest
+    /// first, across every server process since the wrapper started. Only the
+    /// last `log_buffer_lines` from the [WrapperConfig] are kept.
+    ///
+    /// `tail` limits how many lines are returned, `since` leaves out the ones
+    /// that were read before then, and `min_level` leaves out the ones that
+    /// were logged below it. Each filter that's [None] is left out.
+    pub fn recent_logs(
+        &self,
+        tail: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        min_level: Option<LogLevel>,
+    ) -> Vec<LogLine> {
+        self.logs.lines(tail, since, min_level)
+    }
+
+    /// Returns the version of Minecraft that the current server process said
+    /// it's running while it was spinning up, like "1.20.1".
+    pub fn minecraft_version(&self) -> Option<&str> {
+        self.minecraft_version.as_deref()
+    }
+
+    /// Returns which kind of Minecraft server the current server process is,
+    /// or [None] if it hasn't finished spinning up, and hasn't said yet.
+    pub fn server_flavor(&self) -> Option<ServerFlavor> {
+        self.server_flavor
+    }
+
+    /// Returns when the current Minecraft server process was spawned.
+    pub fn server_started_at(&self) -> DateTime<Utc> {
+        self.server_started_at
+    }
+
+    /// Returns the first line of what `java -version` prints, like
+    /// `openjdk version "17.0.8" 2023-07-18`. Java is only asked once, and the
+    /// answer is remembered after that.
+    pub fn java_version(&mut self) -> anyhow::Result<String> {
+        if let Some(java_version) = &self.java_version {
+            return Ok(java_version.clone());
+        }
+        let output = java_command(&self.config)
+            .arg("-version")
+            .stdin(process::Stdio::null())
+            .output()
+            .with_context(|| "Failed to run java -version")?;
+        // Java prints its version to stderr.
+        let java_version = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .next()
+            .map(str::to_owned)
+            .ok_or(anyhow!("java -version didn't print anything"))?;
+        Ok(self.java_version.insert(java_version).clone())
+    }
+
+    /// Returns how the last backup went, or [None] if there hasn't been one
+    /// since the wrapper started.
+    pub fn last_backup(&self) -> Option<BackupStatus> {
+        self.last_backup.lock().unwrap().clone()
+    }
+
+    /// Returns how the last upload of a world backup to the [WrapperConfig]'s
+    /// `backup_upload` or `backup_upload_ssh` went, or how the one that's
+    /// going right now is going, or [None] if there hasn't been one since the
+    /// wrapper started.
+    pub fn last_upload(&self) -> Option<UploadStatus> {
+        self.uploads.last()
+    }
+
+    /// Returns a handle for keeping up with uploads of world backups to the
+    /// [WrapperConfig]'s `backup_upload` and `backup_upload_ssh` without going
+    /// through the [Wrapper].
+    pub fn uploads(&self) -> Uploads {
+        self.uploads.clone()
+    }
+
+    /// Returns how new world backups are compressed, and whether they're
+    /// encrypted.
+    pub fn backup_format(&self) -> BackupFormat {
+        BackupFormat {
+            compression: self.config.backup_compression,
+            encrypted: self.config.backup_encryption.is_some(),
+        }
+    }
+
+    /// Returns the world backups in the `backup_dir`, newest first.
+    pub fn list_backups(&self) -> anyhow::Result<Vec<BackupFile>> {
+        backup_files::list(&self.backup_dir)
+    }
+
+    /// Returns the path to the world backup with the provided ID, or a
+    /// [WrapperError::BackupNotFound](error::WrapperError::BackupNotFound) if
+    /// there isn't one.
+    pub fn backup_path(&self, id: &str) -> anyhow::Result<PathBuf> {
+        backup_files::path_of(&self.backup_dir, id)
+    }
+
+    /// Deletes the world backup with the provided ID, along with its checksum,
+    /// and drops it from the `backup_dir`'s manifest.
+    /// Returns a [WrapperError::BackupNotFound](error::WrapperError::BackupNotFound)
+    /// if there isn't one, and a
+    /// [WrapperError::BackupInUse](error::WrapperError::BackupInUse) if an
+    /// incremental backup is based on it.
+    pub fn delete_backup(&self, id: &str) -> anyhow::Result<()> {
+        let path = self.backup_path(id)?;
+        let dependents = incremental::dependents(&self.backup_dir, id)?;
+        if !dependents.is_empty() {
+            return Err(error::WrapperError::BackupInUse {
+                id: id.to_owned(),
+                dependents,
+            }
+            .into());
+        }
+        backup_files::delete(&path)
+            .with_context(|| format!("Failed to delete the world backup at {:?}", &path))
+    }
+
+    /// Checks that the world backup with the provided ID can be restored, and
+    /// returns how it went, which shows up in [Wrapper::list_backups()] from
+    /// then on. Its checksum has to match the one that was written next to it
+    /// when it was made, and it has to unpack cleanly into a directory next to
+    /// it, which is deleted afterwards. Only that backup is checked, not the
+    /// ones that it's based on if it's an incremental backup.
+    ///
+    /// Returns a [WrapperError::BackupNotFound](error::WrapperError::BackupNotFound)
+    /// if there isn't one, and a
+    /// [WrapperError::InsufficientDiskSpace](error::WrapperError::InsufficientDiskSpace)
+    /// if there isn't room to unpack it.
+    pub fn verify_backup(&self, id: &str) -> anyhow::Result<BackupVerification> {
+        let path = self.backup_path(id)?;
+        verification::verify(
+            &path,
+            self.config.backup_encryption.as_ref(),
+            self.config.min_free_space_bytes,
+        )
+    }
+
+    /// Replaces the world with the world backup with the provided ID, and
+    /// returns what was replaced. When `dry_run` is set, nothing is touched,
+    /// and only what would happen is returned.
+    ///
+    /// Only the dimensions that are in the backup are replaced. Instead of
+    /// being deleted, the world's directories for them are moved into a new
+    /// directory next to it. See [RestorePlan::moved_aside_to].
+    ///
+    /// Restoring an incremental backup unpacks every backup in its chain, from
+    /// the full backup that it starts with, and then deletes the files that
+    /// weren't in the world anymore when it was made.
+    ///
+    /// The backup is unpacked next to the world before the Minecraft server is
+    /// stopped, so that it's down for as short as possible. Then the server is
+    /// stopped, the directories are swapped, and the server is started back
+    /// up. If swapping them fails partway through, the ones that were already
+    /// moved are put back, and the server is started back up with the world
+    /// the way it was.
+    ///
+    /// The same goes if the server doesn't spin up with the restored world, or
+    /// exits within `restore_check` of spinning up. Then, the restored world is
+    /// deleted, what it replaced is moved back, and a
+    /// [WrapperError::RestoredWorldFailedToStart](error::WrapperError::RestoredWorldFailedToStart)
+    /// is returned.
+    ///
+    /// Returns a [WrapperError::BackupNotFound](error::WrapperError::BackupNotFound)
+    /// if there isn't a backup with that ID, a
+    /// [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// if it has anything that would end up outside of the world directory,
+    /// and a [WrapperError::InsufficientDiskSpace](error::WrapperError::InsufficientDiskSpace)
+    /// if there isn't room to unpack it. The server is left running in those
+    /// cases.
+    pub fn restore_backup(&mut self, id: &str, dry_run: bool) -> anyhow::Result<RestorePlan> {
+        let chain = incremental::chain(&self.backup_dir, id)?;
+        let encryption = self.config.backup_encryption.as_ref();
+        let mut contents = None;
+        let mut unpacked_bytes: u64 = 0;
+        for (tarball_path, _) in &chain {
+            let tarball_contents = backup::inspect_tarball(tarball_path, encryption)?;
+            unpacked_bytes += tarball_contents.unpacked_bytes;
+            contents = Some(tarball_contents);
+        }
+        let target_snapshot = chain.last().and_then(|(_, snapshot)| snapshot.as_ref());
+        let contents = match (target_snapshot, contents) {
+            (Some(snapshot), _) if chain.len() > 1 => incremental::contents(snapshot),
+            (_, Some(contents)) => contents,
+            (_, None) => return Err(error::WrapperError::BackupNotFound(id.to_owned()).into()),
+        };
+        if contents.dimensions.is_empty() && contents.extra_dirs.is_empty() {
+            return Err(error::WrapperError::InvalidArgument(format!(
+                "The world backup {:?} is empty",
+                id
+            ))
+            .into());
+        }
+        let aside_dir_name = format!(
+            "{}{}",
+            RESTORE_ASIDE_DIR_PREFIX,
+            Utc::now().format("%Y-%m-%d_%H-%M-%S")
+        );
+        let plan = RestorePlan {
+            id: id.to_owned(),
+            dimensions: contents.dimensions.iter().copied().collect(),
+            extra_dirs: contents.extra_dirs.iter().cloned().collect(),
+            files: contents.files,
+            unpacked_bytes: contents.unpacked_bytes,
+            moved_aside_to: aside_dir_name.clone(),
+            dry_run,
+        };
+        if dry_run {
+            return Ok(plan);
+        }
+
+        match backup::available_space(&self.server_dir) {
+            // Every backup in the chain is unpacked on top of the one before.
+            Some(available) => backup::check_free_space(
+                unpacked_bytes,
+                available,
+                self.config.min_free_space_bytes,
+            )?,
+            None => warn!(
+                "Couldn't tell how much free disk space there is in {:?}. Restoring a backup anyways",
+                &self.server_dir
+            ),
+        }
+        let staging_dir = self.server_dir.join(RESTORE_STAGING_DIR_NAME);
+        // Left behind by a restore that the wrapper didn't get to finish.
+        remove_staging_dir(&staging_dir);
+        let unpacked = chain
+            .iter()
+            .try_for_each(|(tarball_path, _)| {
+                backup::unpack_tarball(tarball_path, &staging_dir, encryption)
+            })
+            .and_then(|()| match target_snapshot {
+                Some(snapshot) if chain.len() > 1 => {
+                    incremental::remove_unlisted_files(&staging_dir, snapshot)
+                }
+                _ => Ok(()),
+            });
+        if let Err(e) = unpacked {
+            remove_staging_dir(&staging_dir);
+            return Err(e);
+        }
+        if let Err(e) = self.stop_server() {
+            remove_staging_dir(&staging_dir);
+            return Err(e);
+        }
+
+        let world_dir = self.world_dir();
+        let aside_dir = self.server_dir.join(&aside_dir_name);
+        let swapped = fs::create_dir_all(&aside_dir)
+            .with_context(|| format!("Failed to create {:?}", &aside_dir))
+            .and_then(|()| {
+                restore_moves(
+                    &self.server_dir,
+                    &world_dir,
+                    &staging_dir,
+                    &aside_dir,
+                    &contents,
+                )
+            })
+            .and_then(|moves| rename_all(&moves).map(|()| moves));
+        // Emptied out once the extra directories were moved into place.
+        let _ = fs::remove_dir(world_dir.join(dimension::EXTRA_DIRS_ARCHIVE_DIR));
+        let moves = match swapped {
+            Ok(moves) => moves,
+            Err(e) => {
+                remove_staging_dir(&staging_dir);
+                // Only removed if nothing was left in it.
+                let _ = fs::remove_dir(&aside_dir);
+                if let Err(e) = self.spawn_new_server_process() {
+                    warn!("Failed to start the Minecraft server back up: {:#}", e);
+                }
+                return Err(e.context(
+                    "Failed to swap the world backup in, so the world was left the way it was",
+                ));
+            }
+        };
+
+        let started = self
+            .spawn_new_server_process()
+            .and_then(|()| self.ensure_server_stays_up(self.config.restore_check));
+        if let Err(e) = started {
+            return Err(self.roll_back_restore(id, &moves, &staging_dir, &aside_dir, e));
+        }
+        Ok(plan)
+    }
+
+    /// Returns an error if the Minecraft server process exits within
+    /// `duration`. Blocks until then.
+    fn ensure_server_stays_up(&mut self, duration: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            if let Some(exit_status) = self.exit_status()? {
+                bail!(
+                    "The Minecraft server process exited within {}s of spinning up, with {}",
+                    duration.as_secs(),
+                    exit_status
+                );
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(remaining.min(RESTORE_CHECK_INTERVAL));
+        }
+    }
+
+    /// Puts the world back the way it was before the world backup with the
+    /// provided ID was swapped in with `moves`, since the Minecraft server
+    /// didn't start with it because of `cause`, and starts the server back up.
+    /// Returns the error to report.
+    ///
+    /// The restored world ends up back in `staging_dir`, and is deleted.
+    fn roll_back_restore(
+        &mut self,
+        id: &str,
+        moves: &[(PathBuf, PathBuf)],
+        staging_dir: &Path,
+        aside_dir: &Path,
+        cause: anyhow::Error,
+    ) -> anyhow::Error {
+        error!(
+            "The Minecraft server didn't start with the world backup {:?}, so putting the world back the way it was: {:#}",
+            id, cause
+        );
+        let failed_to_start = error::WrapperError::RestoredWorldFailedToStart {
+            id: id.to_owned(),
+            error: format!("{:#}", cause),
+        };
+        if let Err(e) = self.kill_server() {
+            warn!("Failed to kill the Minecraft server process: {}", e);
+        }
+        let undo: Vec<(PathBuf, PathBuf)> = moves
+            .iter()
+            .rev()
+            .map(|(from, to)| (to.clone(), from.clone()))
+            .collect();
+        if let Err(e) = rename_all(&undo) {
+            // Whatever was moved back was put back into place again, so the
+            // restored world is still there.
+            return anyhow::Error::from(failed_to_start).context(format!(
+                "Failed to put the world back the way it was, so the restored world was left in place. What it replaced is in {:?}: {:#}",
+                aside_dir, e
+            ));
+        }
+        remove_staging_dir(staging_dir);
+        // Only removed if nothing was left in it.
+        let _ = fs::remove_dir(aside_dir);
+        if let Err(e) = self.spawn_new_server_process() {
+            return anyhow::Error::from(failed_to_start).context(format!(
+                "The world was put back the way it was, but the Minecraft server didn't start with it either: {:#}",
+                e
+            ));
+        }
+        failed_to_start.into()
+    }
+
+    /// Returns the newest crash report in the server's `crash-reports/`
+    /// directory, cut down to its first few lines, or [None] if there aren't
+    /// any.
+    pub fn last_crash_report(&self) -> anyhow::Result<Option<CrashReport>> {
+        let dir = self.server_dir.join(CRASH_REPORTS_DIR_NAME);
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let mut newest: Option<(std::time::SystemTime, PathBuf)> = None;
+        for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read {:?}", &dir))? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            if newest.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+                newest = Some((modified, entry.path()));
+            }
+        }
+        let (modified, path) = match newest {
+            Some(newest) => newest,
+            None => return Ok(None),
+        };
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", &path))?;
+        Ok(Some(CrashReport {
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            modified_at: DateTime::<Utc>::from(modified).to_rfc3339(),
+            head: contents
+                .lines()
+                .take(CRASH_REPORT_HEAD_LINES)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }))
+    }
+
+    /// Blocks until the Minecraft server writes a line to stdout that matches
+    /// the provided pattern, and returns that line. Lines that don't match are
+    /// discarded, and so are lines that could be quoting a player, like chat
+    /// messages, even if they match. See [events::quotes_players()].
+    ///
+    /// Returns an error if no matching line comes in within `timeout`.
+    fn wait_for_line(&mut self, pattern: &Regex, timeout: Duration) -> anyhow::Result<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.stdout.recv_timeout(remaining) {
+                Ok(line) if pattern.is_match(&line) && !events::quotes_players(&line) => {
+                    return Ok(line)
+                }
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(error::WrapperError::OutputTimedOut {
+                        pattern: pattern.as_str().to_owned(),
+                        timeout,
+                    }
+                    .into())
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    bail!("The stdout channel was closed unexpectedly")
+                }
+            }
+        }
+    }
+
+    /// Returns a receiver for events that happen on the Minecraft server, like
+    /// players joining, chatting, and dying. See [ServerEvent].
+    ///
+    /// Every receiver gets every event, so any number of consumers can listen
+    /// at once.
+    ///
+    /// Only events that happen after this is called are received. If a
+    /// subscriber falls too far behind, it misses the oldest events it hadn't
+    /// received yet.
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a receiver for every line that the Minecraft server writes from
+    /// now on, just like they're printed in the wrapper's terminal. With
+    /// `suppress_command_echo` on, that leaves out the responses to commands
+    /// that the wrapper sends on its own. Lines are still received while the
+    /// console is paused.
+    ///
+    /// Every receiver gets every line, and keeps receiving them across
+    /// restarts. If a subscriber falls too far behind, it misses the oldest
+    /// lines it hadn't received yet.
+    pub fn subscribe_console(&self) -> broadcast::Receiver<String> {
+        self.echo_filter.subscribe_console()
+    }
+
+    /// Returns the names of players who are currently logged in and playing on
+    /// the server.
+    pub fn list_players(&mut self) -> anyhow::Result<Vec<String>> {
+        Ok(self.refresh_player_list()?.players.clone())
+    }
+
+    /// Returns the max number of players who can be online at once, as reported
+    /// by the "/list" command.
+    ///
+    /// This doesn't depend on being able to read `server.properties`, and it
+    /// stays correct even if something overrides the value in that file. The
+    /// answer is cached until the next time the player list is refreshed.
+    pub fn max_players(&mut self) -> anyhow::Result<usize> {
+        if let Some(player_list) = &self.player_list_cache {
+            return Ok(player_list.max);
+        }
+        Ok(self.refresh_player_list()?.max)
+    }
+
+    /// Checks that the Minecraft server still responds to commands, by running
+    /// the "/list" command. Returns how long it took to respond.
+    ///
+    /// A server whose process is running, but that's deadlocked or hopelessly
+    /// behind, fails this even though nothing else looks wrong.
+    pub fn probe(&mut self) -> anyhow::Result<Duration> {
+        let started = Instant::now();
+        self.refresh_player_list()?;
+        Ok(started.elapsed())
+    }
+
+    /// Runs the "/list" command, caches its result, and returns it.
+    fn refresh_player_list(&mut self) -> anyhow::Result<&PlayerList> {
+        // Will look something like this:
+        // [16:14:22] [Server thread/INFO]: There are 2 of a max of 20 players online: player1, player2
+        let response = self
+            .run_command_capture("/list", &LIST_RESPONSE_PATTERN, true)
+            .with_context(|| {
+                "Something went wrong while sending the Minecraft server the \"/list\" command"
+            })?;
+
+        let player_list = self.check_response("/list", parse_list_response(&response))?;
+        Ok(self.player_list_cache.insert(player_list))
+    }
+
+    /// Turns maintenance mode on or off. Returns the names of the players who
+    /// were kicked.
+    ///
+    /// Maintenance mode keeps everybody but operators off of the server
+    /// without stopping it. When it's turned on, the whitelist is turned on,
+    /// and every player who's online and isn't an operator is kicked with the
+    /// maintenance message. When it's turned off, the whitelist is turned back
+    /// off, unless it was already on beforehand.
+    ///
+    /// This relies on the whitelist, so whitelisted players who aren't
+    /// operators can still join while maintenance mode is on.
+    pub fn set_maintenance_mode(&mut self, on: bool) -> anyhow::Result<Vec<String>> {
+        if !on {
+            if let Some(maintenance) = self.maintenance {
+                if !maintenance.whitelist_was_on {
+                    self.run_command_capture("/whitelist off", &WHITELIST_TOGGLE_PATTERN, false)
+                        .with_context(|| "Failed to turn the whitelist back off")?;
+                }
+                self.maintenance = None;
+            }
+            return Ok(Vec::new());
+        }
+
+        let kick_message = self.config.maintenance_message.clone();
+        self.turn_on_maintenance_mode(&kick_message)
+    }
+
+    /// Turns maintenance mode on, and kicks every player who isn't an operator
+    /// with the provided message. Returns the names of the players who were
+    /// kicked.
+    fn turn_on_maintenance_mode(&mut self, kick_message: &str) -> anyhow::Result<Vec<String>> {
+        if self.maintenance.is_none() {
+            let response = self
+                .run_command_capture("/whitelist on", &WHITELIST_TOGGLE_PATTERN, false)
+                .with_context(|| "Failed to turn the whitelist on")?;
+            self.maintenance = Some(Maintenance {
+                whitelist_was_on: response.contains("already"),
+            });
+        }
+
+        let server_dir = self.server_dir();
+        let ops = if server_dir.join(ops::OPS_FILE_NAME).exists() {
+            ops::read_op_names(&server_dir)?
+        } else {
+            Default::default()
         };
-        wrapper.wait_for_server_to_spin_up();
 
-        Ok(wrapper)
+        let mut kicked = Vec::new();
+        for player in self.list_players()? {
+            // Minecraft usernames are case-insensitive.
+            if ops.iter().any(|op| op.eq_ignore_ascii_case(&player)) {
+                continue;
+            }
+            self.kick(&player, kick_message)?;
+            kicked.push(player);
+        }
+        Ok(kicked)
+    }
+
+    /// Kicks the provided player off of the server with the provided message.
+    fn kick(&mut self, player: &str, message: &str) -> anyhow::Result<()> {
+        let cmd = format!("/kick {} {}", player, message);
+        self.run_command_capture(&cmd, &KICK_RESPONSE_PATTERN, false)
+            .with_context(|| format!("Failed to kick {}", player))?;
+        Ok(())
+    }
+
+    /// Returns true if maintenance mode is on.
+    pub fn maintenance_mode(&self) -> bool {
+        self.maintenance.is_some()
+    }
+
+    /// Returns every operator in the server's `ops.json` file, along with their
+    /// permission levels. Returns an empty list if that file doesn't exist
+    /// yet.
+    pub fn ops(&self) -> anyhow::Result<Vec<Op>> {
+        let server_dir = self.server_dir();
+        if !server_dir.join(ops::OPS_FILE_NAME).exists() {
+            return Ok(Vec::new());
+        }
+        ops::read_ops(&server_dir)
+    }
+
+    /// Returns every IP address ban in the server's `banned-ips.json` file.
+    /// Returns an empty list if that file doesn't exist yet.
+    pub fn read_banned_ips(&self) -> anyhow::Result<Vec<IpBan>> {
+        let server_dir = self.server_dir();
+        if !server_dir.join(bans::BANNED_IPS_FILE_NAME).exists() {
+            return Ok(Vec::new());
+        }
+        bans::read_banned_ips(&server_dir)
+    }
+
+    /// Makes the provided player an operator with the provided permission
+    /// level, from [MIN_OP_LEVEL](ops::MIN_OP_LEVEL) to
+    /// [MAX_OP_LEVEL](ops::MAX_OP_LEVEL).
+    ///
+    /// Vanilla servers don't have a command that takes a permission level, and
+    /// they can't reload `ops.json` while they're running. So the player is
+    /// opped with "/op" first, which gives them the `op-permission-level` from
+    /// `server.properties` right away, and then their level is changed in
+    /// `ops.json`. The new level takes effect the next time the server starts.
+    ///
+    /// Returns a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// without running anything if the name or level isn't valid, and a
+    /// [WrapperError::PlayerNotFound](error::WrapperError::PlayerNotFound) if
+    /// the server doesn't know about a player with that name. Returns
+    /// [CommandOutcome::NoChange] if they were already an operator with that
+    /// level.
+    pub fn op_with_level(&mut self, name: &str, level: u8) -> anyhow::Result<CommandOutcome> {
+        ops::validate_player_name(name)?;
+        ops::validate_level(level)?;
+
+        let cmd = format!("/op {}", name);
+        let response = self
+            .run_command_capture(&cmd, &ops::OP_RESPONSE_PATTERN, false)
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    &cmd
+                )
+            })?;
+        let outcome = self.check_response(&cmd, ops::check_op_response(&cmd, name, &response))?;
+
+        let level_changed = ops::write_level(&self.server_dir, name, level)?;
+        if level_changed {
+            Ok(CommandOutcome::Changed)
+        } else {
+            Ok(outcome)
+        }
+    }
+
+    /// Returns the data packs that are enabled on the Minecraft server, and the
+    /// ones that are available to enable.
+    pub fn list_datapacks(&mut self) -> anyhow::Result<Datapacks> {
+        // The server writes one line about enabled data packs, and another
+        // about available ones.
+        for _ in 0..2 {
+            self.expect_response(
+                &datapacks::DATAPACK_LIST_PATTERN,
+                self.config.command_timeout,
+            );
+        }
+        let result = self.read_datapack_list();
+        self.command_stats.record("/datapack list", &result);
+        result
+    }
+
+    /// Sends the "/datapack list" command, and parses both lines of the
+    /// Minecraft server's response.
+    fn read_datapack_list(&mut self) -> anyhow::Result<Datapacks> {
+        self.run_custom_command("/datapack list")?;
+
+        let mut datapacks = Datapacks::default();
+        for _ in 0..2 {
+            let line = self
+                .wait_for_line(
+                    &datapacks::DATAPACK_LIST_PATTERN,
+                    self.config.command_timeout,
+                )
+                .with_context(|| {
+                    "Something went wrong while sending the Minecraft server the \"/datapack list\" command"
+                })?;
+            datapacks::parse_list_line(&line, &mut datapacks)?;
+        }
+        Ok(datapacks)
+    }
+
+    /// Enables the provided data pack.
+    ///
+    /// Returns a [WrapperError::DatapackNotFound](error::WrapperError::DatapackNotFound)
+    /// if the server doesn't know about a data pack with that name, and
+    /// [CommandOutcome::NoChange] if it's already enabled.
+    pub fn enable_datapack(&mut self, name: &str) -> anyhow::Result<CommandOutcome> {
+        self.toggle_datapack("enable", name)
+    }
+
+    /// Disables the provided data pack.
+    ///
+    /// Returns a [WrapperError::DatapackNotFound](error::WrapperError::DatapackNotFound)
+    /// if the server doesn't know about a data pack with that name, and
+    /// [CommandOutcome::NoChange] if it's already disabled.
+    pub fn disable_datapack(&mut self, name: &str) -> anyhow::Result<CommandOutcome> {
+        self.toggle_datapack("disable", name)
+    }
+
+    fn toggle_datapack(&mut self, action: &str, name: &str) -> anyhow::Result<CommandOutcome> {
+        let cmd = format!("/datapack {} {}", action, datapacks::quote_name(name)?);
+        let response = self
+            .run_command_capture(&cmd, &datapacks::DATAPACK_TOGGLE_PATTERN, false)
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    &cmd
+                )
+            })?;
+        self.check_response(
+            &cmd,
+            datapacks::check_toggle_response(&cmd, name, &response),
+        )
+    }
+
+    /// Reloads the Minecraft server's data packs, loot tables, advancements,
+    /// and functions.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        self.run_command_capture("/reload", &datapacks::RELOAD_PATTERN, false)
+            .with_context(|| {
+                "Something went wrong while sending the Minecraft server the \"/reload\" command"
+            })?;
+        Ok(())
+    }
+
+    /// Changes or lists the chunks that the Minecraft server keeps loaded at
+    /// all times, and returns what the server reported.
+    ///
+    /// Returns a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// without running anything if the server would reject the action's
+    /// coordinates.
+    pub fn forceload(&mut self, action: ForceloadAction) -> anyhow::Result<ForceloadResponse> {
+        action.validate()?;
+
+        let cmd = action.command();
+        let response = self
+            .run_command_capture(
+                &cmd,
+                &forceload::FORCELOAD_RESPONSE_PATTERN,
+                action == ForceloadAction::Query,
+            )
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    &cmd
+                )
+            })?;
+
+        self.check_response(&cmd, forceload::parse_forceload_response(&response))
+    }
+
+    /// Asks the Minecraft server how busy it is, with the best commands that
+    /// its flavor has. Every flavor reports how many entities there are, and
+    /// Paper-based servers report how many chunks are loaded, too.
+    ///
+    /// Returns a [WrapperError::NotSupported](error::WrapperError::NotSupported)
+    /// if the server's flavor isn't known, like when it didn't announce that
+    /// it's ready before the startup timeout ran out.
+    pub fn performance_snapshot(&mut self) -> anyhow::Result<PerformanceSnapshot> {
+        let flavor = self.server_flavor.ok_or_else(|| {
+            error::WrapperError::NotSupported(
+                "Performance snapshots need to know which kind of Minecraft server is running, and this one didn't say while it was spinning up".to_owned(),
+            )
+        })?;
+
+        let cmd = performance::ENTITY_COUNT_COMMAND;
+        let response = self
+            .run_command_capture(cmd, &performance::ENTITY_COUNT_RESPONSE_PATTERN, true)
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    cmd
+                )
+            })?;
+        let entities =
+            self.check_response(cmd, performance::parse_entity_count_response(&response))?;
+
+        // Output that changes between versions shouldn't sink the whole
+        // snapshot.
+        let loaded_chunks = if performance::reports_loaded_chunks(flavor) {
+            self.loaded_chunks()
+                .map_err(|e| warn!("Failed to count the loaded chunks: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        Ok(PerformanceSnapshot {
+            flavor,
+            entities,
+            loaded_chunks,
+        })
+    }
+
+    /// Asks a Paper-based Minecraft server how many chunks it has loaded in
+    /// the overworld.
+    fn loaded_chunks(&mut self) -> anyhow::Result<usize> {
+        let level_name = self
+            .server_properties()
+            .map(|properties| properties.level_name().to_owned())
+            .unwrap_or_else(|_| "world".to_owned());
+        let cmd = format!("/paper chunkinfo {}", level_name);
+        let response = self
+            .run_command_capture(&cmd, &performance::CHUNK_INFO_RESPONSE_PATTERN, true)
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    &cmd
+                )
+            })?;
+        self.check_response(&cmd, performance::parse_chunk_info_response(&response))
+    }
+
+    /// Asks the Minecraft server what time it is in the world, with
+    /// "/time query daytime", "/time query gametime", and "/time query day".
+    ///
+    /// All three respond with the same kind of line, so they're sent one at a
+    /// time, and each one's response is waited for before the next is sent.
+    pub fn get_time(&mut self) -> anyhow::Result<GameTime> {
+        Ok(GameTime {
+            daytime: self.query_time("daytime")?,
+            gametime: self.query_time("gametime")?,
+            day: self.query_time("day")?,
+        })
+    }
+
+    /// Sends "/time query <query>", and returns the number of ticks that the
+    /// Minecraft server responds with.
+    fn query_time(&mut self, query: &str) -> anyhow::Result<u64> {
+        let cmd = format!("/time query {}", query);
+        let response = self
+            .run_command_capture(&cmd, &game_time::TIME_QUERY_RESPONSE_PATTERN, true)
+            .with_context(|| {
+                format!(
+                    "Something went wrong while sending the Minecraft server the {:?} command",
+                    &cmd
+                )
+            })?;
+        self.check_response(&cmd, game_time::parse_time_query_response(&response))
+    }
+
+    /// Returns the port that the Minecraft server listens for players on, if
+    /// it's known.
+    pub fn server_port(&self) -> Option<u16> {
+        self.server_port
+    }
+
+    /// Works out which port the Minecraft server listens for players on: the
+    /// one set in the [WrapperConfig] if there is one, or else the one in
+    /// `server.properties`.
+    fn resolve_server_port(&self) -> Option<u16> {
+        self.server_probe().server_port()
+    }
+
+    /// Returns a [ServerProbe] for pinging and querying the Minecraft server
+    /// without going through this [Wrapper], so that it still answers while
+    /// the wrapper is busy.
+    pub fn server_probe(&self) -> ServerProbe {
+        ServerProbe::new(
+            &self.server_dir,
+            self.config.server_port,
+            self.config.command_timeout,
+        )
+    }
+
+    /// Returns the directory that the Minecraft server saves the world in,
+    /// going by the `level-name` in `server.properties`. See
+    /// [properties::world_dir()].
+    ///
+    /// It's looked up again every time, since `server.properties` might have
+    /// been changed since the server started. The server picks up the change
+    /// the next time it starts, and so do backups and restores.
+    pub fn world_dir(&self) -> PathBuf {
+        properties::world_dir(&self.server_dir)
+    }
+
+    /// Reads and returns the contents of the Minecraft server's
+    /// `server.properties` file.
+    ///
+    /// Returns a [WrapperError::PropertiesNotFound](error::WrapperError::PropertiesNotFound)
+    /// if that file doesn't exist yet.
+    pub fn server_properties(&self) -> anyhow::Result<ServerProperties> {
+        ServerProperties::read_from_dir(&self.server_dir)
     }
 
-    fn wait_for_server_to_spin_up(&mut self) {
-        // TODO: Implement timeout functionality? What if something goes wrong
-        // with the underlying server and it just hangs?
+    /// Returns the contents of the Minecraft server's `server.properties` file
+    /// as they are, without parsing them.
+    ///
+    /// Returns a [WrapperError::PropertiesNotFound](error::WrapperError::PropertiesNotFound)
+    /// if that file doesn't exist yet.
+    pub fn server_properties_raw(&self) -> anyhow::Result<String> {
+        ServerProperties::read_raw_from_dir(&self.server_dir)
+    }
 
-        // When the Minecraft server finishes spinning up, it will send a
-        // message to stdout that looks something like this:
-        // [02:00:14] [Server thread/INFO]: Done (9.797s)! For help, type "help"
-        //
-        // TODO: Revisit this .unwrap() call on recv().
-        while !self.stdout.recv().unwrap().contains("Done") {
-            continue;
-        }
+    /// Replaces the Minecraft server's `server.properties` file with the
+    /// provided contents, and returns the path to it. The server picks up the
+    /// changes the next time it starts.
+    ///
+    /// Returns a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// without touching anything if `contents` aren't well-formed.
+    pub fn replace_server_properties_raw(&self, contents: &str) -> anyhow::Result<PathBuf> {
+        ServerProperties::write_raw_to_dir(&self.server_dir, contents)
     }
 
-    /// Returns the names of players who are currently logged in and playing on
-    /// the server.
-    pub fn list_players(&mut self) -> anyhow::Result<Vec<String>> {
-        self.run_custom_command("/list").with_context(|| {
-            "Something went wrong while sending the Minecraft server the \"/list\" command"
-        })?;
-        // Will look something like this:
-        // [16:14:22] [Server thread/INFO]: There are 2 of a max of 20 players online: player1, player2
-        let response = self.stdout.recv().unwrap();
+    /// Returns the Minecraft server's icon, which is a PNG image.
+    ///
+    /// Returns a [WrapperError::ServerIconNotFound](error::WrapperError::ServerIconNotFound)
+    /// if the server doesn't have one.
+    pub fn server_icon(&self) -> anyhow::Result<Vec<u8>> {
+        server_icon::read_from_dir(&self.server_dir)
+    }
 
-        // Strip away everything but the list of players.
-        //
-        // Should be safe to unwrap() after the rsplit_one() call since we know
-        // in advance what the contents of response will look like.
-        let (_, players_as_str) = response.rsplit_once(": ").unwrap();
-        if players_as_str.is_empty() {
-            return Ok(Vec::new());
-        }
+    /// Replaces the Minecraft server's icon with the provided PNG image, and
+    /// returns the path to it. The server picks up the new icon the next time
+    /// it starts.
+    ///
+    /// Returns a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// without touching anything if `png` isn't a 64x64 PNG image.
+    pub fn replace_server_icon(&self, png: &[u8]) -> anyhow::Result<PathBuf> {
+        server_icon::write_to_dir(&self.server_dir, png)
+    }
 
-        let players_as_vec = players_as_str
-            .split(',')
-            .map(|name| name.to_owned())
-            .collect();
-        Ok(players_as_vec)
+    /// Writes a minimal `server.properties` file with default values, if there
+    /// isn't one already. The Minecraft server fills in everything else the
+    /// next time it starts.
+    ///
+    /// Returns a [WrapperError::PropertiesAlreadyExist](error::WrapperError::PropertiesAlreadyExist)
+    /// if there's already a `server.properties` file.
+    pub fn init_server_properties(&mut self) -> anyhow::Result<PathBuf> {
+        let path = ServerProperties::write_default_to_dir(&self.server_dir)?;
+        self.server_port = self.resolve_server_port();
+        Ok(path)
     }
 
     pub fn stop_server(&mut self) -> anyhow::Result<()> {
+        self.stop_requested.store(true, Ordering::SeqCst);
+        // Whatever the server wrote before now has nothing to do with this
+        // stop.
+        while self.stdout.try_recv().is_ok() {}
         self.run_custom_command("/stop").with_context(|| {
             "Something went wrong while sending the Minecraft server the \"/stop\" command"
         })?;
@@ -101,10 +1869,127 @@ impl Wrapper {
                 None => bail!("The Minecraft server process was terminated forcefully by a signal"),
             }
         }
+        if !self.saw_stop_ready_line() {
+            warn!(
+                "The Minecraft server exited without writing a line matching {:?}, so it might not have saved everything. If it says something else when it's done saving, set stop_ready_pattern to match that",
+                self.stop_ready_pattern.as_str()
+            );
+        }
 
         Ok(())
     }
 
+    /// Reads the rest of what the Minecraft server wrote before it exited, and
+    /// returns whether any of it matches the `stop_ready_pattern`.
+    fn saw_stop_ready_line(&mut self) -> bool {
+        loop {
+            match self.stdout.recv_timeout(STOP_OUTPUT_GRACE_PERIOD) {
+                Ok(line) if self.stop_ready_pattern.is_match(&line) => return true,
+                Ok(_) => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Forcefully kills the Minecraft server process without giving it a chance
+    /// to save anything, and waits for it to exit.
+    ///
+    /// If the process was spawned in its own process group, the whole group is
+    /// killed, so nothing that the server spawned is left behind.
+    pub(crate) fn kill_server(&mut self) -> io::Result<()> {
+        kill_process(&mut self.process, self.config.own_process_group)
+    }
+
+    /// Returns true if the Minecraft server process has exited, regardless of
+    /// whether it was asked to or not. Doesn't block.
+    pub fn has_exited(&mut self) -> anyhow::Result<bool> {
+        Ok(self.exit_status()?.is_some())
+    }
+
+    /// Returns how the Minecraft server process exited, or [None] if it's
+    /// still running.
+    pub fn exit_status(&mut self) -> anyhow::Result<Option<process::ExitStatus>> {
+        self.process
+            .try_wait()
+            .with_context(|| "Failed to check whether the Minecraft server process has exited")
+    }
+
+    /// Returns true if the wrapper stopped reading what the Minecraft server
+    /// writes to stdout, even though the server process is still running, like
+    /// if the thread that reads it panicked. Nothing that the server says makes
+    /// it to the wrapper after that, so every command times out.
+    pub fn stdout_reader_died(&mut self) -> anyhow::Result<bool> {
+        Ok(self.stdout_reader_exited.load(Ordering::SeqCst) && !self.has_exited()?)
+    }
+
+    /// Returns true if the Minecraft server was asked to stop since the last
+    /// time a server process was spawned.
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns the flag that marks a stop as intentional.
+    ///
+    /// Callers that are about to stop the server should raise this flag before
+    /// they queue up a request for this [Wrapper]. That way, the watchdog
+    /// won't mistake the server exiting for a crash while they're waiting. The
+    /// flag is lowered automatically whenever a new server process is spawned.
+    pub fn stop_requested_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop_requested)
+    }
+
+    /// Returns the flag that pauses printing what the Minecraft server writes
+    /// on the host while it's set, like when somebody wants to type commands
+    /// into the wrapper's terminal in peace.
+    ///
+    /// Lines that come in while it's set aren't printed later on, but they're
+    /// still handled like usual, so nothing else, like events, misses them.
+    /// It's shared across server processes, so it stays set through restarts.
+    pub fn console_paused_flag(&self) -> Arc<AtomicBool> {
+        self.echo_filter.paused_flag()
+    }
+
+    /// Returns the [StateMachine] that keeps track of what the server is up to.
+    ///
+    /// Callers that are about to stop, restart, or back up the server should
+    /// begin that operation with it before they queue up a request for this
+    /// [Wrapper].
+    pub fn state_machine(&self) -> StateMachine {
+        self.state.clone()
+    }
+
+    /// Returns where the watchdog keeps track of the crashes that it notices.
+    pub fn crash_history(&self) -> Arc<Mutex<CrashHistory>> {
+        Arc::clone(&self.crash_history)
+    }
+
+    /// Returns the crashes that the watchdog noticed since the wrapper
+    /// started, newest first. Only the most recent ones are kept.
+    pub fn crashes(&self) -> Vec<Crash> {
+        self.crash_history.lock().unwrap().crashes()
+    }
+
+    /// Deletes the lockfile in the Minecraft server's directory, so that
+    /// another wrapper can manage the server. See [Wrapper::new()].
+    ///
+    /// Call this once the server has been stopped for good, when the [Wrapper]
+    /// might not be dropped before the process exits.
+    pub fn release_server_dir_lock(&mut self) {
+        self.server_dir_lock = None;
+    }
+
+    /// Returns the [CommandStats] that count how the commands that the wrapper
+    /// sends on its own have fared.
+    pub fn command_stats(&self) -> CommandStats {
+        self.command_stats.clone()
+    }
+
+    /// Returns the [StdoutStats] that count the lines that the Minecraft
+    /// server wrote which were dropped before the wrapper read them.
+    pub fn stdout_stats(&self) -> StdoutStats {
+        self.stdout_stats.clone()
+    }
+
     /// Stops the Minecraft server process, spawns a one, and overwrites this
     /// [Wrapper]'s struct fields with the `process`, `stdin`, and `stdout` for
     /// the new process.
@@ -125,7 +2010,7 @@ impl Wrapper {
         if self.stop_server().is_err() {
             // If something goes wrong trying to stop the server, then kill the
             // process manually.
-            if let Err(e) = self.process.kill() {
+            if let Err(e) = self.kill_server() {
                 // e will be an InvalidInput error if the process was already
                 // killed.
                 if e.kind() != io::ErrorKind::InvalidInput {
@@ -134,66 +2019,1085 @@ impl Wrapper {
             }
         }
 
-        let (process, stdin, stdout_rx) =
-            spawn_server_process(self.max_memory_buffer_size, &self.server_jar_path)?;
+        self.spawn_new_server_process()?;
+        Ok(())
+    }
+
+    /// Checks that the configured server jar, memory limit, and environment
+    /// can start a Minecraft server, without touching the one that's running.
+    ///
+    /// A second server is started in an empty, throwaway directory. Since its
+    /// EULA hasn't been agreed to there, it exits on its own once it's gotten
+    /// far enough to check, and that's treated as a success. So is the server
+    /// announcing that it's ready, in which case it's killed. Returns an error
+    /// with the last lines that the server wrote if it exits any other way, or
+    /// if it doesn't get that far within the startup timeout.
+    pub fn validate_launch(&self) -> anyhow::Result<()> {
+        // The throwaway server runs in a different directory, so the jar's
+        // path can't be relative.
+        let server_jar_path =
+            fs::canonicalize(&self.config.server_jar_path).with_context(|| {
+                format!(
+                    "Failed to find the server jar at {:?}",
+                    &self.config.server_jar_path
+                )
+            })?;
+        let scratch_dir = std::env::temp_dir().join(format!(
+            "mc-server-wrapper-validate-launch-{}",
+            process::id()
+        ));
+        fs::create_dir_all(&scratch_dir)
+            .with_context(|| format!("Failed to create {:?}", &scratch_dir))?;
+
+        let result = self.launch_in(&scratch_dir, &server_jar_path);
+        if let Err(e) = fs::remove_dir_all(&scratch_dir) {
+            warn!("Failed to clean up {:?}: {}", &scratch_dir, e);
+        }
+        result
+    }
+
+    fn launch_in(&self, dir: &Path, server_jar_path: &Path) -> anyhow::Result<()> {
+        let mut process = server_command(&self.config, server_jar_path)
+            .current_dir(dir)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .with_context(|| "Failed to start java")?;
+
+        // Read both output streams, since a server that fails early might only
+        // say why on stderr.
+        let (lines_tx, lines_rx) = mpsc::channel::<String>();
+        if let Some(stdout) = process.stdout.take() {
+            let lines_tx = lines_tx.clone();
+            thread::spawn(move || send_lines(io::BufReader::new(stdout), lines_tx));
+        }
+        if let Some(stderr) = process.stderr.take() {
+            thread::spawn(move || send_lines(io::BufReader::new(stderr), lines_tx));
+        }
+
+        let deadline = Instant::now() + self.config.startup_timeout;
+        let mut last_lines = VecDeque::with_capacity(VALIDATE_LAUNCH_OUTPUT_LINES);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match lines_rx.recv_timeout(remaining) {
+                Ok(line) => {
+                    if EULA_NOT_AGREED_PATTERN.is_match(&line)
+                        || SERVER_READY_PATTERN.is_match(&line)
+                    {
+                        // Either way, the throwaway server isn't needed
+                        // anymore.
+                        let _ = kill_process(&mut process, self.config.own_process_group);
+                        return Ok(());
+                    }
+                    if last_lines.len() == VALIDATE_LAUNCH_OUTPUT_LINES {
+                        last_lines.pop_front();
+                    }
+                    last_lines.push_back(line);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = kill_process(&mut process, self.config.own_process_group);
+                    bail!(
+                        "The Minecraft server didn't get far enough to check its EULA within {}s. Its last lines of output were:\n{}",
+                        self.config.startup_timeout.as_secs(),
+                        Vec::from(last_lines).join("\n")
+                    );
+                }
+                // Both of the server's output streams were closed, which means
+                // that it exited.
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = process.wait()?;
+                    bail!(
+                        "The Minecraft server exited early ({}). Its last lines of output were:\n{}",
+                        status,
+                        Vec::from(last_lines).join("\n")
+                    );
+                }
+            }
+        }
+    }
+
+    /// Stops the Minecraft server, creates a compressed tarball of the server's
+    /// world directory, which `level-name` in `server.properties` points to,
+    /// and starts a new Minecraft server process. Returns
+    /// the [PathBuf] to that tarball.
+    ///
+    /// Once it's made, it's verified if `verify_backups` is set, and the old
+    /// backups that the [BackupRetention] doesn't keep are deleted, and listed
+    /// in [Wrapper::last_backup()]. Nothing is deleted if it fails
+    /// verification, since the old backups might be the only good ones left.
+    ///
+    /// If the backup is still going when the backup timeout runs out, it's
+    /// abandoned, and a [WrapperError::BackupTimedOut](error::WrapperError::BackupTimedOut)
+    /// is returned. The server isn't started back up in that case, so callers
+    /// should call [Wrapper::restart_server()].
+    ///
+    /// Before the server is stopped, the size of the world directory is
+    /// checked against the free disk space. If the tarball might not leave
+    /// `min_free_space_bytes` free, a
+    /// [WrapperError::InsufficientDiskSpace](error::WrapperError::InsufficientDiskSpace)
+    /// is returned, and the server is left running.
+    ///
+    /// Only the provided `dimensions` are backed up, or every dimension that
+    /// the world has if that's [None]. See [Wrapper::check_backup_dimensions()]
+    /// for what happens when the world doesn't have one of them.
+    ///
+    /// The backup is added to the `backup_dir`'s manifest along with its
+    /// `details`, which have to pass [BackupDetails::check()].
+    pub fn make_world_backup(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+    ) -> anyhow::Result<PathBuf> {
+        let mut hooks = self.hook_run();
+        let result = details
+            .check()
+            .and_then(|()| hooks.before())
+            .and_then(|()| self.try_make_world_backup(dimensions, details));
+        self.record_backup(false, &result);
+        if let Ok(tarball_path) = &result {
+            if self.verify_new_backup(tarball_path) {
+                self.prune_backups();
+            }
+            self.upload_backup(tarball_path);
+        }
+        self.finish_hooks(hooks, &result);
+        result
+    }
+
+    fn try_make_world_backup(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+    ) -> anyhow::Result<PathBuf> {
+        let started_at = Instant::now();
+        let deadline = self
+            .config
+            .backup_timeout
+            .map(|timeout| started_at + timeout);
+
+        let dimension_dirs = self.backup_dimension_dirs(dimensions)?;
+        let world_size = self.check_backup_space(&dimension_dirs, false)?;
+        self.announce(self.config.backup_announce_message.clone());
+        self.stop_server()?;
+        let tarball_path =
+            self.compress_world_dir(&dimension_dirs, dimensions.is_none(), deadline)?;
+        self.record_in_manifest(&tarball_path, details, world_size, started_at);
+
+        self.spawn_new_server_process()?;
+        self.announce(self.config.backup_complete_message.clone());
+        Ok(tarball_path)
+    }
+
+    /// Like [Wrapper::make_world_backup()], but only keeps the Minecraft
+    /// server stopped for as long as it takes to copy the world directory.
+    /// Returns the [PathBuf] that the tarball is going to be written to.
+    ///
+    /// The copy is put in a staging directory next to where the tarball goes,
+    /// and compressed into the tarball on a background thread after the server
+    /// is started back up. The staging directory is deleted afterwards, and
+    /// how it went shows up in [Wrapper::last_backup()]. The backup timeout
+    /// only covers the copy, since the compression doesn't hold anything up.
+    /// Old backups are pruned once the compression succeeds, and it's verified
+    /// if `verify_backups` is set.
+    ///
+    /// There has to be room on the disk for both the copy and the tarball.
+    /// Just like with [Wrapper::make_world_backup()], the server is left
+    /// running if there isn't, or if the world doesn't have one of the
+    /// `dimensions`, or if the `details` don't pass [BackupDetails::check()].
+    /// The backup is added to the `backup_dir`'s manifest once the
+    /// compression succeeds.
+    pub fn make_world_backup_in_background(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+    ) -> anyhow::Result<PathBuf> {
+        let mut hooks = Some(self.hook_run());
+        let result = match details
+            .check()
+            .and_then(|()| hooks.as_mut().map_or(Ok(()), HookRun::before))
+        {
+            Err(e) => Err(e),
+            Ok(()) => self.try_make_world_backup_in_background(dimensions, details, &mut hooks),
+        };
+        // Otherwise, the background thread records how it went once it's
+        // done.
+        if result.is_err() {
+            self.record_backup(false, &result);
+        }
+        // The background thread runs the `after` commands once it's done,
+        // unless it never got started.
+        if let Some(hooks) = hooks {
+            self.finish_hooks(hooks, &result);
+        }
+        result
+    }
+
+    /// Takes `hooks` for the background thread, once it's started.
+    fn try_make_world_backup_in_background(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+        hooks: &mut Option<HookRun>,
+    ) -> anyhow::Result<PathBuf> {
+        let started_at = Instant::now();
+        let deadline = self
+            .config
+            .backup_timeout
+            .map(|timeout| started_at + timeout);
+
+        let dimension_dirs = self.backup_dimension_dirs(dimensions)?;
+        let world_size = self.check_backup_space(&dimension_dirs, true)?;
+        self.announce(self.config.backup_announce_message.clone());
+        self.stop_server()?;
+
+        let tarball_path = self.new_tarball_path();
+        let mut staging_dir = tarball_path.clone().into_os_string();
+        staging_dir.push(STAGING_DIR_SUFFIX);
+        let staging_dir = PathBuf::from(staging_dir);
+        if let Err(e) = self.copy_dimension_dirs(&dimension_dirs, &staging_dir, deadline) {
+            remove_staging_dir(&staging_dir);
+            return Err(e);
+        }
+
+        let follow_symlinks = self.config.follow_symlinks;
+        let compression = self.config.backup_compression;
+        let level = self.config.backup_compression_level;
+        let encryption = self.config.backup_encryption.clone();
+        let verify_backups = self.config.verify_backups;
+        let min_free = self.config.min_free_space_bytes;
+        let backup_dir = self.backup_dir.clone();
+        let retention = self.config.backup_retention.clone();
+        let last_backup = Arc::clone(&self.last_backup);
+        let upload_destinations = self.upload_destinations();
+        let uploads = self.uploads.clone();
+        // Counts the backup as pending from now on, rather than from when
+        // it's done being compressed, so that nothing waiting on uploads
+        // thinks that they're all done in the meantime.
+        let pending_upload = (!upload_destinations.is_empty()).then(|| uploads.expect());
+        let metadata = BackupMetadata::new(details, world_size, self.minecraft_version.clone());
+        let hooks = hooks.take();
+        thread::spawn({
+            let tarball_path = tarball_path.clone();
+            move || {
+                let result = backup::write_tarball_file(
+                    &tarball_path,
+                    compression,
+                    level,
+                    encryption.as_ref(),
+                    |tarball| {
+                        backup::append_dir_all(
+                            tarball,
+                            Path::new(""),
+                            &staging_dir,
+                            &Exclude::default(),
+                            follow_symlinks,
+                            None,
+                        )
+                    },
+                );
+                remove_staging_dir(&staging_dir);
+                match &result {
+                    Ok(()) => info!("Finished compressing a new world backup: {:?}", &tarball_path),
+                    Err(e) => warn!(
+                        "Something went wrong while compressing a new world backup in the background: {:#}",
+                        e
+                    ),
+                }
+                let mut status = BackupStatus::new(false, &result);
+                if result.is_ok() {
+                    manifest::record(&backup_dir, &tarball_path, metadata.finished(started_at));
+                    if verify_backups {
+                        status.verification =
+                            verify_new_backup(&tarball_path, encryption.as_ref(), min_free);
+                    }
+                    if status.verification.as_ref().is_none_or(|v| v.passed) {
+                        status.pruned = retention::prune_backups(&backup_dir, &retention);
+                    }
+                }
+                *last_backup.lock().unwrap() = Some(status);
+                if result.is_ok() {
+                    remote_backup::spawn_upload(upload_destinations, tarball_path.clone(), uploads);
+                }
+                drop(pending_upload);
+                if let Some(mut hooks) = hooks {
+                    hooks.after(result.is_ok(), result.is_ok().then_some(&*tarball_path));
+                    if let Some(status) = last_backup.lock().unwrap().as_mut() {
+                        status.hooks = hooks.into_results();
+                    }
+                }
+            }
+        });
+
+        self.spawn_new_server_process()?;
+        self.announce(self.config.backup_complete_message.clone());
+        Ok(tarball_path)
+    }
+
+    /// Copies the provided dimensions of the world directory into
+    /// `staging_dir`, laid out the same way that they are in a tarball.
+    fn copy_dimension_dirs(
+        &self,
+        dimension_dirs: &[DimensionDir],
+        staging_dir: &Path,
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        for dir in dimension_dirs {
+            backup::copy_dir_all(
+                &dir.path,
+                &staging_dir.join(&dir.archive_path),
+                &dir.exclude,
+                self.config.follow_symlinks,
+                deadline,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Spawns a new Minecraft server process, overwrites this [Wrapper]'s
+    /// struct fields with the `process`, `stdin`, and `stdout` for that
+    /// process, and blocks until the server has finished spinning up.
+    ///
+    /// The old server process must have already exited.
+    fn spawn_new_server_process(&mut self) -> anyhow::Result<()> {
+        let (process, stdin, stdout_rx, stdout_reader_exited) = spawn_server_process(
+            &self.config,
+            &self.server_dir,
+            self.events.clone(),
+            Arc::clone(&self.echo_filter),
+            Arc::clone(&self.prompt_patterns),
+            Arc::clone(&self.logs),
+            self.stdout_stats.clone(),
+        )?;
+        self.server_started_at = Utc::now();
         self.process = process;
         self.stdin = stdin;
         self.stdout = stdout_rx;
+        self.stdout_reader_exited = stdout_reader_exited;
+        self.stop_requested.store(false, Ordering::SeqCst);
+        self.player_list_cache = None;
+        self.saves_frozen_at = None;
+        self.rcon = None;
+        // server.properties might have been edited while the server was down.
+        self.server_port = self.resolve_server_port();
+
+        self.wait_for_server_to_spin_up()
+    }
+
+    /// Returns an error if the world doesn't have every one of the provided
+    /// `dimensions`, so that a backup of them would fail.
+    ///
+    /// That error is a
+    /// [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument),
+    /// since a dimension only shows up on disk once a player has been there.
+    /// [None] stands for every dimension that the world has, so it's always
+    /// fine.
+    pub fn check_backup_dimensions(
+        &self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+    ) -> anyhow::Result<()> {
+        self.backup_dimension_dirs(dimensions)?;
+        Ok(())
+    }
+
+    /// Returns where the provided `dimensions` are saved, or every dimension
+    /// that the world has along with the `backup_extra_dirs` if that's [None].
+    /// Each one leaves out what `backup_exclude` says to.
+    fn backup_dimension_dirs(
+        &self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+    ) -> anyhow::Result<Vec<DimensionDir>> {
+        let dirs = dimension::find_dimension_dirs(&self.world_dir());
+        let mut dirs = dimension::select_dimension_dirs(dirs, dimensions)?;
+        if dimensions.is_none() {
+            for extra_dir in &self.config.backup_extra_dirs {
+                let path = self.server_dir.join(extra_dir);
+                if !path.is_dir() {
+                    warn!(
+                        "Leaving {:?} out of the world backup, since it isn't a directory",
+                        &path
+                    );
+                    continue;
+                }
+                dirs.push(DimensionDir::extra(path));
+            }
+        }
+        for dir in &mut dirs {
+            dir.exclude.patterns = self.backup_exclude.clone();
+        }
+        Ok(dirs)
+    }
+
+    /// Makes sure that there's room on the disk for a tarball of the provided
+    /// dimensions of the world directory, and for a copy of them too if
+    /// they're `staged` first.
+    ///
+    /// The tarball is compressed, so it's almost always smaller than the
+    /// directories, which makes their size a safe estimate. Returns how many
+    /// bytes the directories take up.
+    fn check_backup_space(
+        &self,
+        dimension_dirs: &[DimensionDir],
+        staged: bool,
+    ) -> anyhow::Result<u64> {
+        let backup_dir = self.backup_dir();
+        let mut world_size: u64 = 0;
+        for dir in dimension_dirs {
+            world_size += backup::dir_size(&dir.path, &dir.exclude, self.config.follow_symlinks)
+                .with_context(|| format!("Failed to work out how big {:?} is", &dir.path))?;
+        }
+        let estimated = if staged {
+            world_size.saturating_mul(2)
+        } else {
+            world_size
+        };
+        let available = match backup::available_space(&backup_dir) {
+            Some(available) => available,
+            None => {
+                warn!(
+                    "Couldn't tell how much free disk space there is in {:?}. Making a backup anyways",
+                    &backup_dir
+                );
+                return Ok(world_size);
+            }
+        };
+        backup::check_free_space(estimated, available, self.config.min_free_space_bytes)?;
+        Ok(world_size)
+    }
+
+    /// Compresses the world directory where the Minecraft server saves all
+    /// its info about the world and the players who play on it. Returns the
+    /// [PathBuf] to that tarball.
+    ///
+    /// Creates a tarball with the current timestamp as the file name,
+    /// compressed however the [WrapperConfig]'s `backup_compression` says, and
+    /// encrypted if it has a `backup_encryption`.
+    /// Ex: "2022-01-01 00:00:00.000000 UTC.tar.gz"
+    ///
+    /// If the [WrapperConfig] has `incremental_backups`, and the
+    /// `dimension_dirs` are the `whole_world`, the tarball might only have the
+    /// files that changed since the last backup. See [IncrementalBackups].
+    ///
+    /// The tarball is written under a name ending in ".tmp" first, and only
+    /// renamed once it's finished, so a file with the final name is always a
+    /// complete backup, even if the wrapper dies partway through.
+    ///
+    /// If `deadline` passes before the tarball is finished, a
+    /// [WrapperError::BackupTimedOut](error::WrapperError::BackupTimedOut) is
+    /// returned. The tarball is deleted whenever it can't be finished, like
+    /// when the disk fills up.
+    fn compress_world_dir(
+        &self,
+        dimension_dirs: &[DimensionDir],
+        whole_world: bool,
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<PathBuf> {
+        let tarball_path = self.new_tarball_path();
+        let pending = match &self.config.incremental_backups {
+            Some(incremental) if whole_world => Some(incremental::prepare(
+                &self.backup_dir,
+                incremental,
+                dimension_dirs,
+                self.config.follow_symlinks,
+                deadline,
+            )?),
+            _ => None,
+        };
+        backup::write_tarball_file(
+            &tarball_path,
+            self.config.backup_compression,
+            self.config.backup_compression_level,
+            self.config.backup_encryption.as_ref(),
+            |tarball| match &pending {
+                Some(pending) => backup::append_files(
+                    tarball,
+                    &pending.files,
+                    self.config.follow_symlinks,
+                    deadline,
+                ),
+                None => self.append_dimension_dirs(tarball, dimension_dirs, deadline),
+            },
+        )?;
+        if let Some(pending) = &pending {
+            incremental::write_snapshot(&tarball_path, &pending.snapshot);
+        }
+        Ok(tarball_path)
+    }
+
+    /// Returns the path to write a new backup to, in the `backup_dir`, with
+    /// the current timestamp as its file name, and an extension for how it's
+    /// compressed and whether it's encrypted.
+    fn new_tarball_path(&self) -> PathBuf {
+        self.backup_dir
+            .new_backup_path(Utc::now(), self.backup_format())
+    }
+
+    /// Writes a compressed tarball of the provided dimensions of the world
+    /// directory into `writer`, encrypted if the [WrapperConfig] has a
+    /// `backup_encryption`, and returns `writer` once the tarball is finished.
+    ///
+    /// Returns a [WrapperError::BackupTimedOut](error::WrapperError::BackupTimedOut)
+    /// if `deadline` passes before the tarball is finished.
+    fn write_world_tarball<W: Write>(
+        &self,
+        writer: W,
+        dimension_dirs: &[DimensionDir],
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<W> {
+        let writer = EncryptingWriter::new(writer, self.config.backup_encryption.as_ref())?;
+        let writer = backup::write_tarball(
+            writer,
+            self.config.backup_compression,
+            self.config.backup_compression_level,
+            |tarball| self.append_dimension_dirs(tarball, dimension_dirs, deadline),
+        )?;
+        writer
+            .finish()
+            .with_context(|| "Failed to finish encrypting the tarball")
+    }
+
+    /// Adds the provided dimensions of the world directory to `tarball`.
+    fn append_dimension_dirs<W: Write>(
+        &self,
+        tarball: &mut tar::Builder<W>,
+        dimension_dirs: &[DimensionDir],
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        for dir in dimension_dirs {
+            backup::append_dir_all(
+                tarball,
+                &dir.archive_path,
+                &dir.path,
+                &dir.exclude,
+                self.config.follow_symlinks,
+                deadline,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes a compressed tarball of the world directory into `writer`
+    /// without stopping the Minecraft server.
+    ///
+    /// The server is told to save everything to disk and stop saving
+    /// automatically before the tarball is started, so that the world doesn't
+    /// change while it's being copied. Automatic saving is turned back on
+    /// afterwards, even if something goes wrong.
+    ///
+    /// Since nothing is written to disk, this works for streaming a backup
+    /// somewhere else. If the backup timeout runs out first, a
+    /// [WrapperError::BackupTimedOut](error::WrapperError::BackupTimedOut) is
+    /// returned, and `writer` is left with a partial tarball.
+    ///
+    /// If the [WrapperConfig] has a [BackupDrain], every player is warned and
+    /// kicked before the backup starts. See [BackupDrain] for how.
+    ///
+    /// Only the provided `dimensions` are backed up, like with
+    /// [Wrapper::make_world_backup()].
+    pub fn stream_world_backup<W: Write>(
+        &mut self,
+        writer: W,
+        dimensions: Option<&BTreeSet<Dimension>>,
+    ) -> anyhow::Result<()> {
+        let warned_at = self.drain_warned_at.take();
+        let mut hooks = self.hook_run();
+        let result = hooks
+            .before()
+            .and_then(|()| self.try_stream_world_backup(writer, dimensions, warned_at));
+        self.record_backup(true, &result);
+        hooks.after(result.is_ok(), None);
+        self.record_hooks(hooks);
+        result
+    }
+
+    fn try_stream_world_backup<W: Write>(
+        &mut self,
+        writer: W,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        warned_at: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        let deadline = self
+            .config
+            .backup_timeout
+            .map(|timeout| Instant::now() + timeout);
+
+        let dimension_dirs = self.backup_dimension_dirs(dimensions)?;
+        self.while_saving_paused(warned_at, |w| {
+            w.write_world_tarball(writer, &dimension_dirs, deadline)
+                .map(|_| ())
+        })
+    }
+
+    /// Like [Wrapper::make_world_backup()], but keeps the Minecraft server
+    /// running, the same way [Wrapper::stream_world_backup()] does. Returns
+    /// the [PathBuf] to the tarball.
+    ///
+    /// The server is told to save everything to disk with "/save-all flush",
+    /// and to stop saving automatically with "/save-off", while the tarball is
+    /// written, so that players can keep playing without the world changing
+    /// underneath it. Automatic saving is turned back on afterwards, even if
+    /// something goes wrong.
+    ///
+    /// Just like with [Wrapper::make_world_backup()], the free disk space is
+    /// checked first, and old backups are pruned afterwards. If the
+    /// [WrapperConfig] has a [BackupDrain], every player is warned and kicked
+    /// before the backup starts. It's added to the `backup_dir`'s manifest
+    /// along with its `details`, too.
+    pub fn make_hot_world_backup(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+    ) -> anyhow::Result<PathBuf> {
+        let warned_at = self.drain_warned_at.take();
+        let mut hooks = self.hook_run();
+        let result = details
+            .check()
+            .and_then(|()| hooks.before())
+            .and_then(|()| self.try_make_hot_world_backup(dimensions, details, warned_at));
+        self.record_backup(false, &result);
+        if let Ok(tarball_path) = &result {
+            if self.verify_new_backup(tarball_path) {
+                self.prune_backups();
+            }
+            self.upload_backup(tarball_path);
+        }
+        self.finish_hooks(hooks, &result);
+        result
+    }
+
+    fn try_make_hot_world_backup(
+        &mut self,
+        dimensions: Option<&BTreeSet<Dimension>>,
+        details: &BackupDetails,
+        warned_at: Option<Instant>,
+    ) -> anyhow::Result<PathBuf> {
+        let started_at = Instant::now();
+        let deadline = self
+            .config
+            .backup_timeout
+            .map(|timeout| started_at + timeout);
+
+        let dimension_dirs = self.backup_dimension_dirs(dimensions)?;
+        let world_size = self.check_backup_space(&dimension_dirs, false)?;
+        self.while_saving_paused(warned_at, |w| {
+            let tarball_path =
+                w.compress_world_dir(&dimension_dirs, dimensions.is_none(), deadline)?;
+            w.record_in_manifest(&tarball_path, details, world_size, started_at);
+            Ok(tarball_path)
+        })
+    }
+
+    /// Runs `backup` while the Minecraft server isn't saving automatically,
+    /// after it's saved everything to disk, for a backup that doesn't stop
+    /// the server. Players are told about the backup, and drained first if
+    /// the [WrapperConfig] has a [BackupDrain]. Saving and maintenance mode are
+    /// put back the way they were afterwards, even if something went wrong.
+    ///
+    /// `warned_at` is when players were warned about being drained, if they
+    /// were. See [Wrapper::drain_players()].
+    fn while_saving_paused<T>(
+        &mut self,
+        warned_at: Option<Instant>,
+        backup: impl FnOnce(&mut Wrapper) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        self.announce(self.config.backup_announce_message.clone());
+        let mut turned_on_maintenance = false;
+        let result = self
+            .drain_players(warned_at, &mut turned_on_maintenance)
+            .and_then(|()| self.pause_saving())
+            .and_then(|()| {
+                let result = backup(self);
+                let resume_result = self.resume_saving();
+                let value = result?;
+                resume_result?;
+                Ok(value)
+            });
+        // Put things back the way they were, even if something went wrong.
+        let value = if turned_on_maintenance {
+            let maintenance_result = self
+                .set_maintenance_mode(false)
+                .with_context(|| "Failed to turn maintenance mode back off after the backup");
+            let value = result?;
+            maintenance_result?;
+            value
+        } else {
+            result?
+        };
+        self.announce(self.config.backup_complete_message.clone());
+        Ok(value)
+    }
+
+    /// Remembers how a backup went for [Wrapper::last_backup()].
+    fn record_backup<T>(&mut self, streamed: bool, result: &anyhow::Result<T>) {
+        *self.last_backup.lock().unwrap() = Some(BackupStatus::new(streamed, result));
+    }
+
+    /// Remembers that a backup was skipped for the provided reason, for
+    /// [Wrapper::last_backup()].
+    pub(crate) fn record_skipped_backup(&mut self, reason: &str) {
+        let mut status = BackupStatus::new(false, &Ok(()));
+        status.skipped = Some(reason.to_owned());
+        *self.last_backup.lock().unwrap() = Some(status);
+    }
+
+    /// Adds the world backup that was just written to the provided path to
+    /// the `backup_dir`'s manifest. `world_size` is how many bytes the
+    /// directories in it take up, and `started_at` is when it was started.
+    fn record_in_manifest(
+        &self,
+        tarball_path: &Path,
+        details: &BackupDetails,
+        world_size: u64,
+        started_at: Instant,
+    ) {
+        let metadata = BackupMetadata::new(details, world_size, self.minecraft_version.clone());
+        manifest::record(
+            &self.backup_dir,
+            tarball_path,
+            metadata.finished(started_at),
+        );
+    }
+
+    /// Verifies the world backup that was just written to the provided path if
+    /// `verify_backups` is set, and adds how it went to
+    /// [Wrapper::last_backup()]. Returns false if it failed verification.
+    fn verify_new_backup(&mut self, tarball_path: &Path) -> bool {
+        if !self.config.verify_backups {
+            return true;
+        }
+        let verification = verify_new_backup(
+            tarball_path,
+            self.config.backup_encryption.as_ref(),
+            self.config.min_free_space_bytes,
+        );
+        let passed = verification.as_ref().is_none_or(|v| v.passed);
+        if let Some(status) = self.last_backup.lock().unwrap().as_mut() {
+            status.verification = verification;
+        }
+        passed
+    }
+
+    /// Returns a [HookRun] for the `backup_hooks` of a new world backup.
+    fn hook_run(&self) -> HookRun {
+        HookRun::new(self.config.backup_hooks.clone(), self.server_dir.clone())
+    }
+
+    /// Runs the `after` commands of `hooks` for a world backup that was
+    /// written to disk with the provided result, and adds how every command
+    /// went to [Wrapper::last_backup()].
+    fn finish_hooks(&mut self, mut hooks: HookRun, result: &anyhow::Result<PathBuf>) {
+        hooks.after(result.is_ok(), result.as_ref().ok().map(PathBuf::as_path));
+        self.record_hooks(hooks);
+    }
+
+    fn record_hooks(&mut self, hooks: HookRun) {
+        if let Some(status) = self.last_backup.lock().unwrap().as_mut() {
+            status.hooks = hooks.into_results();
+        }
+    }
+
+    /// Deletes the old world backups that the [BackupRetention] doesn't keep,
+    /// and adds them to [Wrapper::last_backup()].
+    fn prune_backups(&mut self) {
+        let pruned = retention::prune_backups(&self.backup_dir, &self.config.backup_retention);
+        if let Some(status) = self.last_backup.lock().unwrap().as_mut() {
+            status.pruned = pruned;
+        }
+    }
+
+    /// Uploads the world backup at the provided path to the [WrapperConfig]'s
+    /// `backup_upload` and `backup_upload_ssh` in the background, if there are
+    /// any. How it went shows up in [Wrapper::last_upload()].
+    fn upload_backup(&self, tarball_path: &Path) {
+        remote_backup::spawn_upload(
+            self.upload_destinations(),
+            tarball_path.to_path_buf(),
+            self.uploads.clone(),
+        );
+    }
+
+    /// Returns everywhere that world backups are uploaded to, in the order
+    /// that they're uploaded in.
+    fn upload_destinations(&self) -> Vec<Destination> {
+        let s3 = self.config.backup_upload.clone().map(Destination::S3);
+        let ssh = self.config.backup_upload_ssh.clone().map(Destination::Ssh);
+        s3.into_iter().chain(ssh).collect()
+    }
+
+    /// Tells every player the provided message with "/say", if there is one.
+    /// Something going wrong is only logged, since it shouldn't hold up
+    /// whatever the message is about.
+    fn announce(&mut self, message: Option<String>) {
+        let message = match message {
+            Some(message) => message,
+            None => return,
+        };
+        if let Err(e) = self.run_custom_command(&format!("/say {}", message)) {
+            warn!("Failed to tell players {:?}: {}", &message, e);
+        }
+    }
+
+    /// Warns every player that they're about to be kicked for a backup, if the
+    /// [WrapperConfig] has a [BackupDrain] and anybody's online, and returns
+    /// how long to wait before starting the backup.
+    ///
+    /// The wait is left to the caller so that this [Wrapper] isn't tied up
+    /// while the warning runs out. Backups that don't stop the Minecraft server
+    /// only kick players once it has, so waiting is just a way to keep them
+    /// from holding up everything else in the meantime.
+    pub fn warn_players_before_backup(&mut self) -> anyhow::Result<Option<Duration>> {
+        let drain = match &self.config.backup_drain {
+            Some(drain) => drain.clone(),
+            None => return Ok(None),
+        };
+        if self.list_players()?.is_empty() {
+            return Ok(None);
+        }
+        self.warn_players_of_drain(&drain)?;
+        self.drain_warned_at = Some(Instant::now());
+        Ok(Some(drain.warning))
+    }
+
+    fn warn_players_of_drain(&mut self, drain: &BackupDrain) -> anyhow::Result<()> {
+        info!(
+            "Kicking every player in {}s to take a backup",
+            drain.warning.as_secs()
+        );
+        self.run_custom_command(&format!(
+            "/say {} Everyone will be kicked in {} seconds.",
+            &drain.message,
+            drain.warning.as_secs()
+        ))
+    }
+
+    /// Kicks every player for a backup, if the [WrapperConfig] has a
+    /// [BackupDrain].
+    ///
+    /// `warned_at` is when [Wrapper::warn_players_before_backup()] warned them.
+    /// If they weren't warned, they're warned now. Either way, they're only
+    /// kicked once the warning runs out, which only blocks here if the caller
+    /// didn't wait for it.
+    ///
+    /// Sets `turned_on_maintenance` if maintenance mode was turned on to keep
+    /// them from coming back, so that the caller can turn it back off. It's set
+    /// even if something goes wrong afterwards.
+    fn drain_players(
+        &mut self,
+        warned_at: Option<Instant>,
+        turned_on_maintenance: &mut bool,
+    ) -> anyhow::Result<()> {
+        let drain = match &self.config.backup_drain {
+            Some(drain) => drain.clone(),
+            None => return Ok(()),
+        };
+        if self.list_players()?.is_empty() {
+            return Ok(());
+        }
+
+        let warned_at = match warned_at {
+            Some(warned_at) => warned_at,
+            None => {
+                self.warn_players_of_drain(&drain)?;
+                Instant::now()
+            }
+        };
+        thread::sleep(drain.warning.saturating_sub(warned_at.elapsed()));
+
+        if drain.maintenance && !self.maintenance_mode() {
+            *turned_on_maintenance = true;
+            self.turn_on_maintenance_mode(&drain.message)?;
+        }
+        // Maintenance mode doesn't kick operators.
+        for player in self.list_players()? {
+            self.kick(&player, &drain.message)?;
+        }
+        Ok(())
+    }
+
+    /// Turns off automatic saving, and has the Minecraft server save
+    /// everything to disk right away. Blocks until it's done saving.
+    fn pause_saving(&mut self) -> anyhow::Result<()> {
+        self.run_command_capture("/save-off", &SAVE_OFF_PATTERN, false)
+            .with_context(|| "Failed to turn off automatic saving")?;
+
+        // Saving a big world can take longer than most commands do.
+        let save_timeout = self.config.command_timeout.max(MIN_SAVE_ALL_TIMEOUT);
+        self.expect_response(&SAVED_PATTERN, save_timeout);
+        let saved = self
+            .run_custom_command("/save-all flush")
+            .and_then(|()| self.wait_for_line(&SAVED_PATTERN, save_timeout));
+        self.command_stats.record("/save-all flush", &saved);
+        let saved = saved.with_context(|| "Failed to save the world to disk");
+        if let Err(e) = saved {
+            let _ = self.resume_saving();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Turns automatic saving back on after [Wrapper::pause_saving()], unless
+    /// it's frozen. See [Wrapper::freeze_saves()].
+    fn resume_saving(&mut self) -> anyhow::Result<()> {
+        if self.saves_frozen_at.is_some() {
+            return Ok(());
+        }
+        self.run_command_capture("/save-on", &SAVE_ON_PATTERN, false)
+            .with_context(|| "Failed to turn automatic saving back on")?;
+        Ok(())
+    }
+
+    /// Turns off automatic saving, and has the Minecraft server save
+    /// everything to disk right away, so that the server's files can be
+    /// snapshotted by something else, like ZFS or LVM. Saving stays off until
+    /// [Wrapper::unfreeze_saves()] is called, or until the server restarts.
+    ///
+    /// Returns a [WrapperError::SavesAlreadyFrozen](error::WrapperError::SavesAlreadyFrozen)
+    /// if saving is already frozen, since that probably means that the last
+    /// snapshot never unfroze it.
+    pub fn freeze_saves(&mut self) -> anyhow::Result<()> {
+        if let Some(frozen_at) = self.saves_frozen_at {
+            return Err(error::WrapperError::SavesAlreadyFrozen(frozen_at.to_rfc3339()).into());
+        }
+        self.pause_saving()?;
+        self.saves_frozen_at = Some(Utc::now());
+        Ok(())
+    }
 
-        self.wait_for_server_to_spin_up();
+    /// Turns automatic saving back on after [Wrapper::freeze_saves()].
+    ///
+    /// Returns a [WrapperError::SavesNotFrozen](error::WrapperError::SavesNotFrozen)
+    /// if saving isn't frozen.
+    pub fn unfreeze_saves(&mut self) -> anyhow::Result<()> {
+        let frozen_at = self
+            .saves_frozen_at
+            .take()
+            .ok_or(error::WrapperError::SavesNotFrozen)?;
+        if let Err(e) = self.resume_saving() {
+            // Saving is probably still off.
+            self.saves_frozen_at = Some(frozen_at);
+            return Err(e);
+        }
         Ok(())
     }
 
-    /// Stops the Minecraft server, creates a compressed tarball of the server's
-    /// `world/` directory, and starts a new Minecraft server process. Returns
-    /// the [PathBuf] to that tarball.
-    pub fn make_world_backup(&mut self) -> anyhow::Result<PathBuf> {
-        self.stop_server()?;
-        let tarball_path = self.compress_world_dir()?;
+    /// Returns when saving was frozen with [Wrapper::freeze_saves()], or
+    /// [None] if it isn't frozen.
+    pub fn saves_frozen_at(&self) -> Option<DateTime<Utc>> {
+        self.saves_frozen_at
+    }
 
-        let (process, stdin, stdout_rx) =
-            spawn_server_process(self.max_memory_buffer_size, &self.server_jar_path)?;
-        self.process = process;
-        self.stdin = stdin;
-        self.stdout = stdout_rx;
+    /// Returns the path to the directory where the Minecraft server keeps its
+    /// files, like `server.properties` and the world directory.
+    ///
+    /// That's the `server_dir` from the [WrapperConfig] if it's set, and the
+    /// wrapper's working directory otherwise, since that's where the server
+    /// runs. It's only the jar's directory if the working directory can't be
+    /// worked out. Every feature that reads or writes the server's files uses
+    /// this.
+    pub fn server_dir(&self) -> PathBuf {
+        self.server_dir.clone()
+    }
 
-        self.wait_for_server_to_spin_up();
-        Ok(tarball_path)
+    /// Returns the directory that world backups are written to. That's the
+    /// `backup_dir` from the [WrapperConfig] if it's set, and the server's
+    /// directory otherwise.
+    pub fn backup_dir(&self) -> PathBuf {
+        self.backup_dir.path().to_path_buf()
     }
 
-    /// Compresses the `world/` directory where the Minecraft server saves all
-    /// its info about the world and the players who play on it. Returns the
-    /// [PathBuf] to that tarball.
+    /// Runs `f` with the command timeout set to `timeout` instead of the
+    /// configured one, if a `timeout` is provided. The configured one is put
+    /// back afterwards.
+    pub fn with_command_timeout<T>(
+        &mut self,
+        timeout: Option<Duration>,
+        f: impl FnOnce(&mut Wrapper) -> T,
+    ) -> T {
+        let configured = self.config.command_timeout;
+        if let Some(timeout) = timeout {
+            self.config.command_timeout = timeout;
+        }
+        let result = f(self);
+        self.config.command_timeout = configured;
+        result
+    }
+
+    /// Gives the Minecraft server the provided command, and returns the first
+    /// line that the server writes to stdout afterwards that matches
+    /// `response_pattern`. Anything else that the server writes in the
+    /// meantime, like players chatting or dying, is skipped. See
+    /// [Wrapper::wait_for_line()].
     ///
-    /// Creates a compressed tarball with the current timestamp as the file
-    /// name. Ex: "2022-01-01 00:00:00.000000 UTC.tar.gz"
-    fn compress_world_dir(&self) -> anyhow::Result<PathBuf> {
-        let mc_server_root_dir_path = Path::new(&self.server_jar_path)
-            .parent()
-            .ok_or(anyhow!("Failed to get the parent directory of the path to the server jar. Double check the \"server_jar_path\" value in mc-server-wrapper's config.yaml"))?
-            .to_path_buf();
+    /// Returns an error if no matching line comes in within the command
+    /// timeout. If the command is `idempotent`, meaning that running it more
+    /// than once is harmless, it's re-sent up to `command_retries` times before
+    /// giving up, waiting twice as long each time. Never mark a command that
+    /// changes something, like "/kick", as idempotent.
+    ///
+    /// How it went is counted in the [CommandStats].
+    fn run_command_capture(
+        &mut self,
+        cmd: &str,
+        response_pattern: &Regex,
+        idempotent: bool,
+    ) -> anyhow::Result<String> {
+        let result = self.try_run_command_capture(cmd, response_pattern, idempotent);
+        self.command_stats.record(cmd, &result);
+        result
+    }
 
-        let cur_timestamp = Utc::now().to_string();
-        // TODO: For now, create the tarball in the dir that the shell session
-        // which launched the `mc-server-wrapper` binary is in. Later, though,
-        // make this tarball in a dir specified in config.yaml.
-        let mut tarball_path = mc_server_root_dir_path.clone();
-        tarball_path.push(format!("{}.tar.gz", cur_timestamp));
+    /// Does the work for [Wrapper::run_command_capture()], without counting
+    /// how it went.
+    fn try_run_command_capture(
+        &mut self,
+        cmd: &str,
+        response_pattern: &Regex,
+        idempotent: bool,
+    ) -> anyhow::Result<String> {
+        let retries = if idempotent {
+            self.config.command_retries
+        } else {
+            0
+        };
 
-        let tarball_file = File::create(&tarball_path)
-            .with_context(|| format!("Failed to create new tarball at {:?}", &tarball_path))?;
-        let encoder = GzEncoder::new(tarball_file, Compression::default());
-        let mut tarball = tar::Builder::new(encoder);
+        let mut timeout = self.config.command_timeout;
+        let mut last_err = None;
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                timeout *= 2;
+            }
 
-        let mut world_dir_path = mc_server_root_dir_path.clone();
-        world_dir_path.push("world");
+            self.expect_response(response_pattern, timeout);
+            self.run_custom_command(cmd)?;
+            match self.wait_for_line(response_pattern, timeout) {
+                Ok(line) => return Ok(line),
+                Err(e) => last_err = Some(e),
+            }
+        }
 
-        tarball.append_dir_all(&mc_server_root_dir_path, &world_dir_path)?;
-        tarball
-            .finish()
-            .with_context(|| "Failed to finish writing the world/ into a tarball")?;
+        Err(last_err
+            .unwrap()
+            .context(format!("The Minecraft server didn't respond to {:?}", cmd)))
+    }
 
-        Ok(tarball_path)
+    /// Counts the provided result of checking the Minecraft server's response to
+    /// a command as a failure of that command if it's an error, and returns
+    /// it.
+    fn check_response<T>(&self, cmd: &str, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        if let Err(e) = &result {
+            self.command_stats.record_rejection(cmd, e);
+        }
+        result
+    }
+
+    /// Keeps the response to a command that the wrapper is about to send on
+    /// its own from being printed on the host, if `suppress_command_echo` is
+    /// on.
+    fn expect_response(&self, response_pattern: &Regex, timeout: Duration) {
+        if self.config.suppress_command_echo {
+            self.echo_filter
+                .expect(response_pattern, Instant::now() + timeout);
+        }
     }
 
     /// Gives the Minecraft server the provided custom command. This function
@@ -202,8 +3106,26 @@ impl Wrapper {
     ///
     /// The provided `cmd` string doesn't need a trailing newline `\n`
     /// character.
-    pub fn run_custom_command(&mut self, cmd: &str) -> io::Result<()> {
-        self.disregard_irrelevant_stdout_contents()?;
+    ///
+    /// Returns a [WrapperError::ProcessExited](error::WrapperError::ProcessExited)
+    /// if the server can't be given commands anymore. When that happens, the
+    /// server process is killed to make sure that it's really gone, which lets
+    /// the watchdog restart it if `auto_restart` is on.
+    pub fn run_custom_command(&mut self, cmd: &str) -> anyhow::Result<()> {
+        // The stdout channel is only closed once the server process has closed
+        // its stdout, which it does when it exits.
+        if let Err(e) = self.disregard_irrelevant_stdout_contents() {
+            return Err(self.treat_server_as_dead(e));
+        }
+        // With read_stderr_as_logs on, the stdout channel stays open after the
+        // thread that reads stdout stops, so the server's responses would never
+        // come in.
+        if self.stdout_reader_exited.load(Ordering::SeqCst) {
+            return Err(self.treat_server_as_dead(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Stopped reading the Minecraft server's stdout",
+            )));
+        }
 
         // Make sure the command is suffixed with a newline char. This is
         // necessary because the Minecraft server waits until a newline char
@@ -215,8 +3137,118 @@ impl Wrapper {
             format!("{}\n", cmd)
         };
 
-        self.stdin.write_all(cmd_with_newline.as_bytes())?;
-        Ok(())
+        match self.write_to_stdin(cmd_with_newline.as_bytes()) {
+            Ok(()) => Ok(()),
+            // The other end of the pipe is gone, or it's been full for so long
+            // that nothing can be written to it. Either way, the server isn't
+            // reading its commands anymore.
+            Err(e)
+                if e.kind() == io::ErrorKind::BrokenPipe
+                    || e.kind() == io::ErrorKind::WouldBlock =>
+            {
+                Err(self.treat_server_as_dead(e))
+            }
+            Err(e) => {
+                Err(e).with_context(|| "Failed to write to the Minecraft server process's stdin")
+            }
+        }
+    }
+
+    /// Gives the Minecraft server the provided custom command, and returns
+    /// every line that it writes to stdout in response.
+    ///
+    /// The wrapper doesn't know what an arbitrary command prints, so lines are
+    /// collected until the server goes quiet for a moment after it starts
+    /// responding, or until the command timeout runs out, whichever comes
+    /// first. A command that doesn't print anything returns no lines once the
+    /// timeout runs out. Anything else that the server writes in the meantime,
+    /// like a player chatting, is returned too.
+    ///
+    /// Returns a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument)
+    /// if `cmd` is empty or spans more than one line, since the server would
+    /// take every line as a separate command.
+    pub fn run_command_capture_output(&mut self, cmd: &str) -> anyhow::Result<Vec<String>> {
+        let cmd = cmd.trim_end_matches(['\r', '\n']);
+        if cmd.trim().is_empty() || cmd.contains(['\r', '\n']) {
+            return Err(error::WrapperError::InvalidArgument(
+                "Commands have to be exactly one line long".to_owned(),
+            )
+            .into());
+        }
+        self.run_custom_command(cmd)?;
+
+        let deadline = Instant::now() + self.config.command_timeout;
+        let mut lines = Vec::new();
+        loop {
+            let mut wait = deadline.saturating_duration_since(Instant::now());
+            if !lines.is_empty() {
+                wait = wait.min(COMMAND_OUTPUT_QUIET_PERIOD);
+            }
+            match self.stdout.recv_timeout(wait) {
+                Ok(line) => lines.push(line),
+                Err(RecvTimeoutError::Timeout) => return Ok(lines),
+                Err(RecvTimeoutError::Disconnected) => {
+                    bail!("The stdout channel was closed unexpectedly")
+                }
+            }
+        }
+    }
+
+    /// Runs the provided command over RCON, and returns the Minecraft server's
+    /// whole response to it. Unlike [Wrapper::run_command_capture_output()],
+    /// the response is exactly what the command printed, without any log
+    /// prefixes or unrelated lines.
+    ///
+    /// Connects using the RCON settings in `server.properties` the first time
+    /// that it's called, and reuses that connection afterwards. Returns a
+    /// [WrapperError::NotSupported](error::WrapperError::NotSupported) if RCON
+    /// isn't turned on there. See [RconClient].
+    pub fn run_rcon_command(&mut self, cmd: &str) -> anyhow::Result<String> {
+        let cmd = cmd.trim().trim_start_matches('/');
+        let timeout = self.config.command_timeout;
+        let rcon = match &mut self.rcon {
+            Some(rcon) => rcon,
+            None => {
+                let properties = self.server_properties()?;
+                self.rcon
+                    .insert(RconClient::connect_with_properties(&properties, timeout)?)
+            }
+        };
+        let result = rcon
+            .set_timeout(timeout)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| rcon.command(cmd));
+        // The connection might be in a bad state, so start over next time.
+        if result.is_err() {
+            self.rcon = None;
+        }
+        result
+    }
+
+    /// Kills the Minecraft server process to make sure that it's really gone,
+    /// and returns a [WrapperError::ProcessExited](error::WrapperError::ProcessExited)
+    /// for callers to pass along.
+    ///
+    /// `cause` is whatever gave away that the server can't be talked to
+    /// anymore.
+    fn treat_server_as_dead(&mut self, cause: io::Error) -> anyhow::Error {
+        warn!(
+            "Lost contact with the Minecraft server process, so treating it as dead: {}",
+            cause
+        );
+        if let Err(e) = self.kill_server() {
+            warn!("Failed to kill the Minecraft server process: {}", e);
+        }
+        error::WrapperError::ProcessExited.into()
+    }
+
+    /// Writes the provided bytes to the Minecraft server's stdin.
+    ///
+    /// Flushes right away so that a server that isn't reading its stdin anymore
+    /// is noticed now, not the next time that something is written.
+    fn write_to_stdin(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stdin.write_all(bytes)?;
+        self.stdin.flush()
     }
 
     /// Reads all the lines written to stdout that haven't been processed yet,
@@ -230,48 +3262,467 @@ impl Wrapper {
     /// command against the server.
     fn disregard_irrelevant_stdout_contents(&mut self) -> io::Result<()> {
         loop {
-            if let Err(e) = self.stdout.try_recv() {
-                match e {
-                    mpsc::TryRecvError::Empty => return Ok(()),
-                    mpsc::TryRecvError::Disconnected => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::BrokenPipe,
-                            // TODO: Improve error message?
-                            "The stdout channel was closed unexpectedly",
-                        ));
+            match self.stdout.try_recv() {
+                // A server that didn't announce that it was ready in time
+                // might still get there eventually.
+                Ok(line) => {
+                    if self.readiness == Readiness::Unknown && SERVER_READY_PATTERN.is_match(&line)
+                    {
+                        self.readiness = Readiness::Ready;
                     }
                 }
+                Err(mpsc::TryRecvError::Empty) => return Ok(()),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        // TODO: Improve error message?
+                        "The stdout channel was closed unexpectedly",
+                    ));
+                }
             }
         }
     }
 }
 
-/// Starts a Minecraft server, captures stdin so we can interact with that
-/// server while it's running, and captures the contents of stdout so we can see
-/// what that server is up to.
-///
-/// This function spawns a separate thread which reads new lines that the server
-/// writes to stdout. When a new line comes in, it prints that line to stdout on
-/// the host for visibility, and it sends the line along a mpsc channel. Some
-/// consumer can then pull messages from this channel if it needs to parse
-/// messages that the Minecraft server produces.
-fn spawn_server_process(
-    max_memory_buffer_size: u16,
-    server_jar_path: &str,
-) -> anyhow::Result<(process::Child, process::ChildStdin, Receiver<String>)> {
-    let (stdout_tx, stdout_rx) = mpsc::channel::<String>();
+impl Drop for Wrapper {
+    fn drop(&mut self) {
+        // Don't leave a server process running without anything to manage it.
+        if let Ok(None) = self.process.try_wait() {
+            let _ = self.kill_server();
+        }
+    }
+}
+
+/// Parses the Minecraft server's response to the "/list" command, which looks
+/// something like this:
+/// [16:14:22] [Server thread/INFO]: There are 2 of a max of 20 players online: player1, player2
+fn parse_list_response(response: &str) -> anyhow::Result<PlayerList> {
+    let unexpected_response = || {
+        anyhow!(
+            "Unexpected response to the \"/list\" command: {:?}",
+            response
+        )
+    };
+
+    let (_, counts_and_players) = response
+        .split_once("There are ")
+        .ok_or_else(unexpected_response)?;
+    let (counts, players_as_str) = counts_and_players
+        .split_once(" players online:")
+        .ok_or_else(unexpected_response)?;
+    let (online, max) = counts
+        .split_once(" of a max of ")
+        .ok_or_else(unexpected_response)?;
+
+    let players = players_as_str
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect();
+    Ok(PlayerList {
+        online: online.trim().parse().map_err(|_| unexpected_response())?,
+        max: max.trim().parse().map_err(|_| unexpected_response())?,
+        players,
+    })
+}
+
+/// Returns the error for what kept the Minecraft server from starting, if the
+/// provided line that it wrote says that it can't start because of something
+/// that trying again won't fix.
+fn startup_failure(line: &str) -> Option<error::WrapperError> {
+    if let Some(captures) = WORLD_LOCKED_PATTERN.captures(line) {
+        return Some(error::WrapperError::WorldLocked(PathBuf::from(
+            &captures[1],
+        )));
+    }
+    if EULA_NOT_AGREED_PATTERN.is_match(line) {
+        return Some(error::WrapperError::EulaNotAccepted);
+    }
+    if PORT_IN_USE_PATTERN.is_match(line) {
+        return Some(error::WrapperError::PortInUse(line.trim().to_owned()));
+    }
+    if BAD_JAR_PATTERN.is_match(line) {
+        return Some(error::WrapperError::BadJar(line.trim().to_owned()));
+    }
+    None
+}
+
+/// Compiles the pattern of each of the provided startup prompts.
+fn compile_prompt_patterns(prompts: &[StartupPrompt]) -> anyhow::Result<Vec<Regex>> {
+    prompts
+        .iter()
+        .map(|prompt| {
+            Regex::new(&prompt.pattern).with_context(|| {
+                format!(
+                    "{:?} isn't a valid pattern for a startup prompt",
+                    &prompt.pattern
+                )
+            })
+        })
+        .collect()
+}
+
+/// Compiles the provided `stop_ready_pattern`, or the default one if there
+/// isn't one.
+fn compile_stop_ready_pattern(pattern: Option<&str>) -> anyhow::Result<Regex> {
+    let pattern = pattern.unwrap_or(DEFAULT_STOP_READY_PATTERN);
+    Regex::new(pattern)
+        .with_context(|| format!("{:?} isn't a valid pattern for stop_ready_pattern", pattern))
+}
+
+/// Sends each line that `reader` reads along `lines_tx` until it's closed.
+fn send_lines(reader: impl io::BufRead, lines_tx: mpsc::Sender<String>) {
+    for line in reader.lines().map_while(Result::ok) {
+        if lines_tx.send(line).is_err() {
+            return;
+        }
+    }
+}
+
+/// Returns a command that runs `java` with the environment from the
+/// [WrapperConfig].
+fn java_command(config: &WrapperConfig) -> process::Command {
+    let mut command = process::Command::new("java");
+    if config.server_env_clear {
+        command.env_clear();
+    }
+    command.envs(&config.server_env);
+    command
+}
 
-    let mut process = process::Command::new("java")
+/// Returns the command that starts the Minecraft server with the provided jar.
+fn server_command(config: &WrapperConfig, server_jar_path: &Path) -> process::Command {
+    let mut command = java_command(config);
+    command
         .args([
             // Just in case...
             // https://cve.mitre.org/cgi-bin/cvename.cgi?name=CVE-2021-44832
             // https://twitter.com/slicedlime/status/1469164192389287939
             "-Dlog4j2.formatMsgNoLookups=true",
-            &format!("-Xmx{}m", max_memory_buffer_size),
+            &format!("-Xmx{}m", config.max_memory.resolve()),
             "-jar",
-            server_jar_path,
-            "nogui",
         ])
+        .arg(server_jar_path)
+        .arg("nogui");
+    #[cfg(unix)]
+    if config.own_process_group {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command
+}
+
+/// Kills the provided Minecraft server process, and waits for it to exit. See
+/// [Wrapper::kill_server()].
+fn kill_process(process: &mut process::Child, own_process_group: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    if own_process_group {
+        // Once the server process has been reaped, its ID could belong to
+        // another process group already, so only signal the group while the
+        // server process is still around.
+        if process.try_wait()?.is_some() {
+            return Ok(());
+        }
+        // The process group's ID is the same as the server process's ID, since
+        // the server process is the group's leader. Passing a negative ID to
+        // kill() signals every process in the group.
+        //
+        // SAFETY: kill() doesn't touch any memory that we own.
+        let result = unsafe { libc::kill(-(process.id() as libc::pid_t), libc::SIGKILL) };
+        if result != 0 {
+            let e = io::Error::last_os_error();
+            // ESRCH means that the group is already gone, which is what we
+            // wanted anyways. Reap the process below either way.
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(e);
+            }
+        }
+        process.wait()?;
+        return Ok(());
+    }
+
+    process.kill()?;
+    process.wait()?;
+    Ok(())
+}
+
+/// Verifies the world backup that was just written to the provided path, and
+/// logs how it went. Returns [None] if it couldn't be verified at all.
+fn verify_new_backup(
+    tarball_path: &Path,
+    encryption: Option<&BackupEncryption>,
+    min_free: u64,
+) -> Option<BackupVerification> {
+    match verification::verify(tarball_path, encryption, min_free) {
+        Ok(verification) => {
+            match &verification.error {
+                None => info!("Verified the new world backup at {:?}", tarball_path),
+                Some(e) => error!(
+                    "The new world backup at {:?} failed verification, so no old backups were deleted: {}",
+                    tarball_path, e
+                ),
+            }
+            Some(verification)
+        }
+        Err(e) => {
+            warn!(
+                "Couldn't verify the new world backup at {:?}: {:#}",
+                tarball_path, e
+            );
+            None
+        }
+    }
+}
+
+/// Works out what to rename to where to swap the world backup that was
+/// unpacked into `staging_dir` in for the `dimensions` of the world in
+/// `world_dir`. What's replaced goes in `aside_dir`.
+fn restore_moves(
+    server_dir: &Path,
+    world_dir: &Path,
+    staging_dir: &Path,
+    aside_dir: &Path,
+    contents: &backup::TarballContents,
+) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let dimensions = &contents.dimensions;
+    let mut moves = Vec::new();
+    if world_dir.exists() {
+        let aside_world_dir = aside_dir.join(world_dir.file_name().unwrap_or_default());
+        moves.push((world_dir.to_path_buf(), aside_world_dir.clone()));
+        // Whatever the backup doesn't replace is moved back into the restored
+        // world.
+        for dir in dimension::find_dimension_dirs(world_dir) {
+            let replaced = dir.dimension.is_some_and(|d| dimensions.contains(&d));
+            if dir.dimension == Some(Dimension::Overworld) {
+                if replaced {
+                    continue;
+                }
+                let entries = fs::read_dir(world_dir)
+                    .with_context(|| format!("Failed to read {:?}", world_dir))?;
+                for entry in entries {
+                    let name = entry?.file_name();
+                    if dimension::dimension_of(&name) == Dimension::Overworld {
+                        moves.push((aside_world_dir.join(&name), staging_dir.join(&name)));
+                    }
+                }
+            } else if dir.path.starts_with(world_dir) {
+                if !replaced {
+                    moves.push((
+                        aside_world_dir.join(&dir.archive_path),
+                        staging_dir.join(&dir.archive_path),
+                    ));
+                }
+            } else if replaced {
+                // Bukkit-based servers keep this dimension in a world
+                // directory of its own, like "world_nether/DIM-1". They'd keep
+                // using it instead of the restored one if it were left there.
+                if let Some(bukkit_world_dir) = dir.path.parent() {
+                    if let Some(name) = bukkit_world_dir.file_name() {
+                        moves.push((bukkit_world_dir.to_path_buf(), aside_dir.join(name)));
+                    }
+                }
+            }
+        }
+    }
+    // The extra directories are put back next to the world, and whatever's
+    // there already is moved aside along with it.
+    let staged_extra_dirs = staging_dir.join(dimension::EXTRA_DIRS_ARCHIVE_DIR);
+    for name in &contents.extra_dirs {
+        let dir = server_dir.join(name);
+        if fs::symlink_metadata(&dir).is_ok() {
+            moves.push((dir.clone(), aside_dir.join(name)));
+        }
+        moves.push((staged_extra_dirs.join(name), dir));
+    }
+    moves.push((staging_dir.to_path_buf(), world_dir.to_path_buf()));
+    Ok(moves)
+}
+
+/// Returns an error if the provided `backup_extra_dirs` can't go in a backup
+/// together, like if two of them have the same name.
+fn check_backup_extra_dirs(extra_dirs: &[PathBuf], world_dir: &Path) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    for dir in extra_dirs {
+        let name = dir.file_name().ok_or_else(|| {
+            error::WrapperError::InvalidArgument(format!(
+                "{:?} in backup_extra_dirs doesn't have a name of its own",
+                dir
+            ))
+        })?;
+        if Some(name) == world_dir.file_name() {
+            return Err(error::WrapperError::InvalidArgument(format!(
+                "{:?} in backup_extra_dirs has the same name as the world directory, which is always backed up",
+                dir
+            ))
+            .into());
+        }
+        if !names.insert(name) {
+            return Err(error::WrapperError::InvalidArgument(format!(
+                "More than one directory in backup_extra_dirs is named {:?}",
+                name
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Renames each path to where it goes, in order. If one of them fails, the
+/// ones that were already renamed are put back.
+fn rename_all(moves: &[(PathBuf, PathBuf)]) -> anyhow::Result<()> {
+    for (i, (from, to)) in moves.iter().enumerate() {
+        if let Err(e) = fs::rename(from, to) {
+            for (from, to) in moves[..i].iter().rev() {
+                if let Err(e) = fs::rename(to, from) {
+                    error!("Failed to move {:?} back to {:?}: {}", to, from, e);
+                }
+            }
+            return Err(e).with_context(|| format!("Failed to move {:?} to {:?}", from, to));
+        }
+    }
+    Ok(())
+}
+
+/// Deletes a staging directory that a backup was copied into. Something going
+/// wrong is only logged, since the backup itself is already done or failed.
+fn remove_staging_dir(staging_dir: &Path) {
+    match fs::remove_dir_all(staging_dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => warn!(
+            "Failed to delete the backup staging directory at {:?}: {}",
+            staging_dir, e
+        ),
+    }
+}
+
+/// Expands a leading "~" in the provided path to the home directory, and
+/// "$VAR" and "${VAR}" to the environment variables with those names. Returns
+/// a [WrapperError::InvalidArgument](error::WrapperError::InvalidArgument) if
+/// one of them isn't set.
+fn expand_path(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = path.to_string_lossy();
+    let mut expanded = String::new();
+    let mut rest: &str = &path;
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let home = directories::BaseDirs::new().ok_or_else(|| {
+            error::WrapperError::InvalidArgument(format!(
+                "{:?} starts with ~, but the home directory couldn't be found",
+                &path
+            ))
+        })?;
+        expanded.push_str(&home.home_dir().to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remaining) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => (braced, ""),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                after.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            // Just a dollar sign.
+            expanded.push('$');
+        } else {
+            let value = std::env::var(name).map_err(|_| {
+                error::WrapperError::InvalidArgument(format!(
+                    "{:?} mentions ${}, but that environment variable isn't set",
+                    &path, name
+                ))
+            })?;
+            expanded.push_str(&value);
+        }
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Works out which directory the Minecraft server runs in. See
+/// [Wrapper::server_dir()].
+fn resolve_server_dir(config: &WrapperConfig) -> PathBuf {
+    if let Some(server_dir) = &config.server_dir {
+        return server_dir.clone();
+    }
+    std::env::current_dir().unwrap_or_else(|e| {
+        let jar_dir = Path::new(&config.server_jar_path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        warn!(
+            "Couldn't work out the wrapper's working directory, so assuming that the Minecraft server keeps its files in {:?}: {}",
+            &jar_dir, e
+        );
+        jar_dir
+    })
+}
+
+/// Deletes the world directory's `session.lock` file, if there is one.
+fn remove_session_lock(server_dir: &Path) -> anyhow::Result<()> {
+    let path = properties::world_dir(server_dir).join(SESSION_LOCK_FILE_NAME);
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            warn!("Deleted {:?} before starting the Minecraft server", &path);
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to delete {:?}", &path)),
+    }
+}
+
+/// Starts a Minecraft server, captures stdin so we can interact with that
+/// server while it's running, and captures the contents of stdout so we can see
+/// what that server is up to.
+///
+/// This function spawns a separate thread which reads new lines that the server
+/// writes to stdout. When a new line comes in, it prints that line to stdout on
+/// the host for visibility, sends any [ServerEvent] it describes to the provided
+/// broadcast channel, and it sends the line along a channel that holds up to
+/// `stdout_channel_capacity` lines. Some consumer can then pull messages from
+/// this channel if it needs to parse messages that the Minecraft server
+/// produces. If `read_stderr_as_logs` is set, stderr is read the same way, and
+/// its lines go along the same channel.
+fn spawn_server_process(
+    config: &WrapperConfig,
+    server_dir: &Path,
+    events_tx: broadcast::Sender<ServerEvent>,
+    echo_filter: Arc<EchoFilter>,
+    prompt_patterns: Arc<Vec<Regex>>,
+    logs: Arc<LogBuffer>,
+    stdout_stats: StdoutStats,
+) -> anyhow::Result<(
+    process::Child,
+    process::ChildStdin,
+    LineReceiver,
+    Arc<AtomicBool>,
+)> {
+    let (stdout_tx, stdout_rx) =
+        line_channel::channel(config.stdout_channel_capacity, stdout_stats);
+
+    if config.force_unlock {
+        remove_session_lock(server_dir)?;
+    }
+
+    // The server runs in its own directory, so the jar's path can't be
+    // relative to the wrapper's.
+    let server_jar_path = std::path::absolute(&config.server_jar_path).with_context(|| {
+        format!(
+            "Failed to resolve the server jar's path, {:?}",
+            &config.server_jar_path
+        )
+    })?;
+    let mut process = server_command(config, &server_jar_path)
+        .current_dir(server_dir)
         .stdin(process::Stdio::piped())
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::piped())
@@ -285,23 +3736,533 @@ fn spawn_server_process(
     let stdout_reader = io::BufReader::new(process.stdout.take().with_context(|| {
         "Failed to capture stdout of the newly-spawned Minecraft server process"
     })?);
+    // Shared by both output streams, since the server might announce that it's
+    // ready on either of them.
+    let starting = Arc::new(AtomicBool::new(true));
+    let recent_lines = config
+        .read_stderr_as_logs
+        .then(|| Arc::new(Mutex::new(RecentLines::default())));
+    if let Some(recent_lines) = &recent_lines {
+        let stderr_reader = io::BufReader::new(process.stderr.take().with_context(|| {
+            "Failed to capture stderr of the newly-spawned Minecraft server process"
+        })?);
+        let forwarder = LineForwarder {
+            stream: OutputStream::Stderr,
+            lines_tx: stdout_tx.clone(),
+            events_tx: events_tx.clone(),
+            recent_lines: Some(Arc::clone(recent_lines)),
+            starting: Arc::clone(&starting),
+            echo_filter: Arc::clone(&echo_filter),
+            prompt_patterns: Arc::clone(&prompt_patterns),
+            logs: Arc::clone(&logs),
+        };
+        thread::spawn(move || forwarder.run(stderr_reader));
+    }
     // Spawn a separate thread to read the messages the Minecraft server
-    // writes to stdout, and send those messages along the mpsc channel we
-    // were given.
-    thread::spawn(move || {
-        stdout_reader
-            .lines()
-            .filter_map(|line| line.ok())
-            .for_each(|line| {
-                // Print each line for visibility.
-                println!("{}", line);
-                // TODO: Revisit this .unwrap() call on send().
-                //
-                // Do we even want to handle errors here? A Q&D solution
-                // might be to just drop stdout messages that fail to send.
-                stdout_tx.send(line).unwrap()
-            });
+    // writes to stdout, and send those messages along the channel we were
+    // given.
+    let forwarder = LineForwarder {
+        stream: OutputStream::Stdout,
+        lines_tx: stdout_tx,
+        events_tx,
+        recent_lines,
+        starting,
+        echo_filter,
+        prompt_patterns,
+        logs,
+    };
+    let stdout_reader_exited = Arc::new(AtomicBool::new(false));
+    thread::spawn({
+        let exited = RaiseOnDrop(Arc::clone(&stdout_reader_exited));
+        move || {
+            let _exited = exited;
+            forwarder.run(stdout_reader)
+        }
     });
 
-    Ok((process, stdin, stdout_rx))
+    Ok((process, stdin, stdout_rx, stdout_reader_exited))
+}
+
+#[cfg(all(test, unix))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    // Stands in for java. Acts like a Minecraft server that spins up right
+    // away, and that stops when it's told to. It writes its environment to an
+    // "environment" file next to the world when it starts. Once there's a "restoring" file
+    // next to the world, a "crash" file in the world keeps it from spinning
+    // up, and an "exit" file makes it exit right after it spins up. A
+    // "close-stdout" file next to the world makes it close its stdout right
+    // after it spins up, and keep running, and a "close-stdin" file does the
+    // same with its stdin. "/crash" makes it exit without saying anything. It
+    // won't start while there's a "session.lock" file in the world, like a
+    // real server that another one is already using the world of.
+    const FAKE_SERVER: &str = r#"#!/bin/sh
+env > environment
+if [ -e world/session.lock ]; then
+    echo "net.minecraft.util.DirectoryLock\$LockException: ./world/session.lock: already locked (possibly by other Minecraft instance?)"
+    exit 1
+fi
+if [ -e restoring ] && [ -e world/crash ]; then
+    echo "[00:00:00] [Server thread/ERROR]: Failed to load the world"
+    exit 1
+fi
+echo '[00:00:00] [Server thread/INFO]: Done (0.1s)! For help, type "help"'
+if [ -e restoring ] && [ -e world/exit ]; then
+    exit 1
+fi
+if [ -e close-stdout ]; then
+    exec 1>&-
+fi
+if [ -e close-stdin ]; then
+    exec 0<&-
+    exec sleep 60
+fi
+while read -r line; do
+    if [ "$line" = "/crash" ]; then
+        exit 1
+    fi
+    if [ "$line" = "/stop" ]; then
+        echo "[00:00:00] [Server thread/INFO]: All dimensions are saved"
+        exit 0
+    fi
+done
+"#;
+
+    /// A server directory with a world in it, and a fake java to run it with,
+    /// which are deleted when it's dropped.
+    pub(crate) struct TestServer {
+        dir: TempDir,
+    }
+
+    impl TestServer {
+        pub(crate) fn new(name: &str) -> TestServer {
+            use std::os::unix::fs::PermissionsExt;
+
+            let dir = TempDir::new(name);
+            fs::create_dir_all(dir.join("bin")).unwrap();
+            fs::create_dir_all(dir.join("server/world/region")).unwrap();
+            fs::write(dir.join("server/world/level.dat"), "old").unwrap();
+            fs::write(dir.join("server/world/region/r.0.0.mca"), "old").unwrap();
+            let java = dir.join("bin/java");
+            fs::write(&java, FAKE_SERVER).unwrap();
+            fs::set_permissions(&java, fs::Permissions::from_mode(0o755)).unwrap();
+            TestServer { dir }
+        }
+
+        fn world_dir(&self) -> PathBuf {
+            self.dir.join("server/world")
+        }
+
+        /// Makes the fake server close its stdout once it spins up, from the
+        /// next time it starts.
+        pub(crate) fn close_stdout_after_starting(&self) {
+            fs::write(self.dir.join("server/close-stdout"), "").unwrap();
+        }
+
+        /// Returns the environment that the fake server last started with, as
+        /// "NAME=value" lines.
+        fn environment(&self) -> Vec<String> {
+            fs::read_to_string(self.dir.join("server/environment"))
+                .unwrap()
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        }
+
+        /// Makes the fake server close its stdin once it spins up, from the
+        /// next time it starts.
+        fn close_stdin_after_starting(&self) {
+            fs::write(self.dir.join("server/close-stdin"), "").unwrap();
+        }
+
+        pub(crate) fn config(&self) -> WrapperConfig {
+            let path = format!(
+                "{}:{}",
+                self.dir.join("bin").display(),
+                std::env::var("PATH").unwrap_or_default()
+            );
+            WrapperConfig {
+                server_jar_path: "server.jar".to_owned(),
+                server_dir: Some(self.dir.join("server")),
+                max_memory: MaxMemory::Megabytes(64),
+                own_process_group: false,
+                server_port: Some(25565),
+                command_timeout: Duration::from_secs(5),
+                command_retries: 0,
+                startup_timeout: Duration::from_secs(10),
+                startup_timeout_action: StartupTimeoutAction::FailHard,
+                maintenance_message: String::new(),
+                read_stderr_as_logs: false,
+                server_env: HashMap::from([("PATH".to_owned(), path)]),
+                server_env_clear: false,
+                backup_timeout: None,
+                force_unlock: false,
+                suppress_command_echo: false,
+                startup_prompts: Vec::new(),
+                stop_ready_pattern: None,
+                stdout_channel_capacity: 100,
+                log_buffer_lines: 0,
+                log_files: None,
+                backup_announce_message: None,
+                backup_complete_message: None,
+                backup_drain: None,
+                min_free_space_bytes: 0,
+                follow_symlinks: false,
+                backup_extra_dirs: Vec::new(),
+                backup_dir: Some(PathBuf::from("backups")),
+                backup_file_name_format: backup_files::DEFAULT_FILE_NAME_FORMAT.to_owned(),
+                backup_exclude: Vec::new(),
+                backup_compression: BackupCompression::default(),
+                backup_compression_level: None,
+                backup_encryption: None,
+                incremental_backups: None,
+                verify_backups: false,
+                restore_check: Duration::from_secs(1),
+                backup_hooks: None,
+                backup_retention: BackupRetention::default(),
+                backup_upload: None,
+                backup_upload_ssh: None,
+            }
+        }
+
+        /// Makes a world backup with a file with the provided name added to
+        /// the world, and returns its ID. The file is gone from the world
+        /// afterwards, and the fake server looks for it from then on.
+        fn back_up_with(&self, wrapper: &mut Wrapper, file_name: &str) -> String {
+            let path = self.world_dir().join(file_name);
+            fs::write(&path, "").unwrap();
+            wrapper
+                .make_world_backup(None, &BackupDetails::default())
+                .unwrap();
+            fs::remove_file(&path).unwrap();
+            fs::write(self.dir.join("server/restoring"), "").unwrap();
+            wrapper.list_backups().unwrap().pop().unwrap().id
+        }
+
+        fn leftover_restore_dirs(&self) -> Vec<String> {
+            fs::read_dir(self.dir.join("server"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| {
+                    name.starts_with(RESTORE_ASIDE_DIR_PREFIX) || name == RESTORE_STAGING_DIR_NAME
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn startup_failures_are_recognized() {
+        assert!(matches!(
+            startup_failure(
+                "net.minecraft.util.DirectoryLock$LockException: /srv/mc/./world/session.lock: already locked (possibly by other Minecraft instance?)"
+            ),
+            Some(error::WrapperError::WorldLocked(path)) if path == Path::new("/srv/mc/./world/session.lock")
+        ));
+        assert!(
+            startup_failure("[00:00:00] [Server thread/INFO]: Preparing level \"world\"").is_none()
+        );
+    }
+
+    #[test]
+    fn locked_world_fails_to_start() {
+        let server = TestServer::new("locked-world");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        fs::write(server.world_dir().join(SESSION_LOCK_FILE_NAME), "").unwrap();
+
+        let e = wrapper.restart_server().unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::WorldLocked(path)) if path == Path::new("./world/session.lock")
+        ));
+        assert!(server.world_dir().join(SESSION_LOCK_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn force_unlock_deletes_a_stale_session_lock() {
+        let server = TestServer::new("force-unlock");
+        fs::write(server.world_dir().join(SESSION_LOCK_FILE_NAME), "").unwrap();
+
+        let mut config = server.config();
+        config.force_unlock = true;
+        let mut wrapper = Wrapper::new(config).unwrap();
+        assert!(!server.world_dir().join(SESSION_LOCK_FILE_NAME).exists());
+        assert!(!wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn backup_is_turned_away_without_enough_free_space() {
+        let server = TestServer::new("insufficient-disk-space");
+        let mut config = server.config();
+        config.min_free_space_bytes = u64::MAX;
+        let mut wrapper = Wrapper::new(config).unwrap();
+
+        let e = wrapper
+            .make_world_backup(None, &BackupDetails::default())
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::InsufficientDiskSpace { .. })
+        ));
+        let leftovers: Vec<_> = fs::read_dir(server.dir.join("server/backups"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+        assert!(!wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn restore_that_fails_to_start_is_rolled_back() {
+        let server = TestServer::new("restore-fails-to-start");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let id = server.back_up_with(&mut wrapper, "crash");
+        fs::write(server.world_dir().join("level.dat"), "new").unwrap();
+
+        let e = wrapper.restore_backup(&id, false).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::RestoredWorldFailedToStart { .. })
+        ));
+        assert!(!server.world_dir().join("crash").exists());
+        assert_eq!(
+            fs::read_to_string(server.world_dir().join("level.dat")).unwrap(),
+            "new"
+        );
+        assert!(server.leftover_restore_dirs().is_empty());
+        assert!(!wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn restore_that_exits_after_starting_is_rolled_back() {
+        let server = TestServer::new("restore-exits-after-starting");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let id = server.back_up_with(&mut wrapper, "exit");
+
+        let e = wrapper.restore_backup(&id, false).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::RestoredWorldFailedToStart { .. })
+        ));
+        assert!(!server.world_dir().join("exit").exists());
+        assert!(server.leftover_restore_dirs().is_empty());
+        assert!(!wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn restore_that_starts_is_kept() {
+        let server = TestServer::new("restore-starts");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let id = server.back_up_with(&mut wrapper, "restored");
+
+        let plan = wrapper.restore_backup(&id, false).unwrap();
+        assert!(server.world_dir().join("restored").exists());
+        assert_eq!(server.leftover_restore_dirs(), vec![plan.moved_aside_to]);
+        assert!(!wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn closed_stdout_is_noticed() {
+        let server = TestServer::new("closed-stdout");
+        server.close_stdout_after_starting();
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        assert!(wait_until(|| wrapper.stdout_reader_died().unwrap()));
+
+        // Rather than waiting on a response that would never come.
+        let e = wrapper.run_custom_command("/list").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::ProcessExited)
+        ));
+        assert!(wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn closed_stdin_is_noticed() {
+        let server = TestServer::new("closed-stdin");
+        server.close_stdin_after_starting();
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        // Give the server a moment to close it.
+        thread::sleep(Duration::from_millis(200));
+        assert!(!wrapper.has_exited().unwrap());
+
+        let e = wrapper.run_custom_command("/list").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::ProcessExited)
+        ));
+        // Rather than being left running with no way to tell it anything.
+        assert!(wrapper.has_exited().unwrap());
+    }
+
+    #[test]
+    fn server_env_is_applied() {
+        let server = TestServer::new("server-env");
+        let mut config = server.config();
+        config.server_env.insert(
+            "MC_WRAPPER_TEST".to_owned(),
+            "a value with spaces".to_owned(),
+        );
+        // Something that the server would inherit from the wrapper, other than
+        // what the shell sets on its own.
+        let inherited = std::env::vars().find(|(name, value)| {
+            !config.server_env.contains_key(name)
+                && !matches!(name.as_str(), "PWD" | "OLDPWD" | "SHLVL" | "_")
+                && !value.contains('\n')
+        });
+
+        let mut wrapper = Wrapper::new(config.clone()).unwrap();
+        let environment = server.environment();
+        assert!(environment.contains(&"MC_WRAPPER_TEST=a value with spaces".to_owned()));
+        if let Some((name, value)) = &inherited {
+            assert!(environment.contains(&format!("{}={}", name, value)));
+        }
+        wrapper.stop_server().unwrap();
+        drop(wrapper);
+
+        config.server_env_clear = true;
+        let _wrapper = Wrapper::new(config).unwrap();
+        let environment = server.environment();
+        assert!(environment.contains(&"MC_WRAPPER_TEST=a value with spaces".to_owned()));
+        if let Some((name, _)) = &inherited {
+            assert!(!environment
+                .iter()
+                .any(|line| line.starts_with(&format!("{}=", name))));
+        }
+    }
+
+    /// Swaps the wrapper's stdout channel for one that the test sends lines
+    /// to, in place of the server.
+    fn fake_stdout(wrapper: &mut Wrapper) -> line_channel::LineSender {
+        let (tx, rx) = line_channel::channel(100, StdoutStats::default());
+        wrapper.stdout = rx;
+        tx
+    }
+
+    #[test]
+    fn wait_for_line_skips_lines_that_dont_match() {
+        let server = TestServer::new("wait-for-line");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let tx = fake_stdout(&mut wrapper);
+        for line in [
+            "[12:00:00] [Server thread/INFO]: Saving chunks for level 'world'",
+            // Players quoting what the server says don't count.
+            "[12:00:00] [Server thread/INFO]: <Steve> Saved the game",
+            "[12:00:00] [Server thread/INFO]: [Steve] Saved the game",
+            "[12:00:00] [Server thread/INFO]: * Steve Saved the game",
+            "[12:00:00] [Server thread/INFO]: Saved the game",
+            "[12:00:00] [Server thread/INFO]: Saved the game again",
+        ] {
+            tx.send(line.to_owned()).unwrap();
+        }
+
+        let pattern = Regex::new(r"\]: Saved the game").unwrap();
+        assert_eq!(
+            wrapper
+                .wait_for_line(&pattern, Duration::from_secs(1))
+                .unwrap(),
+            "[12:00:00] [Server thread/INFO]: Saved the game"
+        );
+        // Lines after the one that matched are left for whatever's next.
+        assert_eq!(
+            wrapper.stdout.try_recv().unwrap(),
+            "[12:00:00] [Server thread/INFO]: Saved the game again"
+        );
+    }
+
+    #[test]
+    fn wait_for_line_waits_for_lines_that_come_in_later() {
+        let server = TestServer::new("wait-for-line-later");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let tx = fake_stdout(&mut wrapper);
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            tx.send("[12:00:00] [Server thread/INFO]: Decoy".to_owned())
+                .unwrap();
+            thread::sleep(Duration::from_millis(50));
+            tx.send("[12:00:00] [Server thread/INFO]: Saved the game".to_owned())
+                .unwrap();
+            tx
+        });
+
+        let pattern = Regex::new(r"\]: Saved the game").unwrap();
+        assert!(wrapper
+            .wait_for_line(&pattern, Duration::from_secs(5))
+            .is_ok());
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_line_times_out() {
+        let server = TestServer::new("wait-for-line-timeout");
+        let mut wrapper = Wrapper::new(server.config()).unwrap();
+        let tx = fake_stdout(&mut wrapper);
+        tx.send("[12:00:00] [Server thread/INFO]: <Steve> Saved the game".to_owned())
+            .unwrap();
+
+        let pattern = Regex::new(r"\]: Saved the game").unwrap();
+        let timeout = Duration::from_millis(100);
+        let started_at = Instant::now();
+        let e = wrapper.wait_for_line(&pattern, timeout).unwrap_err();
+        assert!(started_at.elapsed() >= timeout);
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(error::WrapperError::OutputTimedOut { .. })
+        ));
+
+        // A closed channel isn't waited on at all.
+        drop(tx);
+        let started_at = Instant::now();
+        let e = wrapper
+            .wait_for_line(&pattern, Duration::from_secs(5))
+            .unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert!(e.downcast_ref::<error::WrapperError>().is_none());
+    }
+
+    #[test]
+    fn parses_list_responses() {
+        assert_eq!(
+            parse_list_response(
+                "[16:14:22] [Server thread/INFO]: There are 2 of a max of 20 players online: player1, player_2"
+            )
+            .unwrap(),
+            PlayerList {
+                online: 2,
+                max: 20,
+                players: vec!["player1".to_owned(), "player_2".to_owned()],
+            }
+        );
+        assert_eq!(
+            parse_list_response(
+                "[16:14:22] [Server thread/INFO]: There are 0 of a max of 10 players online: "
+            )
+            .unwrap(),
+            PlayerList {
+                online: 0,
+                max: 10,
+                players: Vec::new(),
+            }
+        );
+        for response in [
+            "[16:14:22] [Server thread/INFO]: Unknown command",
+            "[16:14:22] [Server thread/INFO]: There are two of a max of 20 players online: a, b",
+            "[16:14:22] [Server thread/INFO]: There are 2/20 players online: a, b",
+        ] {
+            assert!(parse_list_response(response).is_err(), "{:?}", response);
+        }
+    }
+
+    /// Returns whether `done` returns true within a few seconds.
+    pub(crate) fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
 }
@@ -0,0 +1,217 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvTimeoutError, SendError, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::stats::StdoutStats;
+
+/// Returns both ends of a channel for lines that the Minecraft server wrote,
+/// which holds at most `capacity` lines.
+///
+/// Sending never blocks. When the channel is full, the oldest line in it is
+/// dropped to make room, and counted in `stats`. The threads that read the
+/// server's output can't wait for room, since the server would block on its
+/// own writes once the pipe between them filled up, and stop responding
+/// altogether.
+pub(crate) fn channel(capacity: usize, stats: StdoutStats) -> (LineSender, LineReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            lines: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        available: Condvar::new(),
+        capacity,
+        stats,
+    });
+    (
+        LineSender {
+            shared: Arc::clone(&shared),
+        },
+        LineReceiver { shared },
+    )
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Notified whenever a line is sent, or the last sender is dropped.
+    available: Condvar,
+    capacity: usize,
+    stats: StdoutStats,
+}
+
+struct State {
+    lines: VecDeque<String>,
+    // How many [LineSender]s are still around. The channel is closed once
+    // there aren't any left.
+    senders: usize,
+    receiver_alive: bool,
+}
+
+/// The sending end of a [channel()]. Can be cloned to send from more than one
+/// thread.
+pub(crate) struct LineSender {
+    shared: Arc<Shared>,
+}
+
+impl LineSender {
+    /// Sends a line, dropping the oldest one in the channel if it's full.
+    ///
+    /// Returns the line back if the receiving end is gone.
+    pub(crate) fn send(&self, line: String) -> Result<(), SendError<String>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(SendError(line));
+        }
+        if state.lines.len() >= self.shared.capacity {
+            state.lines.pop_front();
+            self.shared.stats.record_dropped_line();
+        }
+        state.lines.push_back(line);
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for LineSender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        LineSender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for LineSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.available.notify_all();
+        }
+    }
+}
+
+/// The receiving end of a [channel()]. Works like a
+/// [Receiver](std::sync::mpsc::Receiver), and returns the same errors.
+pub(crate) struct LineReceiver {
+    shared: Arc<Shared>,
+}
+
+impl LineReceiver {
+    /// Returns the oldest line in the channel without blocking.
+    pub(crate) fn try_recv(&self) -> Result<String, TryRecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.lines.pop_front() {
+            Some(line) => Ok(line),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Blocks for up to `timeout` until there's a line in the channel, and
+    /// returns the oldest one.
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<String, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(line) = state.lines.pop_front() {
+                return Ok(line);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .available
+                .wait_timeout(state, remaining)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl Drop for LineReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiver_alive = false;
+        // Nothing is ever going to read these.
+        state.lines.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn full_channel_drops_the_oldest_lines() {
+        let stats = StdoutStats::default();
+        let (tx, rx) = channel(3, stats.clone());
+        for i in 0..5 {
+            tx.send(format!("line {}", i)).unwrap();
+        }
+        assert_eq!(stats.dropped_lines(), 2);
+        assert_eq!(rx.try_recv().unwrap(), "line 2");
+        assert_eq!(rx.try_recv().unwrap(), "line 3");
+        assert_eq!(rx.try_recv().unwrap(), "line 4");
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        // There's room again once the lines are read.
+        tx.send("line 5".to_owned()).unwrap();
+        assert_eq!(stats.dropped_lines(), 2);
+        assert_eq!(rx.try_recv().unwrap(), "line 5");
+    }
+
+    #[test]
+    fn closes_once_every_sender_is_gone() {
+        let (tx, rx) = channel(3, StdoutStats::default());
+        let tx2 = tx.clone();
+        tx.send("first".to_owned()).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv().unwrap(), "first");
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        let reader = thread::spawn(move || rx.recv_timeout(Duration::from_secs(5)));
+        drop(tx2);
+        assert_eq!(reader.join().unwrap(), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn recv_timeout_waits_for_a_line() {
+        let (tx, rx) = channel(3, StdoutStats::default());
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            tx.send("late".to_owned()).unwrap();
+            tx
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "late");
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn sending_fails_once_the_receiver_is_gone() {
+        let (tx, rx) = channel(3, StdoutStats::default());
+        drop(rx);
+        assert_eq!(
+            tx.send("nobody".to_owned()),
+            Err(SendError("nobody".to_owned()))
+        );
+    }
+}
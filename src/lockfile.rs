@@ -0,0 +1,93 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Context;
+use log::warn;
+use sysinfo::{Pid, ProcessRefreshKind, System};
+
+use crate::error::WrapperError;
+
+// The file in the Minecraft server's directory that says which wrapper is
+// managing it.
+pub(crate) const LOCK_FILE_NAME: &str = ".mc-server-wrapper.lock";
+
+/// Keeps other wrappers from managing the same Minecraft server directory
+/// while it's held. The lockfile is deleted when this is dropped.
+#[derive(Debug)]
+pub(crate) struct ServerDirLock {
+    path: PathBuf,
+}
+
+impl ServerDirLock {
+    /// Creates a lockfile in `server_dir` holding this process's pid.
+    ///
+    /// Returns a [WrapperError::ServerDirLocked] if there's already a lockfile
+    /// there, and the process it names is still running. A lockfile left
+    /// behind by a process that isn't running anymore is taken over.
+    pub(crate) fn acquire(server_dir: &Path) -> anyhow::Result<ServerDirLock> {
+        let path = server_dir.join(LOCK_FILE_NAME);
+        // Only loops again after a stale lockfile is deleted, in case another
+        // wrapper snuck in and took it first.
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", process::id())
+                        .with_context(|| format!("Failed to write to {:?}", &path))?;
+                    return Ok(ServerDirLock { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create {:?}", &path));
+                }
+            }
+
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                // It was deleted in the meantime, so try again.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+            };
+            match contents.trim().parse::<u32>() {
+                Ok(pid) if pid != process::id() && is_running(pid) => {
+                    return Err(WrapperError::ServerDirLocked { path, pid }.into());
+                }
+                Ok(pid) => warn!(
+                    "Taking over {:?}, which was left behind by a wrapper (pid {}) that isn't running anymore",
+                    &path, pid
+                ),
+                Err(_) => warn!(
+                    "Taking over {:?}, which doesn't hold a pid: {:?}",
+                    &path, contents
+                ),
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to delete {:?}", &path)),
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Failed to create {:?}. Another wrapper might be starting up against the same server directory",
+            &path
+        ))
+    }
+}
+
+impl Drop for ServerDirLock {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete {:?}: {}", &self.path, e),
+        }
+    }
+}
+
+/// Returns whether there's a process with the provided pid.
+fn is_running(pid: u32) -> bool {
+    System::new().refresh_process_specifics(Pid::from_u32(pid), ProcessRefreshKind::new())
+}
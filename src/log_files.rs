@@ -0,0 +1,194 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::Local;
+use regex::Regex;
+
+/// The name of the file that [LogFiles] is currently writing to. Older files
+/// are renamed to include when they were rotated.
+pub const CURRENT_LOG_FILE_NAME: &str = "mc-server-wrapper.log";
+
+// Rotated files are named something like
+// "mc-server-wrapper-2022-11-30_02-00-14.log". A number is added to the end in
+// case more than one file is rotated in the same second.
+static ROTATED_LOG_FILE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^mc-server-wrapper-\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2}(?:_\d+)?\.log$").unwrap()
+});
+
+/// Where [LogFiles] writes, and when it moves on to a new file.
+#[derive(Debug, Clone)]
+pub struct LogFilesConfig {
+    /// The directory to write log files in. It's created if it doesn't exist.
+    pub dir: PathBuf,
+    /// Move on to a new file before the current one grows past this many
+    /// bytes. Files never get too big if it's 0.
+    pub max_file_bytes: u64,
+    /// Move on to a new file once the current one is this old. When it's
+    /// [None], files never get too old.
+    pub max_file_age: Option<Duration>,
+    /// How many old files to keep around, on top of the current one. Older
+    /// ones are deleted.
+    pub files_to_keep: usize,
+}
+
+/// Log files that the Minecraft server's output can be written to, which are
+/// rotated once they get too big or too old.
+///
+/// Cheap to clone, and every clone writes to the same file, so the wrapper's
+/// own log messages can be written alongside the server's output.
+#[derive(Debug, Clone)]
+pub struct LogFiles {
+    inner: Arc<Mutex<Inner>>,
+}
+
+// Nothing in here uses the log crate's macros, since the wrapper's logger
+// writes to these files, too.
+#[derive(Debug)]
+struct Inner {
+    config: LogFilesConfig,
+    file: Option<File>,
+    // How many bytes were written to the current file.
+    size: u64,
+    opened_at: Instant,
+    // Whether the last write failed, so that a problem is only reported once.
+    failing: bool,
+}
+
+impl LogFiles {
+    /// Starts writing to a new file in the configured directory. A file that's
+    /// left over from the last time the wrapper ran is rotated first, the same
+    /// way the Minecraft server handles its `logs/latest.log` file.
+    pub fn open(config: LogFilesConfig) -> anyhow::Result<LogFiles> {
+        fs::create_dir_all(&config.dir).with_context(|| {
+            format!(
+                "Something went wrong while making a {:?} directory for log files to live in",
+                &config.dir
+            )
+        })?;
+        let dir = config.dir.clone();
+        let mut inner = Inner {
+            config,
+            file: None,
+            size: 0,
+            opened_at: Instant::now(),
+            failing: false,
+        };
+        inner
+            .rotate()
+            .with_context(|| format!("Failed to start a new log file in {:?}", dir))?;
+        Ok(LogFiles {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Appends a line to the current file, moving on to a new one first if the
+    /// current one is too big or too old.
+    ///
+    /// Problems are printed to stderr instead of being returned, since there's
+    /// nothing that the line's writer could do about them.
+    pub fn write_line(&self, line: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.write_line(line) {
+            Ok(()) => inner.failing = false,
+            Err(e) => {
+                if !inner.failing {
+                    eprintln!(
+                        "Failed to write to the log files in {:?}, and will keep trying: {}",
+                        &inner.config.dir, e
+                    );
+                }
+                inner.failing = true;
+                // Start over with a new file next time, in case this one is
+                // the problem.
+                inner.file = None;
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let too_big = self.config.max_file_bytes > 0
+            && self.size > 0
+            && self.size + len > self.config.max_file_bytes;
+        let too_old = self
+            .config
+            .max_file_age
+            .is_some_and(|max_age| self.opened_at.elapsed() >= max_age);
+        if self.file.is_none() || too_big || too_old {
+            self.rotate()?;
+        }
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+            self.size += len;
+        }
+        Ok(())
+    }
+
+    /// Renames the current file to include the time, deletes the oldest
+    /// rotated files beyond the ones to keep, and starts a new current file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let current = self.config.dir.join(CURRENT_LOG_FILE_NAME);
+        match fs::metadata(&current) {
+            Ok(metadata) if metadata.len() > 0 => {
+                fs::rename(&current, rotated_path(&self.config.dir))?;
+                delete_old_files(&self.config.dir, self.config.files_to_keep)?;
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.file = Some(File::create(&current)?);
+        self.size = 0;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+}
+
+/// Returns a path in the provided directory to rotate the current file to,
+/// which isn't taken yet.
+fn rotated_path(dir: &Path) -> PathBuf {
+    let stem = format!(
+        "mc-server-wrapper-{}",
+        Local::now().format("%Y-%m-%d_%H-%M-%S")
+    );
+    let mut path = dir.join(format!("{}.log", stem));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}_{}.log", stem, n));
+        n += 1;
+    }
+    path
+}
+
+/// Deletes the oldest rotated files in the provided directory, until there
+/// are only `files_to_keep` left.
+fn delete_old_files(dir: &Path, files_to_keep: usize) -> io::Result<()> {
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| ROTATED_LOG_FILE_PATTERN.is_match(name))
+        })
+        .collect();
+    if rotated.len() <= files_to_keep {
+        return Ok(());
+    }
+    // The names start with when the file was rotated, so they sort oldest
+    // first.
+    rotated.sort();
+    for path in &rotated[..rotated.len() - files_to_keep] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
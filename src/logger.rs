@@ -0,0 +1,68 @@
+use std::{env, sync::OnceLock};
+
+use chrono::Local;
+use log::{Log, Metadata, Record};
+use mc_server_wrapper::log_files::LogFiles;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Prints the wrapper's own log messages the same way pretty_env_logger does,
+/// and writes them to the log files, too, once there are some.
+struct Logger {
+    terminal: Box<dyn Log>,
+    files: OnceLock<LogFiles>,
+}
+
+/// Sets up the wrapper's logger. Which messages are logged is read from the
+/// `RUST_LOG` environment variable, just like with `pretty_env_logger::init()`.
+///
+/// Panics if it's called more than once.
+pub(crate) fn init() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let terminal = builder.build();
+    let max_level = terminal.filter();
+    let logger = LOGGER.get_or_init(|| Logger {
+        terminal: Box::new(terminal),
+        files: OnceLock::new(),
+    });
+    log::set_logger(logger).expect("The logger was already set up");
+    log::set_max_level(max_level);
+}
+
+/// Writes every message that's logged from now on to the provided files, too,
+/// in between the lines that the Minecraft server writes.
+pub(crate) fn write_to(files: LogFiles) {
+    if let Some(logger) = LOGGER.get() {
+        let _ = logger.files.set(files);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.terminal.log(record);
+        // Formatted like the Minecraft server's own lines, so that the two
+        // line up in the files.
+        if let Some(files) = self.files.get() {
+            files.write_line(&format!(
+                "[{}] [mc-server-wrapper/{}]: {}",
+                Local::now().format("%H:%M:%S"),
+                record.level(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::log_files::LogFiles;
+
+// How many of the most recent warnings and errors to remember.
+const RECENT_WARNINGS_CAPACITY: usize = 50;
+
+// Lines that the Minecraft server logs start with something like
+// "[12:34:56] [Server thread/INFO]: ".
+static LEVEL_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\[[^\]]*\] \[[^\]]*/(TRACE|DEBUG|INFO|WARN|ERROR|FATAL)\]: ").unwrap()
+});
+
+/// How severe a line that the Minecraft server logged is. Ordered from least
+/// to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    /// Returns the level that the provided line was logged at, if it says.
+    fn parse(line: &str) -> Option<LogLevel> {
+        let level = match &LEVEL_PATTERN.captures(line)?[1] {
+            "TRACE" => LogLevel::Trace,
+            "DEBUG" => LogLevel::Debug,
+            "INFO" => LogLevel::Info,
+            "WARN" => LogLevel::Warn,
+            "ERROR" => LogLevel::Error,
+            _ => LogLevel::Fatal,
+        };
+        Some(level)
+    }
+}
+
+/// A line that the Minecraft server wrote, and when the wrapper read it.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// When the wrapper read the line, in RFC 3339 format.
+    pub timestamp: String,
+    /// The level that the line was logged at. Lines that don't say, like the
+    /// ones in a stack trace, take the level of the line before them. [None]
+    /// if no line before them said, either.
+    pub level: Option<LogLevel>,
+    pub line: String,
+    #[serde(skip)]
+    read_at: DateTime<Utc>,
+}
+
+/// The most recent lines that any Minecraft server process wrote, kept across
+/// restarts. Also writes every line to log files, if there are any.
+#[derive(Debug)]
+pub(crate) struct LogBuffer {
+    state: Mutex<State>,
+    capacity: usize,
+    files: Option<LogFiles>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    lines: VecDeque<LogLine>,
+    // Kept apart from `lines` so that they don't get pushed out by the lines
+    // in between them.
+    warnings: VecDeque<String>,
+    // The level of the last line that said what its level was.
+    last_level: Option<LogLevel>,
+}
+
+impl LogBuffer {
+    /// Returns a buffer that holds up to `capacity` lines. The most recent
+    /// warnings and errors are kept on top of that, even if it's 0.
+    pub(crate) fn new(capacity: usize, files: Option<LogFiles>) -> LogBuffer {
+        LogBuffer {
+            state: Mutex::default(),
+            capacity,
+            files,
+        }
+    }
+
+    /// Remembers a line that the Minecraft server just wrote, dropping the
+    /// oldest one if the buffer is full.
+    pub(crate) fn push(&self, line: &str) {
+        if let Some(files) = &self.files {
+            files.write_line(line);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let own_level = LogLevel::parse(line);
+        if own_level.is_some() {
+            state.last_level = own_level;
+        }
+
+        if matches!(own_level, Some(LogLevel::Warn | LogLevel::Error)) {
+            if state.warnings.len() == RECENT_WARNINGS_CAPACITY {
+                state.warnings.pop_front();
+            }
+            state.warnings.push_back(line.to_owned());
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if state.lines.len() == self.capacity {
+            state.lines.pop_front();
+        }
+        let read_at = Utc::now();
+        let level = state.last_level;
+        state.lines.push_back(LogLine {
+            timestamp: read_at.to_rfc3339(),
+            level,
+            line: line.to_owned(),
+            read_at,
+        });
+    }
+
+    /// Returns the most recent lines that were logged at WARN or ERROR, oldest
+    /// first.
+    pub(crate) fn recent_warnings(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .warnings
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the last `tail` lines that were read after `since`, and that
+    /// were logged at `min_level` or above, oldest first. Each filter that's
+    /// [None] is left out.
+    pub(crate) fn lines(
+        &self,
+        tail: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        min_level: Option<LogLevel>,
+    ) -> Vec<LogLine> {
+        let state = self.state.lock().unwrap();
+        let mut lines: Vec<LogLine> = state
+            .lines
+            .iter()
+            .rev()
+            .take_while(|line| since.is_none_or(|since| line.read_at > since))
+            .filter(|line| min_level.is_none_or(|min| line.level.is_some_and(|l| l >= min)))
+            .take(tail.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        lines.reverse();
+        lines
+    }
+}
@@ -1,142 +1,1164 @@
+mod audit;
+mod auth;
+mod console;
 mod handlers;
+mod idempotency;
+mod jobs;
+mod logger;
+mod rcon_proxy;
 
 use std::{
+    collections::HashMap,
     error,
     fs::{self, File},
     io::{self, BufRead, Read, Write},
     net::SocketAddr,
+    path::PathBuf,
     process,
-    sync::{Arc, Mutex},
+    sync::{atomic::Ordering, Arc, Mutex},
     thread,
+    time::Duration,
 };
 
 use anyhow::{bail, Context};
-use axum::{routing::get, Router};
+use audit::{AuditLog, LOCAL_CONSOLE_IDENTITY};
+use auth::{Auth, TokenConfig};
+use axum::{
+    body::{Body, Bytes},
+    error_handling::HandleErrorLayer,
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Extension, Json, Path, Query},
+    http::Request,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use chrono::Utc;
 use directories::ProjectDirs;
-use log::{error, warn};
-use mc_server_wrapper::Wrapper;
+use idempotency::IdempotencyKeys;
+use jobs::Jobs;
+use log::{error, info, warn};
+use mc_server_wrapper::{
+    acl_watcher,
+    actor::WrapperHandle,
+    automation::{self, OnJoinCommand},
+    backup_files,
+    compression::BackupCompression,
+    encryption::BackupEncryption,
+    error::WrapperError,
+    forceload::ForceloadAction,
+    hooks::BackupHooks,
+    incremental::IncrementalBackups,
+    log_files::{LogFiles, LogFilesConfig},
+    memory::MaxMemory,
+    remote_backup::{S3Destination, SshDestination},
+    retention::BackupRetention,
+    schedule::{self, CronSchedule},
+    state::{ServerState, StateMachine, Transition},
+    watchdog::{self, ExitCondition, RestartBackoff},
+    BackupDrain, StartupPrompt, StartupTimeoutAction, Wrapper, WrapperConfig,
+};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
+use tower::{util::MapRequestLayer, ServiceBuilder};
+
+// How often to check whether a restart or backup has finished when waiting to
+// stop the Minecraft server.
+const BEGIN_STOPPING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Typed into the wrapper's stdin to stop and start printing the Minecraft
+// server's output.
+const PAUSE_CONSOLE_COMMAND: &str = "@pause";
+const RESUME_CONSOLE_COMMAND: &str = "@resume";
+// Replaces secrets in the config that GET /diagnostics responds with.
+const REDACTED: &str = "<redacted>";
 
 const DEFAULT_CONFIG_FILE_NAME: &str = "config.yaml";
 const DEFAULT_PORT: u16 = 6969;
+const DEFAULT_ENABLE_HTTP_API: bool = true;
+const DEFAULT_LANDING_PAGE: bool = true;
 // Assume that users run the mc-server-wrapper binary in the same directory as
 // their server.jar file.
 const DEFAULT_SERVER_JAR_PATH: &str = "server.jar";
-const DEFAULT_MAX_MEMORY_BUFFER_SIZE: u16 = 2048;
+const DEFAULT_MAX_MEMORY_BUFFER_SIZE: MaxMemory = MaxMemory::Megabytes(2048);
+const DEFAULT_AUTO_RESTART: bool = false;
+const DEFAULT_RESTART_CONFIRM_SECONDS: u64 = 5;
+const DEFAULT_HEALTH_CHECK_INTERVAL_SECONDS: u64 = 0;
+const DEFAULT_HEALTH_CHECK_FAILURE_THRESHOLD: u32 = 3;
+const DEFAULT_OWN_PROCESS_GROUP: bool = true;
+const DEFAULT_WATCH_ACL_FILES: bool = false;
+const DEFAULT_COMMAND_TIMEOUT_SECONDS: u64 = 5;
+const DEFAULT_COMMAND_RETRIES: u32 = 0;
+const DEFAULT_STARTUP_TIMEOUT_SECONDS: u64 = 300;
+const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 30;
+// 1 GiB.
+const DEFAULT_MIN_FREE_SPACE_BYTES: u64 = 1024 * 1024 * 1024;
+const DEFAULT_FOLLOW_SYMLINKS: bool = true;
+const DEFAULT_RESTORE_CHECK_SECONDS: u64 = 30;
+const DEFAULT_REJOIN_DEBOUNCE_SECONDS: u64 = 10;
+const DEFAULT_ROSTER_LOG_INTERVAL_MINUTES: u64 = 0;
+const DEFAULT_FORCE_UNLOCK: bool = false;
+const DEFAULT_SUPPRESS_COMMAND_ECHO: bool = false;
+const DEFAULT_STDOUT_CHANNEL_CAPACITY: usize = 10_000;
+const DEFAULT_LOG_BUFFER_LINES: usize = 1000;
+const DEFAULT_LOG_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_FILE_MAX_AGE_HOURS: u64 = 24;
+const DEFAULT_LOG_FILES_TO_KEEP: usize = 10;
+const DEFAULT_MAINTENANCE_MESSAGE: &str =
+    "The server is down for maintenance. Please check back later!";
+const DEFAULT_DRAIN_PLAYERS_BEFORE_BACKUP: bool = false;
+const DEFAULT_DRAIN_WARNING_SECONDS: u64 = 10;
+const DEFAULT_DRAIN_MESSAGE: &str = "The server is taking a backup. Please rejoin in a minute!";
+const DEFAULT_MAINTENANCE_DURING_BACKUP: bool = false;
+const DEFAULT_BACKUP_SCHEDULE_WARNINGS_SECONDS: [u64; 2] = [300, 60];
+const DEFAULT_BACKUP_SCHEDULE_WARNING_MESSAGE: &str =
+    "The server is going down for a backup in {time}.";
+const DEFAULT_READ_STDERR_AS_LOGS: bool = false;
+const DEFAULT_SERVER_ENV_CLEAR: bool = false;
+const DEFAULT_STARTUP_TIMEOUT_ACTION: StartupTimeoutAction = StartupTimeoutAction::FailHard;
 
 // TODO: Write doc comments for each of these fields.
+//
+// Fields that are missing from a config file fall back to their default
+// values, so config files written by older versions of mc-server-wrapper keep
+// working.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 struct Config {
     port: u16,
+    enable_http_api: bool,
+    landing_page: bool,
     server_jar_path: String,
-    max_memory_buffer_size: u16,
+    server_dir: Option<String>,
+    // Also accepted under the shorter "max_memory" key.
+    #[serde(alias = "max_memory")]
+    max_memory_buffer_size: MaxMemory,
+    auto_restart: bool,
+    auto_restart_on: Vec<ExitCondition>,
+    auto_restart_backoff: RestartBackoff,
+    restart_confirm_seconds: u64,
+    health_check_interval_seconds: u64,
+    health_check_failure_threshold: u32,
+    own_process_group: bool,
+    on_join_commands: Vec<OnJoinCommand>,
+    rejoin_debounce_seconds: u64,
+    roster_log_interval_minutes: u64,
+    watch_acl_files: bool,
+    server_port: Option<u16>,
+    command_timeout_seconds: u64,
+    command_retries: u32,
+    startup_timeout_seconds: u64,
+    startup_timeout_action: StartupTimeoutAction,
+    maintenance_message: String,
+    read_stderr_as_logs: bool,
+    server_env: HashMap<String, String>,
+    server_env_clear: bool,
+    audit_log_path: Option<String>,
+    backup_timeout_seconds: Option<u64>,
+    request_timeout_seconds: u64,
+    force_unlock: bool,
+    api_token: Option<String>,
+    tokens: Vec<TokenConfig>,
+    rcon_port: Option<u16>,
+    rcon_password: Option<String>,
+    suppress_command_echo: bool,
+    startup_prompts: Vec<StartupPrompt>,
+    stop_ready_pattern: Option<String>,
+    stdout_channel_capacity: usize,
+    log_buffer_lines: usize,
+    log_dir: Option<String>,
+    log_file_max_bytes: u64,
+    log_file_max_age_hours: u64,
+    log_files_to_keep: usize,
+    min_free_space_bytes: u64,
+    follow_symlinks: bool,
+    backup_extra_dirs: Vec<PathBuf>,
+    backup_dir: Option<PathBuf>,
+    backup_file_name_format: String,
+    backup_exclude: Vec<String>,
+    backup_compression: BackupCompression,
+    backup_compression_level: Option<u32>,
+    backup_encryption: Option<BackupEncryption>,
+    incremental_backups: Option<IncrementalBackups>,
+    verify_backups: bool,
+    restore_check_seconds: u64,
+    backup_hooks: Option<BackupHooks>,
+    backup_announce_message: Option<String>,
+    backup_complete_message: Option<String>,
+    drain_players_before_backup: bool,
+    drain_warning_seconds: u64,
+    drain_message: String,
+    maintenance_during_backup: bool,
+    backup_retention: BackupRetention,
+    backup_upload: Option<S3Destination>,
+    backup_upload_ssh: Option<SshDestination>,
+    backup_schedule: Option<String>,
+    backup_schedule_warnings_seconds: Vec<u64>,
+    backup_schedule_warning_message: String,
+    backup_schedule_skip_when_idle: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: DEFAULT_PORT,
+            enable_http_api: DEFAULT_ENABLE_HTTP_API,
+            landing_page: DEFAULT_LANDING_PAGE,
+            server_jar_path: DEFAULT_SERVER_JAR_PATH.to_string(),
+            server_dir: None,
+            max_memory_buffer_size: DEFAULT_MAX_MEMORY_BUFFER_SIZE,
+            auto_restart: DEFAULT_AUTO_RESTART,
+            auto_restart_on: vec![ExitCondition::Crash],
+            auto_restart_backoff: RestartBackoff::default(),
+            restart_confirm_seconds: DEFAULT_RESTART_CONFIRM_SECONDS,
+            health_check_interval_seconds: DEFAULT_HEALTH_CHECK_INTERVAL_SECONDS,
+            health_check_failure_threshold: DEFAULT_HEALTH_CHECK_FAILURE_THRESHOLD,
+            own_process_group: DEFAULT_OWN_PROCESS_GROUP,
+            on_join_commands: Vec::new(),
+            rejoin_debounce_seconds: DEFAULT_REJOIN_DEBOUNCE_SECONDS,
+            roster_log_interval_minutes: DEFAULT_ROSTER_LOG_INTERVAL_MINUTES,
+            watch_acl_files: DEFAULT_WATCH_ACL_FILES,
+            server_port: None,
+            command_timeout_seconds: DEFAULT_COMMAND_TIMEOUT_SECONDS,
+            command_retries: DEFAULT_COMMAND_RETRIES,
+            startup_timeout_seconds: DEFAULT_STARTUP_TIMEOUT_SECONDS,
+            startup_timeout_action: DEFAULT_STARTUP_TIMEOUT_ACTION,
+            maintenance_message: DEFAULT_MAINTENANCE_MESSAGE.to_string(),
+            read_stderr_as_logs: DEFAULT_READ_STDERR_AS_LOGS,
+            server_env: HashMap::new(),
+            server_env_clear: DEFAULT_SERVER_ENV_CLEAR,
+            audit_log_path: None,
+            backup_timeout_seconds: None,
+            request_timeout_seconds: DEFAULT_REQUEST_TIMEOUT_SECONDS,
+            force_unlock: DEFAULT_FORCE_UNLOCK,
+            api_token: None,
+            tokens: Vec::new(),
+            rcon_port: None,
+            rcon_password: None,
+            suppress_command_echo: DEFAULT_SUPPRESS_COMMAND_ECHO,
+            startup_prompts: Vec::new(),
+            stop_ready_pattern: None,
+            stdout_channel_capacity: DEFAULT_STDOUT_CHANNEL_CAPACITY,
+            log_buffer_lines: DEFAULT_LOG_BUFFER_LINES,
+            log_dir: None,
+            log_file_max_bytes: DEFAULT_LOG_FILE_MAX_BYTES,
+            log_file_max_age_hours: DEFAULT_LOG_FILE_MAX_AGE_HOURS,
+            log_files_to_keep: DEFAULT_LOG_FILES_TO_KEEP,
+            min_free_space_bytes: DEFAULT_MIN_FREE_SPACE_BYTES,
+            follow_symlinks: DEFAULT_FOLLOW_SYMLINKS,
+            backup_extra_dirs: Vec::new(),
+            backup_dir: None,
+            backup_file_name_format: backup_files::DEFAULT_FILE_NAME_FORMAT.to_owned(),
+            backup_exclude: Vec::new(),
+            backup_compression: BackupCompression::default(),
+            backup_compression_level: None,
+            backup_encryption: None,
+            incremental_backups: None,
+            verify_backups: false,
+            restore_check_seconds: DEFAULT_RESTORE_CHECK_SECONDS,
+            backup_hooks: None,
+            backup_announce_message: None,
+            backup_complete_message: None,
+            drain_players_before_backup: DEFAULT_DRAIN_PLAYERS_BEFORE_BACKUP,
+            drain_warning_seconds: DEFAULT_DRAIN_WARNING_SECONDS,
+            drain_message: DEFAULT_DRAIN_MESSAGE.to_string(),
+            maintenance_during_backup: DEFAULT_MAINTENANCE_DURING_BACKUP,
+            backup_retention: BackupRetention::default(),
+            backup_upload: None,
+            backup_upload_ssh: None,
+            backup_schedule: None,
+            backup_schedule_warnings_seconds: DEFAULT_BACKUP_SCHEDULE_WARNINGS_SECONDS.to_vec(),
+            backup_schedule_warning_message: DEFAULT_BACKUP_SCHEDULE_WARNING_MESSAGE.to_string(),
+            backup_schedule_skip_when_idle: false,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
-    pretty_env_logger::init();
+    logger::init();
+    let wrapper_started_at = Utc::now();
 
     // Initialize a Config with default values. If a config file is present on
     // disk, those defaults are replaced by that file's contents.
     let config = get_config()?;
 
+    // Writes everything that the Minecraft server and the wrapper log to files,
+    // if the user wants that.
+    let log_files = match &config.log_dir {
+        Some(dir) => {
+            let files = LogFiles::open(LogFilesConfig {
+                dir: PathBuf::from(dir),
+                max_file_bytes: config.log_file_max_bytes,
+                max_file_age: match config.log_file_max_age_hours {
+                    0 => None,
+                    hours => Some(Duration::from_secs(hours * 60 * 60)),
+                },
+                files_to_keep: config.log_files_to_keep,
+            })?;
+            logger::write_to(files.clone());
+            Some(files)
+        }
+        None => None,
+    };
+
+    // Records who asked the wrapper to change what, if the user wants that.
+    let audit_log = Arc::new(AuditLog::open(config.audit_log_path.as_deref())?);
+    // Decides which HTTP API requests are allowed through.
+    let auth = Arc::new(Auth::new(config.api_token.clone(), config.tokens.clone()));
+
+    config
+        .backup_compression
+        .check_level(config.backup_compression_level)
+        .with_context(|| "Failed to read backup_compression_level")?;
+    config
+        .auto_restart_backoff
+        .check()
+        .with_context(|| "Failed to read auto_restart_backoff")?;
+    if let Some(encryption) = &config.backup_encryption {
+        encryption
+            .check()
+            .with_context(|| "Failed to read backup_encryption")?;
+    }
+    if let Some(incremental) = &config.incremental_backups {
+        incremental
+            .check()
+            .with_context(|| "Failed to read incremental_backups")?;
+    }
+    if let Some(hooks) = &config.backup_hooks {
+        hooks
+            .check()
+            .with_context(|| "Failed to read backup_hooks")?;
+    }
+    if let Some(destination) = &config.backup_upload {
+        destination
+            .check()
+            .with_context(|| "Failed to read backup_upload")?;
+    }
+    if let Some(destination) = &config.backup_upload_ssh {
+        destination
+            .check()
+            .with_context(|| "Failed to read backup_upload_ssh")?;
+    }
+
     // Get a new server wrapper, and wait for that wrapper to launch the
     // underlying Minecraft server.
-    //
-    // The wrapper is guarded by a mutex because it's not designed to be used
-    // asynchronously, but a shared reference of it is given to each HTTP API
-    // handler.
-    //
-    // That whole thing is wrapped in an Arc so we can share ownership of that
-    // mutex across multiple async tasks, and consequently multiple threads.
-    let wrapper = Arc::new(Mutex::new(Wrapper::new(
-        config.max_memory_buffer_size,
-        &config.server_jar_path,
-    )?));
+    let wrapper = Wrapper::new(WrapperConfig {
+        server_jar_path: config.server_jar_path.clone(),
+        server_dir: config.server_dir.as_ref().map(PathBuf::from),
+        max_memory: config.max_memory_buffer_size,
+        own_process_group: config.own_process_group,
+        server_port: config.server_port,
+        command_timeout: Duration::from_secs(config.command_timeout_seconds),
+        command_retries: config.command_retries,
+        startup_timeout: Duration::from_secs(config.startup_timeout_seconds),
+        startup_timeout_action: config.startup_timeout_action,
+        maintenance_message: config.maintenance_message.clone(),
+        read_stderr_as_logs: config.read_stderr_as_logs,
+        server_env: config.server_env.clone(),
+        server_env_clear: config.server_env_clear,
+        backup_timeout: config.backup_timeout_seconds.map(Duration::from_secs),
+        force_unlock: config.force_unlock,
+        suppress_command_echo: config.suppress_command_echo,
+        startup_prompts: config.startup_prompts.clone(),
+        stop_ready_pattern: config.stop_ready_pattern.clone(),
+        stdout_channel_capacity: config.stdout_channel_capacity,
+        log_buffer_lines: config.log_buffer_lines,
+        log_files: log_files.clone(),
+        min_free_space_bytes: config.min_free_space_bytes,
+        follow_symlinks: config.follow_symlinks,
+        backup_extra_dirs: config.backup_extra_dirs.clone(),
+        backup_dir: config.backup_dir.clone(),
+        backup_file_name_format: config.backup_file_name_format.clone(),
+        backup_exclude: config.backup_exclude.clone(),
+        backup_compression: config.backup_compression,
+        backup_compression_level: config.backup_compression_level,
+        backup_encryption: config.backup_encryption.clone(),
+        incremental_backups: config.incremental_backups.clone(),
+        verify_backups: config.verify_backups,
+        restore_check: Duration::from_secs(config.restore_check_seconds),
+        backup_hooks: config.backup_hooks.clone(),
+        backup_retention: config.backup_retention.clone(),
+        backup_upload: config.backup_upload.clone(),
+        backup_upload_ssh: config.backup_upload_ssh.clone(),
+        backup_announce_message: config.backup_announce_message.clone(),
+        backup_complete_message: config.backup_complete_message.clone(),
+        backup_drain: config.drain_players_before_backup.then(|| BackupDrain {
+            warning: Duration::from_secs(config.drain_warning_seconds),
+            message: config.drain_message.clone(),
+            maintenance: config.maintenance_during_backup,
+        }),
+    })?;
+
+    // Raised before stopping the server on purpose so that the watchdog doesn't
+    // bring it back up.
+    let stop_requested = wrapper.stop_requested_flag();
+    // Keeps stops, restarts, and backups from running on top of each other.
+    let state = wrapper.state_machine();
+    // Pauses printing the Minecraft server's output in the wrapper's terminal.
+    let console_paused = wrapper.console_paused_flag();
+    // Counts how the commands that the wrapper sends on its own fare.
+    let command_stats = wrapper.command_stats();
+    // Counts the lines that the Minecraft server wrote which nothing read.
+    let stdout_stats = wrapper.stdout_stats();
+    let server_dir = wrapper.server_dir();
+    // Pings and queries the Minecraft server without going through the wrapper.
+    let probe = wrapper.server_probe();
+
+    // The wrapper isn't designed to be used by more than one thing at once, so
+    // it gets a thread of its own. Everything else, like the HTTP API's
+    // handlers and the watchdog, queues up requests for that thread with a
+    // handle, and async code awaits them without tying up the async runtime's
+    // threads.
+    let wrapper = WrapperHandle::spawn(wrapper);
+
+    // Restart the Minecraft server if it crashes, or exits on its own for some
+    // other reason.
+    if config.auto_restart {
+        watchdog::spawn(
+            wrapper.clone(),
+            Duration::from_secs(config.restart_confirm_seconds),
+            config.auto_restart_on.clone(),
+            config.auto_restart_backoff.clone(),
+        );
+    }
+
+    // Restart the Minecraft server if it's still running, but stops responding.
+    if config.health_check_interval_seconds > 0 {
+        watchdog::spawn_health_check(
+            wrapper.clone(),
+            Duration::from_secs(config.health_check_interval_seconds),
+            config.health_check_failure_threshold.max(1),
+            config.auto_restart,
+        );
+    }
+
+    if !config.on_join_commands.is_empty() {
+        automation::spawn_on_join_commands(
+            wrapper.clone(),
+            config.on_join_commands.clone(),
+            Duration::from_secs(config.rejoin_debounce_seconds),
+        );
+    }
+
+    if config.roster_log_interval_minutes > 0 {
+        automation::spawn_roster_log(
+            wrapper.clone(),
+            Duration::from_secs(config.roster_log_interval_minutes * 60),
+        );
+    }
+
+    if let Some(expression) = &config.backup_schedule {
+        let schedule: CronSchedule = expression
+            .parse()
+            .with_context(|| "Failed to read backup_schedule")?;
+        schedule::spawn_backups(
+            wrapper.clone(),
+            schedule,
+            config
+                .backup_schedule_warnings_seconds
+                .iter()
+                .map(|&secs| Duration::from_secs(secs))
+                .collect(),
+            config.backup_schedule_warning_message.clone(),
+            config.backup_schedule_skip_when_idle,
+        );
+    }
+
+    if config.watch_acl_files {
+        acl_watcher::spawn(wrapper.clone(), server_dir)?;
+    }
+
+    if let Some(port) = config.rcon_port {
+        rcon_proxy::spawn(
+            port,
+            config.rcon_password.clone(),
+            wrapper.clone(),
+            state.clone(),
+            Arc::clone(&audit_log),
+        )?;
+    }
 
     // Get a one-time-use channel that will carry a message indicating that the
     // HTTP server should be shut down. Designed to be used by the handler for
     // the /stop route -- this way, when the Minecraft server spins down, we'll
-    // stop serving new incoming requests to talk to it.
+    // stop serving new incoming requests to talk to it. The /shutdown-api route
+    // uses it too, but leaves the Minecraft server running.
     let (shutdown_signal_tx, shutdown_signal_rx) = oneshot::channel::<()>();
     // Wrapped in an Arc<Mutex<_>> for the same reasons as the server wrapper.
     let shutdown_signal_tx_mutex = Arc::new(Mutex::new(Some(shutdown_signal_tx)));
+    // Raised once the HTTP server starts shutting down. It waits for every
+    // response to finish first, so responses that never end on their own, like
+    // GET /events, watch this to end themselves.
+    let (api_shutting_down_tx, api_shutting_down_rx) = watch::channel(false);
 
-    // Set up API route handlers.
-    let routes = Router::new()
+    // Backups, restores, and restarts that were started in the background, for
+    // GET /jobs/:id.
+    let jobs = Jobs::default();
+
+    // Turns away requests that send the Minecraft server commands while it
+    // isn't running, instead of letting them wait for it to come back.
+    let require_running = middleware::from_fn({
+        let state = state.clone();
+        move |req, next| handlers::require_running(state.clone(), req, next)
+    });
+
+    // Set up API route handlers. Stopping, restarting, and backing up the
+    // server, and checking that it can start, legitimately take a while, and
+    // they have timeouts of their own, so they're left out of the request
+    // timeout.
+    let long_running_routes = Router::new()
         .route(
             "/stop",
             get({
-                let wrapper = Arc::clone(&wrapper);
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                let stop_requested = Arc::clone(&stop_requested);
                 let shutdown_signal_tx_mutex = Arc::clone(&shutdown_signal_tx_mutex);
                 move || {
                     handlers::stop_server(
-                        Arc::clone(&wrapper),
+                        wrapper.clone(),
+                        state.clone(),
+                        Arc::clone(&stop_requested),
                         Arc::clone(&shutdown_signal_tx_mutex),
                     )
                 }
             }),
         )
         .route(
-            "/list-players",
+            "/restart",
             get({
-                let wrapper = Arc::clone(&wrapper);
-                move || handlers::list_players(Arc::clone(&wrapper))
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                move || handlers::restart_server(wrapper.clone(), state.clone())
+            })
+            .post({
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                let jobs = jobs.clone();
+                move || handlers::start_restart_job(wrapper.clone(), state.clone(), jobs.clone())
             }),
         )
         .route(
             "/make-world-backup",
             get({
-                let wrapper = Arc::clone(&wrapper);
-                move || handlers::make_world_backup(Arc::clone(&wrapper))
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                move |Query(params): Query<handlers::BackupParams>| {
+                    handlers::make_world_backup(wrapper.clone(), state.clone(), params)
+                }
+            })
+            .post({
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                let jobs = jobs.clone();
+                move |Query(params): Query<handlers::BackupParams>| {
+                    handlers::start_backup_job(wrapper.clone(), state.clone(), jobs.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/validate-launch",
+            post({
+                let wrapper = wrapper.clone();
+                move || handlers::validate_launch(wrapper.clone())
+            }),
+        )
+        .route(
+            "/save/freeze",
+            post({
+                let wrapper = wrapper.clone();
+                move || handlers::freeze_saves(wrapper.clone())
+            })
+            .layer(require_running.clone()),
+        )
+        .route(
+            "/backups/stream",
+            get({
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                move |Query(params): Query<handlers::BackupParams>| {
+                    handlers::stream_world_backup(wrapper.clone(), state.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/backups",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::list_backups(wrapper.clone())
+            }),
+        )
+        .route(
+            "/backups/:id/download",
+            get({
+                let wrapper = wrapper.clone();
+                move |Path(id): Path<String>| handlers::download_backup(wrapper.clone(), id)
+            }),
+        )
+        .route(
+            "/backups/:id",
+            delete({
+                let wrapper = wrapper.clone();
+                move |Path(id): Path<String>| handlers::delete_backup(wrapper.clone(), id)
+            }),
+        )
+        .route(
+            "/backups/:id/verify",
+            post({
+                let wrapper = wrapper.clone();
+                move |Path(id): Path<String>| handlers::verify_backup(wrapper.clone(), id)
+            }),
+        )
+        .route(
+            "/backups/:id/restore",
+            post({
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                let jobs = jobs.clone();
+                move |Path(id): Path<String>, Query(params): Query<handlers::RestoreParams>| {
+                    handlers::restore_backup(
+                        wrapper.clone(),
+                        state.clone(),
+                        jobs.clone(),
+                        id,
+                        params,
+                    )
+                }
             }),
         );
+    // Routes that send the Minecraft server commands.
+    let command_routes = Router::new()
+        .route(
+            "/list-players",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>| {
+                    handlers::list_players(wrapper.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/datapacks",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>| {
+                    handlers::list_datapacks(wrapper.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/datapacks/:name/enable",
+            post({
+                let wrapper = wrapper.clone();
+                move |Path(name): Path<String>,
+                      Query(params): Query<handlers::DatapackParams>,
+                      Query(timeout_params): Query<handlers::TimeoutParams>| {
+                    handlers::set_datapack_enabled(
+                        wrapper.clone(),
+                        name,
+                        true,
+                        params,
+                        timeout_params,
+                    )
+                }
+            }),
+        )
+        .route(
+            "/datapacks/:name/disable",
+            post({
+                let wrapper = wrapper.clone();
+                move |Path(name): Path<String>,
+                      Query(params): Query<handlers::DatapackParams>,
+                      Query(timeout_params): Query<handlers::TimeoutParams>| {
+                    handlers::set_datapack_enabled(
+                        wrapper.clone(),
+                        name,
+                        false,
+                        params,
+                        timeout_params,
+                    )
+                }
+            }),
+        )
+        .route(
+            "/performance",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>| {
+                    handlers::performance(wrapper.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/time",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>| {
+                    handlers::game_time(wrapper.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/op/:name",
+            put({
+                let wrapper = wrapper.clone();
+                move |Path(name): Path<String>,
+                      Query(timeout_params): Query<handlers::TimeoutParams>,
+                      Json(params): Json<handlers::OpParams>| {
+                    handlers::op_with_level(wrapper.clone(), name, params, timeout_params)
+                }
+            }),
+        )
+        .route(
+            "/forceload",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>| {
+                    handlers::forceload(wrapper.clone(), ForceloadAction::Query, params)
+                }
+            })
+            .post({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::TimeoutParams>,
+                      Json(action): Json<ForceloadAction>| {
+                    handlers::forceload(wrapper.clone(), action, params)
+                }
+            }),
+        )
+        .route(
+            "/save/unfreeze",
+            post({
+                let wrapper = wrapper.clone();
+                move || handlers::unfreeze_saves(wrapper.clone())
+            }),
+        )
+        .route(
+            "/maintenance",
+            post({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::MaintenanceParams>,
+                      Query(timeout_params): Query<handlers::TimeoutParams>| {
+                    handlers::set_maintenance_mode(wrapper.clone(), params, timeout_params)
+                }
+            }),
+        )
+        .route(
+            "/command",
+            post({
+                let wrapper = wrapper.clone();
+                move |Query(timeout_params): Query<handlers::TimeoutParams>,
+                      Json(request): Json<handlers::CommandRequest>| {
+                    handlers::run_command(wrapper.clone(), request, timeout_params)
+                }
+            }),
+        )
+        .layer(require_running);
+    let routes = Router::new()
+        .route(
+            "/whoami",
+            get(|Extension(caller): Extension<auth::Caller>| handlers::whoami(caller)),
+        )
+        .route(
+            "/console",
+            get({
+                let wrapper = wrapper.clone();
+                let state = state.clone();
+                let audit_log = Arc::clone(&audit_log);
+                move |ws: WebSocketUpgrade,
+                      ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                      Extension(caller): Extension<auth::Caller>| {
+                    let identity = match caller.token_name() {
+                        Some(name) => format!("console {}", name),
+                        None => format!("console {}", addr.ip()),
+                    };
+                    console::upgrade(
+                        ws,
+                        identity,
+                        wrapper.clone(),
+                        state.clone(),
+                        Arc::clone(&audit_log),
+                    )
+                }
+            }),
+        )
+        .route(
+            "/logs",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::LogsParams>| {
+                    handlers::logs(wrapper.clone(), params)
+                }
+            }),
+        )
+        .route(
+            "/events",
+            get({
+                let wrapper = wrapper.clone();
+                move |Query(params): Query<handlers::EventsParams>| {
+                    handlers::stream_events(wrapper.clone(), params, api_shutting_down_rx.clone())
+                }
+            }),
+        )
+        .route(
+            "/console/pause",
+            post({
+                let console_paused = Arc::clone(&console_paused);
+                move || handlers::set_console_paused(Arc::clone(&console_paused), true)
+            }),
+        )
+        .route(
+            "/console/resume",
+            post({
+                let console_paused = Arc::clone(&console_paused);
+                move || handlers::set_console_paused(Arc::clone(&console_paused), false)
+            }),
+        )
+        .route(
+            "/stats/commands",
+            get({
+                let command_stats = command_stats.clone();
+                move || handlers::command_stats(command_stats.clone())
+            }),
+        )
+        .route(
+            "/stats/commands/reset",
+            post({
+                let command_stats = command_stats.clone();
+                move || handlers::reset_command_stats(command_stats.clone())
+            }),
+        )
+        .route(
+            "/stats/stdout",
+            get({
+                let stdout_stats = stdout_stats.clone();
+                let capacity = config.stdout_channel_capacity;
+                move || handlers::stdout_stats(stdout_stats.clone(), capacity)
+            }),
+        )
+        .route(
+            "/shutdown-api",
+            post({
+                let shutdown_signal_tx_mutex = Arc::clone(&shutdown_signal_tx_mutex);
+                move || handlers::shutdown_api(Arc::clone(&shutdown_signal_tx_mutex))
+            }),
+        )
+        .route(
+            "/jobs/:id",
+            get({
+                let jobs = jobs.clone();
+                move |Path(id): Path<String>| handlers::job(jobs.clone(), id)
+            }),
+        )
+        .route(
+            "/info",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::info(wrapper.clone())
+            }),
+        )
+        .route(
+            "/diagnostics",
+            get({
+                let wrapper = wrapper.clone();
+                let config = Arc::new(redacted_config(&config).map_err(|e| e.to_string()));
+                move || {
+                    handlers::diagnostics(wrapper.clone(), Arc::clone(&config), wrapper_started_at)
+                }
+            }),
+        )
+        .route(
+            "/startup-warnings",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::startup_warnings(wrapper.clone())
+            }),
+        )
+        .route(
+            "/crashes",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::crashes(wrapper.clone())
+            }),
+        )
+        // Querying and pinging don't send the server any commands, so they're
+        // answered while the server is being backed up or restarted, too.
+        .route(
+            "/query",
+            get({
+                let probe = probe.clone();
+                move || handlers::query_status(probe.clone())
+            }),
+        )
+        .route(
+            "/ping",
+            get({
+                let probe = probe.clone();
+                move || handlers::ping(probe.clone())
+            }),
+        )
+        .route(
+            "/properties/init",
+            post({
+                let wrapper = wrapper.clone();
+                move || handlers::init_server_properties(wrapper.clone())
+            }),
+        )
+        .route(
+            "/properties/raw",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::server_properties_raw(wrapper.clone())
+            })
+            .put({
+                let wrapper = wrapper.clone();
+                move |contents: String| {
+                    handlers::replace_server_properties_raw(wrapper.clone(), contents)
+                }
+            }),
+        )
+        .route(
+            "/server-icon",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::server_icon(wrapper.clone())
+            })
+            .put({
+                let wrapper = wrapper.clone();
+                move |png: Bytes| handlers::replace_server_icon(wrapper.clone(), png)
+            }),
+        )
+        .route(
+            "/bans/ips",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::list_banned_ips(wrapper.clone())
+            }),
+        )
+        .route(
+            "/ops",
+            get({
+                let wrapper = wrapper.clone();
+                move || handlers::list_ops(wrapper.clone())
+            }),
+        )
+        .merge(command_routes)
+        // Give up on requests that take too long, like when the Minecraft
+        // server is wedged, so that clients don't hang forever.
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handlers::handle_timeout_error))
+                .timeout(Duration::from_secs(config.request_timeout_seconds)),
+        )
+        .merge(long_running_routes);
+    // Say hello to people who point a browser at the wrapper's port.
+    let routes = if config.landing_page {
+        routes.route("/", get(handlers::landing_page))
+    } else {
+        routes
+    };
+    let routes = routes
+        // Answer retries of POST requests with an Idempotency-Key header with
+        // the first response, instead of carrying them out again.
+        .layer(middleware::from_fn({
+            let keys = Arc::new(IdempotencyKeys::default());
+            move |req, next| idempotency::deduplicate(Arc::clone(&keys), req, next)
+        }))
+        // Record every request that changes something in the audit log. This
+        // runs after the token is checked, so requests that are turned away
+        // aren't recorded, and the rest are recorded under their token's name.
+        .layer(MapRequestLayer::new({
+            let audit_log = Arc::clone(&audit_log);
+            move |req: Request<Body>| {
+                audit_log.record_request(&req);
+                req
+            }
+        }))
+        // Turn away requests that don't present a valid API token, if one is
+        // configured.
+        .layer(middleware::from_fn({
+            let auth = Arc::clone(&auth);
+            move |req, next| auth::require_token(Arc::clone(&auth), req, next)
+        }));
 
     // Pass any lines that are written to stdin onto the underlying Minecraft
     // server's stdin pipe. This lets server admins with access to the machine
     // that the server is running on interact with it the same way they would if
     // this wrapper weren't present.
     let stdin_reader = io::BufReader::new(io::stdin());
-    thread::spawn(move || {
-        stdin_reader
-            .lines()
-            .filter_map(|line| line.ok())
-            .for_each(|line| {
+    let stdin_thread = thread::spawn({
+        let wrapper = wrapper.clone();
+        let state = state.clone();
+        let stop_requested = Arc::clone(&stop_requested);
+        let shutdown_signal_tx_mutex = Arc::clone(&shutdown_signal_tx_mutex);
+        let audit_log = Arc::clone(&audit_log);
+        let console_paused = Arc::clone(&console_paused);
+        move || {
+            for line in stdin_reader.lines().map_while(Result::ok) {
+                audit_log.record(LOCAL_CONSOLE_IDENTITY, &line);
+
+                // "@pause" and "@resume" are for the wrapper, not the
+                // Minecraft server, which never sees them.
+                if line == PAUSE_CONSOLE_COMMAND || line == RESUME_CONSOLE_COMMAND {
+                    let paused = line == PAUSE_CONSOLE_COMMAND;
+                    console_paused.store(paused, Ordering::SeqCst);
+                    info!(
+                        "{} printing the Minecraft server's output",
+                        if paused { "Paused" } else { "Resumed" }
+                    );
+                    continue;
+                }
+
                 // If a user types "/stop", we want to shut down the API server,
                 // as well. Intercept "/stop" commands and treat them as a
                 // special case.
                 if line == "/stop" {
-                    if let Err(e) = wrapper.lock().unwrap().stop_server() {
-                        warn!("Something went wrong while trying to stop the Minecraft server: {}", e);
+                    // Somebody else is already stopping the server, and they'll
+                    // shut down the API server, too.
+                    let transition = match begin_stopping(&state) {
+                        Some(transition) => transition,
+                        None => break,
+                    };
+                    stop_requested.store(true, Ordering::SeqCst);
+                    if let Err(e) = wrapper.blocking_stop() {
+                        warn!(
+                            "Something went wrong while trying to stop the Minecraft server: {}",
+                            e
+                        );
                         // Don't fail fast with process::exit() or something. If
                         // we fail to properly shut down the Minecraft server,
                         // we still want to try to shut down the API server.
                     }
+                    transition.finish(ServerState::Stopped);
 
-                    if let Err(e) = send_api_server_shutdown_signal(shutdown_signal_tx_mutex.clone()) {
-                        error!("{}", e);
-                        process::exit(1);
+                    // The API server might have already been shut down on its
+                    // own with /shutdown-api, in which case there's nothing
+                    // left to signal.
+                    let api_server_running = shutdown_signal_tx_mutex.lock().unwrap().is_some();
+                    if api_server_running {
+                        if let Err(e) = send_api_server_shutdown_signal(shutdown_signal_tx_mutex) {
+                            error!("{}", e);
+                            process::exit(1);
+                        }
                     }
-                } else if let Err(e) = wrapper.lock().unwrap().run_custom_command(&line) {
+                    break;
+                } else if let Err(e) = state.ensure_running() {
+                    warn!("Didn't pass {:?} on to the Minecraft server: {}", line, e);
+                } else if let Err(e) = wrapper.blocking_run_command(line) {
                     warn!("Something went wrong while trying to pass a command to the wrapper's stdin: {}", e);
                 }
-            });
+            }
+        }
     });
 
-    // Stand up the API server.
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
-    axum::Server::bind(&addr)
-        .serve(routes.into_make_service())
-        .with_graceful_shutdown(async {
-            shutdown_signal_rx.await.ok();
-        })
-        .await
-        .unwrap();
+    // Stop the Minecraft server gracefully when the wrapper is asked to exit,
+    // like when somebody presses Ctrl-C in the terminal it's running in.
+    tokio::spawn({
+        let wrapper = wrapper.clone();
+        let state = state.clone();
+        let stop_requested = Arc::clone(&stop_requested);
+        let shutdown_signal_tx_mutex = Arc::clone(&shutdown_signal_tx_mutex);
+        async move {
+            wait_for_exit_signal().await;
+            info!("Received a signal to exit. Stopping the Minecraft server");
+
+            let wrapper_for_exit = wrapper.clone();
+            let stop_result = tokio::task::spawn_blocking(move || {
+                let transition = begin_stopping(&state)?;
+                stop_requested.store(true, Ordering::SeqCst);
+                let result = wrapper.blocking_stop();
+                transition.finish(ServerState::Stopped);
+                Some(result)
+            })
+            .await;
+            match stop_result {
+                Ok(Some(Ok(()))) => {}
+                Ok(Some(Err(e))) => warn!(
+                    "Something went wrong while trying to stop the Minecraft server: {}",
+                    e
+                ),
+                // Somebody else is already stopping the server, and they'll
+                // shut down the API server, too.
+                Ok(None) => return,
+                Err(e) => error!("The task stopping the Minecraft server panicked: {}", e),
+            }
+
+            // If the API server was already shut down with /shutdown-api,
+            // there's nothing left to signal, and the main thread is waiting on
+            // stdin. Exit right away instead.
+            let api_server_running = shutdown_signal_tx_mutex.lock().unwrap().is_some();
+            if !api_server_running {
+                release_server_dir_lock(&wrapper_for_exit).await;
+                process::exit(0);
+            }
+            if let Err(e) = send_api_server_shutdown_signal(shutdown_signal_tx_mutex) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+    });
+
+    if config.enable_http_api {
+        // Stand up the API server.
+        let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+        axum::Server::bind(&addr)
+            .serve(routes.into_make_service_with_connect_info::<SocketAddr, _>())
+            .with_graceful_shutdown(async move {
+                shutdown_signal_rx.await.ok();
+                let _ = api_shutting_down_tx.send(true);
+            })
+            .await
+            .unwrap();
+    } else {
+        // Stay up until the Minecraft server is stopped, the same way the API
+        // server would, even if stdin is closed.
+        info!("The HTTP API is turned off. Type \"/stop\" to stop the Minecraft server");
+        shutdown_signal_rx.await.ok();
+    }
+
+    // If the API server was shut down on its own with /shutdown-api, the
+    // Minecraft server is still running. Keep passing stdin along to it until
+    // somebody types "/stop".
+    if !stop_requested.load(Ordering::SeqCst) {
+        info!("The API server has shut down, but the Minecraft server is still running. Type \"/stop\" to stop it");
+        if stdin_thread.join().is_err() {
+            error!("The thread passing stdin along to the Minecraft server panicked");
+            process::exit(1);
+        }
+    }
+
+    // Other threads might still be holding onto handles for the wrapper, so it
+    // won't necessarily be dropped before the process exits.
+    release_server_dir_lock(&wrapper).await;
 
     Ok(())
 }
 
+/// Deletes the lockfile in the Minecraft server's directory, since the wrapper
+/// won't be managing the server anymore.
+async fn release_server_dir_lock(wrapper: &WrapperHandle) {
+    let _ = wrapper
+        .call(|w| {
+            w.release_server_dir_lock();
+            Ok(())
+        })
+        .await;
+}
+
+/// Begins stopping the Minecraft server. If it's being restarted or backed up,
+/// waits for that to finish first.
+///
+/// Returns [None] if somebody else is already stopping the server.
+fn begin_stopping(state: &StateMachine) -> Option<Transition> {
+    loop {
+        match state.begin(ServerState::Stopping) {
+            Ok(transition) => return Some(transition),
+            Err(WrapperError::OperationInProgress(
+                ServerState::Stopping | ServerState::Stopped,
+            )) => return None,
+            Err(_) => thread::sleep(BEGIN_STOPPING_POLL_INTERVAL),
+        }
+    }
+}
+
 /// Reads configs from a config file, and returns a [Config] with those values.
 /// If a config file doesn't exist, it creates one with sensible defaults, and
 /// returns a [Config] populated with those defaults.
@@ -147,11 +1169,7 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
 fn get_config() -> anyhow::Result<Config> {
     // Create a Config with sensible defaults. If a config file is present,
     // these will be overwritten after that file is read.
-    let mut config = Config {
-        port: DEFAULT_PORT,
-        server_jar_path: DEFAULT_SERVER_JAR_PATH.to_string(),
-        max_memory_buffer_size: DEFAULT_MAX_MEMORY_BUFFER_SIZE,
-    };
+    let mut config = Config::default();
 
     if let Some(proj_dirs) = ProjectDirs::from("com", "nchaloult", "mc-server-wrapper") {
         let config_dir = proj_dirs.config_dir();
@@ -167,7 +1185,7 @@ fn get_config() -> anyhow::Result<Config> {
                     // Create an empty config file. Later on, when we see that
                     // this file is empty, we won't overwrite any of the values
                     // in our default config instantiated above.
-                    fs::create_dir_all(&config_dir).with_context(|| format!("Something went wrong while making a {:?} directory for the config file to live in", &config_dir))?;
+                    fs::create_dir_all(config_dir).with_context(|| format!("Something went wrong while making a {:?} directory for the config file to live in", &config_dir))?;
                     // We can't use something more simple here like
                     // fs::File::create() because we need to be able to read
                     // from this file later on.
@@ -217,6 +1235,67 @@ fn get_config() -> anyhow::Result<Config> {
     Ok(config)
 }
 
+/// Returns the provided [Config] as JSON, with API tokens, the values of
+/// `server_env`, the secret key for `backup_upload`, and the passphrase for
+/// `backup_encryption` replaced, since they might be secrets.
+fn redacted_config(config: &Config) -> anyhow::Result<serde_json::Value> {
+    let redacted = serde_json::Value::from(REDACTED);
+    let mut value = serde_json::to_value(config)?;
+    if let Some(api_token) = value.get_mut("api_token").filter(|token| !token.is_null()) {
+        *api_token = redacted.clone();
+    }
+    if let Some(tokens) = value.get_mut("tokens").and_then(|t| t.as_array_mut()) {
+        for token in tokens.iter_mut().filter_map(|t| t.get_mut("token")) {
+            *token = redacted.clone();
+        }
+    }
+    if let Some(env) = value.get_mut("server_env").and_then(|e| e.as_object_mut()) {
+        for env_value in env.values_mut() {
+            *env_value = redacted.clone();
+        }
+    }
+    for pointer in [
+        "/rcon_password",
+        "/backup_upload/access_key_id",
+        "/backup_upload/secret_access_key",
+        "/backup_upload_ssh/key_path",
+        "/backup_encryption/passphrase",
+        "/backup_encryption/identity_file",
+    ] {
+        if let Some(secret) = value
+            .pointer_mut(pointer)
+            .filter(|secret| !secret.is_null())
+        {
+            *secret = redacted.clone();
+        }
+    }
+    Ok(value)
+}
+
+/// Waits until the wrapper receives a signal asking it to exit: SIGINT (Ctrl-C),
+/// or SIGTERM on Unix.
+async fn wait_for_exit_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
 /// Sends a signal to the API server to begin gracefully shutting down.
 ///
 /// Sends an empty message along the provided [oneshot channel](tokio::sync::oneshot::channel),
@@ -239,3 +1318,40 @@ fn send_api_server_shutdown_signal(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_config_hides_every_secret() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+api_token: hunter2-admin
+rcon_password: hunter2-rcon
+backup_upload:
+  endpoint: https://s3.example.com
+  bucket: backups
+  access_key_id: hunter2-key-id
+  secret_access_key: hunter2-key
+backup_upload_ssh:
+  host: backups.example.com
+  key_path: /home/me/.ssh/id-hunter2
+  remote_dir: /srv/backups
+backup_encryption:
+  identity_file: /etc/age/identity-hunter2.txt
+"#,
+        )
+        .unwrap();
+        let redacted = redacted_config(&config).unwrap().to_string();
+        assert!(!redacted.contains("hunter2"), "{}", redacted);
+        assert!(redacted.contains("https://s3.example.com"));
+    }
+
+    #[test]
+    fn redacted_config_leaves_unset_secrets_null() {
+        let redacted = redacted_config(&Config::default()).unwrap();
+        assert!(redacted["rcon_password"].is_null());
+        assert!(redacted["backup_upload"].is_null());
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::Context;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{backup_files::BackupDir, error::WrapperError};
+
+/// The name of the file in the `backup_dir` that says what each world backup
+/// in it was made for.
+pub const MANIFEST_FILE_NAME: &str = "backups.json";
+// How long a backup's name and description can be, in characters.
+const MAX_NAME_CHARS: usize = 100;
+const MAX_DESCRIPTION_CHARS: usize = 2000;
+
+// Held while the manifest is read, changed, and written back, since backups
+// that are compressed in the background, pruned, or uploaded all change it
+// from other threads.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Why a world backup was made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupTrigger {
+    /// Somebody asked for it, through the API.
+    #[default]
+    Manual,
+    /// The `backup_schedule` made it.
+    Scheduled,
+}
+
+/// What a new world backup is for, which goes in the `backup_dir`'s manifest
+/// along with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupDetails {
+    /// A short, human-friendly name for the backup, like "before the end
+    /// update".
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub trigger: BackupTrigger,
+}
+
+impl BackupDetails {
+    /// Details for a backup that the `backup_schedule` is making.
+    pub fn scheduled() -> BackupDetails {
+        BackupDetails {
+            trigger: BackupTrigger::Scheduled,
+            ..BackupDetails::default()
+        }
+    }
+
+    /// Returns a [WrapperError::InvalidArgument] if the name or description
+    /// is blank or too long, or if the name isn't all on one line.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let invalid = |reason: String| Err(WrapperError::InvalidArgument(reason).into());
+        if let Some(name) = &self.name {
+            if name.trim().is_empty() {
+                return invalid("A backup's name can't be blank".to_owned());
+            }
+            if name.chars().count() > MAX_NAME_CHARS {
+                return invalid(format!(
+                    "A backup's name can't be longer than {} characters",
+                    MAX_NAME_CHARS
+                ));
+            }
+            if name.chars().any(char::is_control) {
+                return invalid("A backup's name has to be all on one line".to_owned());
+            }
+        }
+        if let Some(description) = &self.description {
+            if description.trim().is_empty() {
+                return invalid("A backup's description can't be blank".to_owned());
+            }
+            if description.chars().count() > MAX_DESCRIPTION_CHARS {
+                return invalid(format!(
+                    "A backup's description can't be longer than {} characters",
+                    MAX_DESCRIPTION_CHARS
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the `backup_dir`'s manifest says about a world backup, on top of
+/// what can be told from its file. Shows up in `GET /backups`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub trigger: BackupTrigger,
+    /// How many bytes the directories that were backed up took up, before
+    /// they were compressed.
+    pub world_size_bytes: u64,
+    /// The version of Minecraft that the server said it was running, if it
+    /// did.
+    pub server_version: Option<String>,
+    /// How long it took to make the backup, from when the world started being
+    /// backed up to when the tarball was finished.
+    pub duration_ms: u64,
+}
+
+impl BackupMetadata {
+    pub(crate) fn new(
+        details: &BackupDetails,
+        world_size_bytes: u64,
+        server_version: Option<String>,
+    ) -> BackupMetadata {
+        BackupMetadata {
+            name: details.name.clone(),
+            description: details.description.clone(),
+            trigger: details.trigger,
+            world_size_bytes,
+            server_version,
+            duration_ms: 0,
+        }
+    }
+
+    /// Returns this with the `duration_ms` of a backup that was started at
+    /// `started_at`, and just finished.
+    pub(crate) fn finished(mut self, started_at: Instant) -> BackupMetadata {
+        self.duration_ms = started_at.elapsed().as_millis() as u64;
+        self
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    backups: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    id: String,
+    file_name: String,
+    #[serde(flatten)]
+    metadata: BackupMetadata,
+}
+
+/// Adds the world backup at the provided path to the manifest in `dir`.
+/// Entries for backups that aren't there anymore are dropped while it's at it.
+/// Something going wrong is only logged, since the backup itself is fine
+/// either way.
+pub(crate) fn record(dir: &BackupDir, backup_path: &Path, metadata: BackupMetadata) {
+    let (id, file_name) = match (dir.id_of(backup_path), file_name(backup_path)) {
+        (Some(id), Some(file_name)) => (id, file_name),
+        _ => {
+            warn!(
+                "Couldn't tell which world backup {:?} is, so it was left out of the {}",
+                backup_path, MANIFEST_FILE_NAME
+            );
+            return;
+        }
+    };
+    let result = update(dir.path(), |manifest| {
+        manifest
+            .backups
+            .retain(|entry| entry.id != id && dir.path().join(&entry.file_name).is_file());
+        manifest.backups.push(ManifestEntry {
+            id,
+            file_name,
+            metadata,
+        });
+    });
+    if let Err(e) = result {
+        warn!(
+            "Failed to add the world backup at {:?} to the {}: {:#}",
+            backup_path, MANIFEST_FILE_NAME, e
+        );
+    }
+}
+
+/// Drops the world backup at the provided path from the manifest next to it,
+/// if it's in there. Something going wrong is only logged.
+pub(crate) fn forget(backup_path: &Path) {
+    let (dir, file_name) = match (backup_path.parent(), file_name(backup_path)) {
+        (Some(dir), Some(file_name)) => (dir, file_name),
+        _ => return,
+    };
+    if !manifest_path(dir).exists() {
+        return;
+    }
+    let result = update(dir, |manifest| {
+        manifest
+            .backups
+            .retain(|entry| entry.file_name != file_name)
+    });
+    if let Err(e) = result {
+        warn!(
+            "Failed to remove the world backup at {:?} from the {}: {:#}",
+            backup_path, MANIFEST_FILE_NAME, e
+        );
+    }
+}
+
+/// Returns what the manifest in `dir` says about each world backup, by ID.
+/// Backups that aren't in it, like ones that were made before it was kept,
+/// are left out.
+pub(crate) fn read(dir: &Path) -> Vec<(String, BackupMetadata)> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    match load(dir) {
+        Ok(manifest) => manifest
+            .backups
+            .into_iter()
+            .map(|entry| (entry.id, entry.metadata))
+            .collect(),
+        Err(e) => {
+            warn!("{:#}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Reads the manifest in `dir`, changes it with `change`, and writes it back.
+///
+/// It's written to a temporary file first, which is then moved into place, so
+/// the manifest is never left half-written.
+fn update(dir: &Path, change: impl FnOnce(&mut Manifest)) -> anyhow::Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let mut manifest = load(dir)?;
+    change(&mut manifest);
+
+    let path = manifest_path(dir);
+    let tmp_path = dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    fs::write(&tmp_path, json).with_context(|| format!("Failed to write to {:?}", &tmp_path))?;
+    if let Err(e) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).with_context(|| format!("Failed to replace {:?}", &path));
+    }
+    Ok(())
+}
+
+/// Returns the manifest in `dir`, or an empty one if there isn't one yet.
+fn load(dir: &Path) -> anyhow::Result<Manifest> {
+    let path = manifest_path(dir);
+    let json = match fs::read(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Manifest::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+    };
+    serde_json::from_slice(&json).with_context(|| format!("Failed to parse {:?}", &path))
+}
+
+fn manifest_path(dir: &Path) -> PathBuf {
+    dir.join(MANIFEST_FILE_NAME)
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_str()?.to_owned())
+}
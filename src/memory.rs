@@ -0,0 +1,186 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+// Never hand the JVM less than this, no matter how little memory the machine
+// has or how small of a percentage was asked for.
+const MIN_MAX_MEMORY_MB: u64 = 512;
+// When sizing the memory allocation buffer as a percentage of the machine's
+// memory, always leave at least this much for the operating system and
+// everything else that's running on the machine.
+const OS_HEADROOM_MB: u64 = 1024;
+
+/// The max size of the Minecraft server process's memory allocation buffer on
+/// the JVM.
+///
+/// Either an absolute number of megabytes, or a percentage of the total memory
+/// on the machine that the server runs on. Percentages are resolved each time a
+/// server process is spawned, which makes the same config portable across
+/// machines of different sizes.
+///
+/// In a config file, absolute values are written as plain numbers (`2048`), and
+/// percentages are written as strings (`"75%"`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "MaxMemoryRepr", into = "MaxMemoryRepr")]
+pub enum MaxMemory {
+    Megabytes(u64),
+    PercentOfSystem(u8),
+}
+
+impl MaxMemory {
+    /// Returns the number of megabytes that should be passed into the `-Xmx`
+    /// option when spawning the server process.
+    pub fn resolve(&self) -> u64 {
+        match self {
+            MaxMemory::Megabytes(mb) => *mb,
+            MaxMemory::PercentOfSystem(_) => {
+                let mut system = System::new();
+                system.refresh_memory();
+                self.resolve_with_total(system.total_memory() / 1024 / 1024)
+            }
+        }
+    }
+
+    /// Returns the number of megabytes that should be passed into the `-Xmx`
+    /// option, given that the machine has `total_memory_mb` megabytes of memory.
+    ///
+    /// Percentages are clamped so that at least [OS_HEADROOM_MB] is left over
+    /// for everything else, and so that the result is never smaller than
+    /// [MIN_MAX_MEMORY_MB].
+    pub fn resolve_with_total(&self, total_memory_mb: u64) -> u64 {
+        match self {
+            MaxMemory::Megabytes(mb) => *mb,
+            MaxMemory::PercentOfSystem(percent) => {
+                let requested = total_memory_mb * u64::from(*percent) / 100;
+                let available = total_memory_mb.saturating_sub(OS_HEADROOM_MB);
+                requested.min(available).max(MIN_MAX_MEMORY_MB)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MaxMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaxMemory::Megabytes(mb) => write!(f, "{}", mb),
+            MaxMemory::PercentOfSystem(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl FromStr for MaxMemory {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent: u8 = percent
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("{:?} isn't a valid percentage", s))?;
+                if percent == 0 || percent > 100 {
+                    bail!(
+                        "{:?} isn't a valid percentage. It must be between 1% and 100%",
+                        s
+                    );
+                }
+                Ok(MaxMemory::PercentOfSystem(percent))
+            }
+            None => s.parse().map(MaxMemory::Megabytes).map_err(|_| {
+                anyhow!(
+                    "{:?} isn't a valid amount of memory. Use a number of megabytes, like 2048, or a percentage, like \"75%\"",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+// How a MaxMemory is written in a config file.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum MaxMemoryRepr {
+    Megabytes(u64),
+    Text(String),
+}
+
+impl TryFrom<MaxMemoryRepr> for MaxMemory {
+    type Error = anyhow::Error;
+
+    fn try_from(repr: MaxMemoryRepr) -> Result<Self, Self::Error> {
+        match repr {
+            MaxMemoryRepr::Megabytes(mb) => Ok(MaxMemory::Megabytes(mb)),
+            MaxMemoryRepr::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl From<MaxMemory> for MaxMemoryRepr {
+    fn from(max_memory: MaxMemory) -> Self {
+        match max_memory {
+            MaxMemory::Megabytes(mb) => MaxMemoryRepr::Megabytes(mb),
+            MaxMemory::PercentOfSystem(_) => MaxMemoryRepr::Text(max_memory.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentages_are_of_the_total_memory() {
+        let max_memory = MaxMemory::PercentOfSystem(75);
+        assert_eq!(max_memory.resolve_with_total(16 * 1024), 12 * 1024);
+        assert_eq!(max_memory.resolve_with_total(8000), 6000);
+    }
+
+    #[test]
+    fn percentages_leave_room_for_the_os() {
+        assert_eq!(
+            MaxMemory::PercentOfSystem(100).resolve_with_total(8192),
+            7168
+        );
+        assert_eq!(
+            MaxMemory::PercentOfSystem(90).resolve_with_total(4096),
+            3072
+        );
+    }
+
+    #[test]
+    fn percentages_are_never_too_small() {
+        assert_eq!(MaxMemory::PercentOfSystem(1).resolve_with_total(8192), 512);
+        // Even when the machine barely has any memory to begin with.
+        assert_eq!(MaxMemory::PercentOfSystem(50).resolve_with_total(1024), 512);
+        assert_eq!(MaxMemory::PercentOfSystem(50).resolve_with_total(0), 512);
+    }
+
+    #[test]
+    fn megabytes_are_used_as_is() {
+        assert_eq!(MaxMemory::Megabytes(2048).resolve_with_total(1024), 2048);
+        assert_eq!(MaxMemory::Megabytes(100).resolve(), 100);
+    }
+
+    #[test]
+    fn parses_config_values() {
+        let parse = |yaml: &str| serde_yaml::from_str::<MaxMemory>(yaml);
+        assert_eq!(parse("2048").unwrap(), MaxMemory::Megabytes(2048));
+        assert_eq!(parse("\"75%\"").unwrap(), MaxMemory::PercentOfSystem(75));
+        assert_eq!(
+            parse("\" 100 % \"").unwrap(),
+            MaxMemory::PercentOfSystem(100)
+        );
+        assert_eq!(parse("\"4096\"").unwrap(), MaxMemory::Megabytes(4096));
+        for invalid in ["\"0%\"", "\"101%\"", "\"-5%\"", "\"lots\"", "\"2G\""] {
+            assert!(parse(invalid).is_err(), "{} should be invalid", invalid);
+        }
+
+        for max_memory in [MaxMemory::Megabytes(2048), MaxMemory::PercentOfSystem(75)] {
+            let yaml = serde_yaml::to_string(&max_memory).unwrap();
+            assert_eq!(parse(&yaml).unwrap(), max_memory);
+        }
+    }
+}
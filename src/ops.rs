@@ -0,0 +1,138 @@
+use std::{collections::BTreeSet, fs, path::Path, sync::LazyLock};
+
+use anyhow::{bail, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::WrapperError,
+    outcome::{parse_outcome, CommandOutcome, OutcomeMessages},
+};
+
+pub(crate) const OPS_FILE_NAME: &str = "ops.json";
+
+/// The lowest permission level that an operator can have.
+pub const MIN_OP_LEVEL: u8 = 1;
+/// The highest permission level that an operator can have.
+pub const MAX_OP_LEVEL: u8 = 4;
+
+// Matches every line that the Minecraft server might write in response to
+// "/op".
+pub(crate) static OP_RESPONSE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: (Made .* a server operator|Nothing changed\. The player already is an operator|That player does not exist|Unknown or incomplete command)").unwrap()
+});
+// What "/op" writes for each of its outcomes, on top of the messages that
+// every command shares.
+const OP_OUTCOME_MESSAGES: OutcomeMessages = OutcomeMessages {
+    changed: &["Made "],
+    no_change: &[],
+    target_not_found: &[],
+};
+
+/// An operator from the server's `ops.json` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op {
+    pub name: String,
+    /// The operator's permission level, from [MIN_OP_LEVEL] to [MAX_OP_LEVEL].
+    pub level: u8,
+}
+
+/// Returns every operator in the server's `ops.json` file.
+pub(crate) fn read_ops(server_dir: &Path) -> anyhow::Result<Vec<Op>> {
+    let path = server_dir.join(OPS_FILE_NAME);
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", &path))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse the contents of {:?}", &path))
+}
+
+/// Returns the names of every player in the server's `ops.json` file.
+pub(crate) fn read_op_names(server_dir: &Path) -> anyhow::Result<BTreeSet<String>> {
+    Ok(read_ops(server_dir)?
+        .into_iter()
+        .map(|op| op.name)
+        .collect())
+}
+
+/// Sets the permission level of the provided operator in the server's
+/// `ops.json` file, leaving everything else in that file alone. Returns false
+/// without touching the file if they already have that level.
+///
+/// Like with `server.properties`, the file is replaced all at once, so it's
+/// never left half-written.
+pub(crate) fn write_level(server_dir: &Path, name: &str, level: u8) -> anyhow::Result<bool> {
+    let path = server_dir.join(OPS_FILE_NAME);
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", &path))?;
+    let mut entries: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse the contents of {:?}", &path))?;
+
+    // Minecraft usernames are case-insensitive.
+    let entry = entries.iter_mut().find(|entry| {
+        entry
+            .get("name")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(|n| n.eq_ignore_ascii_case(name))
+    });
+    match entry {
+        Some(entry)
+            if entry.get("level").and_then(serde_json::Value::as_u64) == Some(level.into()) =>
+        {
+            return Ok(false);
+        }
+        Some(entry) => {
+            entry.insert("level".to_owned(), level.into());
+        }
+        None => bail!("{} isn't in {:?}", name, &path),
+    }
+
+    let tmp_path = server_dir.join(format!("{}.tmp", OPS_FILE_NAME));
+    let contents = serde_json::to_string_pretty(&entries)?;
+    fs::write(&tmp_path, contents)
+        .with_context(|| format!("Failed to write to {:?}", &tmp_path))?;
+    if let Err(e) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).with_context(|| format!("Failed to replace {:?}", &path));
+    }
+    Ok(true)
+}
+
+/// Returns a [WrapperError::InvalidArgument] if `level` isn't a permission
+/// level that an operator can have.
+pub(crate) fn validate_level(level: u8) -> Result<(), WrapperError> {
+    if !(MIN_OP_LEVEL..=MAX_OP_LEVEL).contains(&level) {
+        return Err(WrapperError::InvalidArgument(format!(
+            "{} isn't a valid permission level. It must be between {} and {}",
+            level, MIN_OP_LEVEL, MAX_OP_LEVEL
+        )));
+    }
+    Ok(())
+}
+
+/// Returns a [WrapperError::InvalidArgument] if `name` can't be a player's
+/// name. Minecraft usernames are 3 to 16 letters, numbers, and underscores.
+pub(crate) fn validate_player_name(name: &str) -> Result<(), WrapperError> {
+    let valid = (3..=16).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(WrapperError::InvalidArgument(format!(
+            "{:?} isn't a valid player name",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the Minecraft server's response to "/op" for errors.
+///
+/// Opping a player who's already an operator isn't treated as an error. It's
+/// [CommandOutcome::NoChange] instead.
+pub(crate) fn check_op_response(
+    cmd: &str,
+    name: &str,
+    response: &str,
+) -> anyhow::Result<CommandOutcome> {
+    parse_outcome(response, &OP_OUTCOME_MESSAGES)
+        .check(cmd, || WrapperError::PlayerNotFound(name.to_owned()))
+}
@@ -0,0 +1,230 @@
+use serde::Serialize;
+
+use crate::error::WrapperError;
+
+// The beginnings of messages that lots of commands write when there was
+// nothing for them to change.
+const NO_CHANGE_MESSAGES: [&str; 1] = ["Nothing changed"];
+// The beginnings of messages that lots of commands write when whatever they
+// were about doesn't exist.
+const TARGET_NOT_FOUND_MESSAGES: [&str; 3] = [
+    "No player was found",
+    "No entity was found",
+    "That player does not exist",
+];
+// The beginnings of messages that the Minecraft server writes when it doesn't
+// understand a command.
+const UNKNOWN_COMMAND_MESSAGES: [&str; 3] = [
+    "Unknown or incomplete command",
+    "Incorrect argument for command",
+    "Unknown command",
+];
+// The beginnings of messages that the Minecraft server writes when whatever
+// ran a command isn't allowed to. Some plugins keep the console from running
+// certain commands.
+const PERMISSION_DENIED_MESSAGES: [&str; 2] = [
+    "You do not have permission",
+    "I'm sorry, but you do not have permission",
+];
+
+/// How the Minecraft server says a command that changes something went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "message", rename_all = "snake_case")]
+pub enum CommandOutcome {
+    /// The command changed something.
+    Changed,
+    /// The command went through, but there was nothing for it to change, like
+    /// when opping a player who's already an operator.
+    NoChange,
+    /// The player, data pack, or whatever else the command was about doesn't
+    /// exist.
+    TargetNotFound,
+    /// The server doesn't know the command, or didn't understand its
+    /// arguments.
+    UnknownCommand,
+    /// The server didn't let the wrapper run the command.
+    PermissionDenied,
+    /// The server said something that the wrapper doesn't recognize.
+    Unparsed(String),
+}
+
+impl CommandOutcome {
+    /// Returns outcomes that mean that `cmd` went through, and turns the rest
+    /// into errors. `not_found` makes the error for
+    /// [CommandOutcome::TargetNotFound], since only the caller knows what the
+    /// command was about.
+    pub(crate) fn check(
+        self,
+        cmd: &str,
+        not_found: impl FnOnce() -> WrapperError,
+    ) -> anyhow::Result<CommandOutcome> {
+        match self {
+            CommandOutcome::Changed | CommandOutcome::NoChange => Ok(self),
+            CommandOutcome::TargetNotFound => Err(not_found().into()),
+            CommandOutcome::UnknownCommand => {
+                Err(WrapperError::CommandRejected(cmd.to_owned()).into())
+            }
+            CommandOutcome::PermissionDenied => {
+                Err(WrapperError::CommandNotPermitted(cmd.to_owned()).into())
+            }
+            CommandOutcome::Unparsed(message) => Err(anyhow::anyhow!(
+                "Unexpected response to {:?}: {:?}",
+                cmd,
+                message
+            )),
+        }
+    }
+}
+
+/// The beginnings of the messages that one command writes for each of its
+/// outcomes, on top of the ones that every command shares.
+pub(crate) struct OutcomeMessages {
+    pub(crate) changed: &'static [&'static str],
+    pub(crate) no_change: &'static [&'static str],
+    pub(crate) target_not_found: &'static [&'static str],
+}
+
+/// Sorts the Minecraft server's response to a command into a
+/// [CommandOutcome].
+pub(crate) fn parse_outcome(response: &str, messages: &OutcomeMessages) -> CommandOutcome {
+    let (_, message) = response.split_once("]: ").unwrap_or(("", response));
+    let starts_with_any = |prefixes: &[&str]| prefixes.iter().any(|p| message.starts_with(p));
+
+    if starts_with_any(messages.changed) {
+        CommandOutcome::Changed
+    } else if starts_with_any(messages.no_change) || starts_with_any(&NO_CHANGE_MESSAGES) {
+        CommandOutcome::NoChange
+    } else if starts_with_any(messages.target_not_found)
+        || starts_with_any(&TARGET_NOT_FOUND_MESSAGES)
+    {
+        CommandOutcome::TargetNotFound
+    } else if starts_with_any(&UNKNOWN_COMMAND_MESSAGES) {
+        CommandOutcome::UnknownCommand
+    } else if starts_with_any(&PERMISSION_DENIED_MESSAGES) {
+        CommandOutcome::PermissionDenied
+    } else {
+        CommandOutcome::Unparsed(message.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_MESSAGES: OutcomeMessages = OutcomeMessages {
+        changed: &["Made "],
+        no_change: &[],
+        target_not_found: &[],
+    };
+
+    #[test]
+    fn sorts_responses_into_outcomes() {
+        let cases = [
+            (
+                "[12:00:00] [Server thread/INFO]: Made Steve a server operator",
+                CommandOutcome::Changed,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: Nothing changed. The player already is an operator",
+                CommandOutcome::NoChange,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: That player does not exist",
+                CommandOutcome::TargetNotFound,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: No player was found",
+                CommandOutcome::TargetNotFound,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: Unknown or incomplete command, see below for error",
+                CommandOutcome::UnknownCommand,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: I'm sorry, but you do not have permission to perform this command.",
+                CommandOutcome::PermissionDenied,
+            ),
+            (
+                "[12:00:00] [Server thread/INFO]: Something else entirely",
+                CommandOutcome::Unparsed("Something else entirely".to_owned()),
+            ),
+            // Without the usual prefix, the whole line is the message.
+            ("Made Alex a server operator", CommandOutcome::Changed),
+        ];
+        for (response, expected) in cases {
+            assert_eq!(
+                parse_outcome(response, &OP_MESSAGES),
+                expected,
+                "{:?}",
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn command_specific_messages_come_first() {
+        let messages = OutcomeMessages {
+            changed: &[],
+            no_change: &["Data pack is already enabled"],
+            target_not_found: &["Unknown data pack"],
+        };
+        assert_eq!(
+            parse_outcome(
+                "[12:00:00] [Server thread/INFO]: Data pack is already enabled!",
+                &messages
+            ),
+            CommandOutcome::NoChange
+        );
+        assert_eq!(
+            parse_outcome(
+                "[12:00:00] [Server thread/INFO]: Unknown data pack 'x'",
+                &messages
+            ),
+            CommandOutcome::TargetNotFound
+        );
+    }
+
+    #[test]
+    fn check_turns_failures_into_errors() {
+        let not_found = || WrapperError::PlayerNotFound("Steve".to_owned());
+        for outcome in [CommandOutcome::Changed, CommandOutcome::NoChange] {
+            assert_eq!(
+                outcome.clone().check("/op Steve", not_found).unwrap(),
+                outcome
+            );
+        }
+
+        let error = |outcome: CommandOutcome| {
+            outcome
+                .check("/op Steve", not_found)
+                .unwrap_err()
+                .downcast::<WrapperError>()
+                .ok()
+        };
+        assert!(matches!(
+            error(CommandOutcome::TargetNotFound),
+            Some(WrapperError::PlayerNotFound(name)) if name == "Steve"
+        ));
+        assert!(matches!(
+            error(CommandOutcome::UnknownCommand),
+            Some(WrapperError::CommandRejected(cmd)) if cmd == "/op Steve"
+        ));
+        assert!(matches!(
+            error(CommandOutcome::PermissionDenied),
+            Some(WrapperError::CommandNotPermitted(cmd)) if cmd == "/op Steve"
+        ));
+        assert!(error(CommandOutcome::Unparsed("huh".to_owned())).is_none());
+    }
+
+    #[test]
+    fn serializes_with_the_message_alongside() {
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::NoChange).unwrap(),
+            serde_json::json!({"outcome": "no_change"})
+        );
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::Unparsed("huh".to_owned())).unwrap(),
+            serde_json::json!({"outcome": "unparsed", "message": "huh"})
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use regex::Regex;
+use tokio::sync::broadcast;
+
+use crate::{
+    events::{self, ServerEvent, StartupProgress},
+    line_channel::LineSender,
+    logs::LogBuffer,
+    SERVER_READY_PATTERN,
+};
+
+// How many of each stream's most recent lines to remember when checking whether
+// the other stream repeated them.
+const RECENT_LINES_CAPACITY: usize = 64;
+// How many lines each subscriber to the console can fall behind by before it
+// starts missing them.
+const CONSOLE_CHANNEL_CAPACITY: usize = 1024;
+
+/// One of the Minecraft server process's output streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    fn index(self) -> usize {
+        match self {
+            OutputStream::Stdout => 0,
+            OutputStream::Stderr => 1,
+        }
+    }
+}
+
+/// Lines that each of the server's output streams wrote recently, and that the
+/// other stream hasn't repeated yet.
+///
+/// Some setups log everything to both stdout and stderr. When both streams are
+/// read as logs, this keeps each of those lines from being handled twice.
+#[derive(Debug, Default)]
+pub(crate) struct RecentLines {
+    streams: [VecDeque<String>; 2],
+}
+
+impl RecentLines {
+    /// Returns true if the other stream recently wrote the same line as the one
+    /// that `stream` just wrote. Otherwise, remembers the line in case the
+    /// other stream repeats it.
+    fn is_duplicate(&mut self, stream: OutputStream, line: &str) -> bool {
+        let other = &mut self.streams[1 - stream.index()];
+        if let Some(i) = other.iter().position(|l| l == line) {
+            other.remove(i);
+            return true;
+        }
+
+        let own = &mut self.streams[stream.index()];
+        if own.len() == RECENT_LINES_CAPACITY {
+            own.pop_front();
+        }
+        own.push_back(line.to_owned());
+        false
+    }
+}
+
+/// A response that the Minecraft server is expected to write to a command that
+/// the wrapper sent on its own.
+#[derive(Debug)]
+struct ExpectedResponse {
+    pattern: Regex,
+    /// When to stop expecting the response.
+    deadline: Instant,
+    /// Whether each of the server's output streams already wrote the response.
+    seen: [bool; 2],
+}
+
+/// Keeps the responses to commands that the wrapper sent on its own, like the
+/// "/list" command behind health checks, from being printed on the host. Also
+/// keeps everything from being printed while the console is paused.
+///
+/// Lines that aren't printed are still handled like every other line, so the
+/// wrapper and anybody subscribed to events can read them.
+///
+/// Everything that the host would print, paused or not, is also sent to
+/// anybody subscribed to the console. Outlives any single server process, so
+/// subscribers keep receiving lines across restarts.
+#[derive(Debug)]
+pub(crate) struct EchoFilter {
+    expected: Mutex<Vec<ExpectedResponse>>,
+    /// Set while the console is paused.
+    paused: Arc<AtomicBool>,
+    console: broadcast::Sender<String>,
+}
+
+impl Default for EchoFilter {
+    fn default() -> EchoFilter {
+        EchoFilter {
+            expected: Mutex::default(),
+            paused: Arc::default(),
+            console: broadcast::channel(CONSOLE_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl EchoFilter {
+    /// Returns a receiver for every line that the Minecraft server writes from
+    /// now on, minus the ones that this keeps from being printed.
+    pub(crate) fn subscribe_console(&self) -> broadcast::Receiver<String> {
+        self.console.subscribe()
+    }
+
+    /// Returns the flag that pauses printing everything on the host while it's
+    /// set.
+    pub(crate) fn paused_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.paused)
+    }
+
+    /// Hides the next line that matches `pattern` on each output stream, as
+    /// long as it's written before `deadline`. Call this before sending the
+    /// command, so that the response can't beat it.
+    pub(crate) fn expect(&self, pattern: &Regex, deadline: Instant) {
+        self.expected.lock().unwrap().push(ExpectedResponse {
+            pattern: pattern.clone(),
+            deadline,
+            seen: [false; 2],
+        });
+    }
+
+    /// Returns true if the provided line that `stream` wrote is an expected
+    /// response that shouldn't be printed.
+    fn is_expected(&self, stream: OutputStream, line: &str) -> bool {
+        if events::quotes_players(line) {
+            return false;
+        }
+        let mut expected = self.expected.lock().unwrap();
+        let now = Instant::now();
+        expected.retain(|response| response.deadline > now && !response.seen.iter().all(|&s| s));
+
+        match expected
+            .iter_mut()
+            .find(|response| !response.seen[stream.index()] && response.pattern.is_match(line))
+        {
+            Some(response) => {
+                response.seen[stream.index()] = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Reads what the Minecraft server writes to one of its output streams, and
+/// passes it along.
+pub(crate) struct LineForwarder {
+    pub(crate) stream: OutputStream,
+    /// Where complete lines are sent.
+    pub(crate) lines_tx: LineSender,
+    /// Where any [ServerEvent] that a line describes is sent.
+    pub(crate) events_tx: broadcast::Sender<ServerEvent>,
+    /// If this is provided, lines that the other stream already wrote are
+    /// skipped.
+    pub(crate) recent_lines: Option<Arc<Mutex<RecentLines>>>,
+    /// While this is set, every line is sent as a
+    /// [ServerEvent::StartupProgress]. It's cleared once the server announces
+    /// that it's ready.
+    pub(crate) starting: Arc<AtomicBool>,
+    /// Complete lines that this expects aren't printed.
+    pub(crate) echo_filter: Arc<EchoFilter>,
+    /// Prompts that the server might wait for an answer to while it's
+    /// starting. Prompts often aren't followed by a newline, so part of a line
+    /// that matches one of these is sent to `lines_tx` right away.
+    pub(crate) prompt_patterns: Arc<Vec<Regex>>,
+    /// Where the most recent lines are kept.
+    pub(crate) logs: Arc<LogBuffer>,
+}
+
+impl LineForwarder {
+    /// Reads what the Minecraft server writes to the stream until it's closed.
+    ///
+    /// Everything is printed on the same stream on the host for visibility as
+    /// soon as it's read, even if it's only part of a line, so that it shows up
+    /// in the host's console right away. Complete lines are handled
+    /// separately.
+    ///
+    /// Nothing here waits for more output than the server has written, so how
+    /// promptly lines arrive depends on how often the server flushes its own
+    /// output. Vanilla servers flush after every line.
+    pub(crate) fn run(self, mut reader: impl BufRead) {
+        let mut pending = Vec::new();
+        // How much of the line at the front of `pending` was already printed.
+        let mut echoed = 0;
+        // Whether the line at the front of `pending` was already sent as a
+        // prompt.
+        let mut prompt_sent = false;
+        loop {
+            let chunk = match reader.fill_buf() {
+                Ok([]) => break,
+                Ok(chunk) => chunk,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            pending.extend_from_slice(chunk);
+            let len = chunk.len();
+            reader.consume(len);
+
+            while let Some(i) = pending.iter().position(|&b| b == b'\n') {
+                let line_with_newline: Vec<u8> = pending.drain(..=i).collect();
+                let already_echoed = std::mem::take(&mut echoed);
+                let mut line = &line_with_newline[..line_with_newline.len() - 1];
+                if line.last() == Some(&b'\r') {
+                    line = &line[..line.len() - 1];
+                }
+                let line = String::from_utf8_lossy(line).into_owned();
+
+                // Part of a line that was already printed can't be taken back,
+                // so only lines that arrived all at once are hidden.
+                let expected = self.echo_filter.is_expected(self.stream, &line);
+                let shown = already_echoed > 0 || !expected;
+                if shown {
+                    self.echo(&line_with_newline[already_echoed..]);
+                }
+                self.handle_line(line, !std::mem::take(&mut prompt_sent), shown);
+            }
+
+            // Print the start of a line right away, rather than waiting for the
+            // rest of it.
+            if pending.len() > echoed {
+                self.echo(&pending[echoed..]);
+                echoed = pending.len();
+            }
+
+            if !prompt_sent && !pending.is_empty() && self.starting.load(Ordering::SeqCst) {
+                let partial_line = String::from_utf8_lossy(&pending);
+                if self
+                    .prompt_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(&partial_line))
+                {
+                    let _ = self.lines_tx.send(partial_line.into_owned());
+                    prompt_sent = true;
+                }
+            }
+        }
+
+        // The server might not have ended what it wrote last with a newline.
+        if !pending.is_empty() {
+            self.handle_line(
+                String::from_utf8_lossy(&pending).into_owned(),
+                !prompt_sent,
+                true,
+            );
+        }
+    }
+
+    /// Prints what the Minecraft server wrote on the same stream on the host,
+    /// unless the console is paused.
+    fn echo(&self, bytes: &[u8]) {
+        if !self.echo_filter.paused.load(Ordering::SeqCst) {
+            echo(self.stream, bytes);
+        }
+    }
+
+    /// Sends any [ServerEvent] that the provided line describes, and the line
+    /// itself if `send_line` is set. Lines that were printed on the host are
+    /// sent to subscribers to the console, too.
+    fn handle_line(&self, line: String, send_line: bool, shown: bool) {
+        if let Some(recent_lines) = &self.recent_lines {
+            if recent_lines
+                .lock()
+                .unwrap()
+                .is_duplicate(self.stream, &line)
+            {
+                return;
+            }
+        }
+
+        // An error here only means that nobody is subscribed to the console
+        // right now.
+        if shown {
+            let _ = self.echo_filter.console.send(line.clone());
+        }
+
+        self.logs.push(&line);
+
+        let event = if self.starting.load(Ordering::SeqCst) {
+            if SERVER_READY_PATTERN.is_match(&line) {
+                self.starting.store(false, Ordering::SeqCst);
+                ServerEvent::parse(&line)
+            } else {
+                Some(ServerEvent::StartupProgress(StartupProgress::parse(&line)))
+            }
+        } else {
+            ServerEvent::parse(&line)
+        };
+        // An error here only means that nobody is subscribed to events right
+        // now.
+        if let Some(event) = event {
+            let _ = self.events_tx.send(event);
+        }
+        if send_line {
+            // TODO: Revisit this .unwrap() call on send().
+            //
+            // Do we even want to handle errors here? A Q&D solution might be
+            // to just drop stdout messages that fail to send.
+            self.lines_tx.send(line).unwrap()
+        }
+    }
+}
+
+/// Prints what the Minecraft server wrote on the same stream on the host, and
+/// flushes it right away.
+fn echo(stream: OutputStream, bytes: &[u8]) {
+    // A host console that went away shouldn't keep the server's output from
+    // being handled, so errors are ignored.
+    let _ = match stream {
+        OutputStream::Stdout => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(bytes).and_then(|()| stdout.flush())
+        }
+        OutputStream::Stderr => {
+            let mut stderr = io::stderr().lock();
+            stderr.write_all(bytes).and_then(|()| stderr.flush())
+        }
+    };
+}
+
+/// Raises the flag that it holds when it's dropped, even if that's because the
+/// thread holding it panicked.
+pub(crate) struct RaiseOnDrop(pub(crate) Arc<AtomicBool>);
+
+impl Drop for RaiseOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
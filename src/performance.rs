@@ -0,0 +1,79 @@
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, bail};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::flavor::ServerFlavor;
+
+// Counts every entity in every loaded dimension, since the selector doesn't
+// narrow things down by position. Works on every flavor that keeps vanilla's
+// commands.
+pub(crate) const ENTITY_COUNT_COMMAND: &str = "/execute if entity @e";
+
+// Matches every line that the Minecraft server might write in response to
+// ENTITY_COUNT_COMMAND, which look something like this:
+// [16:14:22] [Server thread/INFO]: Test passed, count: 57
+// [16:14:22] [Server thread/INFO]: Test failed
+pub(crate) static ENTITY_COUNT_RESPONSE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: (Test passed, count: \d+|Test failed|Unknown or incomplete command)").unwrap()
+});
+
+// Matches every line that a Paper-based server might write in response to
+// "/paper chunkinfo <world>", which look something like this:
+// [16:14:22] [Server thread/INFO]: Total: 441 Inactive: 0 Border: 12 Ticking: 300 Entity: 129
+pub(crate) static CHUNK_INFO_RESPONSE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: (Total: \d+ Inactive: \d+|Couldn't find world|Unknown or incomplete command)")
+        .unwrap()
+});
+static CHUNK_INFO_TOTAL_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]: Total: (\d+) ").unwrap());
+
+/// How busy the Minecraft server is, as far as it'll say.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PerformanceSnapshot {
+    pub flavor: ServerFlavor,
+    /// How many entities there are in every loaded dimension.
+    pub entities: usize,
+    /// How many chunks are loaded in the overworld. Only Paper-based servers
+    /// report this.
+    pub loaded_chunks: Option<usize>,
+}
+
+/// Returns whether servers of the provided flavor report how many chunks they
+/// have loaded, with "/paper chunkinfo".
+pub(crate) fn reports_loaded_chunks(flavor: ServerFlavor) -> bool {
+    matches!(
+        flavor,
+        ServerFlavor::Paper | ServerFlavor::Purpur | ServerFlavor::Folia
+    )
+}
+
+/// Parses the Minecraft server's response to [ENTITY_COUNT_COMMAND].
+pub(crate) fn parse_entity_count_response(response: &str) -> anyhow::Result<usize> {
+    let (_, message) = response.split_once("]: ").unwrap_or(("", response));
+    if message.starts_with("Test failed") {
+        // Nothing matched the selector.
+        return Ok(0);
+    }
+    if let Some(count) = message.strip_prefix("Test passed, count: ") {
+        return count
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Unexpected entity count: {:?}", message));
+    }
+    bail!("The Minecraft server rejected the command: {}", message)
+}
+
+/// Parses a Paper-based server's response to "/paper chunkinfo <world>".
+pub(crate) fn parse_chunk_info_response(response: &str) -> anyhow::Result<usize> {
+    match CHUNK_INFO_TOTAL_PATTERN.captures(response) {
+        Some(captures) => captures[1]
+            .parse()
+            .map_err(|_| anyhow!("Unexpected chunk count: {:?}", response)),
+        None => {
+            let (_, message) = response.split_once("]: ").unwrap_or(("", response));
+            bail!("The Minecraft server rejected the command: {}", message)
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Servers answer handshakes that ask for this version with their own, instead
+// of refusing them.
+const ANY_PROTOCOL_VERSION: i32 = -1;
+// Tells the server that the client wants its status, not to log in.
+const NEXT_STATE_STATUS: i32 = 1;
+const PACKET_ID_HANDSHAKE: i32 = 0x00;
+const PACKET_ID_STATUS: i32 = 0x00;
+const PACKET_ID_PING: i32 = 0x01;
+// Status responses include the server icon, so they can be a few dozen KiB.
+// Nothing legitimate comes close to this.
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// What a Minecraft server shows in players' server lists, along with how long
+/// it took to answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PingStatus {
+    /// The message of the day, as plain text. Can still have formatting codes
+    /// in it, like "§a".
+    pub motd: String,
+    /// The name of the version that the server runs, like "1.20.1". Servers
+    /// that proxy to others sometimes put something else here.
+    pub version: String,
+    /// The protocol version that the server speaks, like 763 for 1.20.1.
+    pub protocol: i32,
+    pub online_players: usize,
+    pub max_players: usize,
+    /// Some of the names of the players who are online. Servers only send a
+    /// handful, and some send none at all.
+    pub player_sample: Vec<String>,
+    /// How long the server took to answer a ping, in milliseconds.
+    pub latency_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    version: StatusVersion,
+    players: StatusPlayers,
+    #[serde(default)]
+    description: Value,
+}
+
+#[derive(Deserialize)]
+struct StatusVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Deserialize)]
+struct StatusPlayers {
+    max: usize,
+    online: usize,
+    #[serde(default)]
+    sample: Vec<SamplePlayer>,
+}
+
+#[derive(Deserialize)]
+struct SamplePlayer {
+    name: String,
+}
+
+/// Asks the Minecraft server at the provided address for its status with the
+/// Server List Ping, just like players' clients do to fill in their server
+/// lists. Doesn't send the server any commands, so nothing shows up in its
+/// logs, and it works on every server, whatever's in `server.properties`.
+///
+/// `timeout` applies to connecting, and to every read and write afterwards.
+pub fn ping(addr: SocketAddr, timeout: Duration) -> anyhow::Result<PingStatus> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .with_context(|| format!("Failed to connect to the Minecraft server at {}", addr))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+
+    let mut handshake = Vec::new();
+    write_var_int(&mut handshake, ANY_PROTOCOL_VERSION);
+    write_string(&mut handshake, &addr.ip().to_string());
+    handshake.extend_from_slice(&addr.port().to_be_bytes());
+    write_var_int(&mut handshake, NEXT_STATE_STATUS);
+    write_packet(&mut stream, PACKET_ID_HANDSHAKE, &handshake)?;
+    write_packet(&mut stream, PACKET_ID_STATUS, &[])?;
+
+    let (id, response) = read_packet(&mut stream)?;
+    if id != PACKET_ID_STATUS {
+        bail!("Got a packet with ID {} instead of a status response", id);
+    }
+    let json = read_string(&mut response.as_slice())?;
+    let status: StatusResponse = serde_json::from_str(&json)
+        .with_context(|| "Got a status response that isn't what the wrapper expects")?;
+
+    // The server sends the same payload back.
+    let payload = UNIX_EPOCH.elapsed().unwrap_or_default().as_millis() as i64;
+    let started = Instant::now();
+    write_packet(&mut stream, PACKET_ID_PING, &payload.to_be_bytes())?;
+    let (id, pong) = read_packet(&mut stream)?;
+    let latency = started.elapsed();
+    if id != PACKET_ID_PING || pong != payload.to_be_bytes() {
+        bail!("The Minecraft server didn't answer the ping with the same payload");
+    }
+
+    let mut motd = String::new();
+    flatten_text(&status.description, &mut motd);
+    Ok(PingStatus {
+        motd,
+        version: status.version.name,
+        protocol: status.version.protocol,
+        online_players: status.players.online,
+        max_players: status.players.max,
+        player_sample: status.players.sample.into_iter().map(|p| p.name).collect(),
+        latency_ms: latency.as_millis() as u64,
+    })
+}
+
+/// Appends the plain text in a chat component to `out`. Older servers send
+/// their MOTD as a plain string, and newer ones as a component, whose text can
+/// be split between it and its "extra" children.
+fn flatten_text(component: &Value, out: &mut String) {
+    match component {
+        Value::String(text) => out.push_str(text),
+        Value::Array(parts) => parts.iter().for_each(|part| flatten_text(part, out)),
+        Value::Object(fields) => {
+            if let Some(Value::String(text)) = fields.get("text") {
+                out.push_str(text);
+            }
+            if let Some(extra) = fields.get("extra") {
+                flatten_text(extra, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, id: i32, data: &[u8]) -> anyhow::Result<()> {
+    let mut body = Vec::with_capacity(data.len() + 5);
+    write_var_int(&mut body, id);
+    body.extend_from_slice(data);
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    write_var_int(&mut packet, body.len() as i32);
+    packet.extend_from_slice(&body);
+    stream
+        .write_all(&packet)
+        .with_context(|| "Failed to send a packet to the Minecraft server")
+}
+
+/// Reads one packet, and returns its ID and the data after it.
+fn read_packet(stream: &mut TcpStream) -> anyhow::Result<(i32, Vec<u8>)> {
+    let len = read_var_int(stream)?;
+    let len = match usize::try_from(len) {
+        Ok(len) if (1..=MAX_PACKET_LEN).contains(&len) => len,
+        _ => bail!("Got a packet that says it's {} bytes long", len),
+    };
+    let mut packet = vec![0; len];
+    stream
+        .read_exact(&mut packet)
+        .with_context(|| "Failed to read a packet from the Minecraft server")?;
+    let mut data = packet.as_slice();
+    let id = read_var_int(&mut data)?;
+    Ok((id, data.to_vec()))
+}
+
+fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+fn read_var_int(reader: &mut impl Read) -> anyhow::Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                anyhow::anyhow!("The Minecraft server closed the connection")
+            } else {
+                anyhow::Error::new(e).context("Failed to read a packet from the Minecraft server")
+            }
+        })?;
+        value |= ((byte[0] & 0x7F) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    bail!("Got a VarInt that's longer than 5 bytes")
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_var_int(buf, s.len() as i32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_string(reader: &mut &[u8]) -> anyhow::Result<String> {
+    let len = read_var_int(reader)?;
+    let len = match usize::try_from(len) {
+        Ok(len) if len <= reader.len() => len,
+        _ => bail!("Got a string that says it's {} bytes long", len),
+    };
+    let (s, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(String::from_utf8_lossy(s).into_owned())
+}
@@ -0,0 +1,130 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    error::WrapperError,
+    ping::{self, PingStatus},
+    properties::ServerProperties,
+    query::{self, QueryStatus},
+};
+
+/// The port that Minecraft servers listen for players on unless
+/// `server.properties` says otherwise.
+pub(crate) const DEFAULT_SERVER_PORT: u16 = 25565;
+
+/// Asks the Minecraft server about itself over the network, the way that
+/// players' clients and server list sites do, instead of by sending it
+/// commands.
+///
+/// It only needs to know where the server's files are, so it doesn't go through
+/// the [Wrapper](crate::Wrapper), and it works even while the wrapper is busy
+/// backing up or restarting the server. Get one with
+/// [Wrapper::server_probe()](crate::Wrapper::server_probe()).
+#[derive(Debug, Clone)]
+pub struct ServerProbe {
+    server_dir: PathBuf,
+    server_port: Option<u16>,
+    timeout: Duration,
+}
+
+impl ServerProbe {
+    /// `server_port` is the port from the wrapper's config, if it has one,
+    /// which is used instead of the one in `server.properties`. `timeout`
+    /// applies to every round trip.
+    pub fn new(server_dir: &Path, server_port: Option<u16>, timeout: Duration) -> ServerProbe {
+        ServerProbe {
+            server_dir: server_dir.to_owned(),
+            server_port,
+            timeout,
+        }
+    }
+
+    /// Works out which port the Minecraft server listens for players on: the
+    /// one from the wrapper's config if there is one, or else the one in
+    /// `server.properties`. Returns [None] if neither is there.
+    pub fn server_port(&self) -> Option<u16> {
+        if let Some(port) = self.server_port {
+            return Some(port);
+        }
+        match ServerProperties::read_from_dir(&self.server_dir) {
+            // The server uses the default port if server.properties doesn't
+            // say otherwise.
+            Ok(properties) => Some(
+                properties
+                    .get_parsed("server-port")
+                    .unwrap_or(DEFAULT_SERVER_PORT),
+            ),
+            Err(_) => None,
+        }
+    }
+
+    /// Pings the Minecraft server the way that players' clients do to fill in
+    /// their server lists, and returns its MOTD, version, player counts, and
+    /// how long it took to answer. Doesn't send it any commands, so it's a
+    /// health check that doesn't fill up the server's logs, and unlike
+    /// [ServerProbe::query_status()], it works whatever's in
+    /// `server.properties`.
+    ///
+    /// Returns a [WrapperError::NotSupported] if the server's port isn't
+    /// known. See [ping::ping()].
+    pub fn ping(&self) -> anyhow::Result<PingStatus> {
+        let port = self.server_port().ok_or_else(|| {
+            WrapperError::NotSupported(
+                "The Minecraft server's port isn't known. Set server_port in config.yaml"
+                    .to_owned(),
+            )
+        })?;
+        ping::ping(SocketAddr::from(([127, 0, 0, 1], port)), self.timeout)
+    }
+
+    /// Asks the Minecraft server about itself over the Query protocol: its
+    /// MOTD, version, world name, and who's online. Doesn't send it any
+    /// commands, so unlike [Wrapper::list_players()](crate::Wrapper::list_players()),
+    /// it works even while the server is too busy to answer them, and leaves
+    /// nothing in its logs.
+    ///
+    /// Uses the Query settings in `server.properties`. Returns a
+    /// [WrapperError::NotSupported] if queries aren't turned on there. See
+    /// [query::query_with_properties()].
+    pub fn query_status(&self) -> anyhow::Result<QueryStatus> {
+        let properties = ServerProperties::read_from_dir(&self.server_dir)?;
+        query::query_with_properties(&properties, self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn configured_port_wins_over_server_properties() {
+        let dir = TempDir::new("probe-port");
+        fs::write(dir.join("server.properties"), "server-port=25570\n").unwrap();
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            ServerProbe::new(dir.path(), Some(25580), timeout).server_port(),
+            Some(25580)
+        );
+        assert_eq!(
+            ServerProbe::new(dir.path(), None, timeout).server_port(),
+            Some(25570)
+        );
+        fs::write(dir.join("server.properties"), "motd=hi\n").unwrap();
+        assert_eq!(
+            ServerProbe::new(dir.path(), None, timeout).server_port(),
+            Some(DEFAULT_SERVER_PORT)
+        );
+        fs::remove_file(dir.join("server.properties")).unwrap();
+        assert_eq!(
+            ServerProbe::new(dir.path(), None, timeout).server_port(),
+            None
+        );
+    }
+}
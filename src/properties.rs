@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+
+use crate::error::WrapperError;
+
+/// The name of the file where the Minecraft server keeps most of its settings.
+pub const SERVER_PROPERTIES_FILE_NAME: &str = "server.properties";
+
+// Written by ServerProperties::write_default_to_dir(). Just enough for the
+// properties that mc-server-wrapper cares about to have sensible values. The
+// Minecraft server adds everything else the next time it starts.
+const DEFAULT_SERVER_PROPERTIES: &str = "\
+#Minecraft server properties
+level-name=world
+max-players=20
+motd=A Minecraft Server
+server-port=25565
+";
+
+// What the Minecraft server calls its world directory when `level-name` isn't
+// set.
+const DEFAULT_LEVEL_NAME: &str = "world";
+
+/// The contents of a Minecraft server's `server.properties` file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerProperties {
+    properties: HashMap<String, String>,
+}
+
+impl ServerProperties {
+    /// Reads and parses the `server.properties` file in the provided directory.
+    ///
+    /// Returns a [WrapperError::PropertiesNotFound] if that file doesn't exist.
+    pub fn read_from_dir(server_dir: &Path) -> anyhow::Result<ServerProperties> {
+        let path = server_dir.join(SERVER_PROPERTIES_FILE_NAME);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(WrapperError::PropertiesNotFound(path).into())
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+        };
+        Ok(ServerProperties::parse(&contents))
+    }
+
+    /// Writes a minimal `server.properties` file into the provided directory,
+    /// and returns the path to it.
+    ///
+    /// Returns a [WrapperError::PropertiesAlreadyExist] instead of overwriting a
+    /// file that's already there.
+    pub fn write_default_to_dir(server_dir: &Path) -> anyhow::Result<PathBuf> {
+        let path = server_dir.join(SERVER_PROPERTIES_FILE_NAME);
+        let mut file = match File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(WrapperError::PropertiesAlreadyExist(path).into())
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", &path)),
+        };
+        file.write_all(DEFAULT_SERVER_PROPERTIES.as_bytes())
+            .with_context(|| format!("Failed to write to {:?}", &path))?;
+        Ok(path)
+    }
+
+    /// Reads the `server.properties` file in the provided directory as it is,
+    /// without parsing it.
+    ///
+    /// Returns a [WrapperError::PropertiesNotFound] if that file doesn't exist.
+    pub fn read_raw_from_dir(server_dir: &Path) -> anyhow::Result<String> {
+        let path = server_dir.join(SERVER_PROPERTIES_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(WrapperError::PropertiesNotFound(path).into())
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+        }
+    }
+
+    /// Replaces the `server.properties` file in the provided directory with
+    /// `contents`, and returns the path to it.
+    ///
+    /// The new contents are written to a temporary file first, which is then
+    /// moved into place, so the file is never left half-written. Returns a
+    /// [WrapperError::InvalidArgument] without touching anything if `contents`
+    /// has a line that isn't blank, a comment, or a `key=value` pair.
+    pub fn write_raw_to_dir(server_dir: &Path, contents: &str) -> anyhow::Result<PathBuf> {
+        validate(contents)?;
+
+        let path = server_dir.join(SERVER_PROPERTIES_FILE_NAME);
+        let tmp_path = server_dir.join(format!("{}.tmp", SERVER_PROPERTIES_FILE_NAME));
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write to {:?}", &tmp_path))?;
+        if let Err(e) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e).with_context(|| format!("Failed to replace {:?}", &path));
+        }
+        Ok(path)
+    }
+
+    /// Parses the contents of a `server.properties` file.
+    ///
+    /// Blank lines and comments are skipped, as are lines that don't look like
+    /// `key=value` pairs.
+    pub fn parse(contents: &str) -> ServerProperties {
+        let properties = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (unescape(key.trim()), unescape(value.trim())))
+            .collect();
+        ServerProperties { properties }
+    }
+
+    /// Returns the raw value of the provided property, if it's set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    /// Returns the value of the provided property parsed into a `T`, if it's set
+    /// and that value can be parsed.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Returns the `level-name`, which is where the world directory is,
+    /// relative to the server's directory. It's "world" if it isn't set.
+    pub fn level_name(&self) -> &str {
+        self.get("level-name")
+            .filter(|level_name| !level_name.is_empty())
+            .unwrap_or(DEFAULT_LEVEL_NAME)
+    }
+}
+
+/// Returns the world directory of the Minecraft server that keeps its files in
+/// the provided directory, going by the `level-name` in its
+/// `server.properties` file.
+///
+/// The server makes a world directory called "world" when that file doesn't
+/// exist yet, so that's what this falls back on if it can't be read.
+pub fn world_dir(server_dir: &Path) -> PathBuf {
+    let level_name = match ServerProperties::read_from_dir(server_dir) {
+        Ok(properties) => properties.level_name().to_owned(),
+        Err(_) => DEFAULT_LEVEL_NAME.to_owned(),
+    };
+    server_dir.join(level_name)
+}
+
+/// Returns a [WrapperError::InvalidArgument] if the provided contents of a
+/// `server.properties` file have a line that isn't blank, a comment, or a
+/// `key=value` pair.
+fn validate(contents: &str) -> Result<(), WrapperError> {
+    // A line that ends with an unescaped backslash continues onto the next
+    // one.
+    let mut continued = false;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let is_continuation = continued;
+        continued = line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1;
+        if is_continuation || line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, _)) if !key.trim().is_empty() => {}
+            _ => {
+                return Err(WrapperError::InvalidArgument(format!(
+                    "Line {} isn't a key=value pair: {:?}",
+                    i + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Undoes the escaping that Java applies when it writes a `.properties` file.
+/// The Minecraft server escapes colons, equals signs, and non-ASCII characters
+/// like the section sign used for formatting codes in the MOTD.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => unescaped.push(c),
+                    None => {
+                        unescaped.push_str("\\u");
+                        unescaped.push_str(&code);
+                    }
+                }
+            }
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::{error::WrapperError, properties::ServerProperties};
+
+/// The port that Minecraft servers answer queries on unless `server.properties`
+/// says otherwise. Servers use their `server-port` for queries by default, too.
+pub const DEFAULT_QUERY_PORT: u16 = 25565;
+
+// Every request starts with these two bytes.
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const PACKET_TYPE_HANDSHAKE: u8 = 9;
+const PACKET_TYPE_STAT: u8 = 0;
+// Servers only use the low 4 bits of each byte of the session ID.
+const SESSION_ID_MASK: i32 = 0x0F0F_0F0F;
+// Full stat responses start with this, right after their type and session ID.
+const FULL_STAT_PADDING: &[u8] = b"splitnum\0\x80\0";
+// And the list of players starts with this, right after the key-value section.
+const PLAYER_SECTION_PADDING: &[u8] = b"\x01player_\0\0";
+// Big enough for any response. Servers answer with a single datagram.
+const MAX_RESPONSE_LEN: usize = 65_535;
+
+/// What a Minecraft server says about itself over the Query protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueryStatus {
+    /// The message of the day, from `motd` in `server.properties`.
+    pub motd: String,
+    /// The Minecraft version that the server runs, like "1.20.1".
+    pub version: String,
+    /// The name of the world, from `level-name` in `server.properties`.
+    pub map: String,
+    pub game_type: String,
+    /// The names of the players who are online.
+    pub players: Vec<String>,
+    pub num_players: usize,
+    pub max_players: usize,
+    /// What the server says about its plugins. Empty on vanilla servers.
+    pub plugins: String,
+}
+
+/// Asks the Minecraft server at the provided address about itself, with the
+/// Query protocol. Unlike [Wrapper::list_players()](crate::Wrapper::list_players()),
+/// this doesn't send the server any commands, so nothing shows up in its logs.
+///
+/// `timeout` applies to each of the two round trips that a query takes.
+pub fn query(addr: SocketAddr, timeout: Duration) -> anyhow::Result<QueryStatus> {
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))
+        .with_context(|| "Failed to open a UDP socket for querying the Minecraft server")?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    socket
+        .connect(addr)
+        .with_context(|| format!("Failed to query the Minecraft server at {}", addr))?;
+
+    let session_id = std::process::id() as i32 & SESSION_ID_MASK;
+    let challenge = round_trip(&socket, PACKET_TYPE_HANDSHAKE, session_id, &[])?;
+    let challenge: i32 = read_string(&challenge)
+        .0
+        .parse()
+        .with_context(|| "Got a Query handshake response without a challenge token")?;
+
+    // The 4 bytes after the challenge token are what make this a full stat
+    // request instead of a basic one.
+    let mut payload = challenge.to_be_bytes().to_vec();
+    payload.extend_from_slice(&[0; 4]);
+    let response = round_trip(&socket, PACKET_TYPE_STAT, session_id, &payload)?;
+    parse_full_stat(&response)
+}
+
+/// Connects to the Query port of the Minecraft server that runs with the
+/// provided `server.properties`, on this machine, and asks it about itself.
+///
+/// Returns a [WrapperError::NotSupported] if queries aren't turned on there.
+pub fn query_with_properties(
+    properties: &ServerProperties,
+    timeout: Duration,
+) -> anyhow::Result<QueryStatus> {
+    if properties.get("enable-query") != Some("true") {
+        return Err(WrapperError::NotSupported(
+            "Queries aren't turned on. Set enable-query=true in server.properties, and restart the Minecraft server".to_owned(),
+        )
+        .into());
+    }
+    let port = properties
+        .get_parsed("query.port")
+        .unwrap_or(DEFAULT_QUERY_PORT);
+    query(SocketAddr::from(([127, 0, 0, 1], port)), timeout)
+}
+
+/// Sends one request, and returns the body of the server's response to it.
+fn round_trip(
+    socket: &UdpSocket,
+    packet_type: u8,
+    session_id: i32,
+    payload: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let mut request = MAGIC.to_vec();
+    request.push(packet_type);
+    request.extend_from_slice(&session_id.to_be_bytes());
+    request.extend_from_slice(payload);
+    socket
+        .send(&request)
+        .with_context(|| "Failed to send a Query request to the Minecraft server")?;
+
+    let mut response = vec![0; MAX_RESPONSE_LEN];
+    let len = socket.recv(&mut response).with_context(|| {
+        "The Minecraft server didn't answer a Query request. Is enable-query set, and is the server done starting?"
+    })?;
+    response.truncate(len);
+    // Responses start with the request's type and session ID.
+    if len < 5 || response[0] != packet_type || response[1..5] != session_id.to_be_bytes() {
+        bail!("Got an unexpected Query response from the Minecraft server");
+    }
+    Ok(response.split_off(5))
+}
+
+/// Parses the body of a full stat response: a section of null-terminated keys
+/// and values, followed by a list of player names.
+fn parse_full_stat(response: &[u8]) -> anyhow::Result<QueryStatus> {
+    let mut rest = match response.strip_prefix(FULL_STAT_PADDING) {
+        Some(rest) => rest,
+        None => bail!("Got a Query response from the Minecraft server that isn't a full stat"),
+    };
+
+    let mut values = HashMap::new();
+    loop {
+        let (key, after_key) = read_string(rest);
+        if key.is_empty() {
+            rest = after_key;
+            break;
+        }
+        let (value, after_value) = read_string(after_key);
+        values.insert(key, value);
+        rest = after_value;
+    }
+
+    let mut players = Vec::new();
+    if let Some(mut names) = rest.strip_prefix(PLAYER_SECTION_PADDING) {
+        loop {
+            let (name, after_name) = read_string(names);
+            if name.is_empty() {
+                break;
+            }
+            players.push(name);
+            names = after_name;
+        }
+    }
+
+    let mut take = |key: &str| values.remove(key).unwrap_or_default();
+    Ok(QueryStatus {
+        motd: take("hostname"),
+        version: take("version"),
+        map: take("map"),
+        game_type: take("gametype"),
+        num_players: take("numplayers").parse().unwrap_or(players.len()),
+        max_players: take("maxplayers").parse().unwrap_or(0),
+        plugins: take("plugins"),
+        players,
+    })
+}
+
+/// Reads a null-terminated string, and returns it along with everything after
+/// the null byte.
+fn read_string(bytes: &[u8]) -> (String, &[u8]) {
+    let (string, rest) = match bytes.iter().position(|&b| b == 0) {
+        Some(end) => (&bytes[..end], &bytes[end + 1..]),
+        None => (bytes, &[][..]),
+    };
+    (String::from_utf8_lossy(string).into_owned(), rest)
+}
@@ -0,0 +1,220 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{error::WrapperError, properties::ServerProperties};
+
+/// The port that Minecraft servers listen for RCON clients on unless
+/// `server.properties` says otherwise.
+pub const DEFAULT_RCON_PORT: u16 = 25575;
+
+/// The type of packet that carries a response to a command.
+pub const PACKET_TYPE_RESPONSE: i32 = 0;
+/// The type of packet that carries a command. Responses to logins have this
+/// type, too.
+pub const PACKET_TYPE_COMMAND: i32 = 2;
+/// The type of packet that carries a password to log in with.
+pub const PACKET_TYPE_LOGIN: i32 = 3;
+/// The request ID that servers answer a login with when the password is wrong.
+pub const LOGIN_FAILED_ID: i32 = -1;
+/// The longest command that Minecraft servers accept over RCON, in bytes.
+pub const MAX_COMMAND_LEN: usize = 1446;
+/// The most text that Minecraft servers put in one response packet, in bytes.
+/// Longer responses are split across several packets.
+pub const MAX_RESPONSE_BODY_LEN: usize = 4096;
+
+// Minecraft servers answer packets of any type that they don't know with a
+// single packet, which marks the end of the response to the command before it.
+const PACKET_TYPE_END_MARKER: i32 = 200;
+// Everything in a packet but its body: the request ID, the type, and two null
+// bytes. One ends the body, and the other is an empty second string that the
+// protocol still requires.
+const PACKET_OVERHEAD: usize = 4 + 4 + 2;
+
+/// One packet in the Source RCON protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Picked by the client. Responses carry the ID of the packet that they
+    /// answer.
+    pub id: i32,
+    pub packet_type: i32,
+    pub body: String,
+}
+
+/// Reads one packet, whose body can be at most `max_body_len` bytes long.
+pub fn read_packet(reader: &mut impl Read, max_body_len: usize) -> anyhow::Result<Packet> {
+    let len = read_i32(reader)?;
+    let len = match usize::try_from(len) {
+        Ok(len) if (PACKET_OVERHEAD..=PACKET_OVERHEAD + max_body_len).contains(&len) => len,
+        _ => bail!("Got an RCON packet that says it's {} bytes long", len),
+    };
+    let mut packet = vec![0; len];
+    reader
+        .read_exact(&mut packet)
+        .with_context(|| "Failed to read an RCON packet")?;
+
+    Ok(Packet {
+        id: i32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]),
+        packet_type: i32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        // Drop the two null bytes at the end.
+        body: String::from_utf8_lossy(&packet[8..len - 2]).into_owned(),
+    })
+}
+
+/// Writes one packet.
+pub fn write_packet(writer: &mut impl Write, packet: &Packet) -> io::Result<()> {
+    let len = PACKET_OVERHEAD + packet.body.len();
+    let mut bytes = Vec::with_capacity(4 + len);
+    bytes.extend_from_slice(&(len as i32).to_le_bytes());
+    bytes.extend_from_slice(&packet.id.to_le_bytes());
+    bytes.extend_from_slice(&packet.packet_type.to_le_bytes());
+    bytes.extend_from_slice(packet.body.as_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    writer.write_all(&bytes)
+}
+
+/// A connection to the Minecraft server's RCON port, which responds to each
+/// command on its own, instead of mixing the response in with everything else
+/// that the server logs.
+///
+/// The server only listens for RCON clients if `enable-rcon` and
+/// `rcon.password` are set in its `server.properties` file.
+#[derive(Debug)]
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    /// Connects to the RCON port at the provided address, and logs in with
+    /// `password`. `timeout` applies to connecting, and to every read and write
+    /// afterwards.
+    ///
+    /// Returns a [WrapperError::RconLoginFailed] if the server turns down the
+    /// password.
+    pub fn connect(
+        addr: SocketAddr,
+        password: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<RconClient> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)
+            .with_context(|| format!("Failed to connect to RCON at {}", addr))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+
+        let mut client = RconClient { stream, next_id: 1 };
+        let id = client.send(PACKET_TYPE_LOGIN, password)?;
+        // Some servers send an empty response packet before the login's.
+        loop {
+            let packet = client.receive()?;
+            if packet.id == LOGIN_FAILED_ID {
+                return Err(WrapperError::RconLoginFailed.into());
+            }
+            if packet.id == id && packet.packet_type == PACKET_TYPE_COMMAND {
+                return Ok(client);
+            }
+        }
+    }
+
+    /// Connects to the RCON port of the Minecraft server that runs with the
+    /// provided `server.properties`, on this machine.
+    ///
+    /// Returns a [WrapperError::NotSupported] if RCON isn't turned on there.
+    pub fn connect_with_properties(
+        properties: &ServerProperties,
+        timeout: Duration,
+    ) -> anyhow::Result<RconClient> {
+        let password = match properties.get("rcon.password") {
+            Some(password)
+                if properties.get("enable-rcon") == Some("true") && !password.is_empty() =>
+            {
+                password
+            }
+            _ => {
+                return Err(WrapperError::NotSupported(
+                    "RCON isn't turned on. Set enable-rcon=true and rcon.password in server.properties, and restart the Minecraft server".to_owned(),
+                )
+                .into())
+            }
+        };
+        let port = properties
+            .get_parsed("rcon.port")
+            .unwrap_or(DEFAULT_RCON_PORT);
+        RconClient::connect(SocketAddr::from(([127, 0, 0, 1], port)), password, timeout)
+    }
+
+    /// Changes how long reads and writes can take before they're given up on.
+    pub fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.set_write_timeout(Some(timeout))
+    }
+
+    /// Runs the provided command, and returns the server's whole response to
+    /// it. Responses can span more than one line, and they don't have the
+    /// timestamps and thread names that the server puts in its logs.
+    ///
+    /// The command doesn't need a leading slash. Returns a
+    /// [WrapperError::InvalidArgument] if it's too long for the server to
+    /// accept.
+    pub fn command(&mut self, cmd: &str) -> anyhow::Result<String> {
+        if cmd.len() > MAX_COMMAND_LEN {
+            return Err(WrapperError::InvalidArgument(format!(
+                "Commands sent over RCON can be at most {} bytes long, but that one is {}",
+                MAX_COMMAND_LEN,
+                cmd.len()
+            ))
+            .into());
+        }
+
+        let id = self.send(PACKET_TYPE_COMMAND, cmd)?;
+        // Long responses come in more than one packet, with nothing saying
+        // which one is the last. The server answers packets in order, though,
+        // so the answer to this one comes right after all of them.
+        let end_marker_id = self.send(PACKET_TYPE_END_MARKER, "")?;
+        let mut response = String::new();
+        loop {
+            let packet = self.receive()?;
+            if packet.id == end_marker_id {
+                return Ok(response);
+            }
+            if packet.id == id && packet.packet_type == PACKET_TYPE_RESPONSE {
+                response.push_str(&packet.body);
+            }
+        }
+    }
+
+    /// Sends a packet, and returns its request ID.
+    fn send(&mut self, packet_type: i32, body: &str) -> anyhow::Result<i32> {
+        let id = self.next_id;
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        let packet = Packet {
+            id,
+            packet_type,
+            body: body.to_owned(),
+        };
+        write_packet(&mut self.stream, &packet)
+            .with_context(|| "Failed to send a packet over RCON")?;
+        Ok(id)
+    }
+
+    fn receive(&mut self) -> anyhow::Result<Packet> {
+        read_packet(&mut self.stream, MAX_RESPONSE_BODY_LEN)
+    }
+}
+
+fn read_i32(reader: &mut impl Read) -> anyhow::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).map_err(|e| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            anyhow::anyhow!("The RCON connection was closed")
+        } else {
+            anyhow::Error::new(e).context("Failed to read an RCON packet")
+        }
+    })?;
+    Ok(i32::from_le_bytes(bytes))
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+use mc_server_wrapper::{
+    actor::WrapperHandle,
+    rcon::{self, Packet},
+    state::StateMachine,
+};
+
+use crate::{audit::AuditLog, auth::tokens_match};
+
+// How long a client can go without sending anything before it's disconnected.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+// The most clients that can be connected at once. Each one gets its own thread.
+const MAX_CONNECTIONS: usize = 16;
+// How long a client that logged in with the wrong password waits to hear so.
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(2);
+// How many times in a row an IP address can log in with the wrong password
+// before its clients are turned away without being let to try again.
+const MAX_FAILED_LOGINS: u32 = 5;
+// How long an IP address is locked out for, counting from its last failed
+// login. Its failures are also forgotten once this long has passed.
+const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+
+/// What RCON clients need to share to be served.
+struct Proxy {
+    password: String,
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    audit_log: Arc<AuditLog>,
+    // How many clients are connected right now.
+    connections: Arc<AtomicUsize>,
+    failed_logins: FailedLogins,
+}
+
+/// Keeps track of which IP addresses logged in with the wrong password
+/// recently, so that guessing the password takes forever.
+#[derive(Default)]
+struct FailedLogins {
+    by_ip: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+struct Failures {
+    // How many times in a row the wrong password was used.
+    count: u32,
+    last_at: Instant,
+}
+
+impl FailedLogins {
+    /// Returns true if clients from the provided IP address used the wrong
+    /// password too many times recently to be let to try again.
+    fn locked_out(&self, ip: IpAddr, now: Instant) -> bool {
+        match self.by_ip.lock().unwrap().get(&ip) {
+            Some(failures) => {
+                failures.count >= MAX_FAILED_LOGINS
+                    && now.duration_since(failures.last_at) < LOCKOUT_DURATION
+            }
+            None => false,
+        }
+    }
+
+    /// Records that a client from the provided IP address used the wrong
+    /// password.
+    fn failed(&self, ip: IpAddr, now: Instant) {
+        let mut by_ip = self.by_ip.lock().unwrap();
+        by_ip.retain(|_, failures| now.duration_since(failures.last_at) < LOCKOUT_DURATION);
+        let failures = by_ip.entry(ip).or_insert(Failures {
+            count: 0,
+            last_at: now,
+        });
+        failures.count += 1;
+        failures.last_at = now;
+    }
+
+    /// Forgets about the provided IP address's failed logins, now that one of
+    /// its clients used the right password.
+    fn succeeded(&self, ip: IpAddr) {
+        self.by_ip.lock().unwrap().remove(&ip);
+    }
+}
+
+/// Counts a client as connected until it's dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Returns [None] if [MAX_CONNECTIONS] clients are already connected.
+    fn open(connections: &Arc<AtomicUsize>) -> Option<Connection> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < MAX_CONNECTIONS).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Connection(Arc::clone(connections)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Listens for RCON clients on the provided port, and passes the commands of
+/// the ones that log in with `password` on to the Minecraft server, like
+/// they were typed into the wrapper's terminal. Each command is answered with
+/// whatever the server writes in response, without its log prefixes.
+///
+/// Lets RCON tools, like mcrcon, manage the server without it having RCON
+/// turned on itself.
+///
+/// Up to [MAX_CONNECTIONS] clients are served at once, and the rest are
+/// disconnected right away. Clients that use the wrong password wait a bit to
+/// hear so, and an IP address that does that too many times in a row is locked
+/// out for a while.
+pub(crate) fn spawn(
+    port: u16,
+    password: Option<String>,
+    wrapper: WrapperHandle,
+    state: StateMachine,
+    audit_log: Arc<AuditLog>,
+) -> anyhow::Result<()> {
+    let password = match password {
+        Some(password) if !password.is_empty() => password,
+        _ => bail!("rcon_port is set, so rcon_password has to be set, too"),
+    };
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(addr)
+        .with_context(|| format!("Failed to listen for RCON clients on {}", addr))?;
+    info!("Listening for RCON clients on {}", addr);
+
+    let proxy = Arc::new(Proxy {
+        password,
+        wrapper,
+        state,
+        audit_log,
+        connections: Arc::new(AtomicUsize::new(0)),
+        failed_logins: FailedLogins::default(),
+    });
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let connection = match Connection::open(&proxy.connections) {
+                        Some(connection) => connection,
+                        None => {
+                            warn!(
+                                "Turned away an RCON client, since {} are connected already",
+                                MAX_CONNECTIONS
+                            );
+                            continue;
+                        }
+                    };
+                    let proxy = Arc::clone(&proxy);
+                    thread::spawn(move || {
+                        let _connection = connection;
+                        proxy.serve(stream)
+                    });
+                }
+                Err(e) => warn!("Failed to accept an RCON client: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+impl Proxy {
+    /// Answers one client's packets until it disconnects.
+    fn serve(&self, mut stream: TcpStream) {
+        let ip = match stream.peer_addr() {
+            Ok(addr) => addr.ip(),
+            Err(e) => {
+                warn!(
+                    "Failed to tell where an RCON client is connecting from: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let identity = format!("rcon {}", ip);
+        if self.failed_logins.locked_out(ip, Instant::now()) {
+            warn!(
+                "{}: Turned away an RCON client that used the wrong password too many times",
+                identity
+            );
+            return;
+        }
+        if let Err(e) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+            warn!("{}: {}", identity, e);
+            return;
+        }
+
+        let mut logged_in = false;
+        // Read errors just mean that the client went away.
+        while let Ok(packet) = rcon::read_packet(&mut stream, rcon::MAX_COMMAND_LEN) {
+            let responses = match packet.packet_type {
+                rcon::PACKET_TYPE_LOGIN => {
+                    logged_in = tokens_match(&packet.body, &self.password);
+                    if logged_in {
+                        self.failed_logins.succeeded(ip);
+                    } else {
+                        warn!(
+                            "{}: Turned away an RCON client with the wrong password",
+                            identity
+                        );
+                        self.failed_logins.failed(ip, Instant::now());
+                        thread::sleep(FAILED_LOGIN_DELAY);
+                    }
+                    vec![Packet {
+                        id: if logged_in {
+                            packet.id
+                        } else {
+                            rcon::LOGIN_FAILED_ID
+                        },
+                        packet_type: rcon::PACKET_TYPE_COMMAND,
+                        body: String::new(),
+                    }]
+                }
+                _ if !logged_in => {
+                    warn!(
+                        "{}: Turned away an RCON client that didn't log in",
+                        identity
+                    );
+                    return;
+                }
+                rcon::PACKET_TYPE_COMMAND => {
+                    let output = self.run_command(&identity, &packet.body);
+                    split_response(packet.id, &output)
+                }
+                // Clients send packets like these to find out where the
+                // response to their last command ends, and they expect the
+                // answer that Minecraft servers give.
+                other => vec![Packet {
+                    id: packet.id,
+                    packet_type: rcon::PACKET_TYPE_RESPONSE,
+                    body: format!("Unknown request {:x}", other),
+                }],
+            };
+            for response in &responses {
+                if rcon::write_packet(&mut stream, response).is_err() {
+                    return;
+                }
+            }
+            if !logged_in {
+                return;
+            }
+        }
+    }
+
+    /// Passes the provided command on to the Minecraft server, and returns
+    /// what to tell the client.
+    fn run_command(&self, identity: &str, cmd: &str) -> String {
+        let cmd = cmd.trim();
+        // Stopping the server takes the HTTP API down with it, which only the
+        // wrapper's terminal and GET /stop know how to do.
+        if cmd.trim_start_matches('/') == "stop" {
+            return "mc-server-wrapper doesn't stop the Minecraft server over RCON. Use GET /stop, or type /stop into its terminal".to_owned();
+        }
+        self.audit_log.record(identity, cmd);
+        if let Err(e) = self.state.ensure_running() {
+            warn!(
+                "{}: Didn't pass {:?} on to the Minecraft server: {}",
+                identity, cmd, e
+            );
+            return e.to_string();
+        }
+
+        let result = self
+            .wrapper
+            .blocking_run_command_capture_output(cmd.to_owned(), None);
+        match result {
+            Ok(lines) => lines
+                .iter()
+                .map(|line| line.split_once("]: ").map_or(line.as_str(), |(_, m)| m))
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            Err(e) => {
+                let err_msg = format!(
+                    "Something went wrong while trying to pass {:?} on to the Minecraft server: {}",
+                    cmd, e
+                );
+                warn!("{}: {}", identity, err_msg);
+                err_msg
+            }
+        }
+    }
+}
+
+/// Splits the response to a command into as many packets as it takes, the way
+/// that Minecraft servers do.
+fn split_response(id: i32, output: &str) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut rest = output;
+    loop {
+        let mut end = rest.len().min(rcon::MAX_RESPONSE_BODY_LEN);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (body, remainder) = rest.split_at(end);
+        packets.push(Packet {
+            id,
+            packet_type: rcon::PACKET_TYPE_RESPONSE,
+            body: body.to_owned(),
+        });
+        if remainder.is_empty() {
+            return packets;
+        }
+        rest = remainder;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn too_many_failed_logins_lock_out_an_ip() {
+        let failed_logins = FailedLogins::default();
+        let now = Instant::now();
+        for _ in 1..MAX_FAILED_LOGINS {
+            failed_logins.failed(IP, now);
+        }
+        assert!(!failed_logins.locked_out(IP, now));
+
+        failed_logins.failed(IP, now);
+        assert!(failed_logins.locked_out(IP, now));
+        assert!(!failed_logins.locked_out(OTHER_IP, now));
+        assert!(!failed_logins.locked_out(IP, now + LOCKOUT_DURATION));
+    }
+
+    #[test]
+    fn logging_in_forgets_failed_logins() {
+        let failed_logins = FailedLogins::default();
+        let now = Instant::now();
+        for _ in 1..MAX_FAILED_LOGINS {
+            failed_logins.failed(IP, now);
+        }
+        failed_logins.succeeded(IP);
+        failed_logins.failed(IP, now);
+        assert!(!failed_logins.locked_out(IP, now));
+    }
+
+    #[test]
+    fn old_failed_logins_are_forgotten() {
+        let failed_logins = FailedLogins::default();
+        let now = Instant::now();
+        for _ in 1..MAX_FAILED_LOGINS {
+            failed_logins.failed(IP, now);
+        }
+        let later = now + LOCKOUT_DURATION;
+        failed_logins.failed(IP, later);
+        assert!(!failed_logins.locked_out(IP, later));
+    }
+
+    #[test]
+    fn connections_are_capped() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let open: Vec<Connection> = (0..MAX_CONNECTIONS)
+            .map(|_| Connection::open(&connections).unwrap())
+            .collect();
+        assert!(Connection::open(&connections).is_none());
+
+        drop(open);
+        assert_eq!(connections.load(Ordering::SeqCst), 0);
+        assert!(Connection::open(&connections).is_some());
+    }
+
+    #[test]
+    fn long_responses_are_split_on_char_boundaries() {
+        let output = "é".repeat(rcon::MAX_RESPONSE_BODY_LEN);
+        let packets = split_response(7, &output);
+        assert!(packets.len() > 1);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.id == 7 && packet.body.len() <= rcon::MAX_RESPONSE_BODY_LEN));
+        let joined: String = packets.iter().map(|packet| packet.body.as_str()).collect();
+        assert_eq!(joined, output);
+    }
+}
@@ -0,0 +1,1342 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::backup_files::{self, BackupFormat};
+
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_ATTEMPTS: u32 = 5;
+// How long to wait before the second attempt at an upload. Each attempt after
+// that waits twice as long as the one before it.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(10);
+// The biggest object that S3 takes in a single PUT, and the biggest part it
+// takes in a multipart upload.
+const MAX_OBJECT_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+const DEFAULT_PART_SIZE_BYTES: u64 = 16 * 1024 * 1024;
+// S3 doesn't take parts smaller than this in a multipart upload, other than
+// the last one.
+const MIN_PART_SIZE_BYTES: u64 = 5 * 1024 * 1024;
+// The most parts that S3 takes in a multipart upload.
+const MAX_PARTS: u64 = 10_000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the upload is allowed to go without making any progress.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+// How long to wait for a response once the whole backup is sent. Some
+// services check the upload's checksum before they respond.
+const READ_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// How much of an error response, or of what sftp or rsync wrote to stderr, to
+// keep in error messages.
+const MAX_ERROR_BODY_CHARS: usize = 500;
+// Passed to ssh with "-o". Logging in can't wait on somebody to type a
+// password, and a connection that stops responding is given up on after a
+// minute, rather than holding the upload up forever.
+const SSH_OPTIONS: [&str; 4] = [
+    "BatchMode=yes",
+    "ConnectTimeout=30",
+    "ServerAliveInterval=15",
+    "ServerAliveCountMax=4",
+];
+// Added to the end of a backup's name on the other machine while sftp is
+// still copying it.
+const PARTIAL_FILE_SUFFIX: &str = ".part";
+// Where rsync keeps copies that got cut off, in the other machine's
+// `remote_dir`.
+const RSYNC_PARTIAL_DIR: &str = ".rsync-partial";
+
+/// S3-compatible object storage to upload finished world backups to, so that
+/// there's a copy that isn't on the same disk as the world.
+///
+/// This works with anything that speaks S3's API and its Signature Version 4
+/// authentication, like AWS S3, Cloudflare R2, Backblaze B2, and MinIO.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Destination {
+    /// The service's URL, without the bucket, like
+    /// "https://s3.us-east-1.amazonaws.com".
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Put in front of each backup's file name to make its key in the bucket,
+    /// like "world-backups/".
+    #[serde(default)]
+    pub prefix: String,
+    /// Whether the bucket goes in the URL's path, like
+    /// "https://minio.example.com/bucket/key", rather than in its host name,
+    /// like "https://bucket.s3.us-east-1.amazonaws.com/key". MinIO usually
+    /// needs this.
+    #[serde(default)]
+    pub path_style: bool,
+    /// How many times to try uploading each backup before giving up.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Whether to delete a backup from the `backup_dir` once it's
+    /// uploaded.
+    #[serde(default)]
+    pub delete_local_after_upload: bool,
+    /// Backups bigger than this are uploaded in parts this big, with S3's
+    /// multipart uploads. When one of those gets cut off, trying again picks
+    /// up from the last part that made it, rather than starting over.
+    #[serde(default = "default_part_size_bytes")]
+    pub part_size_bytes: u64,
+}
+
+fn default_region() -> String {
+    DEFAULT_REGION.to_string()
+}
+
+fn default_attempts() -> u32 {
+    DEFAULT_ATTEMPTS
+}
+
+fn default_part_size_bytes() -> u64 {
+    DEFAULT_PART_SIZE_BYTES
+}
+
+impl S3Destination {
+    /// Returns an error if this destination can't be uploaded to no matter
+    /// what, like if its endpoint isn't a URL.
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.endpoint_parts()?;
+        if self.bucket.is_empty() {
+            bail!("The bucket can't be empty");
+        }
+        if self.attempts == 0 {
+            bail!("attempts has to be at least 1");
+        }
+        if !(MIN_PART_SIZE_BYTES..=MAX_OBJECT_BYTES).contains(&self.part_size_bytes) {
+            bail!(
+                "part_size_bytes has to be between {} and {}",
+                MIN_PART_SIZE_BYTES,
+                MAX_OBJECT_BYTES
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the endpoint's scheme and host, like ("https", "example.com").
+    fn endpoint_parts(&self) -> anyhow::Result<(&str, &str)> {
+        let (scheme, host) = self.endpoint.split_once("://").ok_or_else(|| {
+            anyhow!(
+                "The endpoint {:?} isn't a URL, like \"https://s3.us-east-1.amazonaws.com\"",
+                &self.endpoint
+            )
+        })?;
+        if scheme != "http" && scheme != "https" {
+            bail!("The endpoint {:?} has to be http or https", &self.endpoint);
+        }
+        let host = host.trim_end_matches('/');
+        if host.is_empty() || host.contains('/') {
+            bail!(
+                "The endpoint {:?} should only be a scheme and a host, without a path or a bucket",
+                &self.endpoint
+            );
+        }
+        Ok((scheme, host))
+    }
+
+    /// Returns the key that the backup with the provided file name is
+    /// uploaded to.
+    fn key(&self, file_name: &str) -> String {
+        format!("{}{}", self.prefix, file_name)
+    }
+
+    /// Returns the scheme, host, and path of the object with the provided key.
+    /// The path is already percent-encoded.
+    fn object_location(&self, key: &str) -> anyhow::Result<(String, String, String)> {
+        let (scheme, host) = self.endpoint_parts()?;
+        let key = uri_encode(key);
+        let (host, path) = if self.path_style {
+            (
+                host.to_string(),
+                format!("/{}/{}", uri_encode(&self.bucket), key),
+            )
+        } else {
+            (format!("{}.{}", &self.bucket, host), format!("/{}", key))
+        };
+        Ok((scheme.to_string(), host, path))
+    }
+
+    /// Returns a request for the object with the provided key, with the
+    /// provided query parameters, signed with AWS Signature Version 4.
+    /// `payload_sha256` is the request body's SHA-256 checksum, in hex.
+    fn request(
+        &self,
+        agent: &ureq::Agent,
+        method: &str,
+        key: &str,
+        query: &[(&str, String)],
+        payload_sha256: &str,
+    ) -> anyhow::Result<ureq::Request> {
+        let (scheme, host, uri_path) = self.object_location(key)?;
+        let query = canonical_query(query);
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, &self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            &uri_path,
+            &query,
+            &host,
+            payload_sha256,
+            &amz_date,
+            signed_headers,
+            payload_sha256
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            &amz_date,
+            &scope,
+            sha256_of(canonical_request.as_bytes())
+        );
+        let signing_key = [date.as_str(), self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", &self.secret_access_key).into_bytes(),
+                |key, part| hmac_sha256(&key, part.as_bytes()),
+            );
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            &self.access_key_id, &scope, signed_headers, &signature
+        );
+
+        let mut url = format!("{}://{}{}", scheme, &host, &uri_path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        Ok(agent
+            .request(method, &url)
+            .set("Authorization", &authorization)
+            .set("x-amz-content-sha256", payload_sha256)
+            .set("x-amz-date", &amz_date))
+    }
+}
+
+/// SSH access to another machine, like a NAS or a VPS, to copy finished world
+/// backups to, so that there's a copy that isn't on the same disk as the
+/// world.
+///
+/// Backups are copied with the `sftp` or `rsync` programs, which have to be
+/// installed, and which log in with `ssh` without asking for anything. The
+/// host has to be in `~/.ssh/known_hosts` already.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SshDestination {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    /// Who to log in as. When it's [None], ssh decides, just like it does on
+    /// the command line.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The private key to log in with. When it's [None], ssh uses its usual
+    /// keys, or an agent.
+    #[serde(default)]
+    pub key_path: Option<String>,
+    /// The directory on the other machine to copy backups to. It has to
+    /// exist already.
+    pub remote_dir: String,
+    #[serde(default)]
+    pub program: SshProgram,
+    /// How many times to try copying each backup before giving up.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Whether to delete a backup from the `backup_dir` once it's
+    /// copied.
+    #[serde(default)]
+    pub delete_local_after_upload: bool,
+}
+
+/// Which program an [SshDestination] copies backups with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshProgram {
+    #[default]
+    Sftp,
+    /// Picks up where a copy that was cut off left off, when it's tried
+    /// again. The other machine needs rsync, too.
+    Rsync,
+}
+
+fn default_ssh_port() -> u16 {
+    DEFAULT_SSH_PORT
+}
+
+impl SshDestination {
+    /// Returns an error if this destination can't be copied to no matter
+    /// what, like if its private key doesn't exist.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.host.is_empty() {
+            bail!("The host can't be empty");
+        }
+        if self.remote_dir.is_empty() {
+            bail!("remote_dir can't be empty");
+        }
+        if self.remote_dir.contains(['"', '\n']) {
+            bail!("remote_dir can't have double quotes or newlines in it");
+        }
+        if self.attempts == 0 {
+            bail!("attempts has to be at least 1");
+        }
+        if let Some(key_path) = &self.key_path {
+            if !Path::new(key_path).is_file() {
+                bail!("The private key {:?} doesn't exist", key_path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns who to log in as, and where, like "backups@nas.local".
+    fn target(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, &self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Returns where the backup with the provided file name is copied to on
+    /// the other machine.
+    fn remote_path(&self, file_name: &str) -> String {
+        format!("{}/{}", self.remote_dir.trim_end_matches('/'), file_name)
+    }
+
+    /// Returns the options that log in to the other machine without asking
+    /// for anything, and give up on it if it stops responding.
+    fn ssh_options(&self) -> Vec<String> {
+        let mut options: Vec<String> = SSH_OPTIONS
+            .iter()
+            .flat_map(|option| ["-o", option])
+            .map(String::from)
+            .collect();
+        if let Some(key_path) = &self.key_path {
+            options.push("-i".to_string());
+            options.push(key_path.clone());
+        }
+        options
+    }
+}
+
+/// Somewhere that world backups are uploaded to.
+#[derive(Debug, Clone)]
+pub(crate) enum Destination {
+    S3(S3Destination),
+    Ssh(SshDestination),
+}
+
+impl Destination {
+    fn attempts(&self) -> u32 {
+        match self {
+            Destination::S3(s3) => s3.attempts,
+            Destination::Ssh(ssh) => ssh.attempts,
+        }
+    }
+
+    fn delete_local_after_upload(&self) -> bool {
+        match self {
+            Destination::S3(s3) => s3.delete_local_after_upload,
+            Destination::Ssh(ssh) => ssh.delete_local_after_upload,
+        }
+    }
+
+    /// Returns where the backup with the provided file name is uploaded to,
+    /// as a URL.
+    fn url(&self, file_name: &str) -> String {
+        match self {
+            Destination::S3(s3) => s3
+                .object_location(&s3.key(file_name))
+                .map(|(scheme, host, path)| format!("{}://{}{}", scheme, host, path))
+                .unwrap_or_default(),
+            Destination::Ssh(ssh) => {
+                let scheme = match ssh.program {
+                    SshProgram::Sftp => "sftp",
+                    SshProgram::Rsync => "ssh",
+                };
+                let remote_path = ssh.remote_path(file_name);
+                // Relative paths start in the user's home directory.
+                let separator = if remote_path.starts_with('/') {
+                    ""
+                } else {
+                    "/~/"
+                };
+                format!(
+                    "{}://{}:{}{}{}",
+                    scheme,
+                    ssh.target(),
+                    ssh.port,
+                    separator,
+                    remote_path
+                )
+            }
+        }
+    }
+
+    /// Uploads the backup at the provided path. `resume` is what the attempts
+    /// before this one got done, so that this one can pick up where they left
+    /// off, and it's updated with what this one gets done.
+    fn upload(
+        &self,
+        path: &Path,
+        file_name: &str,
+        resume: &mut Resume,
+        progress: &Progress,
+    ) -> Result<(), UploadError> {
+        match self {
+            Destination::S3(s3) => upload_to_s3(s3, path, &s3.key(file_name), resume, progress),
+            Destination::Ssh(ssh) => match ssh.program {
+                SshProgram::Sftp => upload_with_sftp(ssh, path, file_name, resume),
+                SshProgram::Rsync => upload_with_rsync(ssh, path),
+            },
+        }
+    }
+
+    /// Cleans up after an upload that's been given up on, so that the parts
+    /// of it that made it aren't kept around. Something going wrong is only
+    /// logged.
+    fn abandon(&self, file_name: &str, resume: &mut Resume) {
+        if let (Destination::S3(s3), Some(multipart)) = (self, resume.multipart.take()) {
+            let key = s3.key(file_name);
+            if let Err(UploadError { error, .. }) =
+                abort_multipart_upload(s3, &key, &multipart.upload_id)
+            {
+                warn!(
+                    "Failed to delete the parts of {:?} that were uploaded to the bucket {:?}: {:#}",
+                    &key, &s3.bucket, error
+                );
+            }
+        }
+    }
+}
+
+/// How the last upload of a world backup to one of the [Destination]s went,
+/// or how the one that's going right now is going.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UploadStatus {
+    /// The backup's file name in the `backup_dir`.
+    pub file_name: String,
+    /// Where the backup was uploaded to, or would have been.
+    pub url: String,
+    pub succeeded: bool,
+    /// What went wrong the last time, if something did.
+    pub error: Option<String>,
+    /// How many times the upload was tried.
+    pub attempts: u32,
+    /// How big the backup is, in bytes.
+    pub size_bytes: u64,
+    /// How many bytes of the backup made it so far. sftp and rsync don't say
+    /// how far along they are, so this only goes up once they're done.
+    pub uploaded_bytes: u64,
+    /// Whether the backup was deleted from the `backup_dir` afterwards.
+    pub deleted_local_copy: bool,
+    /// When the upload finished or gave up, as an RFC 3339 timestamp, or
+    /// [None] while it's still going.
+    pub finished_at: Option<String>,
+}
+
+/// How uploads of world backups are going. Cheap to clone, and every clone
+/// shares the same uploads.
+#[derive(Debug, Clone, Default)]
+pub struct Uploads {
+    last: Arc<Mutex<Option<UploadStatus>>>,
+    // How many backups are being uploaded, or are going to be once they're
+    // written.
+    pending: Arc<AtomicUsize>,
+}
+
+impl Uploads {
+    /// Returns how the last upload of a world backup went, or how the one
+    /// that's going right now is going, or [None] if there hasn't been one
+    /// since the wrapper started.
+    pub fn last(&self) -> Option<UploadStatus> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Returns how many world backups are being uploaded, counting ones that
+    /// are still being written, and that are uploaded once they are.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Counts a world backup as pending until the returned guard is dropped.
+    pub(crate) fn expect(&self) -> PendingUpload {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        PendingUpload(Arc::clone(&self.pending))
+    }
+}
+
+/// Counts a world backup as pending in [Uploads] until it's dropped.
+#[derive(Debug)]
+pub(crate) struct PendingUpload(Arc<AtomicUsize>);
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reports how one upload of a world backup to one [Destination] is going, in
+/// [Uploads]' last status.
+struct Progress<'a> {
+    uploads: &'a Uploads,
+    file_name: &'a str,
+    url: &'a str,
+}
+
+impl Progress<'_> {
+    fn update(&self, f: impl FnOnce(&mut UploadStatus)) {
+        if let Some(status) = self.uploads.last.lock().unwrap().as_mut() {
+            // Leaves another backup's upload alone, if one started since.
+            if status.file_name == self.file_name && status.url == self.url {
+                f(status);
+            }
+        }
+    }
+
+    fn set_uploaded(&self, bytes: u64) {
+        self.update(|status| status.uploaded_bytes = bytes);
+    }
+}
+
+/// Counts everything that's read through it as uploaded.
+struct CountingReader<'a, R> {
+    inner: R,
+    progress: &'a Progress<'a>,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress
+            .update(|status| status.uploaded_bytes += n as u64);
+        Ok(n)
+    }
+}
+
+/// What the attempts at uploading a backup to a [Destination] got done so
+/// far, so that the next attempt can pick up where they left off.
+#[derive(Debug, Default)]
+struct Resume {
+    /// The S3 multipart upload that was started, if one was.
+    multipart: Option<MultipartUpload>,
+    /// Whether sftp started copying the backup, so there might be a ".part"
+    /// file on the other machine to pick up from.
+    sftp_started: bool,
+}
+
+#[derive(Debug)]
+struct MultipartUpload {
+    upload_id: String,
+    /// The ETags of the parts that were uploaded, in order, starting with
+    /// part 1.
+    etags: Vec<String>,
+}
+
+/// Spawns a thread that uploads the world backup at the provided path to each
+/// of the `destinations`, one after another, and records how each one goes in
+/// `uploads`.
+///
+/// Failed uploads are tried again after a while, up to the destination's
+/// `attempts`, unless trying again won't help, like when the credentials are
+/// wrong. If any of the destinations has `delete_local_after_upload`, the
+/// backup is deleted from the `backup_dir` once it's been uploaded to
+/// every one of them.
+pub(crate) fn spawn_upload(
+    destinations: Vec<Destination>,
+    tarball_path: PathBuf,
+    uploads: Uploads,
+) {
+    if destinations.is_empty() {
+        return;
+    }
+    let pending = uploads.expect();
+    thread::spawn(move || {
+        let _pending = pending;
+        let mut uploaded_everywhere = true;
+        for destination in &destinations {
+            uploaded_everywhere &= upload_with_retries(destination, &tarball_path, &uploads);
+        }
+        if !uploaded_everywhere
+            || !destinations
+                .iter()
+                .any(Destination::delete_local_after_upload)
+        {
+            return;
+        }
+        match backup_files::delete(&tarball_path) {
+            Ok(()) => {
+                info!(
+                    "Deleted the world backup {:?} from the backup_dir, since it's been uploaded",
+                    &tarball_path
+                );
+                if let Some(status) = uploads.last.lock().unwrap().as_mut() {
+                    status.deleted_local_copy = true;
+                }
+            }
+            Err(e) => warn!(
+                "Failed to delete the world backup {:?} after uploading it: {}",
+                &tarball_path, e
+            ),
+        }
+    });
+}
+
+/// Uploads the world backup at the provided path to `destination`, trying
+/// again if it fails, and returns whether it made it.
+fn upload_with_retries(destination: &Destination, tarball_path: &Path, uploads: &Uploads) -> bool {
+    let file_name = tarball_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let url = destination.url(&file_name);
+    let attempts = destination.attempts();
+    *uploads.last.lock().unwrap() = Some(UploadStatus {
+        file_name: file_name.clone(),
+        url: url.clone(),
+        succeeded: false,
+        error: None,
+        attempts: 0,
+        size_bytes: fs::metadata(tarball_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default(),
+        uploaded_bytes: 0,
+        deleted_local_copy: false,
+        finished_at: None,
+    });
+    let progress = Progress {
+        uploads,
+        file_name: &file_name,
+        url: &url,
+    };
+
+    let mut resume = Resume::default();
+    let mut succeeded = false;
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        progress.update(|status| status.attempts = attempt);
+        match destination.upload(tarball_path, &file_name, &mut resume, &progress) {
+            Ok(()) => {
+                succeeded = true;
+                break;
+            }
+            Err(UploadError { error, retryable }) => {
+                let error = format!("{:#}", error);
+                let gives_up = !retryable || attempt == attempts;
+                warn!(
+                    "Failed to upload the world backup {:?} to {} (attempt {} of {}){}: {}",
+                    tarball_path,
+                    &url,
+                    attempt,
+                    attempts,
+                    if gives_up { "" } else { ". Trying again soon" },
+                    &error
+                );
+                progress.update(|status| status.error = Some(error));
+                if !retryable {
+                    break;
+                }
+            }
+        }
+    }
+
+    if succeeded {
+        info!("Uploaded the world backup {:?} to {}", tarball_path, &url);
+    } else {
+        destination.abandon(&file_name, &mut resume);
+        error!(
+            "Gave up on uploading the world backup {:?} to {}. It's still in the backup_dir",
+            tarball_path, &url
+        );
+    }
+    progress.update(|status| {
+        status.succeeded = succeeded;
+        if succeeded {
+            status.error = None;
+            status.uploaded_bytes = status.size_bytes;
+        }
+        status.finished_at = Some(Utc::now().to_rfc3339());
+    });
+    succeeded
+}
+
+struct UploadError {
+    error: anyhow::Error,
+    /// Whether trying again might help.
+    retryable: bool,
+}
+
+impl<E: Into<anyhow::Error>> From<E> for UploadError {
+    fn from(error: E) -> UploadError {
+        UploadError {
+            error: error.into(),
+            retryable: true,
+        }
+    }
+}
+
+/// Turns a request to the storage service that failed into an
+/// [UploadError].
+fn response_error(error: ureq::Error) -> UploadError {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let body: String = body.trim().chars().take(MAX_ERROR_BODY_CHARS).collect();
+            UploadError {
+                error: anyhow!("The storage service responded with a {}: {}", code, body),
+                // Anything else means that the request itself was wrong, like
+                // the credentials or the bucket.
+                retryable: code >= 500 || code == 408 || code == 429,
+            }
+        }
+        e => e.into(),
+    }
+}
+
+/// Uploads the file at the provided path to `key` in the destination's
+/// bucket. Files that are bigger than its `part_size_bytes` are uploaded in
+/// parts, starting after the ones in `resume` that already made it.
+fn upload_to_s3(
+    destination: &S3Destination,
+    path: &Path,
+    key: &str,
+    resume: &mut Resume,
+    progress: &Progress,
+) -> Result<(), UploadError> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read {:?}", path))?
+        .len();
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_write(WRITE_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    if size <= destination.part_size_bytes {
+        put_object(destination, &agent, path, key, size, progress)
+    } else {
+        upload_parts(destination, &agent, path, key, size, resume, progress)
+    }
+}
+
+/// Uploads the file at the provided path to `key` in the destination's bucket
+/// with a single PUT.
+fn put_object(
+    destination: &S3Destination,
+    agent: &ureq::Agent,
+    path: &Path,
+    key: &str,
+    size: u64,
+    progress: &Progress,
+) -> Result<(), UploadError> {
+    if size > MAX_OBJECT_BYTES {
+        return Err(UploadError {
+            error: anyhow!(
+                "The backup is {} bytes, but S3 only takes up to {} bytes in a single upload",
+                size,
+                MAX_OBJECT_BYTES
+            ),
+            retryable: false,
+        });
+    }
+    // The checksum is sent along, so that the service can tell if the backup
+    // got mangled on the way there.
+    let payload_sha256 = match backup_files::read_checksum(path) {
+        Some(sha256) => sha256,
+        None => sha256_of_file(path)?,
+    };
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    progress.set_uploaded(0);
+    destination
+        .request(agent, "PUT", key, &[], &payload_sha256)?
+        .set("Content-Length", &size.to_string())
+        .set("Content-Type", BackupFormat::of_path(path).content_type())
+        .send(CountingReader {
+            inner: file,
+            progress,
+        })
+        .map_err(response_error)?;
+    Ok(())
+}
+
+/// Uploads the file at the provided path to `key` in the destination's bucket
+/// with a multipart upload, one `part_size_bytes` part at a time.
+///
+/// The multipart upload, and the parts that made it, are kept in `resume`, so
+/// that trying again after this fails only uploads the parts that didn't.
+fn upload_parts(
+    destination: &S3Destination,
+    agent: &ureq::Agent,
+    path: &Path,
+    key: &str,
+    size: u64,
+    resume: &mut Resume,
+    progress: &Progress,
+) -> Result<(), UploadError> {
+    let part_size = destination.part_size_bytes;
+    let parts = size.div_ceil(part_size);
+    if parts > MAX_PARTS {
+        return Err(UploadError {
+            error: anyhow!(
+                "The backup is {} bytes, which is {} parts of part_size_bytes, but S3 only takes up to {} parts. Try a bigger part_size_bytes",
+                size,
+                parts,
+                MAX_PARTS
+            ),
+            retryable: false,
+        });
+    }
+    let multipart = match resume.multipart.take() {
+        Some(multipart) => multipart,
+        None => MultipartUpload {
+            upload_id: create_multipart_upload(destination, agent, path, key)?,
+            etags: Vec::new(),
+        },
+    };
+    let multipart = resume.multipart.insert(multipart);
+    // The storage service forgets about multipart uploads that are left
+    // alone for too long, so this one has to start over.
+    let forgotten = || UploadError {
+        error: anyhow!(
+            "The storage service doesn't know about the multipart upload anymore. It'll start over"
+        ),
+        retryable: true,
+    };
+
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    progress.set_uploaded(multipart.etags.len() as u64 * part_size);
+    while (multipart.etags.len() as u64) < parts {
+        let part_number = multipart.etags.len() as u64 + 1;
+        let offset = (part_number - 1) * part_size;
+        let length = part_size.min(size - offset);
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut (&mut file).take(length), &mut hasher)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let payload_sha256 = format!("{:x}", hasher.finalize());
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("Failed to read {:?}", path))?;
+
+        let query = [
+            ("partNumber", part_number.to_string()),
+            ("uploadId", multipart.upload_id.clone()),
+        ];
+        let result = destination
+            .request(agent, "PUT", key, &query, &payload_sha256)?
+            .set("Content-Length", &length.to_string())
+            .send(CountingReader {
+                inner: (&mut file).take(length),
+                progress,
+            });
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                resume.multipart = None;
+                return Err(forgotten());
+            }
+            Err(e) => return Err(response_error(e)),
+        };
+        let etag = response.header("ETag").ok_or_else(|| {
+            anyhow!(
+                "The storage service didn't respond with part {}'s ETag",
+                part_number
+            )
+        })?;
+        multipart.etags.push(etag.to_string());
+    }
+
+    let body: String = multipart
+        .etags
+        .iter()
+        .enumerate()
+        .map(|(i, etag)| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                etag
+            )
+        })
+        .collect();
+    let body = format!(
+        "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+        body
+    );
+    let query = [("uploadId", multipart.upload_id.clone())];
+    let result = destination
+        .request(agent, "POST", key, &query, &sha256_of(body.as_bytes()))?
+        .set("Content-Type", "application/xml")
+        .send_string(&body);
+    let response = match result {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => {
+            resume.multipart = None;
+            return Err(forgotten());
+        }
+        Err(e) => return Err(response_error(e)),
+    };
+    // S3 responds with a 200 before it's done putting the parts together, so
+    // something going wrong after that ends up in the body instead.
+    let response = response.into_string()?;
+    if response.contains("<Error>") {
+        let response: String = response.trim().chars().take(MAX_ERROR_BODY_CHARS).collect();
+        return Err(anyhow!(
+            "The storage service failed to put the upload's parts together: {}",
+            response
+        )
+        .into());
+    }
+    resume.multipart = None;
+    Ok(())
+}
+
+/// Starts a multipart upload to `key` in the destination's bucket, and returns
+/// its ID.
+fn create_multipart_upload(
+    destination: &S3Destination,
+    agent: &ureq::Agent,
+    path: &Path,
+    key: &str,
+) -> Result<String, UploadError> {
+    let response = destination
+        .request(
+            agent,
+            "POST",
+            key,
+            &[("uploads", String::new())],
+            &sha256_of(b""),
+        )?
+        .set("Content-Type", BackupFormat::of_path(path).content_type())
+        .send_bytes(&[])
+        .map_err(response_error)?
+        .into_string()?;
+    match xml_element(&response, "UploadId") {
+        Some(upload_id) => Ok(upload_id.to_string()),
+        None => {
+            Err(anyhow!("The storage service didn't respond with the multipart upload's ID").into())
+        }
+    }
+}
+
+/// Deletes the parts of a multipart upload to `key` in the destination's
+/// bucket that made it, and stops the upload.
+fn abort_multipart_upload(
+    destination: &S3Destination,
+    key: &str,
+    upload_id: &str,
+) -> Result<(), UploadError> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    destination
+        .request(
+            &agent,
+            "DELETE",
+            key,
+            &[("uploadId", upload_id.to_string())],
+            &sha256_of(b""),
+        )?
+        .call()
+        .map_err(response_error)?;
+    Ok(())
+}
+
+/// Copies the file at the provided path into the destination's `remote_dir`
+/// with sftp. It's copied to a ".part" file first, and only renamed once it's
+/// all there, so that a copy that gets cut off doesn't look like a backup.
+///
+/// If an attempt before this one got cut off, this one picks up from the end
+/// of its ".part" file, rather than starting over.
+fn upload_with_sftp(
+    destination: &SshDestination,
+    path: &Path,
+    file_name: &str,
+    resume: &mut Resume,
+) -> Result<(), UploadError> {
+    let remote_path = destination.remote_path(file_name);
+    let partial_path = format!("{}{}", &remote_path, PARTIAL_FILE_SUFFIX);
+    let put = if resume.sftp_started && sftp_file_exists(destination, &partial_path) {
+        "reput"
+    } else {
+        "put"
+    };
+    resume.sftp_started = true;
+    let batch = format!(
+        "{} {} {}\nrename {} {}\n",
+        put,
+        sftp_quote(&path.to_string_lossy()),
+        sftp_quote(&partial_path),
+        sftp_quote(&partial_path),
+        sftp_quote(&remote_path)
+    );
+    run(sftp_command(destination), "sftp", Some(&batch))
+}
+
+/// Returns whether there's a file at the provided path on the destination's
+/// machine. Not being able to tell counts as there not being one.
+fn sftp_file_exists(destination: &SshDestination, remote_path: &str) -> bool {
+    let batch = format!("ls {}\n", sftp_quote(remote_path));
+    run(sftp_command(destination), "sftp", Some(&batch)).is_ok()
+}
+
+/// Returns an sftp command that logs in to the destination's machine, and
+/// reads what to do from stdin.
+fn sftp_command(destination: &SshDestination) -> Command {
+    let mut command = Command::new("sftp");
+    command
+        .args(["-b", "-", "-P", &destination.port.to_string()])
+        .args(destination.ssh_options())
+        .arg(destination.target());
+    command
+}
+
+/// Copies the file at the provided path into the destination's `remote_dir`
+/// with rsync. A copy that gets cut off is kept in a hidden directory there,
+/// and picked back up the next time.
+fn upload_with_rsync(destination: &SshDestination, path: &Path) -> Result<(), UploadError> {
+    let mut ssh = format!("ssh -p {}", destination.port);
+    for option in destination.ssh_options() {
+        // rsync splits this up on whitespace, unless it's quoted.
+        if option.contains(char::is_whitespace) {
+            ssh.push_str(&format!(" \"{}\"", option));
+        } else {
+            ssh.push_str(&format!(" {}", option));
+        }
+    }
+    let mut command = Command::new("rsync");
+    command
+        .arg(format!("--partial-dir={}", RSYNC_PARTIAL_DIR))
+        // Keeps the remote shell from splitting up paths with spaces in them.
+        .arg("--protect-args")
+        .args(["-e", &ssh])
+        .arg(path)
+        .arg(format!(
+            "{}:{}/",
+            destination.target(),
+            destination.remote_dir.trim_end_matches('/')
+        ));
+    run(command, "rsync", None)
+}
+
+/// Runs the provided command until it exits, and writes `stdin` to it if
+/// there's anything to write. What it wrote to stderr ends up in the error if
+/// it fails.
+fn run(mut command: Command, program: &str, stdin: Option<&str>) -> Result<(), UploadError> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(UploadError {
+                error: anyhow!("Couldn't find {}. Is it installed?", program),
+                retryable: false,
+            })
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context(format!("Failed to run {}", program))
+                .into())
+        }
+    };
+    if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+        // If it already quit, what it wrote to stderr says why.
+        let _ = child_stdin.write_all(input.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr: String = stderr.trim().chars().take(MAX_ERROR_BODY_CHARS).collect();
+    Err(anyhow!("{} exited with {}: {}", program, output.status, stderr).into())
+}
+
+/// Quotes the provided path for an sftp batch file.
+fn sftp_quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn sha256_of_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn sha256_of(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes everything in the provided path except for the characters
+/// that Signature Version 4 leaves alone, and slashes.
+fn uri_encode(path: &str) -> String {
+    percent_encode(path, true)
+}
+
+/// Returns the provided query parameters the way Signature Version 4 wants
+/// them: sorted by name, percent-encoded, and joined with "&".
+fn canonical_query(query: &[(&str, String)]) -> String {
+    let mut query: Vec<String> = query
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                percent_encode(name, false),
+                percent_encode(value, false)
+            )
+        })
+        .collect();
+    query.sort();
+    query.join("&")
+}
+
+fn percent_encode(s: &str, keep_slashes: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slashes => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Returns the text inside of the first element with the provided name in an
+/// XML document, like "ID" in "<UploadId>ID</UploadId>".
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// A request that [fake_s3()] got, like "PUT /bucket/key?partNumber=1",
+    /// and its body.
+    type Received = (String, Vec<u8>);
+
+    /// Starts a fake S3 that takes multipart uploads, and responds with a 500
+    /// the first time that part 2 of one is uploaded. Returns its endpoint,
+    /// and the requests that it gets.
+    fn fake_s3() -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        thread::spawn({
+            let received = Arc::clone(&received);
+            move || {
+                let mut failed_part_2 = false;
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        let header = header.trim_end();
+                        if header.is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = header.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+
+                    let request: Vec<&str> = request_line.split(' ').take(2).collect();
+                    let request = request.join(" ");
+                    let (status, headers, response) = if request.ends_with("?uploads=") {
+                        (
+                            "200 OK",
+                            String::new(),
+                            "<InitiateMultipartUploadResult><UploadId>upload/1</UploadId></InitiateMultipartUploadResult>".to_owned(),
+                        )
+                    } else if let Some(part) = request
+                        .strip_prefix("PUT ")
+                        .and_then(|target| target.split_once("?partNumber="))
+                        .and_then(|(_, query)| query.split_once('&'))
+                        .map(|(part, _)| part.to_owned())
+                    {
+                        if part == "2" && !failed_part_2 {
+                            failed_part_2 = true;
+                            ("500 Internal Server Error", String::new(), String::new())
+                        } else {
+                            (
+                                "200 OK",
+                                format!("ETag: \"etag-{}\"\r\n", part),
+                                String::new(),
+                            )
+                        }
+                    } else if request.starts_with("POST ") && request.contains("?uploadId=") {
+                        (
+                            "200 OK",
+                            String::new(),
+                            "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>"
+                                .to_owned(),
+                        )
+                    } else {
+                        ("400 Bad Request", String::new(), String::new())
+                    };
+                    received.lock().unwrap().push((request, body));
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+                        status,
+                        response.len(),
+                        headers,
+                        response
+                    );
+                }
+            }
+        });
+        (endpoint, received)
+    }
+
+    #[test]
+    fn multipart_uploads_pick_up_where_they_left_off() {
+        let dir = TempDir::new("multipart");
+        let path = dir.join("world.tar.gz");
+        fs::write(&path, "0123456789").unwrap();
+
+        let (endpoint, received) = fake_s3();
+        let destination = Destination::S3(S3Destination {
+            endpoint,
+            bucket: "bucket".to_owned(),
+            region: DEFAULT_REGION.to_owned(),
+            access_key_id: "id".to_owned(),
+            secret_access_key: "secret".to_owned(),
+            prefix: "backups/".to_owned(),
+            path_style: true,
+            attempts: 2,
+            delete_local_after_upload: false,
+            part_size_bytes: 4,
+        });
+        let uploads = Uploads::default();
+        let url = destination.url("world.tar.gz");
+        *uploads.last.lock().unwrap() = Some(UploadStatus {
+            file_name: "world.tar.gz".to_owned(),
+            url: url.clone(),
+            succeeded: false,
+            error: None,
+            attempts: 0,
+            size_bytes: 10,
+            uploaded_bytes: 0,
+            deleted_local_copy: false,
+            finished_at: None,
+        });
+        let progress = Progress {
+            uploads: &uploads,
+            file_name: "world.tar.gz",
+            url: &url,
+        };
+        let mut resume = Resume::default();
+
+        let first = destination.upload(&path, "world.tar.gz", &mut resume, &progress);
+        assert!(first.is_err_and(|e| e.retryable));
+        assert_eq!(resume.multipart.as_ref().unwrap().etags, ["\"etag-1\""]);
+        let second = destination.upload(&path, "world.tar.gz", &mut resume, &progress);
+        assert!(second.is_ok());
+        assert!(resume.multipart.is_none());
+        assert_eq!(uploads.last().unwrap().uploaded_bytes, 10);
+
+        let received = received.lock().unwrap();
+        let requests: Vec<&str> = received.iter().map(|(r, _)| r.as_str()).collect();
+        assert_eq!(
+            requests,
+            [
+                "POST /bucket/backups/world.tar.gz?uploads=",
+                "PUT /bucket/backups/world.tar.gz?partNumber=1&uploadId=upload%2F1",
+                "PUT /bucket/backups/world.tar.gz?partNumber=2&uploadId=upload%2F1",
+                "PUT /bucket/backups/world.tar.gz?partNumber=2&uploadId=upload%2F1",
+                "PUT /bucket/backups/world.tar.gz?partNumber=3&uploadId=upload%2F1",
+                "POST /bucket/backups/world.tar.gz?uploadId=upload%2F1",
+            ]
+        );
+        let bodies: Vec<&[u8]> = received[1..5].iter().map(|(_, b)| b.as_slice()).collect();
+        assert_eq!(bodies, [&b"0123"[..], b"4567", b"4567", b"89"]);
+        assert_eq!(
+            String::from_utf8_lossy(&received[5].1),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>\"etag-1\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"etag-2\"</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>\"etag-3\"</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+
+    #[test]
+    fn canonical_query_is_sorted_and_encoded() {
+        let query = [
+            ("uploadId", "a/b c".to_owned()),
+            ("partNumber", "2".to_owned()),
+        ];
+        assert_eq!(canonical_query(&query), "partNumber=2&uploadId=a%2Fb%20c");
+        assert_eq!(canonical_query(&[("uploads", String::new())]), "uploads=");
+        assert_eq!(uri_encode("/bucket/a b/c"), "/bucket/a%20b/c");
+    }
+
+    #[test]
+    fn xml_element_finds_the_first_one() {
+        let xml =
+            "<Result><Bucket>b</Bucket><UploadId>abc</UploadId><UploadId>def</UploadId></Result>";
+        assert_eq!(xml_element(xml, "UploadId"), Some("abc"));
+        assert_eq!(xml_element(xml, "Key"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use std::{cmp::Reverse, path::PathBuf};
+
+use chrono::{DateTime, Datelike, Local, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup_files::{self, BackupDir},
+    incremental,
+};
+
+/// Which world backups to keep after a new one is made. The rest are deleted.
+///
+/// A backup is kept if any of the rules keeps it. `keep_last` keeps the most
+/// recent backups, and the others keep the most recent backup from each of
+/// the most recent days, weeks, and months that have one, in the machine's
+/// local time zone. When every rule is 0, every backup is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupRetention {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+}
+
+impl BackupRetention {
+    /// Returns whether this keeps every backup, so nothing is ever deleted.
+    pub fn keeps_everything(&self) -> bool {
+        *self == BackupRetention::default()
+    }
+
+    /// Returns which of the provided backups aren't kept, in no particular
+    /// order.
+    pub fn backups_to_delete<T>(&self, backups: Vec<(T, DateTime<Utc>)>) -> Vec<T> {
+        if self.keeps_everything() {
+            return Vec::new();
+        }
+        let mut backups = backups;
+        // Newest first, so that the first backup in each period is the one
+        // that's kept.
+        backups.sort_by_key(|(_, made_at)| Reverse(*made_at));
+        let times: Vec<DateTime<Local>> = backups
+            .iter()
+            .map(|(_, made_at)| made_at.with_timezone(&Local))
+            .collect();
+
+        let mut keep = vec![false; backups.len()];
+        keep.iter_mut()
+            .take(self.keep_last)
+            .for_each(|keep| *keep = true);
+        keep_newest_per_period(&times, &mut keep, self.keep_daily, |t| {
+            (t.year(), t.ordinal())
+        });
+        keep_newest_per_period(&times, &mut keep, self.keep_weekly, |t| {
+            let week = t.iso_week();
+            (week.year(), week.week())
+        });
+        keep_newest_per_period(&times, &mut keep, self.keep_monthly, |t| {
+            (t.year(), t.month())
+        });
+
+        backups
+            .into_iter()
+            .zip(keep)
+            .filter(|(_, keep)| !keep)
+            .map(|((backup, _), _)| backup)
+            .collect()
+    }
+}
+
+/// Keeps the newest of `times` in each of the `count` most recent periods that
+/// have one. `times` has to be sorted newest first.
+fn keep_newest_per_period<F>(times: &[DateTime<Local>], keep: &mut [bool], count: usize, period: F)
+where
+    F: Fn(&DateTime<Local>) -> (i32, u32),
+{
+    let mut last_period = None;
+    let mut kept = 0;
+    for (i, time) in times.iter().enumerate() {
+        if kept == count {
+            return;
+        }
+        let period = period(time);
+        if last_period != Some(period) {
+            keep[i] = true;
+            kept += 1;
+            last_period = Some(period);
+        }
+    }
+}
+
+/// Deletes the world backups in the provided directory that `retention`
+/// doesn't keep, unless an incremental backup that it does keep is based on
+/// them. Returns the names of the ones that were deleted, sorted.
+///
+/// Something going wrong is only logged, since the backup that was just made
+/// is fine either way.
+pub(crate) fn prune_backups(dir: &BackupDir, retention: &BackupRetention) -> Vec<String> {
+    if retention.keeps_everything() {
+        return Vec::new();
+    }
+    let backups = match backup_files::find(dir) {
+        Ok(backups) => backups,
+        Err(e) => {
+            warn!("{:#}", e);
+            return Vec::new();
+        }
+    };
+    let to_delete = retention.backups_to_delete(backups.clone());
+    let kept: Vec<PathBuf> = backups
+        .iter()
+        .map(|(path, _)| path)
+        .filter(|path| !to_delete.contains(path))
+        .cloned()
+        .collect();
+    // Incremental backups can't be restored without the backups before them.
+    let needed = incremental::bases_of(&backups, &kept);
+    let mut deleted = Vec::new();
+    for path in to_delete.into_iter().filter(|path| !needed.contains(path)) {
+        match backup_files::delete(&path) {
+            Ok(()) => {
+                info!(
+                    "Deleted an old world backup that backup_retention doesn't keep: {:?}",
+                    &path
+                );
+                if let Some(name) = path.file_name() {
+                    deleted.push(name.to_string_lossy().into_owned());
+                }
+            }
+            Err(e) => warn!("Failed to delete an old world backup {:?}: {}", &path, e),
+        }
+    }
+    deleted.sort();
+    deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    // In the machine's time zone, since that's what periods go by.
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn deleted(
+        retention: BackupRetention,
+        backups: &[(&'static str, DateTime<Utc>)],
+    ) -> Vec<&'static str> {
+        let mut deleted = retention.backups_to_delete(backups.to_vec());
+        deleted.sort();
+        deleted
+    }
+
+    #[test]
+    fn keeping_everything_deletes_nothing() {
+        let backups = [("a", at(2024, 6, 1, 12)), ("b", at(2024, 6, 2, 12))];
+        assert!(deleted(BackupRetention::default(), &backups).is_empty());
+    }
+
+    #[test]
+    fn keep_last_keeps_the_newest_backups() {
+        // Out of order, to make sure that they're sorted.
+        let backups = [
+            ("b", at(2024, 6, 2, 12)),
+            ("d", at(2024, 6, 4, 12)),
+            ("a", at(2024, 6, 1, 12)),
+            ("c", at(2024, 6, 3, 12)),
+        ];
+        let retention = BackupRetention {
+            keep_last: 2,
+            ..BackupRetention::default()
+        };
+        assert_eq!(deleted(retention, &backups), ["a", "b"]);
+    }
+
+    #[test]
+    fn keep_daily_keeps_the_newest_backup_of_each_day() {
+        let backups = [
+            ("1st morning", at(2024, 6, 1, 9)),
+            ("1st evening", at(2024, 6, 1, 18)),
+            ("2nd", at(2024, 6, 2, 12)),
+            ("3rd morning", at(2024, 6, 3, 9)),
+            ("3rd evening", at(2024, 6, 3, 18)),
+        ];
+        let retention = BackupRetention {
+            keep_daily: 2,
+            ..BackupRetention::default()
+        };
+        assert_eq!(
+            deleted(retention, &backups),
+            ["1st evening", "1st morning", "3rd morning"]
+        );
+    }
+
+    #[test]
+    fn keep_weekly_goes_by_iso_weeks_across_years() {
+        // December 30th, 2024 is a Monday in the first ISO week of 2025, along
+        // with January 2nd. December 28th is in the last week of 2024.
+        let backups = [
+            ("dec 28", at(2024, 12, 28, 12)),
+            ("dec 30", at(2024, 12, 30, 12)),
+            ("jan 2", at(2025, 1, 2, 12)),
+        ];
+        let retention = BackupRetention {
+            keep_weekly: 2,
+            ..BackupRetention::default()
+        };
+        assert_eq!(deleted(retention, &backups), ["dec 30"]);
+    }
+
+    #[test]
+    fn keep_monthly_keeps_the_newest_backup_of_each_month() {
+        let backups = [
+            ("april", at(2024, 4, 20, 12)),
+            ("may 1st", at(2024, 5, 1, 12)),
+            ("may 31st", at(2024, 5, 31, 12)),
+            ("june", at(2024, 6, 1, 12)),
+        ];
+        let retention = BackupRetention {
+            keep_monthly: 2,
+            ..BackupRetention::default()
+        };
+        assert_eq!(deleted(retention, &backups), ["april", "may 1st"]);
+    }
+
+    #[test]
+    fn a_backup_is_kept_if_any_rule_keeps_it() {
+        let backups = [
+            ("may", at(2024, 5, 15, 12)),
+            ("june 1st", at(2024, 6, 1, 12)),
+            ("june 2nd morning", at(2024, 6, 2, 9)),
+            ("june 2nd evening", at(2024, 6, 2, 18)),
+        ];
+        let retention = BackupRetention {
+            keep_last: 1,
+            keep_daily: 2,
+            keep_weekly: 0,
+            keep_monthly: 2,
+        };
+        // keep_last and keep_daily both keep "june 2nd evening", keep_daily
+        // keeps "june 1st", and keep_monthly keeps "may".
+        assert_eq!(deleted(retention, &backups), ["june 2nd morning"]);
+    }
+
+    #[test]
+    fn keep_newest_per_period_stops_after_count_periods() {
+        let times: Vec<DateTime<Local>> =
+            [at(2024, 6, 3, 12), at(2024, 6, 2, 12), at(2024, 6, 1, 12)]
+                .iter()
+                .map(|time| time.with_timezone(&Local))
+                .collect();
+        let mut keep = vec![false; times.len()];
+        keep_newest_per_period(&times, &mut keep, 2, |t| (t.year(), t.ordinal()));
+        assert_eq!(keep, [true, true, false]);
+
+        let mut keep = vec![false; times.len()];
+        keep_newest_per_period(&times, &mut keep, 0, |t| (t.year(), t.ordinal()));
+        assert_eq!(keep, [false, false, false]);
+    }
+}
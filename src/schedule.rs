@@ -0,0 +1,552 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone};
+use log::{error, info, warn};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    actor::WrapperHandle,
+    error::WrapperError,
+    events::ServerEvent,
+    manifest::BackupDetails,
+    state::{ServerState, StateMachine},
+    BackupMode,
+};
+
+// Replaced with how long is left until the backup in backup warnings.
+const TIME_PLACEHOLDER: &str = "{time}";
+// Why a scheduled backup was skipped, when `skip_when_idle` is on and nobody
+// played since the last one.
+const NO_ACTIVITY: &str = "no activity";
+// Every schedule that can happen at all happens at least once in this many
+// days. The longest gap is between leap days, like with "0 0 29 2 *", and
+// needs a couple of extra years when a century isn't a leap year.
+const MAX_DAYS_TO_SEARCH: u32 = 366 * 9;
+
+/// When something should happen, as a cron expression.
+///
+/// Expressions have the usual five fields: minute, hour, day of the month,
+/// month, and day of the week, where 0 and 7 are both Sunday. Each field is a
+/// `*`, a number, a range like `1-5`, or a comma-separated list of those. Any
+/// of them can be followed by a step, like `*/15`. The `@hourly`, `@daily`,
+/// `@weekly`, `@monthly`, and `@yearly` shorthands work, too.
+///
+/// Just like with cron, if neither the day of the month nor the day of the
+/// week is a `*`, a day only has to match one of them. Times are in the
+/// machine's local time zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    // Each of these has a bit set for every value that the field matches.
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Whether the day fields were restricted, rather than "*".
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> anyhow::Result<CronSchedule> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            bail!(
+                "{:?} isn't a cron expression. It needs five fields: minute, hour, day of the month, month, and day of the week",
+                expression
+            );
+        };
+
+        let parse = |field, name, min, max| {
+            parse_field(field, min, max)
+                .with_context(|| format!("{:?} has a bad {} field", expression, name))
+        };
+        let mut days_of_week_bits = parse(days_of_week, "day of the week", 0, 7)?;
+        // Sunday can be either 0 or 7.
+        if days_of_week_bits & (1 << 7) != 0 {
+            days_of_week_bits |= 1;
+        }
+        let schedule = CronSchedule {
+            minutes: parse(minutes, "minute", 0, 59)?,
+            hours: parse(hours, "hour", 0, 23)?,
+            days_of_month: parse(days_of_month, "day of the month", 1, 31)?,
+            months: parse(months, "month", 1, 12)?,
+            days_of_week: days_of_week_bits,
+            any_day_of_month: days_of_month.starts_with('*'),
+            any_day_of_week: days_of_week.starts_with('*'),
+        };
+        if schedule.next_after(Local::now()).is_none() {
+            bail!("{:?} never happens", expression);
+        }
+        Ok(schedule)
+    }
+}
+
+impl CronSchedule {
+    /// Returns the first time after the provided one that matches this
+    /// schedule, or [None] if there isn't one, like on February 30th.
+    ///
+    /// Times that get skipped when clocks go forward for daylight saving time
+    /// are skipped here, too.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut date = after.date_naive();
+        for _ in 0..MAX_DAYS_TO_SEARCH {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|&h| has_bit(self.hours, h)) {
+                    for minute in (0..60).filter(|&m| has_bit(self.minutes, m)) {
+                        let time = date.and_hms_opt(hour, minute, 0)?;
+                        let time = match Local.from_local_datetime(&time).earliest() {
+                            Some(time) => time,
+                            None => continue,
+                        };
+                        if time > after {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !has_bit(self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = has_bit(self.days_of_month, date.day());
+        let day_of_week = has_bit(self.days_of_week, date.weekday().num_days_from_sunday());
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+}
+
+fn has_bit(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// Returns a bit for every value between `min` and `max` that the provided
+/// field of a cron expression matches.
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| anyhow!("{:?} isn't a step", step))?;
+                if step == 0 {
+                    bail!("Steps can't be 0");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, min, max)?, parse_value(end, min, max)?),
+                // "5/15" means every 15 starting at 5.
+                None if step > 1 => (parse_value(range, min, max)?, max),
+                None => {
+                    let value = parse_value(range, min, max)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            bail!("{:?} is a range that goes backwards", range);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> anyhow::Result<u32> {
+    match value.parse() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => bail!("{:?} isn't a number from {} to {}", value, min, max),
+    }
+}
+
+/// Spawns a thread that backs up the world whenever the provided schedule says
+/// to, just like `GET /make-world-backup` does.
+///
+/// Players are told `warning_message` once for each of the `warnings` before
+/// a backup, with "{time}" replaced with how long is left, like "5 minutes".
+///
+/// A backup is skipped if the Minecraft server isn't running when it's due,
+/// or if it's already being stopped, restarted, or backed up. If a backup
+/// fails, the server is started back up, unless it never went down.
+///
+/// If `skip_when_idle` is set, a backup is skipped too if nobody has joined
+/// since the last scheduled backup, and nobody's online, since the world
+/// hasn't changed. The first scheduled
+/// backup after the wrapper starts is always taken, since there's no telling
+/// what happened before then.
+pub fn spawn_backups(
+    wrapper: WrapperHandle,
+    schedule: CronSchedule,
+    warnings: Vec<Duration>,
+    warning_message: String,
+    skip_when_idle: bool,
+) {
+    let mut warnings = warnings;
+    // Longest first, so that they go out in order.
+    warnings.sort_unstable_by(|a, b| b.cmp(a));
+    warnings.dedup();
+
+    thread::spawn(move || {
+        let (stop_requested, state) =
+            match wrapper.blocking_call(|w| Ok((w.stop_requested_flag(), w.state_machine()))) {
+                Ok(flags) => flags,
+                Err(_) => return,
+            };
+        // Whether anybody has joined since the last scheduled backup.
+        let active = Arc::new(AtomicBool::new(true));
+        if skip_when_idle {
+            watch_for_joins(&wrapper, Arc::clone(&active));
+        }
+
+        loop {
+            let due = match schedule.next_after(Local::now()) {
+                Some(due) => due,
+                None => {
+                    warn!("Scheduled backups: the schedule never happens again, so no more backups will be taken");
+                    return;
+                }
+            };
+            info!(
+                "Scheduled backups: the next backup is at {}",
+                due.to_rfc3339()
+            );
+
+            for &warning in &warnings {
+                let warn_at = due - chrono::Duration::from_std(warning).unwrap_or_default();
+                if warn_at <= Local::now() {
+                    continue;
+                }
+                sleep_until(warn_at);
+                if stop_requested.load(Ordering::SeqCst) || state.current() != ServerState::Running
+                {
+                    continue;
+                }
+                let message = warning_message.replace(TIME_PLACEHOLDER, &describe(warning));
+                let _ = wrapper.blocking_call(move |w| {
+                    w.announce(Some(message));
+                    Ok(())
+                });
+            }
+            sleep_until(due);
+
+            if stop_requested.load(Ordering::SeqCst) {
+                info!("Scheduled backups: the Minecraft server is being stopped on purpose. Skipping this backup");
+                continue;
+            }
+            back_up(&wrapper, &state, skip_when_idle.then_some(&*active));
+        }
+    });
+}
+
+/// Spawns a thread that sets `active` whenever a player joins the Minecraft
+/// server.
+fn watch_for_joins(wrapper: &WrapperHandle, active: Arc<AtomicBool>) {
+    let mut events = match wrapper.blocking_call(|w| Ok(w.subscribe())) {
+        Ok(events) => events,
+        Err(_) => return,
+    };
+    thread::spawn(move || loop {
+        match events.blocking_recv() {
+            Ok(ServerEvent::PlayerJoined(_)) => active.store(true, Ordering::SeqCst),
+            Ok(_) => {}
+            // Somebody might have joined in the events that were missed.
+            Err(RecvError::Lagged(_)) => active.store(true, Ordering::SeqCst),
+            Err(RecvError::Closed) => return,
+        }
+    });
+}
+
+/// Backs up the world for the schedule. If `active` is provided, the backup is
+/// skipped unless it's set or somebody's online, and it's cleared once the
+/// backup is made.
+///
+/// Nothing else can stop, restart, or back up the server while the backup's
+/// transition is held, so the requests below can't be interleaved with any of
+/// that.
+fn back_up(wrapper: &WrapperHandle, state: &StateMachine, active: Option<&AtomicBool>) {
+    // Somebody else might be stopping, restarting, or backing up the server
+    // already.
+    let _transition = match state.begin(ServerState::BackingUp) {
+        Ok(transition) => transition,
+        Err(e) => {
+            info!("Scheduled backups: skipping this backup. {}", e);
+            return;
+        }
+    };
+    if wrapper.blocking_call(|w| w.has_exited()).unwrap_or(true) {
+        info!("Scheduled backups: the Minecraft server isn't running. Skipping this backup");
+        return;
+    }
+
+    if let Some(active) = active {
+        if !active.load(Ordering::SeqCst) && !anybody_online(wrapper) {
+            info!(
+                "Scheduled backups: skipped: {}. Nobody has joined since the last scheduled backup",
+                NO_ACTIVITY
+            );
+            let _ = wrapper.blocking_call(|w| {
+                w.record_skipped_backup(NO_ACTIVITY);
+                Ok(())
+            });
+            return;
+        }
+        // Cleared before the backup starts, so that nobody who joins while
+        // it's going gets missed.
+        active.store(false, Ordering::SeqCst);
+    }
+
+    let result =
+        wrapper.blocking_make_world_backup(None, BackupDetails::scheduled(), BackupMode::Stop);
+    let e = match result {
+        Ok(backup) => {
+            info!(
+                "Scheduled backups: created a new world backup: {}",
+                backup.path.display()
+            );
+            return;
+        }
+        Err(e) => e,
+    };
+    // The next one has to be taken, since this one wasn't.
+    if let Some(active) = active {
+        active.store(true, Ordering::SeqCst);
+    }
+    error!(
+        "Scheduled backups: something went wrong while trying to make a server backup: {:#}",
+        e
+    );
+    // The server is left running when there isn't room for a backup, or when
+    // a pre-backup command fails, so there's nothing to restart.
+    if let Some(
+        WrapperError::InsufficientDiskSpace { .. } | WrapperError::BackupHookFailed { .. },
+    ) = e.downcast_ref()
+    {
+        return;
+    }
+    if let Err(e) = wrapper.blocking_restart() {
+        error!(
+            "Scheduled backups: after failing to make that backup, something went wrong while trying to restart the Minecraft server: {:#}",
+            e
+        );
+    }
+}
+
+/// Returns whether anybody's online, or true if the Minecraft server can't be
+/// asked, to be on the safe side.
+fn anybody_online(wrapper: &WrapperHandle) -> bool {
+    match wrapper.blocking_list_players(None) {
+        Ok(players) => !players.is_empty(),
+        Err(e) => {
+            warn!(
+                "Scheduled backups: couldn't tell whether anybody's online, so backing up anyways: {:#}",
+                e
+            );
+            true
+        }
+    }
+}
+
+/// Sleeps until the provided time. Returns right away if it already passed.
+fn sleep_until(time: DateTime<Local>) {
+    if let Ok(duration) = (time - Local::now()).to_std() {
+        thread::sleep(duration);
+    }
+}
+
+/// Describes a duration the way players would, like "5 minutes" or
+/// "30 seconds".
+fn describe(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (amount, unit) = match secs {
+        s if s >= 60 * 60 && s % (60 * 60) == 0 => (s / (60 * 60), "hour"),
+        s if s >= 60 && s % 60 == 0 => (s / 60, "minute"),
+        s => (s, "second"),
+    };
+    if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn values(bits: u64) -> Vec<u32> {
+        (0..64).filter(|&value| has_bit(bits, value)).collect()
+    }
+
+    #[test]
+    fn fields_are_parsed() {
+        let cases: &[(&str, u32, u32, &[u32])] = &[
+            ("5", 0, 59, &[5]),
+            ("*", 1, 12, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+            ("1-4", 0, 59, &[1, 2, 3, 4]),
+            ("*/15", 0, 59, &[0, 15, 30, 45]),
+            ("10-20/5", 0, 59, &[10, 15, 20]),
+            ("5/20", 0, 59, &[5, 25, 45]),
+            ("1,3,5", 0, 6, &[1, 3, 5]),
+            ("1-2,10-11", 1, 31, &[1, 2, 10, 11]),
+        ];
+        for &(field, min, max, expected) in cases {
+            let bits = parse_field(field, min, max).unwrap();
+            assert_eq!(values(bits), expected, "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn bad_fields_are_turned_away() {
+        let cases = [
+            ("5-1", "goes backwards"),
+            ("*/0", "can't be 0"),
+            ("*/x", "isn't a step"),
+            ("60", "isn't a number from 0 to 59"),
+            ("-1", "isn't a number from 0 to 59"),
+            ("1,,2", "isn't a number from 0 to 59"),
+        ];
+        for (field, message) in cases {
+            let e = parse_field(field, 0, 59).unwrap_err();
+            assert!(e.to_string().contains(message), "{:?}: {}", field, e);
+        }
+    }
+
+    #[test]
+    fn expressions_need_five_fields() {
+        for expression in ["", "0 0 * *", "0 0 * * * *", "@sometimes"] {
+            let e = expression.parse::<CronSchedule>().unwrap_err();
+            assert!(
+                e.to_string().contains("five fields"),
+                "{:?}: {}",
+                expression,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn shorthands_expand_to_their_expressions() {
+        assert_eq!(
+            "@daily".parse::<CronSchedule>().unwrap(),
+            "0 0 * * *".parse::<CronSchedule>().unwrap()
+        );
+        assert_eq!(
+            "@midnight".parse::<CronSchedule>().unwrap(),
+            "@daily".parse::<CronSchedule>().unwrap()
+        );
+        let daily: CronSchedule = "@daily".parse().unwrap();
+        assert_eq!(
+            daily.next_after(local(2024, 6, 1, 12, 0)),
+            Some(local(2024, 6, 2, 0, 0))
+        );
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        assert_eq!(
+            "0 0 * * 7".parse::<CronSchedule>().unwrap(),
+            "0 0 * * 0,7".parse::<CronSchedule>().unwrap()
+        );
+    }
+
+    #[test]
+    fn next_after_finds_the_next_match() {
+        let cases = [
+            // The same minute doesn't count.
+            (
+                "30 4 * * *",
+                local(2024, 6, 1, 4, 30),
+                local(2024, 6, 2, 4, 30),
+            ),
+            (
+                "*/15 * * * *",
+                local(2024, 6, 1, 12, 7),
+                local(2024, 6, 1, 12, 15),
+            ),
+            (
+                "0 12 * * 1-5",
+                local(2024, 6, 1, 13, 0),
+                local(2024, 6, 3, 12, 0),
+            ),
+            (
+                "0 12 1 * *",
+                local(2024, 12, 15, 0, 0),
+                local(2025, 1, 1, 12, 0),
+            ),
+            // Either day field matching is enough when both are restricted.
+            (
+                "0 12 15 * 1",
+                local(2024, 6, 1, 13, 0),
+                local(2024, 6, 3, 12, 0),
+            ),
+            (
+                "0 12 2 * 1",
+                local(2024, 6, 1, 13, 0),
+                local(2024, 6, 2, 12, 0),
+            ),
+        ];
+        for (expression, after, expected) in cases {
+            let schedule: CronSchedule = expression.parse().unwrap();
+            assert_eq!(
+                schedule.next_after(after),
+                Some(expected),
+                "{:?}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn leap_days_are_waited_for() {
+        let schedule: CronSchedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(local(2025, 3, 1, 12, 0)),
+            Some(local(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn days_that_never_happen_are_turned_away() {
+        let e = "0 0 30 2 *".parse::<CronSchedule>().unwrap_err();
+        assert!(e.to_string().contains("never happens"), "{}", e);
+    }
+}
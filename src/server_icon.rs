@@ -0,0 +1,82 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::error::WrapperError;
+
+/// The image in the Minecraft server's directory that players see next to the
+/// server in their multiplayer server list.
+pub const SERVER_ICON_FILE_NAME: &str = "server-icon.png";
+
+// The Minecraft server only uses icons that are exactly this many pixels wide
+// and tall.
+const SERVER_ICON_SIZE: u32 = 64;
+// Every PNG image starts with these bytes.
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Reads the server icon in the provided directory.
+///
+/// Returns a [WrapperError::ServerIconNotFound] if there isn't one.
+pub fn read_from_dir(server_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    let path = server_dir.join(SERVER_ICON_FILE_NAME);
+    match fs::read(&path) {
+        Ok(png) => Ok(png),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(WrapperError::ServerIconNotFound(path).into())
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+    }
+}
+
+/// Replaces the server icon in the provided directory with `png`, and returns
+/// the path to it.
+///
+/// The new icon is written to a temporary file first, which is then moved into
+/// place, so the icon is never left half-written. Returns a
+/// [WrapperError::InvalidArgument] without touching anything if `png` isn't a
+/// 64x64 PNG image.
+pub fn write_to_dir(server_dir: &Path, png: &[u8]) -> anyhow::Result<PathBuf> {
+    validate(png)?;
+
+    let path = server_dir.join(SERVER_ICON_FILE_NAME);
+    let tmp_path = server_dir.join(format!("{}.tmp", SERVER_ICON_FILE_NAME));
+    fs::write(&tmp_path, png).with_context(|| format!("Failed to write to {:?}", &tmp_path))?;
+    if let Err(e) = fs::rename(&tmp_path, &path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).with_context(|| format!("Failed to replace {:?}", &path));
+    }
+    Ok(path)
+}
+
+/// Returns a [WrapperError::InvalidArgument] if the provided bytes aren't a PNG
+/// image that the Minecraft server would use as its icon.
+fn validate(png: &[u8]) -> Result<(), WrapperError> {
+    if !png.starts_with(&PNG_SIGNATURE) {
+        return Err(WrapperError::InvalidArgument(
+            "Server icons have to be PNG images".to_owned(),
+        ));
+    }
+    // The signature is always followed by the IHDR chunk: its length, its
+    // type, and then the image's width and height.
+    let (width, height) = match png.get(PNG_SIGNATURE.len()..PNG_SIGNATURE.len() + 16) {
+        Some(ihdr) if &ihdr[4..8] == b"IHDR" => (
+            u32::from_be_bytes([ihdr[8], ihdr[9], ihdr[10], ihdr[11]]),
+            u32::from_be_bytes([ihdr[12], ihdr[13], ihdr[14], ihdr[15]]),
+        ),
+        _ => {
+            return Err(WrapperError::InvalidArgument(
+                "That PNG image is missing its IHDR chunk".to_owned(),
+            ))
+        }
+    };
+    if width != SERVER_ICON_SIZE || height != SERVER_ICON_SIZE {
+        return Err(WrapperError::InvalidArgument(format!(
+            "Server icons have to be {}x{} pixels, but that image is {}x{}",
+            SERVER_ICON_SIZE, SERVER_ICON_SIZE, width, height
+        )));
+    }
+    Ok(())
+}
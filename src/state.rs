@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+use crate::error::WrapperError;
+
+/// What the Minecraft server is up to, as far as the wrapper is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Running,
+    Stopping,
+    Stopped,
+    Restarting,
+    BackingUp,
+    /// A world backup is replacing the world.
+    Restoring,
+    /// The server couldn't be brought back up because of something that
+    /// restarting it again won't fix, like its EULA not being agreed to. It
+    /// stays down until somebody restarts or stops it.
+    Failed,
+}
+
+impl fmt::Display for ServerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ServerState::Running => "running",
+            ServerState::Stopping => "stopping",
+            ServerState::Stopped => "stopped",
+            ServerState::Restarting => "restarting",
+            ServerState::BackingUp => "backing up",
+            ServerState::Restoring => "restoring a backup",
+            ServerState::Failed => "failed",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Keeps track of the [ServerState], and makes sure that only one operation
+/// that stops the server, like stopping, restarting, or backing it up, runs at
+/// a time.
+///
+/// Cheap to clone, and every clone shares the same state. Callers should begin
+/// an operation before they queue up a request for the
+/// [Wrapper](crate::Wrapper). That way, a second operation that comes in while
+/// the first one is still going is turned away right away, instead of running
+/// against a server that the first one already stopped.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl StateMachine {
+    pub(crate) fn new() -> StateMachine {
+        StateMachine {
+            state: Arc::new(Mutex::new(ServerState::Running)),
+        }
+    }
+
+    /// Returns the current [ServerState].
+    pub fn current(&self) -> ServerState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Moves the server from [ServerState::Running] into the provided state
+    /// for the length of an operation. A server that's
+    /// [ServerState::Failed] can be restarted or stopped, too.
+    ///
+    /// Returns a [WrapperError::OperationInProgress] if the server isn't
+    /// running, like when another operation is already going. Otherwise,
+    /// returns a [Transition] that moves the server back to running when it's
+    /// dropped, unless it's [finished](Transition::finish) with another state.
+    pub fn begin(&self, during: ServerState) -> Result<Transition, WrapperError> {
+        let mut state = self.state.lock().unwrap();
+        let recovering = *state == ServerState::Failed
+            && matches!(during, ServerState::Restarting | ServerState::Stopping);
+        if *state != ServerState::Running && !recovering {
+            return Err(WrapperError::OperationInProgress(*state));
+        }
+        *state = during;
+        Ok(Transition {
+            machine: self.clone(),
+            end_state: ServerState::Running,
+        })
+    }
+
+    /// Returns a [WrapperError::ServerNotReady] if the server isn't
+    /// [ServerState::Running]. While it's starting, stopping, or being backed
+    /// up, commands sent to it are held up until it's done, or lost if it
+    /// stops along the way.
+    pub fn ensure_running(&self) -> Result<(), WrapperError> {
+        match self.current() {
+            ServerState::Running => Ok(()),
+            state => Err(WrapperError::ServerNotReady(state)),
+        }
+    }
+}
+
+/// An operation that's in progress. See [StateMachine::begin()].
+#[derive(Debug)]
+pub struct Transition {
+    machine: StateMachine,
+    end_state: ServerState,
+}
+
+impl Transition {
+    /// Ends the operation, and leaves the server in the provided state.
+    pub fn finish(mut self, state: ServerState) {
+        self.end_state = state;
+    }
+}
+
+impl Drop for Transition {
+    fn drop(&mut self) {
+        *self.machine.state.lock().unwrap() = self.end_state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, thread};
+
+    use super::*;
+
+    #[test]
+    fn only_one_overlapping_operation_goes_through() {
+        let machine = StateMachine::new();
+        let operations = [
+            ServerState::Stopping,
+            ServerState::Restarting,
+            ServerState::Stopping,
+            ServerState::Restarting,
+            ServerState::BackingUp,
+        ];
+        let barrier = Arc::new(Barrier::new(operations.len()));
+        let threads: Vec<_> = operations
+            .into_iter()
+            .map(|during| {
+                let machine = machine.clone();
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    let result = machine.begin(during);
+                    // Hold on to it until every thread has tried.
+                    barrier.wait();
+                    result.map(|_| during)
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        let began: Vec<ServerState> = results
+            .iter()
+            .filter_map(|r| r.as_ref().ok().copied())
+            .collect();
+        assert_eq!(began.len(), 1);
+        for result in &results {
+            if let Err(e) = result {
+                assert!(
+                    matches!(e, WrapperError::OperationInProgress(state) if *state == began[0])
+                );
+            }
+        }
+        assert_eq!(machine.current(), ServerState::Running);
+    }
+
+    #[test]
+    fn second_stop_is_turned_away_until_the_first_is_done() {
+        let machine = StateMachine::new();
+        let stop = machine.begin(ServerState::Stopping).unwrap();
+        assert!(matches!(
+            machine.begin(ServerState::Restarting),
+            Err(WrapperError::OperationInProgress(ServerState::Stopping))
+        ));
+        assert!(matches!(
+            machine.ensure_running(),
+            Err(WrapperError::ServerNotReady(ServerState::Stopping))
+        ));
+
+        stop.finish(ServerState::Stopped);
+        assert_eq!(machine.current(), ServerState::Stopped);
+        assert!(matches!(
+            machine.begin(ServerState::Stopping),
+            Err(WrapperError::OperationInProgress(ServerState::Stopped))
+        ));
+    }
+
+    #[test]
+    fn failed_server_can_only_be_restarted_or_stopped() {
+        let machine = StateMachine::new();
+        machine
+            .begin(ServerState::Restarting)
+            .unwrap()
+            .finish(ServerState::Failed);
+        assert!(machine.begin(ServerState::BackingUp).is_err());
+
+        drop(machine.begin(ServerState::Restarting).unwrap());
+        assert_eq!(machine.current(), ServerState::Running);
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::error::WrapperError;
+
+/// How the commands of one kind that the wrapper sent to the Minecraft server
+/// on its own have fared.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandCounters {
+    /// Commands that the server responded to without an error.
+    pub succeeded: u64,
+    /// Commands that failed for any reason, including timing out.
+    pub failed: u64,
+    /// Commands that the server didn't respond to in time. These are counted
+    /// in `failed`, too.
+    pub timed_out: u64,
+    /// What went wrong the last time one of these commands failed.
+    pub last_error: Option<String>,
+    /// When the last failure happened, as an RFC 3339 timestamp.
+    pub last_error_at: Option<String>,
+}
+
+/// Counts how the commands that the wrapper sends to the Minecraft server on
+/// its own fare, like the "/list" command behind
+/// [Wrapper::list_players()](crate::Wrapper::list_players). Commands are
+/// grouped by their name, like "list" or "whitelist".
+///
+/// Gives a quick idea of whether reading the server's console is working,
+/// without a full metrics stack. Commands that are passed along with
+/// [Wrapper::run_custom_command()](crate::Wrapper::run_custom_command) aren't
+/// counted, since the wrapper doesn't know what they're supposed to print.
+///
+/// Cheap to clone, and every clone shares the same counters, so they can be
+/// read without a request to the [Wrapper](crate::Wrapper).
+#[derive(Debug, Clone, Default)]
+pub struct CommandStats {
+    counters: Arc<Mutex<BTreeMap<String, CommandCounters>>>,
+}
+
+impl CommandStats {
+    /// Returns the counters for every kind of command that was sent since the
+    /// counters were last reset.
+    pub fn snapshot(&self) -> BTreeMap<String, CommandCounters> {
+        self.counters.lock().unwrap().clone()
+    }
+
+    /// Sets every counter back to zero.
+    pub fn reset(&self) {
+        self.counters.lock().unwrap().clear();
+    }
+
+    /// Records how the provided command fared.
+    pub(crate) fn record<T>(&self, cmd: &str, result: &anyhow::Result<T>) {
+        let mut counters = self.counters.lock().unwrap();
+        let counters = counters.entry(command_name(cmd)).or_default();
+        match result {
+            Ok(_) => counters.succeeded += 1,
+            Err(e) => record_failure(counters, e),
+        }
+    }
+
+    /// Records that the Minecraft server responded to the provided command,
+    /// which was already counted as a success, with an error.
+    pub(crate) fn record_rejection(&self, cmd: &str, e: &anyhow::Error) {
+        let mut counters = self.counters.lock().unwrap();
+        let counters = counters.entry(command_name(cmd)).or_default();
+        // The counters might have been reset in between.
+        counters.succeeded = counters.succeeded.saturating_sub(1);
+        record_failure(counters, e);
+    }
+}
+
+/// Counts the lines that the Minecraft server wrote that were dropped because
+/// nothing read them in time. See `stdout_channel_capacity` in
+/// [WrapperConfig](crate::WrapperConfig).
+///
+/// Cheap to clone, and every clone shares the same counter, so it can be read
+/// without a request to the [Wrapper](crate::Wrapper).
+#[derive(Debug, Clone, Default)]
+pub struct StdoutStats {
+    dropped_lines: Arc<AtomicU64>,
+}
+
+impl StdoutStats {
+    /// Returns how many lines were dropped since the wrapper started, across
+    /// every server process it spawned.
+    pub fn dropped_lines(&self) -> u64 {
+        self.dropped_lines.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_dropped_line(&self) {
+        self.dropped_lines.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn record_failure(counters: &mut CommandCounters, e: &anyhow::Error) {
+    counters.failed += 1;
+    if e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref(),
+            Some(WrapperError::OutputTimedOut { .. })
+        )
+    }) {
+        counters.timed_out += 1;
+    }
+    counters.last_error = Some(format!("{:#}", e));
+    counters.last_error_at = Some(Utc::now().to_rfc3339());
+}
+
+/// Returns the name of the provided command, without its leading slash or its
+/// arguments.
+fn command_name(cmd: &str) -> String {
+    cmd.trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{backup, backup_files, encryption::BackupEncryption, error::WrapperError};
+
+// Added to the end of a backup's path for the directory that it's test
+// extracted into while it's being verified.
+const VERIFY_DIR_SUFFIX: &str = ".verifying";
+
+/// How checking that a world backup can be restored went. It's written next to
+/// the backup, and shows up in `GET /backups`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupVerification {
+    /// Whether the tarball still matched its checksum, and could be unpacked.
+    pub passed: bool,
+    /// When the backup was verified, as an RFC 3339 timestamp.
+    pub verified_at: String,
+    /// The tarball's SHA-256 checksum when it was verified, in hex. [None] if
+    /// it couldn't be read.
+    pub sha256: Option<String>,
+    /// How many files were unpacked, if it got that far.
+    pub files: Option<usize>,
+    /// What went wrong, if something did.
+    pub error: Option<String>,
+}
+
+/// Checks that the world backup at `path` can be restored, and writes how it
+/// went next to it. Its checksum has to match the one that was written next to
+/// it when it was made, if there is one, and it has to unpack cleanly into a
+/// directory next to it, which is deleted afterwards. Encrypted backups are
+/// decrypted with `encryption`.
+///
+/// Only returns an error if the backup couldn't be checked at all, like when
+/// there isn't room to unpack it without leaving less than `min_free` bytes
+/// free. Nothing is written next to it in that case.
+pub(crate) fn verify(
+    path: &Path,
+    encryption: Option<&BackupEncryption>,
+    min_free: u64,
+) -> anyhow::Result<BackupVerification> {
+    let mut verification = BackupVerification {
+        passed: false,
+        verified_at: Utc::now().to_rfc3339(),
+        sha256: None,
+        files: None,
+        error: None,
+    };
+    if let Err(e) = check(path, encryption, min_free, &mut verification) {
+        if matches!(
+            e.downcast_ref(),
+            Some(WrapperError::InsufficientDiskSpace { .. })
+        ) {
+            return Err(e);
+        }
+        verification.error = Some(format!("{:#}", e));
+    } else {
+        verification.passed = true;
+    }
+    write_verification(path, &verification);
+    Ok(verification)
+}
+
+fn check(
+    path: &Path,
+    encryption: Option<&BackupEncryption>,
+    min_free: u64,
+    verification: &mut BackupVerification,
+) -> anyhow::Result<()> {
+    let sha256 = hash_file(path)?;
+    verification.sha256 = Some(sha256.clone());
+    if let Some(expected) = backup_files::read_checksum(path) {
+        if expected != sha256 {
+            bail!(
+                "The tarball's checksum is {}, but it was {} when it was made, so it's been changed or corrupted since",
+                sha256,
+                expected
+            );
+        }
+    }
+
+    let contents = backup::inspect_tarball(path, encryption)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    match backup::available_space(dir) {
+        Some(available) => backup::check_free_space(contents.unpacked_bytes, available, min_free)?,
+        None => warn!(
+            "Couldn't tell how much free disk space there is in {:?}. Test extracting a world backup anyways",
+            dir
+        ),
+    }
+    let verify_dir = verify_dir(path);
+    // Left behind by a verification that the wrapper didn't get to finish.
+    remove_verify_dir(&verify_dir);
+    let unpacked = backup::unpack_tarball(path, &verify_dir, encryption);
+    remove_verify_dir(&verify_dir);
+    unpacked?;
+    verification.files = Some(contents.files);
+    Ok(())
+}
+
+/// Returns the SHA-256 checksum of the file at `path`, in hex.
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn verify_dir(path: &Path) -> PathBuf {
+    let mut verify_dir = path.as_os_str().to_owned();
+    verify_dir.push(VERIFY_DIR_SUFFIX);
+    PathBuf::from(verify_dir)
+}
+
+fn remove_verify_dir(verify_dir: &Path) {
+    match fs::remove_dir_all(verify_dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => warn!(
+            "Failed to delete the directory that a world backup was test extracted into at {:?}: {}",
+            verify_dir, e
+        ),
+        _ => {}
+    }
+}
+
+/// Writes how verifying the world backup at the provided path went next to
+/// it. Something going wrong is only logged, since the backup itself is fine
+/// either way.
+fn write_verification(path: &Path, verification: &BackupVerification) {
+    let verification_path = backup_files::verification_path(path);
+    let result = serde_json::to_vec(verification)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| fs::write(&verification_path, json).map_err(anyhow::Error::from));
+    if let Err(e) = result {
+        warn!(
+            "Failed to write how verifying the world backup at {:?} went: {}",
+            path, e
+        );
+    }
+}
+
+/// Returns how verifying the world backup at the provided path went the last
+/// time it was verified, if it ever was.
+pub(crate) fn read_verification(path: &Path) -> Option<BackupVerification> {
+    let json = fs::read(backup_files::verification_path(path)).ok()?;
+    serde_json::from_slice(&json).ok()
+}
@@ -0,0 +1,736 @@
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actor::WrapperHandle,
+    error::WrapperError,
+    state::{ServerState, StateMachine},
+    Wrapper,
+};
+
+// How often the watchdog checks whether the Minecraft server process is still
+// running.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How many crashes the watchdog remembers.
+const MAX_CRASHES: usize = 50;
+
+/// Why the Minecraft server went down, as far as the watchdog can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitCondition {
+    /// The server exited without anyone asking it to, or failed to start back
+    /// up for a reason that wasn't recognized. Covers crashes, running out of
+    /// memory, and being killed by a signal.
+    Crash,
+    /// The server's EULA hasn't been agreed to.
+    EulaNotAccepted,
+    /// Something else is listening on the server's port.
+    PortInUse,
+    /// Another server is using the same world.
+    WorldLocked,
+    /// Java couldn't run the server's jar.
+    BadJar,
+}
+
+impl ExitCondition {
+    /// Returns the condition that the provided error from starting the server
+    /// describes.
+    pub fn of(e: &anyhow::Error) -> ExitCondition {
+        match e.downcast_ref() {
+            Some(WrapperError::EulaNotAccepted) => ExitCondition::EulaNotAccepted,
+            Some(WrapperError::PortInUse(_)) => ExitCondition::PortInUse,
+            Some(WrapperError::WorldLocked(_)) => ExitCondition::WorldLocked,
+            Some(WrapperError::BadJar(_)) => ExitCondition::BadJar,
+            _ => ExitCondition::Crash,
+        }
+    }
+}
+
+/// How the watchdog backs off from restarting a Minecraft server that keeps
+/// crashing.
+///
+/// The first restart after a crash happens as soon as it's been confirmed.
+/// Each crash after that, while the server hasn't stayed up for
+/// `reset_after_seconds` since it was last restarted, waits twice as long as
+/// the one before, starting at `initial_delay_seconds` and going up to
+/// `max_delay_seconds`. After `max_attempts` restarts in a row, the server is
+/// left down instead. 0 means there's no limit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartBackoff {
+    pub initial_delay_seconds: u64,
+    pub max_delay_seconds: u64,
+    pub max_attempts: u32,
+    pub reset_after_seconds: u64,
+}
+
+impl Default for RestartBackoff {
+    fn default() -> Self {
+        RestartBackoff {
+            initial_delay_seconds: 10,
+            max_delay_seconds: 5 * 60,
+            max_attempts: 5,
+            reset_after_seconds: 10 * 60,
+        }
+    }
+}
+
+impl RestartBackoff {
+    /// Returns an error if these settings don't make sense together.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.max_delay_seconds < self.initial_delay_seconds {
+            bail!("max_delay_seconds can't be less than initial_delay_seconds");
+        }
+        Ok(())
+    }
+
+    /// Returns how long to wait before restarting a server that's crashed
+    /// `crashes_in_a_row` times in a row, on top of the time it takes to
+    /// confirm the crash.
+    pub fn delay(&self, crashes_in_a_row: u32) -> Duration {
+        if crashes_in_a_row <= 1 {
+            return Duration::ZERO;
+        }
+        let doublings = (crashes_in_a_row - 2).min(u64::BITS - 1);
+        let secs = self
+            .initial_delay_seconds
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay_seconds);
+        Duration::from_secs(secs)
+    }
+
+    /// Returns whether the server has crashed too many times in a row to be
+    /// restarted again.
+    fn gives_up_after(&self, crashes_in_a_row: u32) -> bool {
+        self.max_attempts > 0 && crashes_in_a_row > self.max_attempts
+    }
+}
+
+/// What the watchdog did about a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrashOutcome {
+    /// It's waiting to restart the server.
+    Waiting,
+    Restarted,
+    /// It tried to restart the server, but that failed too.
+    RestartFailed,
+    /// The server crashed too many times in a row, so it was left down.
+    GaveUp,
+    /// Somebody else stopped or restarted the server before the watchdog got
+    /// to it.
+    Superseded,
+}
+
+/// A time that the Minecraft server exited without anyone asking it to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Crash {
+    /// When the watchdog was sure that the server crashed, as an RFC 3339
+    /// timestamp.
+    pub detected_at: String,
+    /// The server process's exit code, or [None] if a signal killed it.
+    pub exit_code: Option<i32>,
+    /// The signal that killed the server process, on Unix.
+    pub signal: Option<i32>,
+    /// How long the server process had been running.
+    pub uptime_seconds: u64,
+    /// The newest file in the server's `crash-reports/` directory, if the
+    /// server wrote it while it was running.
+    pub crash_report: Option<String>,
+    /// How many times the server has crashed in a row, counting this one.
+    pub crashes_in_a_row: u32,
+    /// How long the watchdog waited before restarting the server, on top of
+    /// the time it took to confirm the crash.
+    pub backoff_seconds: u64,
+    pub outcome: CrashOutcome,
+    /// What went wrong while restarting the server, if something did.
+    pub error: Option<String>,
+}
+
+/// The most recent crashes that the watchdog noticed.
+#[derive(Debug, Default)]
+pub struct CrashHistory {
+    crashes: VecDeque<Crash>,
+}
+
+impl CrashHistory {
+    /// Returns the crashes, newest first.
+    pub fn crashes(&self) -> Vec<Crash> {
+        self.crashes.iter().rev().cloned().collect()
+    }
+
+    fn push(&mut self, crash: Crash) {
+        if self.crashes.len() == MAX_CRASHES {
+            self.crashes.pop_front();
+        }
+        self.crashes.push_back(crash);
+    }
+
+    /// Changes what the watchdog did about the newest crash.
+    fn resolve(&mut self, outcome: CrashOutcome, error: Option<String>) {
+        if let Some(crash) = self.crashes.back_mut() {
+            crash.outcome = outcome;
+            crash.error = error;
+        }
+    }
+}
+
+/// Spawns a thread that keeps an eye on the Minecraft server process, and
+/// restarts it if it exits without anyone asking it to.
+///
+/// When the watchdog notices that the process has exited, it waits for
+/// `restart_confirm_delay` and checks again before restarting anything. A
+/// server that's in the middle of a slow, legitimate shutdown will have had a
+/// stop requested by then, and the watchdog leaves it alone. Every crash that's
+/// confirmed goes in the [Wrapper::crash_history()].
+///
+/// A server that keeps crashing is restarted less and less often, and
+/// eventually left [ServerState::Failed](crate::state::ServerState::Failed),
+/// the way `backoff` says to.
+///
+/// Only the conditions in `restart_on` are restarted from. If restarting the
+/// server fails because of any other condition, like its EULA not being agreed
+/// to, trying again won't help. The server is left
+/// [ServerState::Failed](crate::state::ServerState::Failed) instead, and the
+/// watchdog leaves it alone until somebody restarts it.
+///
+/// If the wrapper stops reading what the server writes to stdout while the
+/// server is still running, the server can't be managed anymore, so the
+/// watchdog kills it. Once it's down, it's treated like it crashed.
+pub fn spawn(
+    wrapper: WrapperHandle,
+    restart_confirm_delay: Duration,
+    restart_on: Vec<ExitCondition>,
+    backoff: RestartBackoff,
+) {
+    thread::spawn(move || {
+        let flags = wrapper.blocking_call(|w| {
+            Ok((
+                w.stop_requested_flag(),
+                w.state_machine(),
+                w.crash_history(),
+            ))
+        });
+        let (stop_requested, state, history) = match flags {
+            Ok(flags) => flags,
+            Err(_) => return,
+        };
+        let mut crashes_in_a_row = 0;
+        // When the watchdog last restarted the server.
+        let mut restarted_at: Option<Instant> = None;
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            // Somebody is stopping the server on purpose, and their request is
+            // probably still waiting for it to exit. Stay out of their way.
+            if stop_requested.load(Ordering::SeqCst) {
+                continue;
+            }
+            // Only a person can bring the server back from this. Whatever they do
+            // about it gets a fresh start.
+            if state.current() == ServerState::Failed {
+                crashes_in_a_row = 0;
+                restarted_at = None;
+                continue;
+            }
+            kill_if_stdout_reader_died(&wrapper, restart_confirm_delay);
+            // The server exiting on its own is always treated as a crash.
+            // Problems with its setup only show up while it's starting.
+            if !restart_on.contains(&ExitCondition::Crash) {
+                continue;
+            }
+
+            // Let other requests through before sleeping below so that whoever
+            // might be stopping the server on purpose gets a chance to say so.
+            match wrapper.blocking_call(exited_unexpectedly) {
+                Ok(false) => continue,
+                Ok(true) => {}
+                Err(e) => {
+                    warn!("Watchdog: {}", e);
+                    continue;
+                }
+            }
+
+            info!(
+            "Watchdog: the Minecraft server process exited unexpectedly. Waiting {}s before restarting it",
+            restart_confirm_delay.as_secs()
+        );
+            thread::sleep(restart_confirm_delay);
+
+            if stop_requested.load(Ordering::SeqCst) {
+                info!("Watchdog: the Minecraft server was stopped on purpose. Not restarting it");
+                continue;
+            }
+            // It's only a crash in a row if the server didn't stay up for long
+            // after the last one.
+            let in_a_row = match restarted_at {
+                Some(at) if at.elapsed() < Duration::from_secs(backoff.reset_after_seconds) => {
+                    crashes_in_a_row + 1
+                }
+                _ => 1,
+            };
+            let delay = backoff.delay(in_a_row);
+            match wrapper.blocking_call(move |w| crash_of(w, in_a_row, delay)) {
+                Ok(Some(crash)) => {
+                    history.lock().unwrap().push(crash);
+                    crashes_in_a_row = in_a_row;
+                }
+                Ok(None) => {
+                    info!(
+                        "Watchdog: the Minecraft server was stopped on purpose. Not restarting it"
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Watchdog: {}", e);
+                    continue;
+                }
+            }
+
+            if backoff.gives_up_after(crashes_in_a_row) {
+                error!(
+                "Watchdog: the Minecraft server crashed {} times in a row, so it'll stay down until somebody restarts it",
+                crashes_in_a_row
+            );
+                give_up(&state, &history);
+                continue;
+            }
+            if !delay.is_zero() {
+                info!(
+                "Watchdog: the Minecraft server crashed {} times in a row. Waiting another {}s before restarting it",
+                crashes_in_a_row,
+                delay.as_secs()
+            );
+                thread::sleep(delay);
+                if stop_requested.load(Ordering::SeqCst) {
+                    info!(
+                        "Watchdog: the Minecraft server was stopped on purpose. Not restarting it"
+                    );
+                    history
+                        .lock()
+                        .unwrap()
+                        .resolve(CrashOutcome::Superseded, None);
+                    continue;
+                }
+            }
+
+            // Somebody else might be stopping, restarting, or backing up the server
+            // already.
+            let transition = match state.begin(ServerState::Restarting) {
+                Ok(transition) => transition,
+                Err(e) => {
+                    info!("Watchdog: not restarting the Minecraft server. {}", e);
+                    history
+                        .lock()
+                        .unwrap()
+                        .resolve(CrashOutcome::Superseded, None);
+                    continue;
+                }
+            };
+            // Nothing else can stop or restart the server while the transition is
+            // held, so it's still down when the restart below is carried out.
+            match wrapper.blocking_call(exited_unexpectedly) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Watchdog: the Minecraft server was stopped or restarted on purpose. Not restarting it");
+                    history
+                        .lock()
+                        .unwrap()
+                        .resolve(CrashOutcome::Superseded, None);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Watchdog: {}", e);
+                    continue;
+                }
+            }
+            restarted_at = Some(Instant::now());
+            match wrapper.blocking_restart() {
+                Ok(()) => {
+                    info!("Watchdog: restarted the Minecraft server");
+                    history
+                        .lock()
+                        .unwrap()
+                        .resolve(CrashOutcome::Restarted, None);
+                }
+                Err(e) => {
+                    error!(
+                    "Watchdog: something went wrong while trying to restart the Minecraft server: {:#}",
+                    e
+                );
+                    history
+                        .lock()
+                        .unwrap()
+                        .resolve(CrashOutcome::RestartFailed, Some(format!("{:#}", e)));
+                    let condition = ExitCondition::of(&e);
+                    if !restart_on.contains(&condition) {
+                        error!(
+                        "Watchdog: restarting the Minecraft server again won't fix that, so it'll stay down until somebody restarts it"
+                    );
+                        transition.finish(ServerState::Failed);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Returns the crash to add to the history if the Minecraft server still
+/// hasn't been restarted, and nobody asked it to stop. Returns [None] if it
+/// has, or if they did.
+fn crash_of(
+    w: &mut Wrapper,
+    crashes_in_a_row: u32,
+    delay: Duration,
+) -> anyhow::Result<Option<Crash>> {
+    if w.stop_requested() {
+        return Ok(None);
+    }
+    let exit_status = match w.exit_status()? {
+        Some(exit_status) => exit_status,
+        None => return Ok(None),
+    };
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&exit_status);
+    #[cfg(not(unix))]
+    let signal = None;
+    let started_at = w.server_started_at();
+    // Only a report from while the server was running has anything to do
+    // with this crash.
+    let crash_report = w.last_crash_report().ok().flatten().filter(|report| {
+        DateTime::parse_from_rfc3339(&report.modified_at)
+            .is_ok_and(|modified_at| modified_at >= started_at)
+    });
+    Ok(Some(Crash {
+        detected_at: Utc::now().to_rfc3339(),
+        exit_code: exit_status.code(),
+        signal,
+        uptime_seconds: (Utc::now() - started_at).num_seconds().max(0) as u64,
+        crash_report: crash_report.map(|report| report.file_name),
+        crashes_in_a_row,
+        backoff_seconds: delay.as_secs(),
+        outcome: CrashOutcome::Waiting,
+        error: None,
+    }))
+}
+
+/// Leaves the Minecraft server [ServerState::Failed] after it crashed too many
+/// times in a row, unless somebody else is already doing something about it.
+fn give_up(state: &StateMachine, history: &Mutex<CrashHistory>) {
+    match state.begin(ServerState::Restarting) {
+        Ok(transition) => {
+            transition.finish(ServerState::Failed);
+            history.lock().unwrap().resolve(CrashOutcome::GaveUp, None);
+        }
+        Err(e) => {
+            info!("Watchdog: not leaving the Minecraft server down. {}", e);
+            history
+                .lock()
+                .unwrap()
+                .resolve(CrashOutcome::Superseded, None);
+        }
+    }
+}
+
+/// Kills the Minecraft server if the wrapper stopped reading its stdout while
+/// it's still running, and that's still the case after `confirm_delay`.
+fn kill_if_stdout_reader_died(wrapper: &WrapperHandle, confirm_delay: Duration) {
+    match wrapper.blocking_call(|w| w.stdout_reader_died()) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            warn!("Watchdog: {}", e);
+            return;
+        }
+    }
+
+    error!(
+        "Watchdog: stopped reading what the Minecraft server writes to stdout, even though it's still running. Waiting {}s before killing it",
+        confirm_delay.as_secs()
+    );
+    // The process might have just been exiting.
+    thread::sleep(confirm_delay);
+
+    let result = wrapper.blocking_call(|w| {
+        if !w.stdout_reader_died()? || w.stop_requested() {
+            return Ok(None);
+        }
+        Ok(Some(w.kill_server()))
+    });
+    match result {
+        Ok(Some(Ok(()))) => error!("Watchdog: killed the Minecraft server, since the wrapper can't see what it writes anymore"),
+        Ok(Some(Err(e))) => error!(
+            "Watchdog: something went wrong while trying to kill the Minecraft server: {}",
+            e
+        ),
+        Ok(None) => {}
+        Err(e) => warn!("Watchdog: {}", e),
+    }
+}
+
+/// Spawns a thread that checks whether the Minecraft server still responds to
+/// commands every `interval`, with [Wrapper::probe()]. That catches a server
+/// whose process is still running, but that's deadlocked, which the watchdog
+/// can't tell apart from a healthy one.
+///
+/// Once `failure_threshold` checks in a row have failed, that's logged, and if
+/// `restart` is set, the server is killed and started back up. Nothing is
+/// checked while the server is being stopped on purpose, or while it isn't
+/// [ServerState::Running], like during a backup. If starting it back up fails,
+/// the watchdog takes it from there, since the server isn't running anymore.
+pub fn spawn_health_check(
+    wrapper: WrapperHandle,
+    interval: Duration,
+    failure_threshold: u32,
+    restart: bool,
+) {
+    thread::spawn(move || {
+        let (stop_requested, state) =
+            match wrapper.blocking_call(|w| Ok((w.stop_requested_flag(), w.state_machine()))) {
+                Ok(flags) => flags,
+                Err(_) => return,
+            };
+        let mut failures = 0;
+        loop {
+            thread::sleep(interval);
+
+            if stop_requested.load(Ordering::SeqCst) || state.current() != ServerState::Running {
+                failures = 0;
+                continue;
+            }
+            let result = wrapper.blocking_call(|w| {
+                // The watchdog handles servers that aren't running anymore.
+                if w.has_exited().unwrap_or(true) {
+                    return Ok(None);
+                }
+                Ok(Some(w.probe()))
+            });
+            let result = match result {
+                Ok(Some(result)) => result,
+                Ok(None) => {
+                    failures = 0;
+                    continue;
+                }
+                Err(_) => return,
+            };
+            match result {
+                Ok(_) => {
+                    failures = 0;
+                    continue;
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        "Health check: the Minecraft server didn't respond ({} of {} in a row): {:#}",
+                        failures, failure_threshold, e
+                    );
+                }
+            }
+            if failures < failure_threshold {
+                continue;
+            }
+            failures = 0;
+
+            if !restart {
+                error!(
+                    "Health check: the Minecraft server is running, but stopped responding to commands. Turn on auto_restart to have it restarted automatically"
+                );
+                continue;
+            }
+            error!("Health check: the Minecraft server is running, but stopped responding to commands. Restarting it");
+            // Somebody else might be stopping, restarting, or backing up the
+            // server already.
+            let _transition = match state.begin(ServerState::Restarting) {
+                Ok(transition) => transition,
+                Err(e) => {
+                    info!("Health check: not restarting the Minecraft server. {}", e);
+                    continue;
+                }
+            };
+            let killed = wrapper.blocking_call(|w| {
+                if w.stop_requested() {
+                    return Ok(false);
+                }
+                // A deadlocked server would never get around to stopping
+                // gracefully.
+                w.kill_server()?;
+                Ok(true)
+            });
+            match killed {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Health check: the Minecraft server is being stopped on purpose. Not restarting it");
+                    continue;
+                }
+                Err(e) => {
+                    error!(
+                        "Health check: something went wrong while trying to kill the Minecraft server: {}",
+                        e
+                    );
+                    continue;
+                }
+            }
+            match wrapper.blocking_restart() {
+                Ok(()) => info!("Health check: restarted the Minecraft server"),
+                Err(e) => error!(
+                    "Health check: something went wrong while trying to restart the Minecraft server: {:#}",
+                    e
+                ),
+            }
+        }
+    });
+}
+
+/// Returns true if the Minecraft server process has exited, and nobody asked it
+/// to.
+fn exited_unexpectedly(wrapper: &mut Wrapper) -> anyhow::Result<bool> {
+    Ok(!wrapper.stop_requested() && wrapper.has_exited()?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::tests::{wait_until, TestServer};
+
+    // How long these tests have the watchdog wait to be sure of a crash.
+    const CONFIRM_DELAY: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn restart_delay_doubles_up_to_the_max() {
+        let backoff = RestartBackoff::default();
+        let delays: Vec<u64> = (0..=8).map(|n| backoff.delay(n).as_secs()).collect();
+        assert_eq!(delays, [0, 0, 10, 20, 40, 80, 160, 300, 300]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn restart_delay_doesnt_overflow() {
+        let backoff = RestartBackoff {
+            initial_delay_seconds: u64::MAX / 2,
+            max_delay_seconds: u64::MAX,
+            ..RestartBackoff::default()
+        };
+        assert_eq!(backoff.delay(3), Duration::from_secs(u64::MAX - 1));
+        assert_eq!(backoff.delay(4), Duration::from_secs(u64::MAX));
+        assert_eq!(backoff.delay(100), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn restarts_give_up_after_max_attempts() {
+        let backoff = RestartBackoff::default();
+        assert!(!backoff.gives_up_after(5));
+        assert!(backoff.gives_up_after(6));
+
+        let unlimited = RestartBackoff {
+            max_attempts: 0,
+            ..RestartBackoff::default()
+        };
+        assert!(!unlimited.gives_up_after(u32::MAX));
+    }
+
+    #[test]
+    fn restart_backoff_is_checked() {
+        assert!(RestartBackoff::default().check().is_ok());
+        let backoff = RestartBackoff {
+            initial_delay_seconds: 60,
+            max_delay_seconds: 30,
+            ..RestartBackoff::default()
+        };
+        assert!(backoff.check().is_err());
+    }
+
+    #[test]
+    fn restart_backoff_fills_in_defaults() {
+        let backoff: RestartBackoff = serde_yaml::from_str("max_attempts: 0").unwrap();
+        assert_eq!(
+            backoff,
+            RestartBackoff {
+                max_attempts: 0,
+                ..RestartBackoff::default()
+            }
+        );
+    }
+
+    #[test]
+    fn crashed_server_is_restarted() {
+        let server = TestServer::new("watchdog-crash");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+        let history = wrapper.blocking_call(|w| Ok(w.crash_history())).unwrap();
+        spawn(
+            wrapper.clone(),
+            CONFIRM_DELAY,
+            vec![ExitCondition::Crash],
+            RestartBackoff::default(),
+        );
+
+        wrapper.blocking_run_command("/crash".to_owned()).unwrap();
+        assert!(wait_until(|| history
+            .lock()
+            .unwrap()
+            .crashes()
+            .first()
+            .is_some_and(|crash| crash.outcome == CrashOutcome::Restarted)));
+        let crashes = history.lock().unwrap().crashes();
+        assert_eq!(crashes.len(), 1);
+        assert_eq!(crashes[0].exit_code, Some(1));
+        assert_eq!(crashes[0].crashes_in_a_row, 1);
+        assert!(!wrapper.blocking_call(|w| w.has_exited()).unwrap());
+    }
+
+    #[test]
+    fn stopped_server_is_left_stopped() {
+        let server = TestServer::new("watchdog-stop");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+        let history = wrapper.blocking_call(|w| Ok(w.crash_history())).unwrap();
+        spawn(
+            wrapper.clone(),
+            CONFIRM_DELAY,
+            vec![ExitCondition::Crash],
+            RestartBackoff::default(),
+        );
+
+        wrapper.blocking_stop().unwrap();
+        // Long enough for the watchdog to notice and confirm a crash, if it
+        // thought that this was one.
+        thread::sleep(POLL_INTERVAL * 2 + CONFIRM_DELAY);
+        assert!(history.lock().unwrap().crashes().is_empty());
+        assert!(wrapper.blocking_call(|w| w.has_exited()).unwrap());
+
+        // Starting the server back up clears the flag, so that the watchdog
+        // looks after it again.
+        wrapper.blocking_restart().unwrap();
+        assert!(!wrapper.blocking_call(|w| Ok(w.stop_requested())).unwrap());
+    }
+
+    #[test]
+    fn server_whose_stdout_closed_is_killed() {
+        let server = TestServer::new("watchdog-closed-stdout");
+        server.close_stdout_after_starting();
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+        assert!(wait_until(|| wrapper
+            .blocking_call(|w| w.stdout_reader_died())
+            .unwrap()));
+
+        kill_if_stdout_reader_died(&wrapper, Duration::ZERO);
+        assert!(wrapper.blocking_call(|w| w.has_exited()).unwrap());
+    }
+
+    #[test]
+    fn server_whose_stdout_is_fine_is_left_alone() {
+        let server = TestServer::new("watchdog-stdout-fine");
+        let wrapper = WrapperHandle::spawn(Wrapper::new(server.config()).unwrap());
+
+        kill_if_stdout_reader_died(&wrapper, Duration::ZERO);
+        assert!(!wrapper.blocking_call(|w| w.has_exited()).unwrap());
+    }
+}